@@ -1,19 +1,29 @@
-use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
-use anyhow::anyhow;
 use futures_util::StreamExt;
 use moly_protocol::{
     open_ai::{
-        ChatResponse, ChatResponseChunkData, ChatResponseData, ChunkChoiceData, MessageData, Role,
-        StopReason,
+        ChatRequestData, ChatResponse, ChatResponseChunkData, ChatResponseData, ChunkChoiceData,
+        ContentPart, MessageContent, MessageData, ResponseFormat, Role, StopReason,
     },
-    protocol::LoadModelOptions,
+    protocol::{BackendFeatures, LoadModelOptions, LoadStage, LoraAdapter, MolyError},
 };
 use wasmedge_sdk::{wasi::WasiModule, Module, Store, Vm};
 
-use crate::store::download_files::DownloadedFile;
+use crate::store::{download_files::DownloadedFile, network_error};
 
-use super::BackendModel;
+use super::{
+    llama_cpp_args, load_error, server_proxy::ServerProxy, sse::SseDecoder, stderr,
+    structured_output, BackendModel, ChatCancel, HealthCheck,
+};
 
 // From https://github.com/L-jasmine/LlamaEdge/tree/feat/support_unload_and_exit
 // A repo that fork from LlamaEdge/LlamaEdge for support unload model and exit
@@ -22,28 +32,51 @@ static WASM: &[u8] = include_bytes!("../../wasm/llama-api-server.wasm");
 /// Use server which is OpenAI compatible
 pub struct LLamaEdgeApiServer {
     id: String,
+    // Where the clients connect to, forwarded by the proxy to the server on `server_addr`.
     listen_addr: SocketAddr,
+    server_addr: SocketAddr,
+    proxy: Option<Arc<ServerProxy>>,
     load_model_options: LoadModelOptions,
     wasm_module: Module,
     embedding: Option<(std::path::PathBuf, u64)>,
-    running_controller: tokio::sync::broadcast::Sender<()>,
-    #[allow(dead_code)]
     model_thread: std::thread::JoinHandle<()>,
+    // Why the server stopped, set once its thread ends.
+    exit_reason: Arc<Mutex<Option<String>>>,
+    active_chats: Arc<AtomicUsize>,
     failed: bool,
 }
 
+/// Counts a chat as running while it is alive.
+struct ActiveChat(Arc<AtomicUsize>);
+
+impl ActiveChat {
+    fn new(active_chats: &Arc<AtomicUsize>) -> Self {
+        active_chats.fetch_add(1, Ordering::AcqRel);
+        Self(active_chats.clone())
+    }
+}
+
+impl Drop for ActiveChat {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+fn context_size(file: &DownloadedFile, load_model: &LoadModelOptions) -> u32 {
+    load_model
+        .n_ctx
+        .unwrap_or(file.context_size.min(8 * 1024) as u32)
+}
+
 fn create_wasi(
     listen_addr: SocketAddr,
     file: &DownloadedFile,
     load_model: &LoadModelOptions,
     embedding: Option<(std::path::PathBuf, u64)>,
+    adapters: &[(std::path::PathBuf, f32)],
+    images_dir: Option<&std::path::Path>,
 ) -> wasmedge_sdk::WasmEdgeResult<WasiModule> {
-    // use model metadata context size
-    let ctx_size_str = if let Some(n_ctx) = load_model.n_ctx {
-        format!("{}", n_ctx)
-    } else {
-        format!("{}", file.context_size.min(8 * 1024))
-    };
+    let ctx_size_str = format!("{}", context_size(file, load_model));
 
     let ctx_size = if let Some((_, embedding_ctx)) = embedding {
         Some(format!("{},{}", ctx_size_str, embedding_ctx))
@@ -118,7 +151,66 @@ fn create_wasi(
     add_args!("-r", reverse_prompt);
     add_args!("--socket-addr", listen_addr);
 
-    WasiModule::create(Some(args), None, None)
+    let llama_cpp_args = llama_cpp_args(load_model, adapters);
+    args.extend(llama_cpp_args.iter().map(String::as_str));
+
+    // Vision models need their projector, and the api-server keeps the images it
+    // receives in its working directory, mapped to `images_dir` on the host.
+    let mmproj = if file.mmproj.is_empty() {
+        None
+    } else {
+        let mmproj_path = std::path::Path::new(&file.download_dir)
+            .join(&file.model_id)
+            .join(&file.mmproj);
+        Some(mmproj_path.to_string_lossy().to_string())
+    };
+    add_args!("--llava-mmproj", mmproj);
+    let preopen = images_dir.map(|dir| format!(".:{}", dir.display()));
+    let preopens = preopen.as_ref().map(|preopen| vec![preopen.as_str()]);
+
+    WasiModule::create(Some(args), None, preopens)
+}
+
+fn image_mime_type(path: &str) -> &'static str {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    match extension.as_str() {
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+/// Replaces the images that point to local files with base64 data URLs.
+fn inline_local_images(data: &mut ChatRequestData) -> Result<(), MolyError> {
+    use base64::Engine;
+
+    for message in &mut data.messages {
+        let MessageContent::Parts(parts) = &mut message.content else {
+            continue;
+        };
+
+        for part in parts {
+            let ContentPart::ImageUrl { image_url } = part else {
+                continue;
+            };
+            let Some(path) = image_url.url.strip_prefix("file://") else {
+                continue;
+            };
+
+            let bytes = std::fs::read(path)
+                .map_err(|e| MolyError::Io(format!("failed to read image {path}: {e}")))?;
+            let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+            *part = ContentPart::image_from_base64(image_mime_type(path), &encoded);
+        }
+    }
+
+    Ok(())
 }
 
 pub fn run_wasm_by_downloaded_file(
@@ -127,12 +219,34 @@ pub fn run_wasm_by_downloaded_file(
     file: DownloadedFile,
     load_model: LoadModelOptions,
     embedding: Option<(std::path::PathBuf, u64)>,
-) {
+    adapters: Vec<(std::path::PathBuf, f32)>,
+) -> Result<(), String> {
     use wasmedge_sdk::AsInstance;
 
     let mut instances = HashMap::new();
 
-    let mut wasi = create_wasi(listen_addr, &file, &load_model, embedding).unwrap();
+    // A directory of its own for the images the server receives, removed once it exits.
+    let images_dir = (!file.mmproj.is_empty()).then(|| {
+        std::env::temp_dir().join(format!(
+            "moly-images-{}-{}",
+            std::process::id(),
+            listen_addr.port()
+        ))
+    });
+    if let Some(dir) = &images_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+    }
+
+    let mut wasi = create_wasi(
+        listen_addr,
+        &file,
+        &load_model,
+        embedding,
+        &adapters,
+        images_dir.as_deref(),
+    )
+    .unwrap();
     instances.insert(wasi.name().to_string(), wasi.as_mut());
 
     let mut wasi_nn = wasmedge_sdk::plugin::PluginManager::load_plugin_wasi_nn().unwrap();
@@ -142,9 +256,66 @@ pub fn run_wasm_by_downloaded_file(
     let mut vm = Vm::new(store);
     vm.register_module(None, wasm_module.clone()).unwrap();
 
-    let _ = vm.run_func(None, "_start", []);
+    let result = vm.run_func(None, "_start", []);
+    if let Some(dir) = &images_dir {
+        let _ = std::fs::remove_dir_all(dir);
+    }
 
     log::debug!("wasm exit");
+    result.map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Whether `new` only changes the scales of the adapters of `old`, which the server can
+/// apply to the loaded model.
+fn only_adapter_scales_differ(old: &LoadModelOptions, new: &LoadModelOptions) -> bool {
+    let same_adapters = old.lora_adapters.len() == new.lora_adapters.len()
+        && old
+            .lora_adapters
+            .iter()
+            .zip(&new.lora_adapters)
+            .all(|(a, b)| a.file_id == b.file_id);
+    let without_adapters = |options: &LoadModelOptions| LoadModelOptions {
+        lora_adapters: vec![],
+        ..options.clone()
+    };
+    same_adapters && without_adapters(old) == without_adapters(new)
+}
+
+/// Sets the scales of the adapters the server was started with, in the same order.
+/// Returns false when the server doesn't support it, the model has to be reloaded then.
+fn set_adapter_scales(listen_addr: SocketAddr, adapters: &[LoraAdapter]) -> bool {
+    let body = adapters
+        .iter()
+        .enumerate()
+        .map(|(id, adapter)| serde_json::json!({ "id": id, "scale": adapter.scale }))
+        .collect::<Vec<_>>();
+    let response = reqwest::blocking::ClientBuilder::new()
+        .timeout(Duration::from_secs(3))
+        .no_proxy()
+        .build()
+        .and_then(|client| {
+            client
+                .post(format!(
+                    "http://localhost:{}/lora-adapters",
+                    listen_addr.port()
+                ))
+                .json(&body)
+                .send()
+        });
+    match response {
+        Ok(response) if response.status().is_success() => true,
+        Ok(response) => {
+            log::warn!(
+                "the server refused the adapter scales: {}",
+                response.status()
+            );
+            false
+        }
+        Err(e) => {
+            log::warn!("failed to set the adapter scales: {e}");
+            false
+        }
+    }
 }
 
 fn stop_chunk(reason: StopReason) -> ChatResponseChunkData {
@@ -162,11 +333,26 @@ fn stop_chunk(reason: StopReason) -> ChatResponseChunkData {
         created: 0,
         model: String::new(),
         system_fingerprint: String::new(),
+        usage: None,
         object: "chat.completion.chunk".to_string(),
     }
 }
 
 impl BackendModel for LLamaEdgeApiServer {
+    const NAME: &'static str = "LLamaEdgeApiServer";
+    const FEATURES: BackendFeatures = BackendFeatures {
+        streaming: true,
+        embeddings: true,
+        tool_calls: false,
+        // Forwarded, but the pinned LlamaEdge server isn't known to return them.
+        logprobs: false,
+        vision: true,
+        lora_adapters: true,
+        constrained_output: true,
+    };
+    // Requests arriving at the same time are scheduled by the server itself.
+    const MAX_CONCURRENT_CHATS: usize = usize::MAX;
+
     fn new_or_reload(
         async_rt: &tokio::runtime::Runtime,
         old_model: Option<Self>,
@@ -174,6 +360,7 @@ impl BackendModel for LLamaEdgeApiServer {
         options: moly_protocol::protocol::LoadModelOptions,
         tx: std::sync::mpsc::Sender<anyhow::Result<moly_protocol::protocol::LoadModelResponse>>,
         embedding: Option<(std::path::PathBuf, u64)>,
+        adapters: Vec<(std::path::PathBuf, f32)>,
     ) -> Self {
         let load_model_options = options.clone();
         let mut need_reload = true;
@@ -194,10 +381,21 @@ impl BackendModel for LLamaEdgeApiServer {
             if !old_model.failed
                 && old_model.id == file.id.as_str()
                 && listen_addr == old_model.listen_addr
-                && old_model.load_model_options.n_ctx == options.n_ctx
-                && old_model.load_model_options.n_batch == options.n_batch
+                && old_model.load_model_options == options
+                && old_model.embedding == embedding
+            {
+                need_reload = false;
+            } else if !old_model.failed
+                && old_model.id == file.id.as_str()
+                && listen_addr == old_model.listen_addr
                 && old_model.embedding == embedding
+                && only_adapter_scales_differ(&old_model.load_model_options, &options)
+                && set_adapter_scales(old_model.server_addr, &options.lora_adapters)
             {
+                log::info!(
+                    "changed the adapter scales of {} without reloading",
+                    file.id
+                );
                 need_reload = false;
             }
             (old_model.wasm_module.clone(), listen_addr)
@@ -228,14 +426,23 @@ impl BackendModel for LLamaEdgeApiServer {
             let _ = tx.send(Ok(moly_protocol::protocol::LoadModelResponse::Completed(
                 moly_protocol::protocol::LoadedModelInfo {
                     file_id: file.id.to_string(),
+                    n_ctx: context_size(&file, &options),
                     model_id: file.model_id,
                     information: "".to_string(),
                     listen_port: listen_addr.port(),
                 },
             )));
-            return old_model.unwrap();
+            let mut old_model = old_model.unwrap();
+            old_model.load_model_options = options;
+            return old_model;
         }
 
+        // The proxy keeps listening while the server restarts on the same address.
+        let old_proxy = old_model
+            .as_ref()
+            .and_then(|old_model| old_model.proxy.clone())
+            .filter(|proxy| proxy.listen_addr() == listen_addr);
+
         // Only stop the old model if it is not failed
         // This is important because the old model may be failed to start due to an
         // external service running on the same port
@@ -245,23 +452,85 @@ impl BackendModel for LLamaEdgeApiServer {
             }
         }
 
-        let wasm_module_ = wasm_module.clone();
-
         let file_id = file.id.to_string();
-
         let listen_port = listen_addr.port();
-        let url = format!("http://localhost:{}/echo", listen_port);
+
+        let server_addr = std::net::TcpListener::bind("localhost:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        let proxy = match old_proxy {
+            Some(proxy) => {
+                proxy.set_server_addr(server_addr);
+                Ok(proxy)
+            }
+            None => ServerProxy::bind(async_rt, listen_addr, server_addr).map(Arc::new),
+        };
+        let proxy = match proxy {
+            Ok(proxy) => proxy,
+            Err(e) => {
+                log::error!("Failed to listen on {listen_addr}: {e}");
+                let _ = tx.send(Err(MolyError::PortInUse(listen_port).into()));
+                return Self {
+                    id: file_id,
+                    wasm_module,
+                    embedding,
+                    listen_addr,
+                    server_addr,
+                    proxy: None,
+                    model_thread: std::thread::spawn(|| {}),
+                    exit_reason: Arc::new(Mutex::new(None)),
+                    active_chats: Arc::new(AtomicUsize::new(0)),
+                    load_model_options,
+                    failed: true,
+                };
+            }
+        };
+
+        let wasm_module_ = wasm_module.clone();
+        let url = format!("http://localhost:{}/echo", server_addr.port());
+        let stderr_mark = stderr::mark();
 
         let file_ = file.clone();
 
         let embedding_ = embedding.clone();
 
+        let exit_reason = Arc::new(Mutex::new(None));
+        let exit_reason_ = exit_reason.clone();
+
         let model_thread = std::thread::spawn(move || {
-            run_wasm_by_downloaded_file(listen_addr, wasm_module_, file, options, embedding_)
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+                run_wasm_by_downloaded_file(
+                    server_addr,
+                    wasm_module_,
+                    file,
+                    options,
+                    embedding_,
+                    adapters,
+                )
+            }));
+            let reason = match result {
+                Ok(Ok(())) => "the model server exited".to_string(),
+                Ok(Err(e)) => format!("the model server failed: {e}"),
+                Err(panic) => {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    format!("the model server panicked: {message}")
+                }
+            };
+            *exit_reason_.lock().unwrap() = Some(reason);
         });
 
+        const PROBES: u32 = 5;
         let mut test_server = false;
-        for _i in 0..5 {
+        for i in 0..PROBES {
+            let _ = tx.send(Ok(moly_protocol::protocol::LoadModelResponse::Progress(
+                file_id.clone(),
+                LoadStage::WarmingUp,
+                0.3 + 0.7 * i as f32 / PROBES as f32,
+            )));
             let r = reqwest::blocking::ClientBuilder::new()
                 .timeout(Duration::from_secs(3))
                 .no_proxy()
@@ -281,24 +550,32 @@ impl BackendModel for LLamaEdgeApiServer {
             let _ = tx.send(Ok(moly_protocol::protocol::LoadModelResponse::Completed(
                 moly_protocol::protocol::LoadedModelInfo {
                     file_id: file_.id.to_string(),
+                    n_ctx: context_size(&file_, &load_model_options),
                     model_id: file_.model_id,
                     information: "".to_string(),
                     listen_port,
                 },
             )));
         } else {
-            let _ = tx.send(Err(anyhow!("Failed to start the model")));
+            let reason = exit_reason
+                .lock()
+                .unwrap()
+                .clone()
+                .unwrap_or_else(|| "the model server did not start".to_string());
+            let error = load_error(file_.id.to_string(), reason, stderr_mark);
+            let _ = tx.send(Err(error.into()));
         }
 
-        let running_controller = tokio::sync::broadcast::channel(1).0;
-
         let new_model = Self {
             id: file_id,
             wasm_module,
             embedding,
             listen_addr,
-            running_controller,
+            server_addr,
+            proxy: Some(proxy),
             model_thread,
+            exit_reason,
+            active_chats: Arc::new(AtomicUsize::new(0)),
             load_model_options,
             failed: !test_server,
         };
@@ -311,28 +588,46 @@ impl BackendModel for LLamaEdgeApiServer {
         async_rt: &tokio::runtime::Runtime,
         mut data: moly_protocol::open_ai::ChatRequestData,
         tx: std::sync::mpsc::Sender<anyhow::Result<ChatResponse>>,
+        mut cancel: ChatCancel,
     ) -> bool {
         let is_stream = data.stream.unwrap_or(false);
         let url = format!(
             "http://localhost:{}/v1/chat/completions",
-            self.listen_addr.port()
+            self.server_addr.port()
         );
-        let mut cancel = self.running_controller.subscribe();
 
         data.model = "moly-chat".to_string();
+        // The answer is checked once complete, the server only constrains the sampling.
+        let response_format = data
+            .response_format
+            .clone()
+            .filter(|format| *format != ResponseFormat::Text);
+        let active_chat = ActiveChat::new(&self.active_chats);
 
         async_rt.spawn(async move {
+            let _active_chat = active_chat;
+            if let Err(e) = inline_local_images(&mut data) {
+                let _ = tx.send(Err(e.into()));
+                let _ = tx.send(Ok(ChatResponse::ChatResponseChunk(stop_chunk(
+                    StopReason::Stop,
+                ))));
+                return;
+            }
+
             let request_body = serde_json::to_string(&data).unwrap();
             let request = reqwest::ClientBuilder::new()
                 .no_proxy()
                 .build()
                 .unwrap()
-                .post(url)
+                .post(&url)
                 .body(request_body);
 
             let resp = tokio::select! {
-                res = request.send() => Some(res.map_err(|e| anyhow!(e))),
-                _ = cancel.recv() => None,
+                res = request.send() => Some(
+                    res.and_then(|resp| resp.error_for_status())
+                        .map_err(|e| network_error(&url, e)),
+                ),
+                _ = cancel.cancelled() => None,
             };
 
             let Some(resp) = resp else {
@@ -346,24 +641,48 @@ impl BackendModel for LLamaEdgeApiServer {
                 Ok(resp) => {
                     if is_stream {
                         let mut stream = resp.bytes_stream();
+                        let mut decoder = SseDecoder::new();
+                        let mut answer = String::new();
 
-                        while let Some(chunk) = tokio::select! {
+                        'stream: while let Some(chunk) = tokio::select! {
                             chunk = stream.next() => chunk,
-                            _ = cancel.recv() => None,
+                            _ = cancel.cancelled() => None,
                         } {
-                            match chunk {
-                                Ok(chunk) => {
-                                    if chunk.starts_with(b"data: [DONE]") {
-                                        break;
-                                    }
-                                    let resp: Result<ChatResponseChunkData, anyhow::Error> =
-                                        serde_json::from_slice(&chunk[5..]).map_err(|e| anyhow!(e));
-                                    let _ = tx.send(resp.map(ChatResponse::ChatResponseChunk));
-                                }
+                            let chunk = match chunk {
+                                Ok(chunk) => chunk,
                                 Err(e) => {
-                                    let _ = tx.send(Err(anyhow!(e)));
+                                    let _ = tx.send(Err(network_error(&url, e).into()));
                                     return;
                                 }
+                            };
+
+                            for event in decoder.push(&chunk) {
+                                if event.data == "[DONE]" {
+                                    break 'stream;
+                                }
+                                let resp = match event.event.as_str() {
+                                    "message" => {
+                                        serde_json::from_str::<ChatResponseChunkData>(&event.data)
+                                            .map(ChatResponse::ChatResponseChunk)
+                                            .map_err(|e| MolyError::InvalidResponse(e.to_string()))
+                                    }
+                                    "error" => Err(MolyError::InvalidResponse(event.data)),
+                                    _ => continue,
+                                };
+                                if let Ok(ChatResponse::ChatResponseChunk(chunk)) = &resp {
+                                    if response_format.is_some() {
+                                        answer.extend(
+                                            chunk.choices.iter().map(|c| c.delta.content.as_str()),
+                                        );
+                                    }
+                                }
+                                let _ = tx.send(resp.map_err(Into::into));
+                            }
+                        }
+
+                        if let Some(format) = response_format.filter(|_| !cancel.is_cancelled()) {
+                            if let Err(e) = structured_output::check_output(&format, &answer) {
+                                let _ = tx.send(Err(e.into()));
                             }
                         }
 
@@ -372,8 +691,10 @@ impl BackendModel for LLamaEdgeApiServer {
                         ))));
                     } else {
                         let resp = tokio::select! {
-                            res = resp.json::<ChatResponseData>() => Some(res.map_err(|e| anyhow!(e))),
-                            _ = cancel.recv() => None,
+                            res = resp.json::<ChatResponseData>() => Some(
+                                res.map_err(|e| MolyError::InvalidResponse(e.to_string())),
+                            ),
+                            _ = cancel.cancelled() => None,
                         };
 
                         let Some(resp) = resp else {
@@ -383,11 +704,28 @@ impl BackendModel for LLamaEdgeApiServer {
                             return;
                         };
 
-                        let _ = tx.send(resp.map(ChatResponse::ChatFinalResponseData));
+                        let mismatch = match (&resp, &response_format) {
+                            (Ok(resp), Some(format)) => {
+                                let answer = resp
+                                    .choices
+                                    .iter()
+                                    .map(|c| c.message.content.as_str())
+                                    .collect::<String>();
+                                structured_output::check_output(format, &answer).err()
+                            }
+                            _ => None,
+                        };
+                        let _ = tx.send(
+                            resp.map(ChatResponse::ChatFinalResponseData)
+                                .map_err(Into::into),
+                        );
+                        if let Some(e) = mismatch {
+                            let _ = tx.send(Err(e.into()));
+                        }
                     }
                 }
                 Err(e) => {
-                    let _ = tx.send(Err(e));
+                    let _ = tx.send(Err(e.into()));
                     let _ = tx.send(Ok(ChatResponse::ChatResponseChunk(stop_chunk(
                         StopReason::Stop,
                     ))));
@@ -398,12 +736,8 @@ impl BackendModel for LLamaEdgeApiServer {
         true
     }
 
-    fn stop_chat(&self, _async_rt: &tokio::runtime::Runtime) {
-        let _ = self.running_controller.send(());
-    }
-
     fn stop(self, _async_rt: &tokio::runtime::Runtime) {
-        let url = format!("http://localhost:{}/admin/exit", self.listen_addr.port());
+        let url = format!("http://localhost:{}/admin/exit", self.server_addr.port());
         let res = reqwest::blocking::ClientBuilder::new()
             .timeout(Duration::from_secs(2))
             .no_proxy()
@@ -414,4 +748,123 @@ impl BackendModel for LLamaEdgeApiServer {
 
         let _ = self.model_thread.join();
     }
+
+    fn stop_crashed(mut self, async_rt: &tokio::runtime::Runtime) -> Option<Self> {
+        // The proxy keeps the port of the model while it restarts, for the other clients.
+        let proxy = self.proxy.take();
+        let stopped = Self {
+            id: self.id.clone(),
+            wasm_module: self.wasm_module.clone(),
+            embedding: self.embedding.clone(),
+            listen_addr: self.listen_addr,
+            server_addr: self.server_addr,
+            proxy,
+            model_thread: std::thread::spawn(|| {}),
+            exit_reason: self.exit_reason.clone(),
+            active_chats: self.active_chats.clone(),
+            load_model_options: self.load_model_options.clone(),
+            // Not stopped again, and always reloaded.
+            failed: true,
+        };
+        self.stop(async_rt);
+        Some(stopped)
+    }
+
+    fn last_client_activity(&self) -> Option<std::time::Instant> {
+        self.proxy.as_ref()?.last_activity()
+    }
+
+    fn health_check(&self) -> Option<HealthCheck> {
+        // A server that failed to start was already reported.
+        if self.failed {
+            return None;
+        }
+
+        const MAX_FAILURES: u32 = 3;
+
+        let url = format!("http://localhost:{}/echo", self.server_addr.port());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .timeout(Duration::from_secs(5))
+            .no_proxy()
+            .build()
+            .unwrap();
+        let exit_reason = self.exit_reason.clone();
+        let active_chats = self.active_chats.clone();
+        let mut failures = 0;
+
+        Some(Box::new(move || {
+            if let Some(reason) = exit_reason.lock().unwrap().clone() {
+                return Err(reason);
+            }
+            // The server may be too busy generating to answer quickly.
+            if active_chats.load(Ordering::Acquire) > 0 {
+                failures = 0;
+                return Ok(());
+            }
+
+            match client.get(&url).send().and_then(|r| r.error_for_status()) {
+                Ok(_) => failures = 0,
+                Err(e) => {
+                    failures += 1;
+                    if failures >= MAX_FAILURES {
+                        return Err(format!("the model server stopped answering: {e}"));
+                    }
+                }
+            }
+            Ok(())
+        }))
+    }
+}
+
+#[test]
+fn test_sampling_and_logprobs_json() {
+    let data = ChatRequestData {
+        messages: vec![],
+        model: "moly-chat".to_string(),
+        logprobs: Some(true),
+        top_logprobs: Some(3),
+        seed: Some(42),
+        stream: Some(true),
+        top_k: Some(40),
+        repeat_penalty: Some(1.1),
+        repeat_last_n: Some(-1),
+        ..Default::default()
+    };
+    let json = serde_json::to_value(&data).unwrap();
+    assert_eq!(json["seed"], 42);
+    assert_eq!(json["logprobs"], true);
+    assert_eq!(json["top_logprobs"], 3);
+    assert_eq!(json["top_k"], 40);
+    assert_eq!(json["repeat_last_n"], -1);
+    // Unset parameters keep the defaults of the server.
+    assert!(json.get("min_p").is_none());
+    assert!(json.get("mirostat").is_none());
+
+    let chunk = r#"{"id":"1","created":0,"model":"moly-chat","system_fingerprint":"",
+        "choices":[{"index":0,"finish_reason":null,"delta":{"role":"assistant","content":"Hi"},
+        "logprobs":{"content":[{"token":"Hi","logprob":-0.25,"bytes":[72,105],
+        "top_logprobs":[{"token":"Hi","logprob":-0.25,"bytes":null},
+        {"token":"Hello","logprob":-1.5,"bytes":null}]},
+        {"token":"!","logprob":-2.0,"bytes":null,"top_logprobs":null}]}}]}"#;
+    let chunk = serde_json::from_str::<ChatResponseChunkData>(chunk).unwrap();
+    let logprobs = chunk.choices[0].logprobs.as_ref().unwrap();
+    assert_eq!(logprobs.content.len(), 2);
+    assert_eq!(logprobs.content[0].top_logprobs[1].token, "Hello");
+    assert!(logprobs.content[1].top_logprobs.is_empty());
+}
+
+#[test]
+fn test_usage_chunk_json() {
+    // The last chunk of llama.cpp counts the tokens of the turn.
+    let chunk = r#"{"id":"1","created":0,"model":"moly-chat","system_fingerprint":"",
+        "choices":[{"index":0,"finish_reason":"stop","delta":{"role":"assistant","content":""}}],
+        "usage":{"completion_tokens":12,"prompt_tokens":30,"total_tokens":42}}"#;
+    let chunk = serde_json::from_str::<ChatResponseChunkData>(chunk).unwrap();
+    assert_eq!(
+        chunk.usage.as_ref().map(|usage| usage.total_tokens),
+        Some(42)
+    );
+
+    let json = serde_json::to_value(stop_chunk(StopReason::Stop)).unwrap();
+    assert!(json.get("usage").is_none());
 }
@@ -0,0 +1,293 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::Sender;
+
+use moly_protocol::open_ai::{
+    ChatRequestData, ChatRequestID, ChatResponse, ChatResponseChunkData, ChunkChoiceData,
+    MessageData, Role, StopReason,
+};
+use moly_protocol::protocol::MolyError;
+
+/// Cancels a single chat request, see [`super::BackendModel::chat`].
+#[derive(Clone, Debug)]
+pub struct ChatCancel(tokio::sync::watch::Receiver<bool>);
+
+impl ChatCancel {
+    fn new() -> (tokio::sync::watch::Sender<bool>, Self) {
+        let (tx, rx) = tokio::sync::watch::channel(false);
+        (tx, Self(rx))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once the request is cancelled.
+    pub async fn cancelled(&mut self) {
+        if self.0.wait_for(|cancelled| *cancelled).await.is_err() {
+            // The request finished, it can't be cancelled anymore.
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+struct WaitingChat {
+    id: ChatRequestID,
+    data: ChatRequestData,
+    tx: Sender<anyhow::Result<ChatResponse>>,
+    position: Option<usize>,
+}
+
+struct RunningChat {
+    user: Option<String>,
+    tx: Sender<anyhow::Result<ChatResponse>>,
+    cancel_tx: tokio::sync::watch::Sender<bool>,
+}
+
+/// A chat request that can start now.
+pub struct StartedChat {
+    pub id: ChatRequestID,
+    pub data: ChatRequestData,
+    pub tx: Sender<anyhow::Result<ChatResponse>>,
+    pub cancel: ChatCancel,
+}
+
+/// Runs chat requests up to a limit and queues the other ones.
+///
+/// Waiting requests of different users take turns: the next one to start belongs to
+/// the user with the fewest running requests, then to the one served the longest ago.
+pub struct ChatQueue {
+    max_running: usize,
+    next_id: ChatRequestID,
+    waiting: VecDeque<WaitingChat>,
+    running: HashMap<ChatRequestID, RunningChat>,
+    // The turn each user was last served in.
+    last_served: HashMap<Option<String>, u64>,
+    turn: u64,
+}
+
+impl ChatQueue {
+    pub fn new(max_running: usize) -> Self {
+        Self {
+            max_running: max_running.max(1),
+            next_id: 1,
+            waiting: VecDeque::new(),
+            running: HashMap::new(),
+            last_served: HashMap::new(),
+            turn: 0,
+        }
+    }
+
+    pub fn set_max_running(&mut self, max_running: usize) {
+        self.max_running = max_running.max(1);
+    }
+
+    /// Queues a request and sends its id to `tx`.
+    pub fn push(
+        &mut self,
+        data: ChatRequestData,
+        tx: Sender<anyhow::Result<ChatResponse>>,
+    ) -> ChatRequestID {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let _ = tx.send(Ok(ChatResponse::Accepted(id)));
+        self.waiting.push_back(WaitingChat {
+            id,
+            data,
+            tx,
+            position: None,
+        });
+        id
+    }
+
+    /// Takes the requests that can run now, and tells the other ones their position.
+    pub fn start_next(&mut self) -> Vec<StartedChat> {
+        let mut started = vec![];
+
+        let order = self.start_order();
+        let free = self.max_running.saturating_sub(self.running.len());
+        let mut to_start = order.iter().take(free).copied().collect::<Vec<_>>();
+        // Removed from the back first so the other indices stay valid.
+        to_start.sort_unstable_by(|a, b| b.cmp(a));
+
+        for index in to_start {
+            let chat = self.waiting.remove(index).unwrap();
+            let (cancel_tx, cancel) = ChatCancel::new();
+
+            self.turn += 1;
+            self.last_served.insert(chat.data.user.clone(), self.turn);
+            self.running.insert(
+                chat.id,
+                RunningChat {
+                    user: chat.data.user.clone(),
+                    tx: chat.tx.clone(),
+                    cancel_tx,
+                },
+            );
+            started.push(StartedChat {
+                id: chat.id,
+                data: chat.data,
+                tx: chat.tx,
+                cancel,
+            });
+        }
+        started.sort_by_key(|chat| chat.id);
+
+        for (position, index) in self.start_order().into_iter().enumerate() {
+            let chat = &mut self.waiting[index];
+            if chat.position != Some(position) {
+                chat.position = Some(position);
+                let _ = chat.tx.send(Ok(ChatResponse::Queued(position)));
+            }
+        }
+
+        started
+    }
+
+    /// Indices of the waiting requests, in the order they will start if nothing else
+    /// is queued meanwhile.
+    fn start_order(&self) -> Vec<usize> {
+        let mut running = HashMap::<&Option<String>, usize>::new();
+        for chat in self.running.values() {
+            *running.entry(&chat.user).or_default() += 1;
+        }
+        let mut last_served = self
+            .last_served
+            .iter()
+            .map(|(user, turn)| (user, *turn))
+            .collect::<HashMap<_, _>>();
+        let mut turn = self.turn;
+
+        let mut pending = (0..self.waiting.len()).collect::<Vec<_>>();
+        let mut order = Vec::with_capacity(pending.len());
+
+        while !pending.is_empty() {
+            let (next, &index) = pending
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, &index)| {
+                    let user = &self.waiting[index].data.user;
+                    (
+                        running.get(user).copied().unwrap_or_default(),
+                        last_served.get(user).copied().unwrap_or_default(),
+                        index,
+                    )
+                })
+                .unwrap();
+            pending.remove(next);
+
+            let user = &self.waiting[index].data.user;
+            turn += 1;
+            last_served.insert(user, turn);
+            *running.entry(user).or_default() += 1;
+            order.push(index);
+        }
+
+        order
+    }
+
+    /// Cancels a request. A queued one ends right away, a running one once the model stops.
+    pub fn stop(&mut self, id: ChatRequestID) {
+        if let Some(chat) = self.running.get(&id) {
+            let _ = chat.cancel_tx.send(true);
+        } else if let Some(index) = self.waiting.iter().position(|chat| chat.id == id) {
+            let chat = self.waiting.remove(index).unwrap();
+            let _ = chat
+                .tx
+                .send(Ok(ChatResponse::ChatResponseChunk(stop_chunk())));
+        }
+    }
+
+    pub fn finish(&mut self, id: ChatRequestID) {
+        self.running.remove(&id);
+    }
+
+    /// Whether no request is running or waiting.
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty() && self.waiting.is_empty()
+    }
+
+    /// Cancels the running requests and fails the queued ones, when the model goes away.
+    pub fn clear(&mut self) {
+        for chat in self.running.values() {
+            let _ = chat.cancel_tx.send(true);
+        }
+        for chat in self.waiting.drain(..) {
+            let _ = chat.tx.send(Err(MolyError::ModelNotLoaded.into()));
+        }
+    }
+
+    /// Fails every request with `error`. The running ones are cancelled too.
+    pub fn fail_all(&mut self, error: MolyError) {
+        for chat in self.running.values() {
+            let _ = chat.tx.send(Err(error.clone().into()));
+            let _ = chat.cancel_tx.send(true);
+        }
+        for chat in self.waiting.drain(..) {
+            let _ = chat.tx.send(Err(error.clone().into()));
+        }
+    }
+}
+
+fn stop_chunk() -> ChatResponseChunkData {
+    ChatResponseChunkData {
+        id: String::new(),
+        choices: vec![ChunkChoiceData {
+            finish_reason: Some(StopReason::Stop),
+            index: 0,
+            delta: MessageData {
+                content: String::new(),
+                role: Role::Assistant,
+            },
+            logprobs: None,
+        }],
+        created: 0,
+        model: String::new(),
+        system_fingerprint: String::new(),
+        usage: None,
+        object: "chat.completion.chunk".to_string(),
+    }
+}
+
+#[test]
+fn test_chat_queue() {
+    fn request(user: &str) -> ChatRequestData {
+        ChatRequestData {
+            messages: vec![],
+            model: String::new(),
+            user: Some(user.to_string()),
+            ..Default::default()
+        }
+    }
+
+    let mut queue = ChatQueue::new(1);
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    let a1 = queue.push(request("a"), tx.clone());
+    let a2 = queue.push(request("a"), tx.clone());
+    let a3 = queue.push(request("a"), tx.clone());
+    let b1 = queue.push(request("b"), tx.clone());
+
+    let started = queue.start_next();
+    assert_eq!(started.len(), 1);
+    assert_eq!(started[0].id, a1);
+
+    // b1 was queued last but b has not been served yet.
+    queue.finish(a1);
+    assert_eq!(queue.start_next()[0].id, b1);
+    queue.finish(b1);
+    assert_eq!(queue.start_next()[0].id, a2);
+
+    let running = queue.start_next();
+    assert!(running.is_empty());
+    queue.stop(a2);
+    queue.stop(a3);
+    assert!(queue.waiting.is_empty());
+    assert_eq!(queue.running.len(), 1);
+
+    let responses = rx.try_iter().collect::<Vec<_>>();
+    assert!(matches!(responses[0], Ok(ChatResponse::Accepted(id)) if id == a1));
+    assert!(responses
+        .iter()
+        .any(|response| matches!(response, Ok(ChatResponse::Queued(2)))));
+}
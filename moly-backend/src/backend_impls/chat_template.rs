@@ -0,0 +1,227 @@
+//! The chat templates of LlamaEdge named in the model cards, to count the tokens of a
+//! conversation as the runtime sends it to the model. Only the markers around the
+//! messages matter for that, the templates that aren't known here are counted as ChatML
+//! whose markers are about as long as the other ones.
+
+use moly_protocol::open_ai::{Message, Role};
+
+struct Turn {
+    prefix: &'static str,
+    suffix: &'static str,
+}
+
+struct Template {
+    system: Turn,
+    /// The system prompt goes at the start of the first message of the user, for the
+    /// templates without a turn for it.
+    fold_system: bool,
+    user: Turn,
+    assistant: Turn,
+    /// What the answer of the model starts after.
+    generation: &'static str,
+}
+
+// The token the models start with is added by the tokenizer, not by the templates.
+
+const CHATML: Template = Template {
+    system: Turn {
+        prefix: "<|im_start|>system\n",
+        suffix: "<|im_end|>\n",
+    },
+    fold_system: false,
+    user: Turn {
+        prefix: "<|im_start|>user\n",
+        suffix: "<|im_end|>\n",
+    },
+    assistant: Turn {
+        prefix: "<|im_start|>assistant\n",
+        suffix: "<|im_end|>\n",
+    },
+    generation: "<|im_start|>assistant\n",
+};
+
+const LLAMA_2: Template = Template {
+    system: Turn {
+        prefix: "<<SYS>>\n",
+        suffix: " <</SYS>>\n\n",
+    },
+    fold_system: true,
+    user: Turn {
+        prefix: "[INST] ",
+        suffix: " [/INST]",
+    },
+    assistant: Turn {
+        prefix: " ",
+        suffix: " </s><s>",
+    },
+    generation: "",
+};
+
+const LLAMA_3: Template = Template {
+    system: Turn {
+        prefix: "<|start_header_id|>system<|end_header_id|>\n\n",
+        suffix: "<|eot_id|>",
+    },
+    fold_system: false,
+    user: Turn {
+        prefix: "<|start_header_id|>user<|end_header_id|>\n\n",
+        suffix: "<|eot_id|>",
+    },
+    assistant: Turn {
+        prefix: "<|start_header_id|>assistant<|end_header_id|>\n\n",
+        suffix: "<|eot_id|>",
+    },
+    generation: "<|start_header_id|>assistant<|end_header_id|>\n\n",
+};
+
+const MISTRAL: Template = Template {
+    system: Turn {
+        prefix: "",
+        suffix: "\n\n",
+    },
+    fold_system: true,
+    user: Turn {
+        prefix: "[INST] ",
+        suffix: " [/INST]",
+    },
+    assistant: Turn {
+        prefix: "",
+        suffix: "</s>",
+    },
+    generation: "",
+};
+
+const PHI_3: Template = Template {
+    system: Turn {
+        prefix: "<|system|>\n",
+        suffix: "<|end|>\n",
+    },
+    fold_system: false,
+    user: Turn {
+        prefix: "<|user|>\n",
+        suffix: "<|end|>\n",
+    },
+    assistant: Turn {
+        prefix: "<|assistant|>\n",
+        suffix: "<|end|>\n",
+    },
+    generation: "<|assistant|>\n",
+};
+
+const GEMMA: Template = Template {
+    system: Turn {
+        prefix: "",
+        suffix: "\n\n",
+    },
+    fold_system: true,
+    user: Turn {
+        prefix: "<start_of_turn>user\n",
+        suffix: "<end_of_turn>\n",
+    },
+    assistant: Turn {
+        prefix: "<start_of_turn>model\n",
+        suffix: "<end_of_turn>\n",
+    },
+    generation: "<start_of_turn>model\n",
+};
+
+const ZEPHYR: Template = Template {
+    system: Turn {
+        prefix: "<|system|>\n",
+        suffix: "</s>\n",
+    },
+    fold_system: false,
+    user: Turn {
+        prefix: "<|user|>\n",
+        suffix: "</s>\n",
+    },
+    assistant: Turn {
+        prefix: "<|assistant|>\n",
+        suffix: "</s>\n",
+    },
+    generation: "<|assistant|>\n",
+};
+
+/// The template of a `--prompt-template` of LlamaEdge, which may be followed by the one
+/// of the embeddings.
+fn template(name: &str) -> &'static Template {
+    match name.split(',').next().unwrap_or_default().trim() {
+        "llama-2-chat" => &LLAMA_2,
+        "llama-3-chat" | "llama-3-tool" => &LLAMA_3,
+        "mistral-instruct" | "mistral-tool" => &MISTRAL,
+        "phi-3-chat" | "phi-3-instruct" => &PHI_3,
+        "gemma-instruct" => &GEMMA,
+        "zephyr" => &ZEPHYR,
+        _ => &CHATML,
+    }
+}
+
+/// The prompt the model is given for `messages`, ending where its answer starts.
+pub fn render(template_name: &str, messages: &[Message]) -> String {
+    let template = template(template_name);
+    let mut prompt = String::new();
+    let mut system = None;
+    for message in messages {
+        let mut content = message.content.text();
+        let turn = match message.role {
+            Role::System if template.fold_system => {
+                system = Some(content);
+                continue;
+            }
+            Role::System => &template.system,
+            Role::User => {
+                if let Some(system) = system.take() {
+                    let system_turn = &template.system;
+                    content = format!(
+                        "{}{system}{}{content}",
+                        system_turn.prefix, system_turn.suffix
+                    );
+                }
+                &template.user
+            }
+            Role::Assistant => &template.assistant,
+        };
+        prompt.push_str(turn.prefix);
+        prompt.push_str(&content);
+        prompt.push_str(turn.suffix);
+    }
+    prompt.push_str(template.generation);
+    prompt
+}
+
+#[test]
+fn test_render() {
+    let message = |role, content: &str| Message {
+        content: content.into(),
+        role,
+        name: None,
+    };
+    let messages = [
+        message(Role::System, "Be brief."),
+        message(Role::User, "Hi"),
+        message(Role::Assistant, "Hello!"),
+        message(Role::User, "Bye"),
+    ];
+
+    assert_eq!(
+        render("chatml", &messages),
+        "<|im_start|>system\nBe brief.<|im_end|>\n\
+        <|im_start|>user\nHi<|im_end|>\n\
+        <|im_start|>assistant\nHello!<|im_end|>\n\
+        <|im_start|>user\nBye<|im_end|>\n\
+        <|im_start|>assistant\n"
+    );
+    assert_eq!(
+        render("llama-2-chat,embedding", &messages),
+        "[INST] <<SYS>>\nBe brief. <</SYS>>\n\nHi [/INST] Hello! </s><s>[INST] Bye [/INST]"
+    );
+    assert_eq!(
+        render("gemma-instruct", &messages[..2]),
+        "<start_of_turn>user\nBe brief.\n\nHi<end_of_turn>\n<start_of_turn>model\n"
+    );
+    // Unknown templates are counted as ChatML.
+    assert_eq!(
+        render("vicuna-1.1-chat", &messages),
+        render("chatml", &messages)
+    );
+}
@@ -25,11 +25,7 @@ use std::{
     collections::HashMap,
     io::Read,
     path::PathBuf,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        mpsc::{Receiver, Sender},
-        Arc,
-    },
+    sync::mpsc::{Receiver, Sender},
     thread::JoinHandle,
 };
 
@@ -38,7 +34,7 @@ use moly_protocol::{
         ChatRequestData, ChatResponse, ChatResponseChunkData, ChatResponseData, ChoiceData,
         ChunkChoiceData, MessageData, Role, StopReason, UsageData,
     },
-    protocol::{LoadModelOptions, LoadModelResponse, LoadedModelInfo},
+    protocol::{BackendFeatures, LoadModelOptions, LoadModelResponse, LoadStage, LoadedModelInfo},
 };
 use wasmedge_sdk::{
     error::{CoreError, CoreExecutionError},
@@ -48,14 +44,22 @@ use wasmedge_sdk::{
 
 use crate::store::download_files::DownloadedFile;
 
+use super::{llama_cpp_args, load_error, stderr, ChatCancel};
+
+type ChatRequest = (
+    ChatRequestData,
+    Sender<anyhow::Result<ChatResponse>>,
+    ChatCancel,
+);
+
 #[derive(Debug)]
 pub struct ChatBotUi {
     pub current_req: std::io::Cursor<Vec<u8>>,
-    pub request_rx: Receiver<(ChatRequestData, Sender<anyhow::Result<ChatResponse>>)>,
+    pub request_rx: Receiver<ChatRequest>,
     request_id: uuid::Uuid,
     chat_completion_message: Option<Vec<u8>>,
     pub token_tx: Option<Sender<anyhow::Result<ChatResponse>>>,
-    running_controller: Arc<AtomicBool>,
+    cancel: Option<ChatCancel>,
     pub load_model_state: Option<(
         DownloadedFile,
         LoadModelOptions,
@@ -65,8 +69,7 @@ pub struct ChatBotUi {
 
 impl ChatBotUi {
     pub fn new(
-        request_rx: Receiver<(ChatRequestData, Sender<anyhow::Result<ChatResponse>>)>,
-        running_controller: Arc<AtomicBool>,
+        request_rx: Receiver<ChatRequest>,
         file: DownloadedFile,
         load_model: LoadModelOptions,
         tx: Sender<anyhow::Result<LoadModelResponse>>,
@@ -75,7 +78,7 @@ impl ChatBotUi {
             request_rx,
             request_id: uuid::Uuid::new_v4(),
             token_tx: None,
-            running_controller,
+            cancel: None,
             current_req: std::io::Cursor::new(vec![]),
             load_model_state: Some((file, load_model, tx)),
             chat_completion_message: None,
@@ -83,7 +86,7 @@ impl ChatBotUi {
     }
 
     fn init_request(&mut self) -> Result<(), ()> {
-        if let Ok((req, tx)) = self.request_rx.recv() {
+        if let Ok((req, tx, cancel)) = self.request_rx.recv() {
             // Init current_req
             if !req.stream.unwrap_or_default() {
                 self.chat_completion_message = Some(Vec::with_capacity(
@@ -94,7 +97,7 @@ impl ChatBotUi {
             self.current_req.set_position(0);
             self.request_id = uuid::Uuid::new_v4();
             self.token_tx = Some(tx);
-            self.running_controller.store(true, Ordering::Release);
+            self.cancel = Some(cancel);
             Ok(())
         } else {
             Err(())
@@ -153,6 +156,7 @@ impl ChatBotUi {
                 created: 0,
                 model: String::new(),
                 system_fingerprint: String::new(),
+                usage: None,
                 object: "chat.completion.chunk".to_string(),
             })));
         };
@@ -178,6 +182,7 @@ impl ChatBotUi {
             created: 0,
             model: String::new(),
             system_fingerprint: String::new(),
+            usage: None,
             object: "chat.completion.chunk".to_string(),
         })));
         true
@@ -196,7 +201,10 @@ impl ChatBotUi {
             }
             (Ok(token), None, Some(tx)) => Self::send_streamed_output(tx, id, token),
             (Err(token_error), chat_completion_message, Some(tx)) => {
-                Self::send_completion_output(tx, id, token_error.into(), chat_completion_message)
+                Self::send_completion_output(tx, id, token_error.into(), chat_completion_message);
+                // Closes the response stream, the request is done.
+                self.token_tx = None;
+                true
             }
             (_, _, None) => false,
         }
@@ -222,7 +230,7 @@ fn get_input(
             .ok_or(CoreError::Execution(CoreExecutionError::MemoryOutOfBounds))?;
 
         if data.current_req.get_ref().is_empty() {
-            if let Some((file, _, tx)) = data.load_model_state.take() {
+            if let Some((file, load_model, tx)) = data.load_model_state.take() {
                 let file_id = file.id.as_ref().clone();
                 let model_id = file.model_id;
                 let _ = tx.send(Ok(LoadModelResponse::Completed(LoadedModelInfo {
@@ -230,6 +238,7 @@ fn get_input(
                     model_id,
                     information: String::new(),
                     listen_port: 0,
+                    n_ctx: load_model.n_ctx.unwrap_or(file.context_size as u32),
                 })));
             }
 
@@ -252,7 +261,11 @@ fn push_token(
     frame: &mut CallingFrame,
     args: Vec<WasmValue>,
 ) -> Result<Vec<WasmValue>, CoreError> {
-    if !data.running_controller.load(Ordering::Acquire) {
+    if data
+        .cancel
+        .as_ref()
+        .is_some_and(|cancel| cancel.is_cancelled())
+    {
         return Ok(vec![WasmValue::from_i32(-1)]);
     }
 
@@ -318,11 +331,13 @@ fn create_wasi(
     file: &DownloadedFile,
     load_model: &LoadModelOptions,
     embedding: Option<(PathBuf, u64)>,
+    adapters: &[(PathBuf, f32)],
 ) -> wasmedge_sdk::WasmEdgeResult<WasiModule> {
+    let ctx_size = load_model.n_ctx.unwrap_or(file.context_size as u32);
     let ctx_size = if let Some((_, embedding_ctx)) = embedding {
-        Some(format!("{},{}", file.context_size, embedding_ctx))
+        Some(format!("{},{}", ctx_size, embedding_ctx))
     } else {
-        Some(format!("{}", file.context_size))
+        Some(format!("{}", ctx_size))
     };
 
     let n_gpu_layers = match load_model.gpu_layers {
@@ -330,8 +345,7 @@ fn create_wasi(
         moly_protocol::protocol::GPULayers::Max => None,
     };
 
-    // Set n_batch to a fixed value of 128.
-    let batch_size = Some(format!("128"));
+    let batch_size = Some(format!("{}", load_model.n_batch.unwrap_or(128)));
 
     let mut prompt_template = load_model.prompt_template.clone();
     if prompt_template.is_none() && !file.prompt_template.is_empty() {
@@ -374,32 +388,32 @@ fn create_wasi(
     add_args!("-p", prompt_template);
     add_args!("-r", reverse_prompt);
 
+    let llama_cpp_args = llama_cpp_args(load_model, adapters);
+    args.extend(llama_cpp_args.iter().map(String::as_str));
+
     WasiModule::create(Some(args), None, None)
 }
 
 pub fn run_wasm_by_downloaded_file(
     wasm_module: Module,
-    request_rx: Receiver<(ChatRequestData, Sender<anyhow::Result<ChatResponse>>)>,
-    model_running_controller: Arc<AtomicBool>,
+    request_rx: Receiver<ChatRequest>,
     file: DownloadedFile,
     load_model: LoadModelOptions,
     tx: Sender<anyhow::Result<LoadModelResponse>>,
     embedding: Option<(PathBuf, u64)>,
+    adapters: Vec<(PathBuf, f32)>,
 ) {
     use wasmedge_sdk::vm::SyncInst;
     use wasmedge_sdk::AsInstance;
 
     let mut instances: HashMap<String, &mut (dyn SyncInst)> = HashMap::new();
 
-    let mut wasi = create_wasi(&file, &load_model, embedding).unwrap();
-    let mut chatui = module(ChatBotUi::new(
-        request_rx,
-        model_running_controller,
-        file,
-        load_model,
-        tx,
-    ))
-    .unwrap();
+    let file_id = file.id.to_string();
+    let failed_tx = tx.clone();
+    let stderr_mark = stderr::mark();
+
+    let mut wasi = create_wasi(&file, &load_model, embedding, &adapters).unwrap();
+    let mut chatui = module(ChatBotUi::new(request_rx, file, load_model, tx)).unwrap();
 
     instances.insert(wasi.name().to_string(), wasi.as_mut());
     let mut wasi_nn = wasmedge_sdk::plugin::PluginManager::load_plugin_wasi_nn().unwrap();
@@ -410,22 +424,37 @@ pub fn run_wasm_by_downloaded_file(
     let mut vm = Vm::new(store);
     vm.register_module(None, wasm_module.clone()).unwrap();
 
-    let _ = vm.run_func(None, "_start", []);
+    if let Err(e) = vm.run_func(None, "_start", []) {
+        let _ = failed_tx.send(Err(load_error(file_id, e.to_string(), stderr_mark).into()));
+    }
 
     log::debug!("wasm exit");
 }
 
 pub struct ChatBotModel {
     id: String,
+    options: LoadModelOptions,
     wasm_module: Module,
-    pub model_tx: Sender<(ChatRequestData, Sender<anyhow::Result<ChatResponse>>)>,
-    pub model_running_controller: Arc<AtomicBool>,
+    pub model_tx: Sender<ChatRequest>,
     pub model_thread: JoinHandle<()>,
 }
 
 static WASM: &[u8] = include_bytes!("../../wasm/chat_ui.wasm");
 
 impl super::BackendModel for ChatBotModel {
+    const NAME: &'static str = "ChatBotModel";
+    const FEATURES: BackendFeatures = BackendFeatures {
+        streaming: true,
+        embeddings: false,
+        tool_calls: false,
+        logprobs: false,
+        vision: false,
+        lora_adapters: true,
+        constrained_output: false,
+    };
+    // A single instance of the wasm app answers the requests one after the other.
+    const MAX_CONCURRENT_CHATS: usize = 1;
+
     fn new_or_reload(
         async_rt: &tokio::runtime::Runtime,
         old_model: Option<Self>,
@@ -433,11 +462,12 @@ impl super::BackendModel for ChatBotModel {
         options: LoadModelOptions,
         tx: Sender<anyhow::Result<LoadModelResponse>>,
         embedding: Option<(PathBuf, u64)>,
+        adapters: Vec<(PathBuf, f32)>,
     ) -> Self {
         let mut need_reload = true;
 
         let wasm_module = if let Some(old_model) = &old_model {
-            if old_model.id == file.id.as_str() {
+            if old_model.id == file.id.as_str() && old_model.options == options {
                 need_reload = false;
             }
             old_model.wasm_module.clone()
@@ -451,35 +481,42 @@ impl super::BackendModel for ChatBotModel {
                 model_id: file.model_id,
                 information: "".to_string(),
                 listen_port: 0,
+                n_ctx: options.n_ctx.unwrap_or(file.context_size as u32),
             })));
             return old_model.unwrap();
         }
 
         let (model_tx, request_rx) = std::sync::mpsc::channel();
-        let model_running_controller = Arc::new(AtomicBool::new(false));
-        let model_running_controller_ = model_running_controller.clone();
 
         let wasm_module_ = wasm_module.clone();
 
         let file_id = file.id.to_string();
+        let options_ = options.clone();
+
+        // The model is ready once the app asks for its first request.
+        let _ = tx.send(Ok(LoadModelResponse::Progress(
+            file_id.clone(),
+            LoadStage::WarmingUp,
+            0.3,
+        )));
 
         let model_thread = std::thread::spawn(move || {
             run_wasm_by_downloaded_file(
                 wasm_module_,
                 request_rx,
-                model_running_controller_,
                 file,
-                options,
+                options_,
                 tx,
                 embedding,
+                adapters,
             )
         });
 
         let new_model = Self {
             id: file_id,
+            options,
             model_tx,
             model_thread,
-            model_running_controller,
             wasm_module,
         };
 
@@ -495,13 +532,9 @@ impl super::BackendModel for ChatBotModel {
         _async_rt: &tokio::runtime::Runtime,
         data: ChatRequestData,
         tx: Sender<anyhow::Result<ChatResponse>>,
+        cancel: ChatCancel,
     ) -> bool {
-        self.model_tx.send((data, tx)).is_ok()
-    }
-
-    fn stop_chat(&self, _async_rt: &tokio::runtime::Runtime) {
-        self.model_running_controller
-            .store(false, Ordering::Release);
+        self.model_tx.send((data, tx, cancel)).is_ok()
     }
 
     fn stop(self, _async_rt: &tokio::runtime::Runtime) {
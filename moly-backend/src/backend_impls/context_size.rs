@@ -0,0 +1,185 @@
+//! Choice of the context size of a model when the user doesn't set one: as long as the
+//! model was trained with, while its KV cache fits in the memory left by the weights.
+
+use std::{collections::HashMap, path::Path};
+
+use moly_protocol::protocol::{KvCacheType, LoadModelOptions};
+
+use crate::store::download_files::DownloadedFile;
+
+use super::{gguf, resources};
+
+/// The context size used when the model or the memory can't be inspected.
+const FALLBACK_CONTEXT: u32 = 8 * 1024;
+const MIN_CONTEXT: u32 = 512;
+/// Kept free for the compute buffers of the runtime and the rest of the system.
+const RESERVED_MEMORY: u64 = 1024 * 1024 * 1024;
+
+pub fn choose(file: &DownloadedFile, path: &Path, options: &LoadModelOptions) -> u32 {
+    let metadata = read_metadata(path)
+        .map_err(|e| log::warn!("can't read the metadata of {}: {e}", path.display()))
+        .unwrap_or_default();
+
+    let trained = metadata
+        .context_length
+        .or(u32::try_from(file.context_size).ok().filter(|&n| n > 0))
+        .unwrap_or(FALLBACK_CONTEXT);
+    let kv_bytes_per_token = metadata.kv_bytes_per_token(options.kv_cache_type);
+
+    let n_ctx = fit_context(
+        trained,
+        kv_bytes_per_token,
+        resources::available_memory(),
+        file.file_size,
+    );
+    log::info!(
+        "chose a context of {n_ctx} tokens for {} (trained with {trained}, {} bytes of KV cache per token)",
+        file.id,
+        kv_bytes_per_token.map_or("unknown".to_string(), |n| n.to_string())
+    );
+    n_ctx
+}
+
+/// The largest context up to `trained` whose KV cache fits in the memory left after the
+/// weights, rounded down to a multiple of 1024 tokens.
+fn fit_context(
+    trained: u32,
+    kv_bytes_per_token: Option<u64>,
+    available_memory: Option<u64>,
+    weights_size: u64,
+) -> u32 {
+    let (Some(kv_bytes_per_token), Some(available_memory)) = (kv_bytes_per_token, available_memory)
+    else {
+        return trained.min(FALLBACK_CONTEXT);
+    };
+
+    let free = available_memory.saturating_sub(weights_size + RESERVED_MEMORY);
+    let fitting = (free / kv_bytes_per_token.max(1)).min(trained as u64) as u32;
+    let rounded = if fitting >= 1024 {
+        fitting / 1024 * 1024
+    } else {
+        fitting
+    };
+    rounded.max(MIN_CONTEXT).min(trained)
+}
+
+/// What the context size depends on, from the GGUF metadata of a model.
+#[derive(Debug, Default, PartialEq)]
+struct ModelMetadata {
+    context_length: Option<u32>,
+    block_count: Option<u64>,
+    embedding_length: Option<u64>,
+    head_count: Option<u64>,
+    head_count_kv: Option<u64>,
+    key_length: Option<u64>,
+    value_length: Option<u64>,
+}
+
+impl ModelMetadata {
+    fn kv_bytes_per_token(&self, cache_type: Option<KvCacheType>) -> Option<u64> {
+        let head_count = self.head_count.filter(|&n| n > 0)?;
+        let head_count_kv = self.head_count_kv.unwrap_or(head_count);
+        let head_size = self.embedding_length.map(|n| n / head_count);
+        let key_length = self.key_length.or(head_size)?;
+        let value_length = self.value_length.or(head_size)?;
+
+        let elements = self.block_count? * head_count_kv * (key_length + value_length);
+        // Quantized types are stored in blocks of 32 values plus a f16 scale.
+        Some(match cache_type.unwrap_or(KvCacheType::F16) {
+            KvCacheType::F16 => elements * 2,
+            KvCacheType::Q8_0 => elements * 34 / 32,
+            KvCacheType::Q4_0 => elements * 18 / 32,
+        })
+    }
+}
+
+fn read_metadata(path: &Path) -> std::io::Result<ModelMetadata> {
+    // The vocabulary is most of the metadata, and doesn't matter here.
+    let values = gguf::read_metadata(path, |key| !key.starts_with("tokenizer."))?;
+    model_metadata(&values)
+}
+
+fn model_metadata(values: &HashMap<String, gguf::Value>) -> std::io::Result<ModelMetadata> {
+    let Some(arch) = values
+        .get("general.architecture")
+        .and_then(gguf::Value::as_str)
+    else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "no architecture in the metadata",
+        ));
+    };
+    let int = |key: &str| {
+        values
+            .get(&format!("{arch}.{key}"))
+            .and_then(gguf::Value::as_u64)
+    };
+
+    Ok(ModelMetadata {
+        context_length: int("context_length").and_then(|n| u32::try_from(n).ok()),
+        block_count: int("block_count"),
+        embedding_length: int("embedding_length"),
+        head_count: int("attention.head_count"),
+        head_count_kv: int("attention.head_count_kv"),
+        key_length: int("attention.key_length"),
+        value_length: int("attention.value_length"),
+    })
+}
+
+#[test]
+fn test_model_metadata() {
+    use gguf::Value;
+
+    let values = HashMap::from([
+        (
+            "general.architecture".to_string(),
+            Value::String("llama".to_string()),
+        ),
+        ("llama.context_length".to_string(), Value::Int(131072)),
+        ("llama.block_count".to_string(), Value::Int(32)),
+        ("llama.embedding_length".to_string(), Value::Int(4096)),
+        ("llama.attention.head_count".to_string(), Value::Int(32)),
+        ("llama.attention.head_count_kv".to_string(), Value::Int(8)),
+    ]);
+
+    let metadata = model_metadata(&values).unwrap();
+    assert_eq!(metadata.context_length, Some(131072));
+    assert_eq!(metadata.key_length, None);
+    // 32 layers * 8 heads * (128 + 128) values of 2 bytes.
+    assert_eq!(metadata.kv_bytes_per_token(None), Some(131072));
+    assert_eq!(
+        metadata.kv_bytes_per_token(Some(KvCacheType::Q8_0)),
+        Some(69632)
+    );
+
+    assert!(model_metadata(&HashMap::new()).is_err());
+}
+
+#[test]
+fn test_fit_context() {
+    const GB: u64 = 1024 * 1024 * 1024;
+
+    // Unknown model or memory.
+    assert_eq!(fit_context(131072, None, Some(64 * GB), 5 * GB), 8192);
+    assert_eq!(fit_context(4096, Some(131072), None, 5 * GB), 4096);
+    // Plenty of memory.
+    assert_eq!(
+        fit_context(32768, Some(131072), Some(64 * GB), 5 * GB),
+        32768
+    );
+    // 2 GB left after the weights and the reserve.
+    assert_eq!(
+        fit_context(131072, Some(131072), Some(8 * GB), 5 * GB),
+        16384
+    );
+    assert_eq!(
+        fit_context(131072, Some(131072), Some(6 * GB + GB / 2), 5 * GB),
+        4096
+    );
+    // Not even the weights fit.
+    assert_eq!(
+        fit_context(131072, Some(131072), Some(4 * GB), 5 * GB),
+        MIN_CONTEXT
+    );
+    assert_eq!(fit_context(256, Some(131072), Some(4 * GB), 5 * GB), 256);
+}
@@ -0,0 +1,141 @@
+use std::any::Any;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use moly_protocol::open_ai::{ChatRequestData, ChatResponse};
+use moly_protocol::protocol::{BackendFeatures, LoadModelOptions, LoadModelResponse};
+
+use crate::store::download_files::DownloadedFile;
+
+use super::{BackendModel, ChatCancel, HealthCheck};
+
+/// A loaded model of any engine, see [`BackendModel`].
+pub trait LoadedModel: Send {
+    fn engine(&self) -> &'static str;
+    fn chat(
+        &self,
+        async_rt: &tokio::runtime::Runtime,
+        data: ChatRequestData,
+        tx: Sender<anyhow::Result<ChatResponse>>,
+        cancel: ChatCancel,
+    ) -> bool;
+    fn stop(self: Box<Self>, async_rt: &tokio::runtime::Runtime);
+    fn stop_crashed(
+        self: Box<Self>,
+        async_rt: &tokio::runtime::Runtime,
+    ) -> Option<Box<dyn LoadedModel>>;
+    fn health_check(&self) -> Option<HealthCheck>;
+    fn last_client_activity(&self) -> Option<std::time::Instant>;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<M: BackendModel + Send + 'static> LoadedModel for M {
+    fn engine(&self) -> &'static str {
+        M::NAME
+    }
+
+    fn chat(
+        &self,
+        async_rt: &tokio::runtime::Runtime,
+        data: ChatRequestData,
+        tx: Sender<anyhow::Result<ChatResponse>>,
+        cancel: ChatCancel,
+    ) -> bool {
+        BackendModel::chat(self, async_rt, data, tx, cancel)
+    }
+
+    fn stop(self: Box<Self>, async_rt: &tokio::runtime::Runtime) {
+        BackendModel::stop(*self, async_rt)
+    }
+
+    fn stop_crashed(
+        self: Box<Self>,
+        async_rt: &tokio::runtime::Runtime,
+    ) -> Option<Box<dyn LoadedModel>> {
+        BackendModel::stop_crashed(*self, async_rt).map(|model| Box::new(model) as _)
+    }
+
+    fn health_check(&self) -> Option<HealthCheck> {
+        BackendModel::health_check(self)
+    }
+
+    fn last_client_activity(&self) -> Option<std::time::Instant> {
+        BackendModel::last_client_activity(self)
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+type LoadFn = fn(
+    &tokio::runtime::Runtime,
+    Option<Box<dyn LoadedModel>>,
+    DownloadedFile,
+    LoadModelOptions,
+    Sender<anyhow::Result<LoadModelResponse>>,
+    Option<(PathBuf, u64)>,
+    Vec<(PathBuf, f32)>,
+) -> Box<dyn LoadedModel>;
+
+fn load<M: BackendModel + Send + 'static>(
+    async_rt: &tokio::runtime::Runtime,
+    old_model: Option<Box<dyn LoadedModel>>,
+    file: DownloadedFile,
+    options: LoadModelOptions,
+    tx: Sender<anyhow::Result<LoadModelResponse>>,
+    embedding: Option<(PathBuf, u64)>,
+    adapters: Vec<(PathBuf, f32)>,
+) -> Box<dyn LoadedModel> {
+    // The caller stops a model of another engine before switching.
+    let old_model = old_model
+        .and_then(|model| model.into_any().downcast::<M>().ok())
+        .map(|model| *model);
+    Box::new(M::new_or_reload(
+        async_rt, old_model, file, options, tx, embedding, adapters,
+    ))
+}
+
+pub struct Engine {
+    pub name: &'static str,
+    pub features: BackendFeatures,
+    pub max_concurrent_chats: usize,
+    pub load: LoadFn,
+}
+
+/// The engines the models can run on, by name.
+pub struct EngineRegistry {
+    engines: Vec<Engine>,
+}
+
+impl Default for EngineRegistry {
+    fn default() -> Self {
+        let mut registry = Self { engines: vec![] };
+        registry.register::<super::api_server::LLamaEdgeApiServer>();
+        registry.register::<super::chat_ui::ChatBotModel>();
+        registry
+    }
+}
+
+impl EngineRegistry {
+    pub fn register<M: BackendModel + Send + 'static>(&mut self) {
+        self.engines.retain(|engine| engine.name != M::NAME);
+        self.engines.push(Engine {
+            name: M::NAME,
+            features: M::FEATURES,
+            max_concurrent_chats: M::MAX_CONCURRENT_CHATS,
+            load: load::<M>,
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Engine> {
+        self.engines.iter().find(|engine| engine.name == name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.engines
+            .iter()
+            .map(|engine| engine.name.to_string())
+            .collect()
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use moly_protocol::protocol::{BackendEvent, EventFilter};
+
+/// Broadcasts [`BackendEvent`]s to the senders registered with `Command::Subscribe`.
+///
+/// A subscriber is dropped the first time an event can't be delivered to it.
+#[derive(Clone, Debug, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<(EventFilter, Sender<BackendEvent>)>>>,
+    // The catalog is synced once at startup, before anyone can subscribe.
+    last_catalog_event: Arc<Mutex<Option<BackendEvent>>>,
+    // The port the local server listens on, while it runs.
+    local_server_port: Arc<Mutex<Option<u16>>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self, filter: EventFilter, tx: Sender<BackendEvent>) {
+        if filter.catalog {
+            if let Some(event) = self.last_catalog_event.lock().unwrap().clone() {
+                if tx.send(event).is_err() {
+                    return;
+                }
+            }
+        }
+        if filter.local_server {
+            if let Some(port) = *self.local_server_port.lock().unwrap() {
+                if tx.send(BackendEvent::LocalServerListening(port)).is_err() {
+                    return;
+                }
+            }
+        }
+        self.subscribers.lock().unwrap().push((filter, tx));
+    }
+
+    /// Broadcasts the changes of the port the local server listens on, `None` once it
+    /// stopped.
+    pub fn set_local_server_port(&self, port: Option<u16>) {
+        let previous = std::mem::replace(&mut *self.local_server_port.lock().unwrap(), port);
+        if previous == port {
+            return;
+        }
+        if let Some(previous) = previous {
+            self.publish(BackendEvent::LocalServerStopped(previous));
+        }
+        if let Some(port) = port {
+            self.publish(BackendEvent::LocalServerListening(port));
+        }
+    }
+
+    pub fn publish(&self, event: BackendEvent) {
+        if matches!(
+            event,
+            BackendEvent::CatalogSynced(_) | BackendEvent::CatalogSyncFailed(_)
+        ) {
+            *self.last_catalog_event.lock().unwrap() = Some(event.clone());
+        }
+
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|(filter, tx)| !filter.matches(&event) || tx.send(event.clone()).is_ok());
+    }
+}
+
+#[test]
+fn test_event_bus() {
+    use moly_protocol::protocol::DownloadState;
+
+    let events = EventBus::default();
+    events.publish(BackendEvent::CatalogSynced(vec![]));
+
+    let (downloads_tx, downloads_rx) = std::sync::mpsc::channel();
+    events.subscribe(
+        EventFilter {
+            downloads: true,
+            ..Default::default()
+        },
+        downloads_tx,
+    );
+
+    let (all_tx, all_rx) = std::sync::mpsc::channel();
+    events.subscribe(EventFilter::all(), all_tx);
+    assert!(matches!(
+        all_rx.try_recv(),
+        Ok(BackendEvent::CatalogSynced(errors)) if errors.is_empty()
+    ));
+
+    events.publish(BackendEvent::DownloadProgress("a#b".to_string(), 50.0));
+    events.publish(BackendEvent::ModelEjected("a#b".to_string()));
+
+    assert!(matches!(
+        downloads_rx.try_recv(),
+        Ok(BackendEvent::DownloadProgress(_, _))
+    ));
+    assert!(downloads_rx.try_recv().is_err());
+    assert!(matches!(
+        all_rx.try_recv(),
+        Ok(BackendEvent::DownloadProgress(_, _))
+    ));
+    assert!(matches!(
+        all_rx.try_recv(),
+        Ok(BackendEvent::ModelEjected(_))
+    ));
+
+    drop(downloads_rx);
+    events.publish(BackendEvent::DownloadStateChanged(
+        "a#b".to_string(),
+        DownloadState::Completed,
+    ));
+    assert_eq!(events.subscribers.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_local_server_events() {
+    let events = EventBus::default();
+    let (tx, rx) = std::sync::mpsc::channel();
+    events.subscribe(
+        EventFilter {
+            local_server: true,
+            ..Default::default()
+        },
+        tx,
+    );
+
+    events.set_local_server_port(Some(8080));
+    // Loading the model again on the same port changes nothing.
+    events.set_local_server_port(Some(8080));
+    events.set_local_server_port(Some(9090));
+    assert!(matches!(
+        rx.try_recv(),
+        Ok(BackendEvent::LocalServerListening(8080))
+    ));
+    assert!(matches!(
+        rx.try_recv(),
+        Ok(BackendEvent::LocalServerStopped(8080))
+    ));
+    assert!(matches!(
+        rx.try_recv(),
+        Ok(BackendEvent::LocalServerListening(9090))
+    ));
+    assert!(rx.try_recv().is_err());
+
+    // New subscribers learn about the running server.
+    let (late_tx, late_rx) = std::sync::mpsc::channel();
+    events.subscribe(EventFilter::all(), late_tx);
+    assert!(matches!(
+        late_rx.try_recv(),
+        Ok(BackendEvent::LocalServerListening(9090))
+    ));
+
+    events.set_local_server_port(None);
+    assert!(matches!(
+        rx.try_recv(),
+        Ok(BackendEvent::LocalServerStopped(9090))
+    ));
+    assert!(matches!(
+        late_rx.try_recv(),
+        Ok(BackendEvent::LocalServerStopped(9090))
+    ));
+}
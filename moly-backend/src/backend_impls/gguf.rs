@@ -0,0 +1,241 @@
+//! Reader of the key-value metadata at the start of GGUF files (version 2 or later).
+//! See https://github.com/ggerganov/ggml/blob/master/docs/gguf.md
+
+use std::{
+    collections::HashMap,
+    io::{BufReader, Read, Seek},
+    path::Path,
+};
+
+pub const MAGIC: &[u8; 4] = b"GGUF";
+
+pub const TYPE_U8: u32 = 0;
+pub const TYPE_I8: u32 = 1;
+pub const TYPE_U16: u32 = 2;
+pub const TYPE_I16: u32 = 3;
+pub const TYPE_U32: u32 = 4;
+pub const TYPE_I32: u32 = 5;
+pub const TYPE_F32: u32 = 6;
+pub const TYPE_BOOL: u32 = 7;
+pub const TYPE_STRING: u32 = 8;
+pub const TYPE_ARRAY: u32 = 9;
+pub const TYPE_U64: u32 = 10;
+pub const TYPE_I64: u32 = 11;
+pub const TYPE_F64: u32 = 12;
+
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    /// Signed values are kept with their bits, as the metadata that matters is positive.
+    Int(u64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Float(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// The metadata of the file whose key `keep` accepts, the other values are skipped.
+pub fn read_metadata(
+    path: &Path,
+    keep: impl Fn(&str) -> bool,
+) -> std::io::Result<HashMap<String, Value>> {
+    let file = std::fs::File::open(path)?;
+    parse_metadata(&mut BufReader::new(file), keep)
+}
+
+pub fn parse_metadata<R: Read + Seek>(
+    reader: &mut R,
+    keep: impl Fn(&str) -> bool,
+) -> std::io::Result<HashMap<String, Value>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a GGUF file"));
+    }
+    if read_u32(reader)? < 2 {
+        return Err(invalid("unsupported GGUF version"));
+    }
+    let _tensor_count = read_u64(reader)?;
+    let kv_count = read_u64(reader)?;
+
+    let mut values = HashMap::new();
+    for _ in 0..kv_count {
+        let key = read_string(reader)?;
+        let value_type = read_u32(reader)?;
+        if keep(&key) {
+            values.insert(key, read_value(reader, value_type)?);
+        } else {
+            skip_value(reader, value_type)?;
+        }
+    }
+    Ok(values)
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn unknown_type(value_type: u32) -> std::io::Error {
+    invalid(&format!("unknown GGUF value type {value_type}"))
+}
+
+fn read_value<R: Read + Seek>(reader: &mut R, value_type: u32) -> std::io::Result<Value> {
+    let value = match value_type {
+        TYPE_U8 => Value::Int(read_bytes::<R, 1>(reader)?[0] as u64),
+        TYPE_I8 => Value::Int(read_bytes::<R, 1>(reader)?[0] as i8 as u64),
+        TYPE_U16 => Value::Int(u16::from_le_bytes(read_bytes(reader)?) as u64),
+        TYPE_I16 => Value::Int(i16::from_le_bytes(read_bytes(reader)?) as u64),
+        TYPE_U32 => Value::Int(read_u32(reader)? as u64),
+        TYPE_I32 => Value::Int(read_u32(reader)? as i32 as u64),
+        TYPE_U64 | TYPE_I64 => Value::Int(read_u64(reader)?),
+        TYPE_F32 => Value::Float(f32::from_le_bytes(read_bytes(reader)?) as f64),
+        TYPE_F64 => Value::Float(f64::from_le_bytes(read_bytes(reader)?)),
+        TYPE_BOOL => Value::Bool(read_bytes::<R, 1>(reader)?[0] != 0),
+        TYPE_STRING => Value::String(read_string(reader)?),
+        TYPE_ARRAY => {
+            let item_type = read_u32(reader)?;
+            let len = read_u64(reader)?;
+            let mut items = Vec::with_capacity(len.min(1 << 20) as usize);
+            for _ in 0..len {
+                items.push(read_value(reader, item_type)?);
+            }
+            Value::Array(items)
+        }
+        _ => return Err(unknown_type(value_type)),
+    };
+    Ok(value)
+}
+
+fn skip_value<R: Read + Seek>(reader: &mut R, value_type: u32) -> std::io::Result<()> {
+    match value_type {
+        TYPE_U8 | TYPE_I8 | TYPE_BOOL => reader.seek_relative(1),
+        TYPE_U16 | TYPE_I16 => reader.seek_relative(2),
+        TYPE_U32 | TYPE_I32 | TYPE_F32 => reader.seek_relative(4),
+        TYPE_U64 | TYPE_I64 | TYPE_F64 => reader.seek_relative(8),
+        TYPE_STRING => {
+            let len = read_u64(reader)?;
+            reader.seek_relative(len as i64)
+        }
+        TYPE_ARRAY => {
+            let item_type = read_u32(reader)?;
+            let len = read_u64(reader)?;
+            for _ in 0..len {
+                skip_value(reader, item_type)?;
+            }
+            Ok(())
+        }
+        _ => Err(unknown_type(value_type)),
+    }
+}
+
+fn read_bytes<R: Read, const N: usize>(reader: &mut R) -> std::io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> std::io::Result<String> {
+    let len = read_u64(reader)?;
+    let mut buf = Vec::new();
+    reader.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+#[test]
+fn test_parse_metadata() {
+    fn string(buf: &mut Vec<u8>, s: &str) {
+        buf.extend((s.len() as u64).to_le_bytes());
+        buf.extend(s.as_bytes());
+    }
+
+    let mut buf = MAGIC.to_vec();
+    buf.extend(3u32.to_le_bytes());
+    buf.extend(0u64.to_le_bytes());
+    buf.extend(4u64.to_le_bytes());
+    string(&mut buf, "general.architecture");
+    buf.extend(TYPE_STRING.to_le_bytes());
+    string(&mut buf, "llama");
+    string(&mut buf, "tokenizer.ggml.tokens");
+    buf.extend(TYPE_ARRAY.to_le_bytes());
+    buf.extend(TYPE_STRING.to_le_bytes());
+    buf.extend(2u64.to_le_bytes());
+    string(&mut buf, "<s>");
+    string(&mut buf, "</s>");
+    string(&mut buf, "tokenizer.ggml.scores");
+    buf.extend(TYPE_ARRAY.to_le_bytes());
+    buf.extend(TYPE_F32.to_le_bytes());
+    buf.extend(2u64.to_le_bytes());
+    buf.extend(0f32.to_le_bytes());
+    buf.extend((-1.5f32).to_le_bytes());
+    string(&mut buf, "llama.context_length");
+    buf.extend(TYPE_U32.to_le_bytes());
+    buf.extend(4096u32.to_le_bytes());
+
+    let all = parse_metadata(&mut std::io::Cursor::new(&buf), |_| true).unwrap();
+    assert_eq!(all["general.architecture"].as_str(), Some("llama"));
+    assert_eq!(all["llama.context_length"].as_u64(), Some(4096));
+    assert_eq!(
+        all["tokenizer.ggml.tokens"].as_array().unwrap()[1].as_str(),
+        Some("</s>")
+    );
+    assert_eq!(
+        all["tokenizer.ggml.scores"].as_array().unwrap()[1],
+        Value::Float(-1.5)
+    );
+
+    let some = parse_metadata(&mut std::io::Cursor::new(&buf), |key| {
+        !key.starts_with("tokenizer.")
+    })
+    .unwrap();
+    assert_eq!(some.len(), 2);
+    assert_eq!(some["llama.context_length"].as_u64(), Some(4096));
+
+    assert!(parse_metadata(&mut std::io::Cursor::new(b"GGML".to_vec()), |_| true).is_err());
+}
@@ -1,18 +1,21 @@
 use std::{
+    collections::HashMap,
     path::{Path, PathBuf},
     sync::{
-        mpsc::{Receiver, Sender},
+        atomic::{AtomicU64, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender},
         Arc, Mutex,
     },
 };
 
 use chrono::Utc;
 use moly_protocol::{
-    data::{DownloadedFile, FileID, Model, PendingDownload},
-    open_ai::{ChatRequestData, ChatResponse},
+    data::{DownloadedFile, FileID, Model, ModelID, PendingDownload},
+    open_ai::{ChatRequestData, ChatRequestID, ChatResponse, Message},
     protocol::{
-        Command, FileDownloadResponse, LoadModelOptions, LoadModelResponse, LocalServerConfig,
-        LocalServerResponse,
+        BackendEvent, BackendFeatures, BackendInfo, Command, DownloadState, EventFilter, FileDownloadResponse,
+        FileVerification, LoadModelOptions, LoadModelResponse, LocalServerConfig,
+        LibraryUpdate, LoadPreset, LoadStage, LocalServerResponse, MolyError, SplitMode,
     },
 };
 
@@ -23,7 +26,30 @@ use crate::store::{
 };
 
 mod api_server;
+mod chat_queue;
+mod chat_template;
 mod chat_ui;
+mod context_size;
+mod engines;
+mod events;
+mod gguf;
+mod residency;
+mod resources;
+mod server_proxy;
+mod sse;
+mod stderr;
+mod structured_output;
+mod supervisor;
+mod tokenizer;
+
+use chat_queue::ChatQueue;
+use residency::{Preload, Residency};
+use tokenizer::Vocab;
+pub use chat_queue::ChatCancel;
+pub use engines::{EngineRegistry, LoadedModel};
+pub use events::EventBus;
+pub use stderr::{capture as capture_stderr, StderrCapture};
+pub use supervisor::HealthCheck;
 
 #[derive(Clone, Debug)]
 enum ModelManagementCommand {
@@ -34,8 +60,15 @@ enum ModelManagementCommand {
     CancelDownload(FileID, Sender<anyhow::Result<()>>),
     GetCurrentDownloads(Sender<anyhow::Result<Vec<PendingDownload>>>),
     GetDownloadedFiles(Sender<anyhow::Result<Vec<DownloadedFile>>>),
+    GetLibraryUpdates(Sender<anyhow::Result<Vec<LibraryUpdate>>>),
+    AcknowledgeNewFiles(ModelID, Sender<anyhow::Result<()>>),
+    ApplyTemplateUpdate(FileID, Sender<anyhow::Result<()>>),
+    VerifyFile(FileID, Sender<anyhow::Result<FileVerification>>),
     DeleteFile(FileID, Sender<anyhow::Result<()>>),
     ChangeModelsLocation(PathBuf),
+    GetLoadPresets(FileID, Sender<anyhow::Result<Vec<LoadPreset>>>),
+    SaveLoadPreset(FileID, LoadPreset, Sender<anyhow::Result<()>>),
+    DeleteLoadPreset(FileID, String, Sender<anyhow::Result<()>>),
 }
 
 #[derive(Clone, Debug)]
@@ -47,7 +80,15 @@ enum ModelInteractionCommand {
     ),
     EjectModel(Sender<anyhow::Result<()>>),
     Chat(ChatRequestData, Sender<anyhow::Result<ChatResponse>>),
-    StopChatCompletion(Sender<anyhow::Result<()>>),
+    StopChatCompletion(ChatRequestID, Sender<anyhow::Result<()>>),
+    Tokenize(String, Sender<anyhow::Result<Vec<u32>>>),
+    CountTokens(String, Sender<anyhow::Result<u32>>),
+    CountChatTokens(Vec<Message>, Sender<anyhow::Result<u32>>),
+    // Sent by the backend itself once the response stream of a request is closed
+    ChatFinished(ChatRequestID),
+    // Sent by the supervisor of the model loaded as the given generation
+    ModelCrashed(u64, String),
+    RestartModel(u64),
     // Command to start a local server to interact with chat models
     StartLocalServer(
         LocalServerConfig,
@@ -55,12 +96,20 @@ enum ModelInteractionCommand {
     ),
     // Command to stop the local server
     StopLocalServer(Sender<anyhow::Result<()>>),
+    SetIdleTimeout(Option<u64>, Sender<anyhow::Result<()>>),
+    PreloadModels(Vec<FileID>, Sender<anyhow::Result<()>>),
 }
 
 #[derive(Clone, Debug)]
 enum BuiltInCommand {
     Model(ModelManagementCommand),
     Interaction(ModelInteractionCommand),
+    Subscribe(EventFilter, Sender<BackendEvent>),
+    GetBackendInfo(Sender<anyhow::Result<BackendInfo>>),
+    SetEngine(String, Sender<anyhow::Result<()>>),
+    SetModelEngine(ModelID, Option<String>, Sender<anyhow::Result<()>>),
+    // Every sender of commands was dropped
+    Shutdown,
 }
 
 impl From<Command> for BuiltInCommand {
@@ -90,15 +139,36 @@ impl From<Command> for BuiltInCommand {
             Command::GetDownloadedFiles(tx) => {
                 Self::Model(ModelManagementCommand::GetDownloadedFiles(tx))
             }
+            Command::GetLibraryUpdates(tx) => {
+                Self::Model(ModelManagementCommand::GetLibraryUpdates(tx))
+            }
+            Command::AcknowledgeNewFiles(model_id, tx) => {
+                Self::Model(ModelManagementCommand::AcknowledgeNewFiles(model_id, tx))
+            }
+            Command::ApplyTemplateUpdate(file_id, tx) => {
+                Self::Model(ModelManagementCommand::ApplyTemplateUpdate(file_id, tx))
+            }
+            Command::VerifyFile(file_id, tx) => {
+                Self::Model(ModelManagementCommand::VerifyFile(file_id, tx))
+            }
             Command::LoadModel(file_id, options, tx) => {
                 Self::Interaction(ModelInteractionCommand::LoadModel(file_id, options, tx))
             }
             Command::EjectModel(tx) => Self::Interaction(ModelInteractionCommand::EjectModel(tx)),
             Command::Chat(request, tx) => {
-                Self::Interaction(ModelInteractionCommand::Chat(request, tx))
+                Self::Interaction(ModelInteractionCommand::Chat(*request, tx))
+            }
+            Command::StopChatCompletion(request_id, tx) => {
+                Self::Interaction(ModelInteractionCommand::StopChatCompletion(request_id, tx))
             }
-            Command::StopChatCompletion(tx) => {
-                Self::Interaction(ModelInteractionCommand::StopChatCompletion(tx))
+            Command::Tokenize(text, tx) => {
+                Self::Interaction(ModelInteractionCommand::Tokenize(text, tx))
+            }
+            Command::CountTokens(text, tx) => {
+                Self::Interaction(ModelInteractionCommand::CountTokens(text, tx))
+            }
+            Command::CountChatTokens(messages, tx) => {
+                Self::Interaction(ModelInteractionCommand::CountChatTokens(messages, tx))
             }
             Command::StartLocalServer(config, tx) => {
                 Self::Interaction(ModelInteractionCommand::StartLocalServer(config, tx))
@@ -109,6 +179,27 @@ impl From<Command> for BuiltInCommand {
             Command::ChangeModelsDir(path) => {
                 Self::Model(ModelManagementCommand::ChangeModelsLocation(path))
             }
+            Command::Subscribe(filter, tx) => Self::Subscribe(filter, tx),
+            Command::GetBackendInfo(tx) => Self::GetBackendInfo(tx),
+            Command::SetEngine(name, tx) => Self::SetEngine(name, tx),
+            Command::SetModelEngine(model_id, name, tx) => {
+                Self::SetModelEngine(model_id, name, tx)
+            }
+            Command::GetLoadPresets(file_id, tx) => {
+                Self::Model(ModelManagementCommand::GetLoadPresets(file_id, tx))
+            }
+            Command::SaveLoadPreset(file_id, preset, tx) => {
+                Self::Model(ModelManagementCommand::SaveLoadPreset(file_id, preset, tx))
+            }
+            Command::DeleteLoadPreset(file_id, name, tx) => {
+                Self::Model(ModelManagementCommand::DeleteLoadPreset(file_id, name, tx))
+            }
+            Command::SetIdleTimeout(secs, tx) => {
+                Self::Interaction(ModelInteractionCommand::SetIdleTimeout(secs, tx))
+            }
+            Command::PreloadModels(file_ids, tx) => {
+                Self::Interaction(ModelInteractionCommand::PreloadModels(file_ids, tx))
+            }
         }
     }
 }
@@ -118,10 +209,13 @@ fn test_chat() {
     use moly_protocol::open_ai::*;
 
     let home = std::env::var("HOME").unwrap();
-    let bk = BackendImpl::<chat_ui::ChatBotModel>::build_command_sender(
+    let bk = BackendImpl::build_command_sender(
         format!("{home}/ai/models"),
         format!("{home}/ai/models"),
         3,
+        false,
+        EngineRegistry::default(),
+        chat_ui::ChatBotModel::NAME,
     );
 
     let (tx, rx) = std::sync::mpsc::channel();
@@ -137,15 +231,9 @@ fn test_chat() {
     let cmd = Command::LoadModel(
         file.file.id.clone(),
         LoadModelOptions {
-            prompt_template: None,
-            gpu_layers: moly_protocol::protocol::GPULayers::Max,
-            use_mlock: false,
-            rope_freq_scale: 0.0,
-            rope_freq_base: 0.0,
-            context_overflow_policy: moly_protocol::protocol::ContextOverflowPolicy::StopAtLimit,
             n_batch: Some(128),
             n_ctx: Some(1024),
-            override_server_address: None,
+            ..Default::default()
         },
         tx,
     );
@@ -156,29 +244,20 @@ fn test_chat() {
 
     let (tx, rx) = std::sync::mpsc::channel();
     let cmd = Command::Chat(
-        ChatRequestData {
+        Box::new(ChatRequestData {
             messages: vec![Message {
-                content: "hello".to_string(),
+                content: "hello".into(),
                 role: Role::User,
                 name: None,
             }],
             model: "llama-2-7b-chat.Q5_K_M".to_string(),
-            frequency_penalty: None,
-            logprobs: None,
-            top_logprobs: None,
-            max_tokens: None,
-            presence_penalty: None,
-            seed: None,
-            stop: None,
             stream: Some(false),
-            temperature: None,
-            top_p: None,
-            n: None,
-            logit_bias: None,
-        },
+            ..Default::default()
+        }),
         tx,
     );
     bk.send(cmd).unwrap();
+    assert!(matches!(rx.recv(), Ok(Ok(ChatResponse::Accepted(_)))));
     if let Ok(Ok(ChatResponse::ChatFinalResponseData(data))) = rx.recv() {
         println!("{:?}", data.choices[0].message);
     }
@@ -194,10 +273,13 @@ fn test_chat_stop() {
     use moly_protocol::open_ai::*;
 
     let home = std::env::var("HOME").unwrap();
-    let bk = BackendImpl::<chat_ui::ChatBotModel>::build_command_sender(
+    let bk = BackendImpl::build_command_sender(
         format!("{home}/ai/models"),
         format!("{home}/ai/models"),
         3,
+        false,
+        EngineRegistry::default(),
+        chat_ui::ChatBotModel::NAME,
     );
 
     let (tx, rx) = std::sync::mpsc::channel();
@@ -213,15 +295,9 @@ fn test_chat_stop() {
     let cmd = Command::LoadModel(
         file.file.id.clone(),
         LoadModelOptions {
-            prompt_template: None,
-            gpu_layers: moly_protocol::protocol::GPULayers::Max,
-            use_mlock: false,
             n_batch: Some(128),
             n_ctx: Some(1024),
-            rope_freq_scale: 0.0,
-            rope_freq_base: 0.0,
-            context_overflow_policy: moly_protocol::protocol::ContextOverflowPolicy::StopAtLimit,
-            override_server_address: None,
+            ..Default::default()
         },
         tx,
     );
@@ -232,29 +308,22 @@ fn test_chat_stop() {
 
     let (tx, rx) = std::sync::mpsc::channel();
     let cmd = Command::Chat(
-        ChatRequestData {
+        Box::new(ChatRequestData {
             messages: vec![Message {
-                content: "hello".to_string(),
+                content: "hello".into(),
                 role: Role::User,
                 name: None,
             }],
             model: "llama-2-7b-chat.Q5_K_M".to_string(),
-            frequency_penalty: None,
-            logprobs: None,
-            top_logprobs: None,
-            max_tokens: None,
-            presence_penalty: None,
-            seed: None,
-            stop: None,
             stream: Some(true),
-            temperature: None,
-            top_p: None,
-            n: None,
-            logit_bias: None,
-        },
+            ..Default::default()
+        }),
         tx,
     );
     bk.send(cmd).unwrap();
+    let Ok(Ok(ChatResponse::Accepted(request_id))) = rx.recv() else {
+        panic!("the chat request was not accepted");
+    };
 
     let mut i = 0;
     while let Ok(Ok(ChatResponse::ChatResponseChunk(data))) = rx.recv() {
@@ -265,7 +334,7 @@ fn test_chat_stop() {
         );
         if i == 5 {
             let (tx, rx) = std::sync::mpsc::channel();
-            let cmd = Command::StopChatCompletion(tx);
+            let cmd = Command::StopChatCompletion(request_id, tx);
             bk.send(cmd).unwrap();
             rx.recv().unwrap().unwrap();
         }
@@ -283,10 +352,13 @@ fn test_chat_stop() {
 #[test]
 fn test_download_file() {
     let home = std::env::var("HOME").unwrap();
-    let bk = BackendImpl::<chat_ui::ChatBotModel>::build_command_sender(
+    let bk = BackendImpl::build_command_sender(
         format!("{home}/ai/models"),
         format!("{home}/ai/models"),
         3,
+        false,
+        EngineRegistry::default(),
+        chat_ui::ChatBotModel::NAME,
     );
 
     let (tx, rx) = std::sync::mpsc::channel();
@@ -331,10 +403,13 @@ fn test_download_file() {
 #[test]
 fn test_get_download_file() {
     let home = std::env::var("HOME").unwrap();
-    let bk = BackendImpl::<chat_ui::ChatBotModel>::build_command_sender(
+    let bk = BackendImpl::build_command_sender(
         format!("{home}/ai/models"),
         format!("{home}/ai/models"),
         3,
+        false,
+        EngineRegistry::default(),
+        chat_ui::ChatBotModel::NAME,
     );
 
     let (tx, rx) = std::sync::mpsc::channel();
@@ -346,15 +421,75 @@ fn test_get_download_file() {
     println!("{files:?}");
 }
 
+/// The commands that no engine handles yet, they fail with [`MolyError::Unsupported`].
+const UNSUPPORTED_COMMANDS: &[&str] = &["StartLocalServer", "StopLocalServer"];
+
+/// The names of the commands that are handled, see [`BackendInfo::supported_commands`].
+fn supported_commands() -> Vec<String> {
+    Command::NAMES
+        .iter()
+        .filter(|name| !UNSUPPORTED_COMMANDS.contains(name))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+#[test]
+fn test_supported_commands() {
+    for name in UNSUPPORTED_COMMANDS {
+        assert!(Command::NAMES.contains(name), "{name}");
+    }
+    let supported = supported_commands();
+    assert_eq!(
+        supported.len(),
+        Command::NAMES.len() - UNSUPPORTED_COMMANDS.len()
+    );
+    assert!(supported.iter().any(|name| name == "PreloadModels"));
+    assert!(!supported.iter().any(|name| name == "StartLocalServer"));
+}
+
+/// The WasmEdge version and the plugins of the installation in use,
+/// found through the environment variables set by `moly-runner`.
+fn wasmedge_info() -> (Option<String>, Vec<String>) {
+    let version = std::env::var_os("WASMEDGE_DIR").and_then(|dir| {
+        let header = Path::new(&dir).join("include/wasmedge/version.h");
+        let header = std::fs::read_to_string(header).ok()?;
+        header.lines().find_map(|line| {
+            let version = line.strip_prefix("#define WASMEDGE_VERSION ")?;
+            Some(version.trim().trim_matches('"').to_string())
+        })
+    });
+
+    let plugins = std::env::var_os("WASMEDGE_PLUGIN_PATH")
+        .and_then(|dir| std::fs::read_dir(dir).ok())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.contains("wasmedgePlugin"))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    (version, plugins)
+}
+
 #[derive(Debug, Clone)]
 pub enum DownloadControlCommand {
     Stop(FileID),
 }
 
-pub type ChatModelBackend = BackendImpl<chat_ui::ChatBotModel>;
-pub type LlamaEdgeApiServerBackend = BackendImpl<api_server::LLamaEdgeApiServer>;
+const MAX_CONCURRENT_CHATS_ENV_VAR: &str = "MOLY_MAX_CONCURRENT_CHATS";
+
+/// The engine models are loaded with unless another one is chosen.
+pub const DEFAULT_ENGINE: &str = <api_server::LLamaEdgeApiServer as BackendModel>::NAME;
 
 pub trait BackendModel: Sized {
+    /// Name of the engine, reported in [`BackendInfo::implementation`].
+    const NAME: &'static str;
+    const FEATURES: BackendFeatures;
+    /// How many chat requests a loaded model can answer at the same time.
+    const MAX_CONCURRENT_CHATS: usize;
+
     fn new_or_reload(
         async_rt: &tokio::runtime::Runtime,
         old_model: Option<Self>,
@@ -362,46 +497,99 @@ pub trait BackendModel: Sized {
         options: LoadModelOptions,
         tx: Sender<anyhow::Result<LoadModelResponse>>,
         embedding: Option<(PathBuf, u64)>,
+        // The paths of `options.lora_adapters`, with their scales.
+        adapters: Vec<(PathBuf, f32)>,
     ) -> Self;
     fn chat(
         &self,
         async_rt: &tokio::runtime::Runtime,
         data: ChatRequestData,
         tx: Sender<anyhow::Result<ChatResponse>>,
+        cancel: ChatCancel,
     ) -> bool;
-    fn stop_chat(&self, async_rt: &tokio::runtime::Runtime);
     fn stop(self, async_rt: &tokio::runtime::Runtime);
+
+    /// Stops the runtime of a model that crashed, but keeps what its next load can reuse,
+    /// like the address of its server. What is returned is only given back to
+    /// `new_or_reload` as the old model.
+    fn stop_crashed(self, async_rt: &tokio::runtime::Runtime) -> Option<Self> {
+        self.stop(async_rt);
+        None
+    }
+
+    /// A check of the runtime of the loaded model, run periodically out of the backend
+    /// thread. The model is restarted once it returns an error.
+    fn health_check(&self) -> Option<HealthCheck> {
+        None
+    }
+
+    /// When clients other than the backend last used the model, through the port of its
+    /// server. Their requests keep the model from being ejected as idle.
+    fn last_client_activity(&self) -> Option<std::time::Instant> {
+        None
+    }
 }
 
-pub struct BackendImpl<Model: BackendModel> {
+pub struct BackendImpl {
     sql_conn: Arc<Mutex<rusqlite::Connection>>,
     model_indexs: ModelCardManager,
     #[allow(unused)]
     app_data_dir: PathBuf,
     models_dir: PathBuf,
-    pub rx: Receiver<Command>,
+    rx: Receiver<BuiltInCommand>,
+    // Lets the backend send commands to itself
+    commands_tx: Sender<BuiltInCommand>,
     download_tx: tokio::sync::mpsc::UnboundedSender<(
         store::models::Model,
         store::download_files::DownloadedFile,
         model_cards::RemoteFile,
         Sender<anyhow::Result<FileDownloadResponse>>,
     )>,
-    model: Option<Model>,
+    model: Option<Box<dyn LoadedModel>>,
+    // The model that crashed, until it is restarted, see `BackendModel::stop_crashed`.
+    crashed_model: Option<Box<dyn LoadedModel>>,
+    loaded_file_id: Option<FileID>,
+    engines: EngineRegistry,
+    engine: String,
+    engine_overrides: HashMap<ModelID, String>,
+    chats: ChatQueue,
+    max_concurrent_chats: usize,
+    loaded_options: Option<LoadModelOptions>,
+    // The context size the loaded model was given, chosen when the options had none.
+    loaded_n_ctx: Option<u32>,
+    // Changes with every load and eject, stopping the supervision of the previous model.
+    model_generation: Arc<AtomicU64>,
+    // The vocabulary of the last model tokens were counted for, read from its file.
+    vocab: Arc<Mutex<Option<(FileID, Arc<Vocab>)>>>,
+    restarts: u32,
+    last_restart: Option<std::time::Instant>,
+    residency: Residency,
 
     #[allow(unused)]
     async_rt: tokio::runtime::Runtime,
     control_tx: tokio::sync::broadcast::Sender<DownloadControlCommand>,
+    events: EventBus,
+    offline: bool,
 }
 
-impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
+impl BackendImpl {
     /// # Arguments
     /// * `app_data_dir` - The directory where application data should be stored.
     /// * `models_dir` - The directory where models should be downloaded.
     /// * `max_download_threads` - Maximum limit on simultaneous file downloads.
+    /// * `offline` - Skip every network call and serve the last synced catalog.
+    /// * `engines` - The engines models can be loaded with.
+    /// * `engine` - The name of the engine used for models without an override.
+    ///
+    /// Up to `MOLY_MAX_CONCURRENT_CHATS` chat requests run at the same time, if the
+    /// engine allows it. One by default.
     pub fn build_command_sender<A: AsRef<Path>, M: AsRef<Path>>(
         app_data_dir: A,
         models_dir: M,
         max_download_threads: usize,
+        offline: bool,
+        engines: EngineRegistry,
+        engine: &str,
     ) -> Sender<Command> {
         let app_data_dir = app_data_dir.as_ref().to_path_buf();
 
@@ -415,14 +603,27 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
             )
         });
 
-        let model_indexs = store::model_cards::sync_model_cards_repo(&app_data_dir);
-        let model_indexs = match model_indexs {
+        let model_indexs = if offline {
+            log::info!("offline mode, loading the last synced model cards");
+            store::model_cards::load_model_cards_snapshot(&app_data_dir)
+        } else {
+            store::model_cards::sync_model_cards_repo(&app_data_dir)
+        };
+        let events = EventBus::default();
+        let mut model_indexs = match model_indexs {
             Ok(model_indexs) => {
-                log::info!("sync model cards repo success");
+                log::info!(
+                    "sync model cards repo success, {} invalid cards",
+                    model_indexs.card_errors().len()
+                );
+                events.publish(BackendEvent::CatalogSynced(
+                    model_indexs.card_errors().to_vec(),
+                ));
                 model_indexs
             }
             Err(e) => {
                 log::error!("sync model cards repo error: {e}");
+                events.publish(BackendEvent::CatalogSyncFailed(e.to_string()));
                 ModelCardManager::empty(app_data_dir.clone())
             }
         };
@@ -432,10 +633,40 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
         // TODO Reorganize these bunch of functions, needs a little more of thought
         let _ = store::models::create_table_models(&sql_conn).unwrap();
         let _ = store::download_files::create_table_download_files(&sql_conn).unwrap();
+        let _ = store::load_presets::create_table_load_presets(&sql_conn).unwrap();
+        if let Err(e) = store::record_known_files(&sql_conn, &mut model_indexs) {
+            log::warn!("failed to record the known files of the downloaded models: {e:#}");
+        }
+
+        let engine = if engines.get(engine).is_some() {
+            engine.to_string()
+        } else {
+            log::warn!("unknown engine {engine}, using {DEFAULT_ENGINE}");
+            DEFAULT_ENGINE.to_string()
+        };
+        let engine_overrides = store::models::get_engine_overrides(&sql_conn).unwrap_or_default();
 
         let sql_conn = Arc::new(Mutex::new(sql_conn));
 
-        let (tx, rx) = std::sync::mpsc::channel();
+        let max_concurrent_chats = std::env::var(MAX_CONCURRENT_CHATS_ENV_VAR)
+            .ok()
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(1);
+        let chats = ChatQueue::new(max_concurrent_chats);
+
+        let (tx, rx) = std::sync::mpsc::channel::<Command>();
+        let (commands_tx, commands_rx) = std::sync::mpsc::channel();
+        {
+            let commands_tx = commands_tx.clone();
+            std::thread::spawn(move || {
+                for cmd in rx {
+                    if commands_tx.send(cmd.into()).is_err() {
+                        return;
+                    }
+                }
+                let _ = commands_tx.send(BuiltInCommand::Shutdown);
+            });
+        }
 
         let async_rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -446,8 +677,14 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
 
         {
             let client = reqwest::Client::new();
-            let downloader =
-                ModelFileDownloader::new(client, sql_conn.clone(), control_tx.clone(),model_indexs.country_code.clone(), 0.1);
+            let downloader = ModelFileDownloader::new(
+                client,
+                sql_conn.clone(),
+                control_tx.clone(),
+                events.clone(),
+                model_indexs.country_code.clone(),
+                0.1,
+            );
             async_rt.spawn(ModelFileDownloader::run_loop(
                 downloader,
                 max_download_threads.max(3),
@@ -460,11 +697,28 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
             model_indexs,
             app_data_dir,
             models_dir: models_dir.as_ref().into(),
-            rx,
+            rx: commands_rx,
+            commands_tx,
             download_tx,
             model: None,
+            crashed_model: None,
+            loaded_file_id: None,
+            engines,
+            engine,
+            engine_overrides,
+            chats,
+            max_concurrent_chats,
+            loaded_options: None,
+            loaded_n_ctx: None,
+            model_generation: Arc::new(AtomicU64::new(0)),
+            vocab: Arc::new(Mutex::new(None)),
+            restarts: 0,
+            last_restart: None,
+            residency: Residency::new(std::time::Instant::now()),
             async_rt,
             control_tx,
+            events,
+            offline,
         };
 
         std::thread::spawn(move || {
@@ -489,12 +743,12 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
 
                             let sql_conn = self.sql_conn.lock().unwrap();
                             let models = ModelCard::to_model(&models, &sql_conn)
-                                .map_err(|e| anyhow::anyhow!("get featured error: {e}"));
+                                .map_err(|e| store::db_error(e).into());
 
                             let _ = tx.send(models);
                         }
                         Err(e) => {
-                            let _ = tx.send(Err(anyhow::anyhow!("get featured models error: {e}")));
+                            let _ = tx.send(Err(MolyError::Catalog(e.to_string()).into()));
                         }
                     }
                 }
@@ -518,33 +772,49 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
                             }
 
                             let models = ModelCard::to_model(&models, &sql_conn)
-                                .map_err(|e| anyhow::anyhow!("search models error: {e}"));
+                                .map_err(|e| store::db_error(e).into());
 
                             let _ = tx.send(models);
                         }
                         Err(e) => {
-                            let _ = tx.send(Err(anyhow::anyhow!("search models error: {e}")));
+                            let _ = tx.send(Err(MolyError::Catalog(e.to_string()).into()));
                         }
                     }
                 }
                 ModelManagementCommand::DownloadFile(file_id, tx) => {
+                    if self.offline {
+                        let _ = tx.send(Err(MolyError::Offline.into()));
+                        return;
+                    }
+
                     //search model from remote
-                    let mut search_model_from_remote = || -> anyhow::Result<( crate::store::models::Model , crate::store::download_files::DownloadedFile,crate::store::model_cards::RemoteFile)> {
+                    let mut search_model_from_remote = || -> Result<( crate::store::models::Model , crate::store::download_files::DownloadedFile,crate::store::model_cards::RemoteFile), MolyError> {
                         let (model_id, file) = file_id
                             .split_once("#")
-                            .ok_or_else(|| anyhow::anyhow!("Illegal file_id"))?;
-
-                        let index = self.model_indexs.get_index_by_id(model_id).ok_or(anyhow::anyhow!("No model found"))?.clone();
-                        let remote_model = self.model_indexs.load_model_card(&index)?;
-                    
-
-                        let remote_file = remote_model
-                            .files
-                            .into_iter()
-                            .find(|f| f.name == file)
-                            .ok_or_else(|| anyhow::anyhow!("file not found"))?;
+                            .ok_or_else(|| MolyError::InvalidFileId(file_id.clone()))?;
+
+                        let index = self
+                            .model_indexs
+                            .get_index_by_id(model_id)
+                            .ok_or_else(|| MolyError::ModelNotFound(model_id.to_string()))?
+                            .clone();
+                        let remote_model = self
+                            .model_indexs
+                            .load_model_card(&index)
+                            .map_err(|e| MolyError::Catalog(e.to_string()))?;
+
+                        let (remote_file, is_adapter) = remote_model
+                            .find_file(file)
+                            .map(|(f, is_adapter)| (f.clone(), is_adapter))
+                            .ok_or_else(|| MolyError::FileNotFound(file_id.clone()))?;
 
                         let remote_file_ = remote_file.clone();
+                        let known_files = remote_model
+                            .files
+                            .iter()
+                            .chain(&remote_model.adapters)
+                            .map(|f| f.name.clone())
+                            .collect();
 
                         let download_model = crate::store::models::Model {
                             id: Arc::new(remote_model.id),
@@ -563,6 +833,7 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
                             }),
                             like_count: remote_model.like_count,
                             download_count: remote_model.download_count,
+                            known_files,
                         };
 
                         let download_file = crate::store::download_files::DownloadedFile {
@@ -581,6 +852,9 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
                             tags:remote_file.tags,
                             featured: false,
                             sha256: remote_file.sha256.unwrap_or_default(),
+                            parts: remote_file.parts.iter().map(|p| p.name.clone()).collect(),
+                            mmproj: remote_file.mmproj.map(|p| p.name).unwrap_or_default(),
+                            is_adapter,
                         };
 
                         Ok((download_model,download_file,remote_file_))
@@ -591,13 +865,17 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
                             let _ = self.download_tx.send((model, file,remote_file, tx));
                         }
                         Err(e) => {
-                            let _ = tx.send(Err(e));
+                            let _ = tx.send(Err(e.into()));
                         }
                     }
                 }
 
                 ModelManagementCommand::PauseDownload(file_id, tx) => {
-                    let _ = self.control_tx.send(DownloadControlCommand::Stop(file_id));
+                    let _ = self.control_tx.send(DownloadControlCommand::Stop(file_id.clone()));
+                    self.events.publish(BackendEvent::DownloadStateChanged(
+                        file_id,
+                        DownloadState::Paused,
+                    ));
                     let _ = tx.send(Ok(()));
                 }
 
@@ -605,46 +883,128 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
                     let file_id_ = file_id.clone();
                     let _ = self.control_tx.send(DownloadControlCommand::Stop(file_id_));
 
-                    {
+                    let names = {
                         let conn = self.sql_conn.lock().unwrap();
+                        let names = store::owned_file_names(&conn, &file_id).unwrap_or_default();
                         let _ = store::download_files::DownloadedFile::remove(&file_id, &conn);
-                    }
+                        names
+                    };
                     let _ = store::remove_downloaded_file(
                         self.models_dir.to_string_lossy().to_string(),
-                        file_id,
+                        file_id.clone(),
+                        &names,
                     );
 
+                    self.events.publish(BackendEvent::DownloadStateChanged(
+                        file_id,
+                        DownloadState::Cancelled,
+                    ));
                     let _ = tx.send(Ok(()));
                 }
 
                 ModelManagementCommand::DeleteFile(file_id, tx) => {
-                    {
+                    let names = {
                         let conn = self.sql_conn.lock().unwrap();
+                        let names = store::owned_file_names(&conn, &file_id).unwrap_or_default();
                         let _ = store::download_files::DownloadedFile::remove(&file_id, &conn);
-                    }
+                        names
+                    };
 
                     let _ = store::remove_downloaded_file(
                         self.models_dir.to_string_lossy().to_string(),
                         file_id,
+                        &names,
                     );
                     let _ = tx.send(Ok(()));
                 }
 
+                ModelManagementCommand::GetLoadPresets(file_id, tx) => {
+                    let presets = {
+                        let conn = self.sql_conn.lock().unwrap();
+                        store::load_presets::get_load_presets(&conn, &file_id)
+                            .map_err(|e| store::db_error(e).into())
+                    };
+                    let _ = tx.send(presets);
+                }
+
+                ModelManagementCommand::SaveLoadPreset(file_id, preset, tx) => {
+                    let result = {
+                        let mut conn = self.sql_conn.lock().unwrap();
+                        store::load_presets::save_load_preset(&mut conn, &file_id, &preset)
+                            .map_err(|e| store::db_error(e).into())
+                    };
+                    let _ = tx.send(result);
+                }
+
+                ModelManagementCommand::DeleteLoadPreset(file_id, name, tx) => {
+                    let result = {
+                        let conn = self.sql_conn.lock().unwrap();
+                        store::load_presets::delete_load_preset(&conn, &file_id, &name)
+                            .map_err(|e| store::db_error(e).into())
+                    };
+                    let _ = tx.send(result);
+                }
+
                 ModelManagementCommand::GetDownloadedFiles(tx) => {
                     let downloads = {
                         let conn = self.sql_conn.lock().unwrap();
-                        store::get_all_download_file(&conn)
-                            .map_err(|e| anyhow::anyhow!("get download file error: {e}"))
+                        store::get_all_download_file(&conn).map_err(|e| store::db_error(e).into())
                     };
 
                     let _ = tx.send(downloads);
                 }
 
+                ModelManagementCommand::GetLibraryUpdates(tx) => {
+                    let updates = {
+                        let conn = self.sql_conn.lock().unwrap();
+                        store::get_library_updates(&conn, &mut self.model_indexs)
+                            .map_err(|e| MolyError::Catalog(format!("{e:#}")).into())
+                    };
+                    let _ = tx.send(updates);
+                }
+
+                ModelManagementCommand::AcknowledgeNewFiles(model_id, tx) => {
+                    let result = {
+                        let conn = self.sql_conn.lock().unwrap();
+                        store::acknowledge_new_files(&conn, &mut self.model_indexs, &model_id)
+                            .map_err(|e| MolyError::Catalog(format!("{e:#}")).into())
+                    };
+                    let _ = tx.send(result);
+                }
+
+                ModelManagementCommand::ApplyTemplateUpdate(file_id, tx) => {
+                    let result = {
+                        let conn = self.sql_conn.lock().unwrap();
+                        store::apply_template_update(&conn, &mut self.model_indexs, &file_id)
+                            .map_err(|e| MolyError::Catalog(format!("{e:#}")).into())
+                    };
+                    let _ = tx.send(result);
+                }
+
+                ModelManagementCommand::VerifyFile(file_id, tx) => {
+                    let parts = {
+                        let conn = self.sql_conn.lock().unwrap();
+                        store::file_parts_to_verify(&conn, &mut self.model_indexs, &file_id)
+                    };
+
+                    match parts {
+                        Ok(parts) => {
+                            // Hashing a model takes a while, don't block the other commands.
+                            self.async_rt.spawn_blocking(move || {
+                                let _ = tx.send(store::verify_parts(&parts).map_err(Into::into));
+                            });
+                        }
+                        Err(e) => {
+                            let _ = tx.send(Err(e.into()));
+                        }
+                    }
+                }
+
                 ModelManagementCommand::GetCurrentDownloads(tx) => {
                     let pending_downloads = {
                         let conn = self.sql_conn.lock().unwrap();
                         store::get_all_pending_downloads(&conn)
-                            .map_err(|e| anyhow::anyhow!("get pending download file error: {e}"))
+                            .map_err(|e| store::db_error(e).into())
                     };
                     let _ = tx.send(pending_downloads);
                 }
@@ -653,53 +1013,607 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
             },
             BuiltInCommand::Interaction(model_cmd) => match model_cmd {
                 ModelInteractionCommand::LoadModel(file_id, options, tx) => {
-                    let conn = self.sql_conn.lock().unwrap();
-                    let download_file =
-                        store::download_files::DownloadedFile::get_by_id(&conn, &file_id);
-
-                    match download_file {
-                        Ok(file) => {
-                            nn_preload_file(&file, self.model_indexs.embedding_model());
-                            let old_model = self.model.take();
-
-                            let model = Model::new_or_reload(
-                                &self.async_rt,
-                                old_model,
-                                file,
-                                options,
-                                tx,
-                                self.model_indexs.embedding_model(),
-                            );
-                            self.model = Some(model);
-                        }
-                        Err(e) => {
-                            let _ = tx.send(Err(anyhow::anyhow!("Load model error: {e}")));
-                        }
-                    }
+                    // A load asked for by the user starts over the count of restarts.
+                    self.restarts = 0;
+                    self.residency.touch(std::time::Instant::now());
+                    self.load_model(file_id, options, tx);
                 }
                 ModelInteractionCommand::EjectModel(tx) => {
-                    if let Some(model) = self.model.take() {
-                        model.stop(&self.async_rt);
-                    }
+                    self.eject_model();
                     let _ = tx.send(Ok(()));
                 }
                 ModelInteractionCommand::Chat(data, tx) => {
-                    if let Some(model) = &self.model {
-                        model.chat(&self.async_rt, data, tx);
+                    if let Err(e) = self.check_constraints(&data) {
+                        let _ = tx.send(Err(e.into()));
+                    } else if self.model.is_some() {
+                        self.residency.touch(std::time::Instant::now());
+                        self.chats.push(data, tx);
+                        self.start_chats();
                     } else {
-                        let _ = tx.send(Err(anyhow::anyhow!("Model not loaded")));
+                        let _ = tx.send(Err(MolyError::ModelNotLoaded.into()));
                     }
                 }
-                ModelInteractionCommand::StopChatCompletion(tx) => {
-                    self.model
-                        .as_ref()
-                        .map(|model| model.stop_chat(&self.async_rt));
+                ModelInteractionCommand::StopChatCompletion(request_id, tx) => {
+                    self.chats.stop(request_id);
+                    self.start_chats();
                     let _ = tx.send(Ok(()));
                 }
-                ModelInteractionCommand::StartLocalServer(_, _) => todo!(),
-                ModelInteractionCommand::StopLocalServer(_) => todo!(),
+                ModelInteractionCommand::Tokenize(text, tx) => {
+                    self.with_vocab(tx, move |vocab| vocab.tokenize(&text));
+                }
+                ModelInteractionCommand::CountTokens(text, tx) => {
+                    self.with_vocab(tx, move |vocab| vocab.tokenize(&text).len() as u32);
+                }
+                ModelInteractionCommand::CountChatTokens(messages, tx) => {
+                    let template = self.prompt_template();
+                    self.with_vocab(tx, move |vocab| {
+                        let prompt = chat_template::render(&template, &messages);
+                        vocab.tokenize(&prompt).len() as u32
+                    });
+                }
+                ModelInteractionCommand::ChatFinished(request_id) => {
+                    self.residency.touch(std::time::Instant::now());
+                    self.chats.finish(request_id);
+                    self.start_chats();
+                }
+                ModelInteractionCommand::ModelCrashed(generation, reason) => {
+                    self.handle_model_crash(generation, reason);
+                }
+                ModelInteractionCommand::RestartModel(generation) => self.restart_model(generation),
+                ModelInteractionCommand::StartLocalServer(_, tx) => {
+                    let error = MolyError::Unsupported("StartLocalServer".to_string());
+                    let _ = tx.send(Err(error.into()));
+                }
+                ModelInteractionCommand::StopLocalServer(tx) => {
+                    let error = MolyError::Unsupported("StopLocalServer".to_string());
+                    let _ = tx.send(Err(error.into()));
+                }
+                ModelInteractionCommand::SetIdleTimeout(secs, tx) => {
+                    self.residency.set_idle_timeout(
+                        secs.map(std::time::Duration::from_secs),
+                        std::time::Instant::now(),
+                    );
+                    let _ = tx.send(Ok(()));
+                }
+                ModelInteractionCommand::PreloadModels(file_ids, tx) => {
+                    self.residency.set_preload(file_ids);
+                    let _ = tx.send(Ok(()));
+                }
+            },
+            BuiltInCommand::Subscribe(filter, tx) => self.events.subscribe(filter, tx),
+            BuiltInCommand::GetBackendInfo(tx) => {
+                let (wasmedge_version, plugins) = wasmedge_info();
+                // The loaded model may use another engine than the default one.
+                let engine = self
+                    .model
+                    .as_ref()
+                    .map_or(self.engine.as_str(), |model| model.engine());
+                let features = self
+                    .engines
+                    .get(engine)
+                    .map(|engine| engine.features.clone())
+                    .unwrap_or_default();
+                let _ = tx.send(Ok(BackendInfo {
+                    protocol_version: moly_protocol::protocol::PROTOCOL_VERSION,
+                    implementation: self.engine.clone(),
+                    engines: self.engines.names(),
+                    supported_commands: supported_commands(),
+                    features,
+                    wasmedge_version,
+                    plugins,
+                }));
+            }
+            BuiltInCommand::SetEngine(name, tx) => {
+                if self.engines.get(&name).is_none() {
+                    let _ = tx.send(Err(MolyError::UnknownEngine(name).into()));
+                    return;
+                }
+                self.engine = name;
+                self.eject_model_of_other_engine();
+                let _ = tx.send(Ok(()));
+            }
+            BuiltInCommand::SetModelEngine(model_id, name, tx) => {
+                if let Some(name) = name.as_ref().filter(|name| self.engines.get(name).is_none()) {
+                    let _ = tx.send(Err(MolyError::UnknownEngine(name.clone()).into()));
+                    return;
+                }
+
+                let res = {
+                    let conn = self.sql_conn.lock().unwrap();
+                    store::models::set_engine_override(&conn, &model_id, name.as_deref())
+                };
+                if let Err(e) = res {
+                    let _ = tx.send(Err(store::db_error(e).into()));
+                    return;
+                }
+
+                match name {
+                    Some(name) => self.engine_overrides.insert(model_id, name),
+                    None => self.engine_overrides.remove(&model_id),
+                };
+                // The new engine is used from the next load of the model.
+                self.eject_model_of_other_engine();
+                let _ = tx.send(Ok(()));
+            }
+            // Handled by `run_loop`.
+            BuiltInCommand::Shutdown => {}
+        }
+    }
+
+    /// Fails when the request asks for constrained output that the loaded model can't
+    /// give.
+    fn check_constraints(&self, data: &ChatRequestData) -> Result<(), MolyError> {
+        if !structured_output::is_constrained(data) {
+            return Ok(());
+        }
+        structured_output::check_request(data)?;
+        let Some(engine) = self.model.as_ref().map(|model| model.engine()) else {
+            return Ok(());
+        };
+        let supported = self
+            .engines
+            .get(engine)
+            .is_some_and(|engine| engine.features.constrained_output);
+        if !supported {
+            return Err(MolyError::InvalidResponseFormat(format!(
+                "the {engine} engine can't constrain the output"
+            )));
+        }
+        Ok(())
+    }
+
+    /// The name of the engine a model is loaded with.
+    fn engine_for(&self, model_id: &str) -> &str {
+        self.engine_overrides
+            .get(model_id)
+            .unwrap_or(&self.engine)
+    }
+
+    /// Ejects the loaded model when it no longer runs with the engine chosen for it.
+    fn eject_model_of_other_engine(&mut self) {
+        let loaded_model_id = self
+            .loaded_file_id
+            .as_ref()
+            .and_then(|file_id| file_id.split_once('#'))
+            .map(|(model_id, _)| model_id.to_string());
+        if let Some(model_id) = loaded_model_id {
+            let engine = self.engine_for(&model_id).to_string();
+            if self.model.as_ref().map(|model| model.engine()) != Some(engine.as_str()) {
+                self.eject_model();
+            }
+        }
+    }
+
+    /// Stops the loaded model, if any, and broadcasts it.
+    fn eject_model(&mut self) {
+        self.model_generation.fetch_add(1, Ordering::AcqRel);
+        self.loaded_options = None;
+        self.chats.clear();
+        self.crashed_model = None;
+        if let Some(model) = self.model.take() {
+            model.stop(&self.async_rt);
+        }
+        self.events.set_local_server_port(None);
+        if let Some(file_id) = self.loaded_file_id.take() {
+            self.events.publish(BackendEvent::ModelEjected(file_id));
+        }
+    }
+
+    /// The prompt template the loaded model runs with, the one of the catalog unless the
+    /// load options chose another one.
+    fn prompt_template(&self) -> String {
+        let chosen = self
+            .loaded_options
+            .as_ref()
+            .and_then(|options| options.prompt_template.clone());
+        if let Some(template) = chosen {
+            return template;
+        }
+        let Some(file_id) = &self.loaded_file_id else {
+            return String::new();
+        };
+        let conn = self.sql_conn.lock().unwrap();
+        store::download_files::DownloadedFile::get_by_id(&conn, file_id)
+            .map(|file| file.prompt_template)
+            .unwrap_or_default()
+    }
+
+    /// Replies with `f` run on the vocabulary of the loaded model, out of the backend
+    /// thread as reading it the first time takes a while.
+    fn with_vocab<T: Send + 'static>(
+        &self,
+        tx: Sender<anyhow::Result<T>>,
+        f: impl FnOnce(&Vocab) -> T + Send + 'static,
+    ) {
+        let Some(file_id) = self.loaded_file_id.clone() else {
+            let _ = tx.send(Err(MolyError::ModelNotLoaded.into()));
+            return;
+        };
+        let cache = self.vocab.clone();
+        let sql_conn = self.sql_conn.clone();
+        std::thread::spawn(move || {
+            // Locked while reading, for the next requests to wait for it.
+            let mut cache = cache.lock().unwrap();
+            let vocab = match cache.as_ref() {
+                Some((cached_id, vocab)) if *cached_id == file_id => Ok(vocab.clone()),
+                _ => read_vocab(&sql_conn, &file_id),
+            };
+            let reply = match vocab {
+                Ok(vocab) => {
+                    let reply = f(&vocab);
+                    *cache = Some((file_id, vocab));
+                    Ok(reply)
+                }
+                Err(e) => Err(e.into()),
+            };
+            let _ = tx.send(reply);
+        });
+    }
+
+    /// The paths of the adapters to load with `file`, which must be downloaded adapters of
+    /// the same model.
+    fn adapter_paths(
+        &self,
+        file: &store::download_files::DownloadedFile,
+        options: &LoadModelOptions,
+    ) -> Result<Vec<(PathBuf, f32)>, MolyError> {
+        let conn = self.sql_conn.lock().unwrap();
+        options
+            .lora_adapters
+            .iter()
+            .map(|adapter| {
+                let adapter_file =
+                    store::download_files::DownloadedFile::get_by_id(&conn, &adapter.file_id)
+                        .map_err(|e| store::file_error(&adapter.file_id, e))?;
+                if !adapter_file.downloaded {
+                    return Err(MolyError::FileNotFound(adapter.file_id.clone()));
+                }
+                if !adapter_file.is_adapter || adapter_file.model_id != file.model_id {
+                    return Err(MolyError::IncompatibleAdapter {
+                        adapter: adapter.file_id.clone(),
+                        model_id: file.model_id.clone(),
+                    });
+                }
+                let path = Path::new(&adapter_file.download_dir)
+                    .join(&adapter_file.model_id)
+                    .join(&adapter_file.name);
+                Ok((path, adapter.scale))
+            })
+            .collect()
+    }
+
+    fn load_model(
+        &mut self,
+        file_id: FileID,
+        options: LoadModelOptions,
+        tx: Sender<anyhow::Result<LoadModelResponse>>,
+    ) {
+        let download_file = {
+            let conn = self.sql_conn.lock().unwrap();
+            store::download_files::DownloadedFile::get_by_id(&conn, &file_id)
+        };
+        let file = match download_file {
+            Ok(file) => file,
+            Err(e) => {
+                let error = store::file_error(&file_id, e);
+                self.events
+                    .publish(BackendEvent::ModelLoadFailed(file_id, error.to_string()));
+                let _ = tx.send(Err(error.into()));
+                return;
+            }
+        };
+
+        if file.is_adapter {
+            let error = MolyError::ModelLoadFailed {
+                file_id: file_id.clone(),
+                reason: "a LoRA adapter is loaded with its base model".to_string(),
+            };
+            self.events
+                .publish(BackendEvent::ModelLoadFailed(file_id, error.to_string()));
+            let _ = tx.send(Err(error.into()));
+            return;
+        }
+        let adapters = match self.adapter_paths(&file, &options) {
+            Ok(adapters) => adapters,
+            Err(error) => {
+                self.events
+                    .publish(BackendEvent::ModelLoadFailed(file_id, error.to_string()));
+                let _ = tx.send(Err(error.into()));
+                return;
+            }
+        };
+
+        let _ = tx.send(Ok(LoadModelResponse::Progress(
+            file_id.clone(),
+            LoadStage::Mapping,
+            0.0,
+        )));
+        // Fails early when the file was removed from disk, instead of in the runtime.
+        let file_path = Path::new(&file.download_dir)
+            .join(&file.model_id)
+            .join(&file.name);
+        if let Err(e) = std::fs::metadata(&file_path) {
+            let error = match e.kind() {
+                std::io::ErrorKind::NotFound => MolyError::FileNotFound(file_id.clone()),
+                _ => MolyError::Io(format!("{}: {e}", file_path.display())),
+            };
+            self.events
+                .publish(BackendEvent::ModelLoadFailed(file_id, error.to_string()));
+            let _ = tx.send(Err(error.into()));
+            return;
+        }
+
+        // The memory used by the model already loaded would make the choice smaller, so
+        // loading it again keeps the size it was given.
+        let n_ctx = match options.n_ctx {
+            Some(n_ctx) => n_ctx,
+            None if self.loaded_file_id.as_ref() == Some(&file_id)
+                && self.loaded_options.as_ref() == Some(&options) =>
+            {
+                self.loaded_n_ctx
+                    .unwrap_or_else(|| context_size::choose(&file, &file_path, &options))
+            }
+            None => context_size::choose(&file, &file_path, &options),
+        };
+
+        let engine_name = self.engine_for(&file.model_id).to_string();
+        let loaded_engine = self
+            .model
+            .as_ref()
+            .or(self.crashed_model.as_ref())
+            .map(|model| model.engine());
+        if loaded_engine != Some(engine_name.as_str()) {
+            self.eject_model();
+        }
+        let Some(engine) = self.engines.get(&engine_name) else {
+            let error = MolyError::UnknownEngine(engine_name);
+            self.events
+                .publish(BackendEvent::ModelLoadFailed(file_id, error.to_string()));
+            let _ = tx.send(Err(error.into()));
+            return;
+        };
+
+        // Stops the supervision of the current model, if any.
+        let generation = self.model_generation.fetch_add(1, Ordering::AcqRel) + 1;
+        let tx = self.relay_load_events(file_id.clone(), generation, tx);
+
+        let _ = tx.send(Ok(LoadModelResponse::Progress(
+            file_id.clone(),
+            LoadStage::Preloading,
+            0.1,
+        )));
+        nn_preload_file(&file, self.model_indexs.embedding_model());
+        // A model that crashed gives its address to the next one.
+        let old_model = self.model.take().or_else(|| self.crashed_model.take());
+
+        let model = (engine.load)(
+            &self.async_rt,
+            old_model,
+            file,
+            LoadModelOptions {
+                n_ctx: Some(n_ctx),
+                ..options.clone()
             },
+            tx,
+            self.model_indexs.embedding_model(),
+            adapters,
+        );
+        self.chats
+            .set_max_running(self.max_concurrent_chats.min(engine.max_concurrent_chats));
+
+        if let Some(check) = model.health_check() {
+            supervisor::supervise(
+                check,
+                generation,
+                self.model_generation.clone(),
+                self.commands_tx.clone(),
+            );
+        }
+        self.model = Some(model);
+        self.loaded_file_id = Some(file_id);
+        self.loaded_options = Some(options);
+        self.loaded_n_ctx = Some(n_ctx);
+    }
+
+    /// Reports the crash of the model, and loads it again after a while.
+    fn handle_model_crash(&mut self, generation: u64, reason: String) {
+        if generation != self.model_generation.load(Ordering::Acquire) {
+            return;
+        }
+        let Some(file_id) = self.loaded_file_id.clone() else {
+            return;
+        };
+        log::error!("model {file_id} crashed: {reason}");
+
+        self.events.publish(BackendEvent::ModelCrashed {
+            file_id: file_id.clone(),
+            reason: reason.clone(),
+            stderr: stderr::recent(),
+        });
+        self.chats.fail_all(MolyError::ModelCrashed(reason.clone()));
+        self.crashed_model = self
+            .model
+            .take()
+            .and_then(|model| model.stop_crashed(&self.async_rt));
+
+        if self
+            .last_restart
+            .is_some_and(|last_restart| last_restart.elapsed() > supervisor::HEALTHY_PERIOD)
+        {
+            self.restarts = 0;
+        }
+        match supervisor::restart_delay(self.restarts) {
+            Some(delay) => {
+                self.restarts += 1;
+                log::info!("restarting {file_id} in {}s", delay.as_secs());
+                supervisor::restart_after(delay, generation, self.commands_tx.clone());
+            }
+            None => {
+                let error = format!("crashed {} times in a row: {reason}", self.restarts + 1);
+                self.events
+                    .publish(BackendEvent::ModelLoadFailed(file_id.clone(), error));
+                self.crashed_model = None;
+                self.events.set_local_server_port(None);
+                self.loaded_file_id = None;
+                self.loaded_options = None;
+                self.events.publish(BackendEvent::ModelEjected(file_id));
+            }
+        }
+    }
+
+    fn restart_model(&mut self, generation: u64) {
+        if generation != self.model_generation.load(Ordering::Acquire) || self.model.is_some() {
+            return;
+        }
+        let (Some(file_id), Some(options)) =
+            (self.loaded_file_id.clone(), self.loaded_options.clone())
+        else {
+            return;
+        };
+
+        self.last_restart = Some(std::time::Instant::now());
+        // The outcome is broadcast, nobody waits for the response.
+        let (tx, _rx) = std::sync::mpsc::channel();
+        self.load_model(file_id, options, tx);
+    }
+
+    /// Hands the chat requests that can run now to the model.
+    fn start_chats(&mut self) {
+        let Some(model) = &self.model else {
+            return;
+        };
+
+        for chat in self.chats.start_next() {
+            let (relay_tx, relay_rx) = std::sync::mpsc::channel::<anyhow::Result<ChatResponse>>();
+            let commands_tx = self.commands_tx.clone();
+            let tx = chat.tx;
+            let request_id = chat.id;
+
+            // The model drops its sender once the request is answered.
+            std::thread::spawn(move || {
+                for response in relay_rx {
+                    let _ = tx.send(response);
+                }
+                let _ = commands_tx.send(BuiltInCommand::Interaction(
+                    ModelInteractionCommand::ChatFinished(request_id),
+                ));
+            });
+
+            model.chat(&self.async_rt, chat.data, relay_tx, chat.cancel);
+        }
+    }
+
+    /// Ejects the model once idle, and preloads the next file of the queue.
+    fn tick(&mut self, now: std::time::Instant) {
+        let busy = !self.chats.is_empty();
+        if let Some(last) = self.model.as_ref().and_then(|m| m.last_client_activity()) {
+            self.residency.touch(last);
         }
+
+        if self.model.is_some() && self.residency.is_idle(now, busy) {
+            if let Some(file_id) = self.loaded_file_id.clone() {
+                log::info!("ejecting {file_id}, idle for too long");
+                self.events.publish(BackendEvent::ModelIdle(file_id));
+            }
+            self.eject_model();
+        }
+
+        match self.residency.next_preload(now, busy, self.model.is_some()) {
+            Some(Preload::Load(file_id)) => {
+                log::info!("preloading {file_id}");
+                let options = self.default_load_options(&file_id);
+                // The outcome is broadcast, nobody waits for the response.
+                let (tx, _rx) = std::sync::mpsc::channel();
+                self.residency.touch(now);
+                self.load_model(file_id, options, tx);
+            }
+            Some(Preload::Warm(file_id)) => self.warm_file(file_id),
+            None => {}
+        }
+    }
+
+    /// The options of the default load preset of the file, if it has one.
+    fn default_load_options(&self, file_id: &FileID) -> LoadModelOptions {
+        let conn = self.sql_conn.lock().unwrap();
+        store::load_presets::get_load_presets(&conn, file_id)
+            .ok()
+            .and_then(|presets| presets.into_iter().find(|preset| preset.is_default))
+            .map(|preset| preset.options)
+            .unwrap_or_default()
+    }
+
+    /// Reads the parts of the file and its projector ahead, out of the backend thread.
+    fn warm_file(&self, file_id: FileID) {
+        let download_file = {
+            let conn = self.sql_conn.lock().unwrap();
+            store::download_files::DownloadedFile::get_by_id(&conn, &file_id)
+        };
+        let Ok(file) = download_file else {
+            log::warn!("can't preload {file_id}, it is not downloaded");
+            return;
+        };
+
+        let dir = Path::new(&file.download_dir).join(&file.model_id);
+        let paths: Vec<_> = file
+            .files_on_disk()
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        let events = self.events.clone();
+        std::thread::spawn(move || {
+            for path in paths {
+                if let Err(e) = residency::warm_file(&path) {
+                    log::warn!("can't read {} ahead: {e}", path.display());
+                    return;
+                }
+            }
+            events.publish(BackendEvent::FileWarmed(file_id));
+        });
+    }
+
+    /// Forwards the responses of a model load to `tx`, and broadcasts its outcome.
+    /// Once loaded, the resources used are reported to `tx` until the model loaded as
+    /// `generation` is replaced.
+    fn relay_load_events(
+        &self,
+        file_id: FileID,
+        generation: u64,
+        tx: Sender<anyhow::Result<LoadModelResponse>>,
+    ) -> Sender<anyhow::Result<LoadModelResponse>> {
+        let (relay_tx, relay_rx) =
+            std::sync::mpsc::channel::<anyhow::Result<LoadModelResponse>>();
+        let events = self.events.clone();
+        let current_generation = self.model_generation.clone();
+
+        std::thread::spawn(move || {
+            for response in relay_rx {
+                match &response {
+                    Ok(LoadModelResponse::Completed(info)) => {
+                        events.publish(BackendEvent::ModelLoaded(info.clone()));
+                        // Engines without a server report no port.
+                        events.set_local_server_port(
+                            Some(info.listen_port).filter(|port| *port != 0),
+                        );
+                        resources::report_usage(
+                            tx.clone(),
+                            generation,
+                            current_generation.clone(),
+                        );
+                    }
+                    Err(e) => {
+                        events.publish(BackendEvent::ModelLoadFailed(
+                            file_id.clone(),
+                            e.to_string(),
+                        ));
+                        events.set_local_server_port(None);
+                    }
+                    Ok(_) => {}
+                }
+                let _ = tx.send(response);
+            }
+        });
+
+        relay_tx
     }
 
     pub fn update_models_dir<M: AsRef<Path>>(&mut self, models_dir: M) {
@@ -707,11 +1621,19 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
     }
 
     fn run_loop(&mut self) {
+        let mut next_tick = std::time::Instant::now() + residency::TICK_INTERVAL;
         loop {
-            if let Ok(cmd) = self.rx.recv() {
-                self.handle_command(cmd.into());
-            } else {
-                break;
+            let timeout = next_tick.saturating_duration_since(std::time::Instant::now());
+            match self.rx.recv_timeout(timeout) {
+                Ok(BuiltInCommand::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                Ok(cmd) => self.handle_command(cmd),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            let now = std::time::Instant::now();
+            if now >= next_tick {
+                self.tick(now);
+                next_tick = now + residency::TICK_INTERVAL;
             }
         }
 
@@ -719,10 +1641,28 @@ impl<Model: BackendModel + Send + 'static> BackendImpl<Model> {
     }
 }
 
+fn read_vocab(
+    sql_conn: &Mutex<rusqlite::Connection>,
+    file_id: &FileID,
+) -> Result<Arc<Vocab>, MolyError> {
+    let file = {
+        let conn = sql_conn.lock().unwrap();
+        store::download_files::DownloadedFile::get_by_id(&conn, file_id)
+    }
+    .map_err(|e| store::file_error(file_id, e))?;
+    let file_path = Path::new(&file.download_dir)
+        .join(&file.model_id)
+        .join(&file.name);
+    let vocab = Vocab::read(&file_path)
+        .map_err(|e| MolyError::Io(format!("{}: {e}", file_path.display())))?;
+    Ok(Arc::new(vocab))
+}
+
 pub fn nn_preload_file(
     file: &store::download_files::DownloadedFile,
     embedding: Option<(PathBuf, u64)>,
 ) {
+    // For a sharded file `name` is the first part, llama.cpp finds the others next to it.
     let file_path = Path::new(&file.download_dir)
         .join(&file.model_id)
         .join(&file.name);
@@ -747,3 +1687,133 @@ pub fn nn_preload_file(
 
     wasmedge_sdk::plugin::PluginManager::nn_preload(preload_vec);
 }
+
+/// The arguments of the llama.cpp options shared by the wasm apps, besides the context
+/// and batch sizes. They are only passed when they differ from the llama.cpp defaults,
+/// so builds of the apps that don't know them still start with the defaults.
+pub fn llama_cpp_args(options: &LoadModelOptions, adapters: &[(PathBuf, f32)]) -> Vec<String> {
+    let mut args = vec![];
+    let mut add_arg = |flag: &str, value: Option<String>| {
+        args.push(flag.to_string());
+        args.extend(value);
+    };
+
+    if let Some(n_threads) = options.n_threads {
+        add_arg("--threads", Some(n_threads.to_string()));
+    }
+    if let Some(n_ubatch) = options.n_ubatch {
+        add_arg("--ubatch-size", Some(n_ubatch.to_string()));
+    }
+    if options.use_mlock {
+        add_arg("--mlock", None);
+    }
+    if options.rope_freq_base != 0.0 {
+        add_arg("--rope-freq-base", Some(options.rope_freq_base.to_string()));
+    }
+    if options.rope_freq_scale != 0.0 {
+        add_arg("--rope-freq-scale", Some(options.rope_freq_scale.to_string()));
+    }
+    if options.flash_attention {
+        add_arg("--flash-attn", None);
+    }
+    if let Some(cache_type) = options.kv_cache_type {
+        add_arg("--cache-type-k", Some(cache_type.as_str().to_string()));
+        add_arg("--cache-type-v", Some(cache_type.as_str().to_string()));
+    }
+    if options.split_mode != SplitMode::default() {
+        add_arg("--split-mode", Some(options.split_mode.as_str().to_string()));
+    }
+    for (path, scale) in adapters {
+        args.extend([
+            "--lora-scaled".to_string(),
+            path.to_string_lossy().to_string(),
+            scale.to_string(),
+        ]);
+    }
+
+    args
+}
+
+#[test]
+fn test_llama_cpp_args() {
+    let mut options = LoadModelOptions {
+        split_mode: SplitMode::Layer,
+        ..Default::default()
+    };
+    assert!(llama_cpp_args(&options, &[]).is_empty());
+
+    options.n_threads = Some(4);
+    options.use_mlock = true;
+    options.rope_freq_base = 10000.0;
+    options.kv_cache_type = Some(moly_protocol::protocol::KvCacheType::Q8_0);
+    options.split_mode = SplitMode::Row;
+    let adapters = [(PathBuf::from("/models/style.gguf"), 0.5)];
+    assert_eq!(
+        llama_cpp_args(&options, &adapters),
+        [
+            "--threads",
+            "4",
+            "--mlock",
+            "--rope-freq-base",
+            "10000",
+            "--cache-type-k",
+            "q8_0",
+            "--cache-type-v",
+            "q8_0",
+            "--split-mode",
+            "row",
+            "--lora-scaled",
+            "/models/style.gguf",
+            "0.5"
+        ]
+    );
+}
+
+/// The error of a model the runtime failed to load: [`MolyError::OutOfMemory`] when it
+/// couldn't allocate the model, told by the reason of the failure or by what the runtime
+/// wrote to stderr since `stderr_mark`, see [`stderr::mark`].
+pub fn load_error(file_id: FileID, reason: String, stderr_mark: usize) -> MolyError {
+    // From llama.cpp, ggml and the C++ runtime.
+    const ALLOCATION_FAILURES: &[&str] = &[
+        "out of memory",
+        "failed to allocate",
+        "unable to allocate",
+        "cannot allocate memory",
+        "bad_alloc",
+    ];
+
+    let stderr = stderr::since(stderr_mark);
+    let allocation_failure = reason.lines().chain(stderr.lines()).find(|line| {
+        let line = line.to_lowercase();
+        ALLOCATION_FAILURES.iter().any(|failure| line.contains(failure))
+    });
+    match allocation_failure {
+        Some(line) => MolyError::OutOfMemory(line.trim().to_string()),
+        None => MolyError::ModelLoadFailed { file_id, reason },
+    }
+}
+
+#[test]
+fn test_load_error() {
+    let error = load_error(
+        "model#file.gguf".to_string(),
+        "the model server failed: llama_model_load: error loading model\n\
+        ggml_backend_cpu_buffer_type_alloc_buffer: failed to allocate buffer of size 8.5 GB"
+            .to_string(),
+        stderr::mark(),
+    );
+    assert_eq!(
+        error,
+        MolyError::OutOfMemory(
+            "ggml_backend_cpu_buffer_type_alloc_buffer: failed to allocate buffer of size 8.5 GB"
+                .to_string()
+        )
+    );
+
+    let error = load_error(
+        "model#file.gguf".to_string(),
+        "the model server did not start".to_string(),
+        stderr::mark(),
+    );
+    assert_eq!(error.code(), "model_load_failed");
+}
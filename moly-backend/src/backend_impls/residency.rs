@@ -0,0 +1,148 @@
+//! How long models stay loaded: the loaded model is ejected after a while without chats
+//! or requests to its server, and the models of the preload queue are loaded once the
+//! backend settles after startup.
+
+use std::{
+    collections::VecDeque,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use moly_protocol::data::FileID;
+
+/// How often the backend checks whether the model is idle or something can be preloaded.
+pub const TICK_INTERVAL: Duration = Duration::from_secs(5);
+/// The preload waits this long after startup, for the first commands of the app to be
+/// answered before.
+const SETTLE_DELAY: Duration = Duration::from_secs(10);
+
+pub struct Residency {
+    idle_timeout: Option<Duration>,
+    last_activity: Instant,
+    started_at: Instant,
+    preload: VecDeque<FileID>,
+    // Only the first file of the queue is loaded, the other ones are read ahead.
+    loaded_first: bool,
+}
+
+/// What to do with the next file of the preload queue.
+#[derive(Debug, PartialEq)]
+pub enum Preload {
+    Load(FileID),
+    Warm(FileID),
+}
+
+impl Residency {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            idle_timeout: None,
+            last_activity: now,
+            started_at: now,
+            preload: VecDeque::new(),
+            loaded_first: false,
+        }
+    }
+
+    /// The timeout starts over from now.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>, now: Instant) {
+        self.idle_timeout = idle_timeout;
+        self.last_activity = now;
+    }
+
+    /// Something used the model: a load, a chat or its end, or a request of a client of
+    /// its server.
+    pub fn touch(&mut self, now: Instant) {
+        self.last_activity = self.last_activity.max(now);
+    }
+
+    /// Whether the model should be ejected. A busy model is never idle.
+    pub fn is_idle(&self, now: Instant, busy: bool) -> bool {
+        !busy
+            && self
+                .idle_timeout
+                .is_some_and(|timeout| now.duration_since(self.last_activity) >= timeout)
+    }
+
+    /// Replaces the files waiting to be preloaded.
+    pub fn set_preload(&mut self, file_ids: Vec<FileID>) {
+        self.preload = file_ids.into();
+        self.loaded_first = false;
+    }
+
+    /// The next file of the queue, once the backend settled and while it is not busy.
+    /// The first one is loaded only when `loaded` is false, a model loaded in the
+    /// meantime is kept.
+    pub fn next_preload(&mut self, now: Instant, busy: bool, loaded: bool) -> Option<Preload> {
+        if busy || now.duration_since(self.started_at) < SETTLE_DELAY {
+            return None;
+        }
+        let file_id = self.preload.pop_front()?;
+        let load = !self.loaded_first && !loaded;
+        self.loaded_first = true;
+        Some(if load {
+            Preload::Load(file_id)
+        } else {
+            Preload::Warm(file_id)
+        })
+    }
+}
+
+/// Asks the system to read the file in its cache, such that the runtime finds it there.
+#[cfg(target_os = "linux")]
+pub fn warm_file(path: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::File::open(path)?;
+    // SAFETY: the descriptor is valid while `file` lives.
+    let res = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_WILLNEED) };
+    if res != 0 {
+        return Err(std::io::Error::from_raw_os_error(res));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn warm_file(path: &Path) -> std::io::Result<()> {
+    let mut file = std::fs::File::open(path)?;
+    std::io::copy(&mut file, &mut std::io::sink())?;
+    Ok(())
+}
+
+#[test]
+fn test_residency() {
+    let start = Instant::now();
+    let mut residency = Residency::new(start);
+    let later = |secs| start + Duration::from_secs(secs);
+
+    assert!(!residency.is_idle(later(3600), false));
+    residency.set_idle_timeout(Some(Duration::from_secs(300)), start);
+    assert!(!residency.is_idle(later(299), false));
+    assert!(residency.is_idle(later(300), false));
+    assert!(!residency.is_idle(later(300), true));
+    residency.touch(later(200));
+    assert!(!residency.is_idle(later(300), false));
+    // An older activity, like the last request of a client of the server, is ignored.
+    residency.touch(later(100));
+    assert!(!residency.is_idle(later(499), false));
+    assert!(residency.is_idle(later(500), false));
+
+    residency.set_preload(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+    assert_eq!(residency.next_preload(later(5), false, false), None);
+    assert_eq!(residency.next_preload(later(20), true, false), None);
+    assert_eq!(
+        residency.next_preload(later(20), false, false),
+        Some(Preload::Load("a".to_string()))
+    );
+    assert_eq!(
+        residency.next_preload(later(25), false, false),
+        Some(Preload::Warm("b".to_string()))
+    );
+
+    // A model loaded before the preload is kept.
+    residency.set_preload(vec!["a".to_string()]);
+    assert_eq!(
+        residency.next_preload(later(30), false, true),
+        Some(Preload::Warm("a".to_string()))
+    );
+    assert_eq!(residency.next_preload(later(35), false, false), None);
+}
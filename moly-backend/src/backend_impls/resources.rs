@@ -0,0 +1,93 @@
+//! Samples of the memory and CPU used by the process, where the model runtimes run,
+//! and of the memory left in the system.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+
+use moly_protocol::protocol::{LoadModelResponse, ModelResourcesInfo};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Sends a sample to `tx` periodically, while the model loaded as `generation` stays
+/// loaded and `tx` is listened to.
+pub fn report_usage(
+    tx: Sender<anyhow::Result<LoadModelResponse>>,
+    generation: u64,
+    current_generation: Arc<AtomicU64>,
+) {
+    std::thread::spawn(move || {
+        let mut sampler = Sampler::new();
+        loop {
+            std::thread::sleep(SAMPLE_INTERVAL);
+            if current_generation.load(Ordering::Acquire) != generation {
+                return;
+            }
+            let Some(info) = sampler.sample() else {
+                return;
+            };
+            if tx
+                .send(Ok(LoadModelResponse::ModelResourcesUsage(info)))
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
+}
+
+struct Sampler {
+    system: sysinfo::System,
+    pid: Option<sysinfo::Pid>,
+}
+
+impl Sampler {
+    fn new() -> Self {
+        let mut sampler = Self {
+            system: sysinfo::System::new(),
+            pid: sysinfo::get_current_pid().ok(),
+        };
+        // The CPU usage is measured from the previous refresh.
+        sampler.refresh();
+        sampler
+    }
+
+    fn refresh(&mut self) -> Option<&sysinfo::Process> {
+        let pid = self.pid?;
+        self.system.refresh_processes_specifics(
+            sysinfo::ProcessesToUpdate::Some(&[pid]),
+            false,
+            sysinfo::ProcessRefreshKind::new().with_memory().with_cpu(),
+        );
+        self.system.process(pid)
+    }
+
+    fn sample(&mut self) -> Option<ModelResourcesInfo> {
+        let process = self.refresh()?;
+        Some(ModelResourcesInfo {
+            ram_usage: process.memory() as f32 / (1024.0 * 1024.0 * 1024.0),
+            cpu_usage: process.cpu_usage(),
+        })
+    }
+}
+
+/// The memory that can be used without swapping, in bytes.
+pub fn available_memory() -> Option<u64> {
+    let mut system = sysinfo::System::new();
+    system.refresh_memory();
+    Some(system.available_memory()).filter(|&bytes| bytes > 0)
+}
+
+#[test]
+fn test_available_memory() {
+    assert!(available_memory().is_some_and(|bytes| bytes > 0));
+}
+
+#[test]
+fn test_sample() {
+    let mut sampler = Sampler::new();
+    let info = sampler.sample().unwrap();
+    assert!(info.ram_usage > 0.0);
+    assert!(info.cpu_usage >= 0.0);
+}
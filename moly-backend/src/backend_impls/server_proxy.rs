@@ -0,0 +1,215 @@
+//! Forwards the connections to the port of a model server to the server itself, which
+//! listens on another port, to know when the model is used by clients other than the
+//! backend.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub struct ServerProxy {
+    listen_addr: SocketAddr,
+    server_addr: Arc<Mutex<SocketAddr>>,
+    traffic: Arc<Traffic>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct Traffic {
+    // Connections with a request the server didn't start to answer yet.
+    waiting: AtomicUsize,
+    last_activity: Mutex<Option<Instant>>,
+}
+
+impl Traffic {
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+/// The traffic of a connection: a request is waiting from the first bytes sent by the
+/// client to the first bytes of the answer.
+struct Exchange {
+    traffic: Arc<Traffic>,
+    waiting: AtomicBool,
+}
+
+impl Exchange {
+    fn request(&self) {
+        self.traffic.touch();
+        if !self.waiting.swap(true, Ordering::AcqRel) {
+            self.traffic.waiting.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    fn answer(&self) {
+        self.traffic.touch();
+        if self.waiting.swap(false, Ordering::AcqRel) {
+            self.traffic.waiting.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        self.answer();
+    }
+}
+
+impl ServerProxy {
+    /// Listens on `listen_addr` and forwards the connections to `server_addr`.
+    pub fn bind(
+        async_rt: &tokio::runtime::Runtime,
+        listen_addr: SocketAddr,
+        server_addr: SocketAddr,
+    ) -> std::io::Result<Self> {
+        let listener = bind(listen_addr)?;
+        let listen_addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        let listener = {
+            let _guard = async_rt.enter();
+            TcpListener::from_std(listener)?
+        };
+
+        let server_addr = Arc::new(Mutex::new(server_addr));
+        let traffic = Arc::new(Traffic::default());
+
+        let server_addr_ = server_addr.clone();
+        let traffic_ = traffic.clone();
+        let task = async_rt.spawn(async move {
+            loop {
+                let client = match listener.accept().await {
+                    Ok((client, _)) => client,
+                    Err(e) => {
+                        log::warn!("Failed to accept a connection to the model server: {e}");
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let server_addr = *server_addr_.lock().unwrap();
+                let exchange = Exchange {
+                    traffic: traffic_.clone(),
+                    waiting: AtomicBool::new(false),
+                };
+                tokio::spawn(async move {
+                    if let Ok(server) = TcpStream::connect(server_addr).await {
+                        forward_connection(client, server, &exchange).await;
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            listen_addr,
+            server_addr,
+            traffic,
+            task,
+        })
+    }
+
+    pub fn listen_addr(&self) -> SocketAddr {
+        self.listen_addr
+    }
+
+    /// The next connections go to `server_addr`, when the server restarts on another port.
+    pub fn set_server_addr(&self, server_addr: SocketAddr) {
+        *self.server_addr.lock().unwrap() = server_addr;
+    }
+
+    /// When a client last sent or received something, now while one waits for an answer.
+    pub fn last_activity(&self) -> Option<Instant> {
+        if self.traffic.waiting.load(Ordering::Acquire) > 0 {
+            return Some(Instant::now());
+        }
+        *self.traffic.last_activity.lock().unwrap()
+    }
+}
+
+impl Drop for ServerProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The listener of a previous proxy on the same address is closed asynchronously, once
+/// its task is aborted, so the address may still be in use for a moment.
+fn bind(addr: SocketAddr) -> std::io::Result<std::net::TcpListener> {
+    const ATTEMPTS: u32 = 10;
+    let mut attempt = 1;
+    loop {
+        match std::net::TcpListener::bind(addr) {
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && attempt < ATTEMPTS => {
+                attempt += 1;
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            r => return r,
+        }
+    }
+}
+
+async fn forward_connection(client: TcpStream, server: TcpStream, exchange: &Exchange) {
+    let (client_read, client_write) = client.into_split();
+    let (server_read, server_write) = server.into_split();
+    let _ = tokio::join!(
+        forward(client_read, server_write, || exchange.request()),
+        forward(server_read, client_write, || exchange.answer()),
+    );
+}
+
+async fn forward(
+    mut from: impl AsyncRead + Unpin,
+    mut to: impl AsyncWrite + Unpin,
+    on_data: impl Fn(),
+) -> std::io::Result<()> {
+    let mut buf = vec![0; 16 * 1024];
+    loop {
+        let n = from.read(&mut buf).await?;
+        if n == 0 {
+            return to.shutdown().await;
+        }
+        on_data();
+        to.write_all(&buf[..n]).await?;
+    }
+}
+
+#[test]
+fn test_proxy_tracks_requests() {
+    use std::io::{Read, Write};
+
+    let async_rt = tokio::runtime::Runtime::new().unwrap();
+
+    // Answers a request once told to.
+    let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let (answer_tx, answer_rx) = std::sync::mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        let (mut stream, _) = server.accept().unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).unwrap();
+        answer_rx.recv().unwrap();
+        stream.write_all(b"pong").unwrap();
+    });
+
+    let proxy = ServerProxy::bind(&async_rt, "127.0.0.1:0".parse().unwrap(), server_addr).unwrap();
+    assert!(proxy.last_activity().is_none());
+
+    let mut client = std::net::TcpStream::connect(proxy.listen_addr()).unwrap();
+    client.write_all(b"ping").unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    // Waiting for the answer counts as activity.
+    let before = Instant::now();
+    assert!(proxy.last_activity().unwrap() >= before);
+
+    answer_tx.send(()).unwrap();
+    let mut buf = [0; 4];
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"pong");
+    std::thread::sleep(Duration::from_millis(100));
+
+    let last_activity = proxy.last_activity().unwrap();
+    assert!(last_activity < Instant::now());
+    assert_eq!(proxy.last_activity(), Some(last_activity));
+}
@@ -0,0 +1,149 @@
+//! Incremental decoder of Server-Sent Events, for the streamed responses of
+//! HTTP-based models. See https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+/// An event of the stream. `data` joins the `data:` lines of the event with `\n`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SseEvent {
+    /// The `event:` field, `message` when there is none.
+    pub event: String,
+    pub data: String,
+    pub id: Option<String>,
+}
+
+/// Turns the bytes of a response, in chunks of any size, into events.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    // The last chunk ended with `\r`, a `\n` at the start of the next one belongs to it.
+    skip_lf: bool,
+    started: bool,
+    event: Option<String>,
+    data: Option<String>,
+    id: Option<String>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the next chunk of the stream, returning the events it completes.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        let mut chunk = chunk;
+        if self.skip_lf {
+            self.skip_lf = false;
+            if let Some(rest) = chunk.strip_prefix(b"\n") {
+                chunk = rest;
+            }
+        }
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = vec![];
+        let mut start = 0;
+        let mut i = 0;
+        while i < self.buffer.len() {
+            let end_len = match self.buffer[i] {
+                b'\n' => 1,
+                b'\r' => match self.buffer.get(i + 1) {
+                    Some(b'\n') => 2,
+                    Some(_) => 1,
+                    None => {
+                        self.skip_lf = true;
+                        1
+                    }
+                },
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            let line = String::from_utf8_lossy(&self.buffer[start..i]).into_owned();
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+            i += end_len;
+            start = i;
+        }
+        self.buffer.drain(..start);
+
+        events
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        let line = if self.started {
+            line
+        } else {
+            self.started = true;
+            line.strip_prefix('\u{feff}').unwrap_or(line)
+        };
+
+        if line.is_empty() {
+            let event = self.event.take();
+            let id = self.id.take();
+            return self.data.take().map(|data| SseEvent {
+                event: event.unwrap_or_else(|| "message".to_string()),
+                data,
+                id,
+            });
+        }
+
+        if line.starts_with(':') {
+            // A comment, e.g. a keep-alive.
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        match field {
+            "data" => match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "event" => self.event = Some(value.to_string()),
+            "id" => self.id = Some(value.to_string()),
+            // `retry` only matters to clients that reconnect.
+            _ => {}
+        }
+
+        None
+    }
+}
+
+#[test]
+fn test_sse_decoder() {
+    let mut decoder = SseDecoder::new();
+
+    // An event split across chunks, then two in a single chunk.
+    assert!(decoder.push(b"\xef\xbb\xbfdata: {\"a\":").is_empty());
+    let events = decoder.push(b" 1}\n\ndata: {\"b\": 2}\r\n\r\n: keep-alive\n\ndata:[DONE]\n\n");
+    assert_eq!(
+        events.iter().map(|e| e.data.as_str()).collect::<Vec<_>>(),
+        vec!["{\"a\": 1}", "{\"b\": 2}", "[DONE]"]
+    );
+    assert!(events.iter().all(|e| e.event == "message"));
+
+    // Multi-line data, an event type, and a `\r\n` split between chunks.
+    assert!(decoder
+        .push(b"event: error\nid: 7\ndata: first\ndata: second\r")
+        .is_empty());
+    let events = decoder.push(b"\n\r\n");
+    assert_eq!(
+        events,
+        vec![SseEvent {
+            event: "error".to_string(),
+            data: "first\nsecond".to_string(),
+            id: Some("7".to_string()),
+        }]
+    );
+
+    // Fields without data don't make an event.
+    assert!(decoder.push(b"event: ping\n\n").is_empty());
+    assert!(decoder.push(b"data: x\n\n")[0].event == "message");
+}
@@ -0,0 +1,199 @@
+//! Keeps the last lines written to the stderr of the process, where the model runtimes
+//! and llama.cpp write their logs, to report them when a model crashes.
+//!
+//! The runtimes run within the process, so their output can only be told apart from the
+//! rest of stderr in a process that doesn't write anything else there, like the daemon.
+//! Capturing is then left to the binaries, with [`capture`]. Everything is still written
+//! to the original stderr.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+const MAX_LINES: usize = 100;
+const MAX_LINE_LEN: usize = 1024;
+
+static RECENT_LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+// How many lines were captured, kept ones or not.
+static CAPTURED_LINES: AtomicUsize = AtomicUsize::new(0);
+
+/// Captures the stderr of the process while it is alive. Dropping it puts the original
+/// stderr back and waits for what was already written to be copied to it.
+pub struct StderrCapture {
+    #[cfg(unix)]
+    original: std::os::fd::OwnedFd,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+/// Starts capturing stderr, to include its last lines in [`BackendEvent::ModelCrashed`].
+/// Only one capture can be alive at a time, and it is only supported on Unix.
+///
+/// [`BackendEvent::ModelCrashed`]: moly_protocol::protocol::BackendEvent::ModelCrashed
+pub fn capture() -> std::io::Result<StderrCapture> {
+    if CAPTURING.swap(true, Ordering::AcqRel) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "stderr is already captured",
+        ));
+    }
+
+    #[cfg(unix)]
+    let r = tee_stderr();
+    #[cfg(not(unix))]
+    let r = Err(std::io::ErrorKind::Unsupported.into());
+
+    if r.is_err() {
+        CAPTURING.store(false, Ordering::Release);
+    }
+    r
+}
+
+/// The last lines captured, oldest first. Empty if stderr was never captured.
+pub fn recent() -> String {
+    let lines = RECENT_LINES.lock().unwrap_or_else(|e| e.into_inner());
+    lines
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Marks the current end of the captured lines, see [`since`].
+pub fn mark() -> usize {
+    CAPTURED_LINES.load(Ordering::Acquire)
+}
+
+/// The lines captured after `mark`, the ones still kept.
+pub fn since(mark: usize) -> String {
+    let lines = RECENT_LINES.lock().unwrap_or_else(|e| e.into_inner());
+    let count = CAPTURED_LINES.load(Ordering::Acquire).saturating_sub(mark);
+    let skipped = lines.len().saturating_sub(count);
+    lines
+        .iter()
+        .skip(skipped)
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn push_line(line: &[u8]) {
+    let line = String::from_utf8_lossy(&line[..line.len().min(MAX_LINE_LEN)]);
+    let mut lines = RECENT_LINES.lock().unwrap_or_else(|e| e.into_inner());
+    if lines.len() == MAX_LINES {
+        lines.pop_front();
+    }
+    lines.push_back(line.trim_end().to_string());
+    CAPTURED_LINES.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Replaces stderr with a pipe, copied to the original stderr by a thread.
+#[cfg(unix)]
+fn tee_stderr() -> std::io::Result<StderrCapture> {
+    use std::fs::File;
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    let mut fds = [0; 2];
+    // SAFETY: plain calls on file descriptors owned by this function.
+    let (original, copy) = unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let original = libc::dup(libc::STDERR_FILENO);
+        let copy = if original >= 0 {
+            libc::dup(original)
+        } else {
+            -1
+        };
+        if copy < 0 || libc::dup2(fds[1], libc::STDERR_FILENO) < 0 {
+            let e = std::io::Error::last_os_error();
+            for fd in [fds[0], fds[1], original, copy] {
+                if fd >= 0 {
+                    libc::close(fd);
+                }
+            }
+            return Err(e);
+        }
+        // Stderr is now the only write end, so the pipe closes when it is put back.
+        libc::close(fds[1]);
+        (OwnedFd::from_raw_fd(original), copy)
+    };
+
+    // SAFETY: both descriptors are open and not used anywhere else.
+    let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+    let mut writer = unsafe { File::from_raw_fd(copy) };
+
+    let thread = std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        let mut line = Vec::new();
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            // The copy goes on even if the original stderr is gone.
+            let _ = writer.write_all(&buf[..n]);
+
+            for &byte in &buf[..n] {
+                if byte == b'\n' {
+                    push_line(&line);
+                    line.clear();
+                } else if line.len() < MAX_LINE_LEN {
+                    line.push(byte);
+                }
+            }
+        }
+        if !line.is_empty() {
+            push_line(&line);
+        }
+        // Nobody reads the pipe anymore, writers would block once it is full.
+        // SAFETY: `writer` is an open descriptor of the original stderr.
+        unsafe { libc::dup2(writer.as_raw_fd(), libc::STDERR_FILENO) };
+    });
+
+    Ok(StderrCapture {
+        original,
+        thread: Some(thread),
+    })
+}
+
+impl Drop for StderrCapture {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsRawFd;
+            // SAFETY: `original` is an open descriptor of the original stderr.
+            unsafe { libc::dup2(self.original.as_raw_fd(), libc::STDERR_FILENO) };
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        CAPTURING.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_capture_keeps_the_last_lines() {
+    let guard = capture().unwrap();
+    assert!(capture().is_err());
+    let mark = mark();
+
+    // Written to the descriptor, as the runtimes do, the test harness only captures the
+    // `eprintln!` of the tests. The last line has no newline and is kept when dropping.
+    let text = b"moly stderr test line\nmoly stderr test last line";
+    // SAFETY: writes a buffer to the stderr descriptor.
+    unsafe { libc::write(libc::STDERR_FILENO, text.as_ptr().cast(), text.len()) };
+    drop(guard);
+
+    let recent = recent();
+    assert!(recent.ends_with("moly stderr test line\nmoly stderr test last line"));
+    assert_eq!(
+        since(mark),
+        "moly stderr test line\nmoly stderr test last line"
+    );
+    capture().unwrap();
+}
@@ -0,0 +1,209 @@
+//! Checks of the chats constrained to JSON: the request before it is sent to the model,
+//! and the answer against the JSON schema of the request.
+
+use jsonschema::{JSONSchema, ValidationError};
+use moly_protocol::{
+    open_ai::{ChatRequestData, ResponseFormat},
+    protocol::MolyError,
+};
+use serde_json::Value;
+
+/// Whether the request asks for constrained output.
+pub fn is_constrained(data: &ChatRequestData) -> bool {
+    data.grammar.is_some()
+        || data
+            .response_format
+            .as_ref()
+            .is_some_and(|format| *format != ResponseFormat::Text)
+}
+
+pub fn check_request(data: &ChatRequestData) -> Result<(), MolyError> {
+    let format = data
+        .response_format
+        .as_ref()
+        .unwrap_or(&ResponseFormat::Text);
+    if data.grammar.is_some() && *format != ResponseFormat::Text {
+        return Err(MolyError::InvalidResponseFormat(
+            "a grammar can't be combined with a JSON response format".to_string(),
+        ));
+    }
+    if data.grammar.as_ref().is_some_and(|g| g.trim().is_empty()) {
+        return Err(MolyError::InvalidResponseFormat(
+            "the grammar is empty".to_string(),
+        ));
+    }
+    if let ResponseFormat::JsonSchema { json_schema } = format {
+        if !json_schema.schema.is_object() && !json_schema.schema.is_boolean() {
+            return Err(MolyError::InvalidResponseFormat(format!(
+                "the schema {} is not an object",
+                json_schema.name
+            )));
+        }
+        if let Some(reference) = external_reference(&json_schema.schema) {
+            return Err(MolyError::InvalidResponseFormat(format!(
+                "the reference {reference} to another document can't be resolved"
+            )));
+        }
+        compile(&json_schema.schema)?;
+    }
+    Ok(())
+}
+
+/// Checks the whole answer of the model against the format of the request.
+pub fn check_output(format: &ResponseFormat, output: &str) -> Result<(), MolyError> {
+    let schema = match format {
+        ResponseFormat::Text => return Ok(()),
+        ResponseFormat::JsonObject => None,
+        ResponseFormat::JsonSchema { json_schema } => Some(&json_schema.schema),
+    };
+
+    let value = serde_json::from_str::<Value>(output.trim())
+        .map_err(|e| MolyError::SchemaMismatch(format!("the answer is not JSON: {e}")))?;
+    match schema {
+        Some(schema) => validate(schema, &value),
+        None if value.is_object() => Ok(()),
+        None => Err(MolyError::SchemaMismatch(
+            "the answer is not a JSON object".to_string(),
+        )),
+    }
+}
+
+/// Compiles the schema of a request, checking every keyword including the formats.
+/// A schema that can't be checked, e.g. with an unknown format or a reference to another
+/// document, is rejected rather than partly checked.
+fn compile(schema: &Value) -> Result<JSONSchema, MolyError> {
+    JSONSchema::options()
+        .should_validate_formats(true)
+        .should_ignore_unknown_formats(false)
+        .compile(schema)
+        .map_err(|e| MolyError::InvalidResponseFormat(located(&e)))
+}
+
+/// The first place where `value` doesn't match `schema`.
+fn validate(schema: &Value, value: &Value) -> Result<(), MolyError> {
+    let schema = compile(schema)?;
+    let result = schema.validate(value).map_err(|mut errors| {
+        let reason = errors.next().map(|e| located(&e)).unwrap_or_default();
+        MolyError::SchemaMismatch(reason)
+    });
+    result
+}
+
+/// A `$ref` of the schema that isn't within the schema itself.
+fn external_reference(schema: &Value) -> Option<&str> {
+    match schema {
+        Value::Object(object) => object
+            .get("$ref")
+            .and_then(Value::as_str)
+            .filter(|reference| !reference.starts_with('#'))
+            .or_else(|| object.values().find_map(external_reference)),
+        Value::Array(items) => items.iter().find_map(external_reference),
+        _ => None,
+    }
+}
+
+fn located(error: &ValidationError) -> String {
+    let path = error.instance_path.to_string();
+    if path.is_empty() {
+        error.to_string()
+    } else {
+        format!("{path}: {error}")
+    }
+}
+
+#[test]
+fn test_check_output() {
+    use moly_protocol::open_ai::JsonSchemaFormat;
+    use serde_json::json;
+
+    let format = ResponseFormat::JsonSchema {
+        json_schema: JsonSchemaFormat {
+            name: "person".to_string(),
+            description: None,
+            schema: json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string", "minLength": 1 },
+                    "age": { "type": "integer", "minimum": 0 },
+                    "tags": { "type": "array", "items": { "enum": ["a", "b"] } }
+                },
+                "required": ["name", "age"],
+                "additionalProperties": false
+            }),
+            strict: Some(true),
+        },
+    };
+
+    assert!(check_output(&format, r#" {"name": "Ada", "age": 36, "tags": ["a"]} "#).is_ok());
+    let mismatch = |output: &str| match check_output(&format, output) {
+        Err(MolyError::SchemaMismatch(reason)) => reason,
+        other => panic!("unexpected {other:?}"),
+    };
+    assert_eq!(
+        mismatch(r#"{"name": "Ada"}"#),
+        r#""age" is a required property"#
+    );
+    assert_eq!(
+        mismatch(r#"{"name": "Ada", "age": 3.5}"#),
+        r#"/age: 3.5 is not of type "integer""#
+    );
+    assert_eq!(
+        mismatch(r#"{"name": "Ada", "age": 36, "tags": ["c"]}"#),
+        r#"/tags/0: "c" is not one of ["a","b"]"#
+    );
+    assert!(mismatch(r#"{"name": "Ada", "age": 36, "email": ""}"#).contains("email"));
+    assert!(mismatch("Sure! Here is the JSON").starts_with("the answer is not JSON"));
+
+    assert!(check_output(&ResponseFormat::JsonObject, "{}").is_ok());
+    assert!(check_output(&ResponseFormat::JsonObject, "[1]").is_err());
+    assert!(check_output(&ResponseFormat::Text, "anything").is_ok());
+}
+
+#[test]
+fn test_check_output_formats_and_patterns() {
+    use moly_protocol::open_ai::JsonSchemaFormat;
+    use serde_json::json;
+
+    let format = |schema: Value| ResponseFormat::JsonSchema {
+        json_schema: JsonSchemaFormat {
+            name: "contact".to_string(),
+            description: None,
+            schema,
+            strict: None,
+        },
+    };
+    let contact = format(json!({
+        "type": "object",
+        "properties": {
+            "email": { "type": "string", "format": "email" },
+            "code": { "$ref": "#/$defs/code" }
+        },
+        "$defs": { "code": { "type": "string", "pattern": "^[A-Z]{3}$" } }
+    }));
+
+    assert!(check_output(&contact, r#"{"email": "ada@example.com", "code": "ADA"}"#).is_ok());
+    assert!(matches!(
+        check_output(&contact, r#"{"email": "ada"}"#),
+        Err(MolyError::SchemaMismatch(_))
+    ));
+    assert!(matches!(
+        check_output(&contact, r#"{"code": "ada"}"#),
+        Err(MolyError::SchemaMismatch(_))
+    ));
+
+    // The schemas that can't be fully checked are refused with the request.
+    let request = |format| ChatRequestData {
+        response_format: Some(format),
+        ..Default::default()
+    };
+    assert!(check_request(&request(contact)).is_ok());
+    for schema in [
+        json!({ "$ref": "https://example.com/contact.json" }),
+        json!({ "type": "string", "format": "postal-code" }),
+    ] {
+        assert!(matches!(
+            check_request(&request(format(schema))),
+            Err(MolyError::InvalidResponseFormat(_))
+        ));
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{BuiltInCommand, ModelInteractionCommand};
+
+/// Checks the runtime of a model, see [`super::BackendModel::health_check`].
+/// The error describes what went wrong.
+pub type HealthCheck = Box<dyn FnMut() -> Result<(), String> + Send>;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_RESTARTS: u32 = 5;
+/// A model running this long after a restart starts over the count of restarts.
+pub const HEALTHY_PERIOD: Duration = Duration::from_secs(5 * 60);
+
+/// Runs `check` until it fails or the model loaded as `generation` is replaced.
+pub fn supervise(
+    mut check: HealthCheck,
+    generation: u64,
+    current_generation: Arc<AtomicU64>,
+    commands_tx: Sender<BuiltInCommand>,
+) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(CHECK_INTERVAL);
+        if current_generation.load(Ordering::Acquire) != generation {
+            return;
+        }
+
+        if let Err(reason) = check() {
+            // It may have been stopped on purpose while checking.
+            if current_generation.load(Ordering::Acquire) == generation {
+                let _ = commands_tx.send(BuiltInCommand::Interaction(
+                    ModelInteractionCommand::ModelCrashed(generation, reason),
+                ));
+            }
+            return;
+        }
+    });
+}
+
+/// How long to wait before the next restart, after `restarts` in a row. `None` once
+/// it is time to give up.
+pub fn restart_delay(restarts: u32) -> Option<Duration> {
+    (restarts < MAX_RESTARTS).then(|| Duration::from_secs(1 << restarts))
+}
+
+pub fn restart_after(delay: Duration, generation: u64, commands_tx: Sender<BuiltInCommand>) {
+    std::thread::spawn(move || {
+        std::thread::sleep(delay);
+        let _ = commands_tx.send(BuiltInCommand::Interaction(
+            ModelInteractionCommand::RestartModel(generation),
+        ));
+    });
+}
+
+#[test]
+fn test_restart_delay() {
+    let delays = (0..=MAX_RESTARTS).map(restart_delay).collect::<Vec<_>>();
+    assert_eq!(delays[0], Some(Duration::from_secs(1)));
+    assert_eq!(delays[4], Some(Duration::from_secs(16)));
+    assert_eq!(delays[5], None);
+}
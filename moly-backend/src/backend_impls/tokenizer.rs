@@ -0,0 +1,491 @@
+//! Tokenizer of the models read from the vocabulary of their GGUF file, to count tokens
+//! as llama.cpp does, the runtime having no way to ask it. Both kinds of vocabularies of
+//! llama.cpp are supported: SentencePiece (`llama`) and byte-level BPE (`gpt2`). The
+//! special tokens in the text, like the markers of the chat templates, are single tokens.
+//! BPE splits the text with the pre-tokenizer of the Llama 3 and Qwen 2 families, and
+//! with the one of GPT-2 for the other ones.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap},
+    io,
+    path::Path,
+};
+
+use fancy_regex::Regex;
+
+use super::gguf::{self, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    SentencePiece,
+    BytePair,
+}
+
+pub struct Vocab {
+    kind: Kind,
+    ids: HashMap<String, u32>,
+    scores: Vec<f32>,
+    /// The rank of the merges of BPE, keyed by the two parts separated by a space.
+    merges: HashMap<String, usize>,
+    bos: Option<u32>,
+    add_bos: bool,
+    unknown: Option<u32>,
+    /// The control and user defined tokens, found as such in the text.
+    special: Vec<(String, u32)>,
+    /// Splits the text in the words of BPE.
+    split: Regex,
+    /// The characters standing for the bytes in the tokens of BPE.
+    byte_chars: [char; 256],
+}
+
+enum Fragment<'a> {
+    Text(&'a str),
+    Special(u32),
+}
+
+// The types of `tokenizer.ggml.token_type` that are special tokens.
+const CONTROL: u64 = 3;
+const USER_DEFINED: u64 = 4;
+
+impl Vocab {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let values = gguf::read_metadata(path, |key| key.starts_with("tokenizer.ggml."))?;
+        Self::from_metadata(&values)
+    }
+
+    fn from_metadata(values: &HashMap<String, Value>) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let get = |key: &str| values.get(&format!("tokenizer.ggml.{key}"));
+        let strings = |key: &str| -> Vec<String> {
+            get(key)
+                .and_then(Value::as_array)
+                .unwrap_or_default()
+                .iter()
+                .map(|v| v.as_str().unwrap_or_default().to_string())
+                .collect()
+        };
+        let id = |key: &str| get(key).and_then(Value::as_u64).map(|n| n as u32);
+
+        let kind = match get("model").and_then(Value::as_str) {
+            Some("llama") => Kind::SentencePiece,
+            Some("gpt2") => Kind::BytePair,
+            Some(model) => return Err(invalid(format!("unsupported tokenizer {model}"))),
+            None => return Err(invalid("no tokenizer in the metadata".to_string())),
+        };
+        let tokens = strings("tokens");
+        if tokens.is_empty() {
+            return Err(invalid("no tokens in the metadata".to_string()));
+        }
+        let scores = get("scores")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .map(|v| v.as_f64().unwrap_or_default() as f32)
+            .collect();
+        let special = get("token_type")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .zip(&tokens)
+            .enumerate()
+            .filter(|(_, (token_type, token))| {
+                matches!(token_type.as_u64(), Some(CONTROL | USER_DEFINED)) && !token.is_empty()
+            })
+            .map(|(id, (_, token))| (token.clone(), id as u32))
+            .collect();
+        let pre = get("pre").and_then(Value::as_str);
+        let split = Regex::new(split_pattern(pre))
+            .map_err(|e| invalid(format!("invalid pre-tokenizer: {e}")))?;
+
+        Ok(Self {
+            kind,
+            ids: tokens
+                .into_iter()
+                .enumerate()
+                .map(|(id, token)| (token, id as u32))
+                .collect(),
+            scores,
+            merges: strings("merges")
+                .into_iter()
+                .enumerate()
+                .map(|(rank, merge)| (merge, rank))
+                .collect(),
+            bos: id("bos_token_id"),
+            add_bos: get("add_bos_token")
+                .and_then(Value::as_bool)
+                .unwrap_or(kind == Kind::SentencePiece),
+            unknown: id("unknown_token_id"),
+            special,
+            split,
+            byte_chars: byte_chars(),
+        })
+    }
+
+    pub fn tokenize(&self, text: &str) -> Vec<u32> {
+        let mut ids = Vec::new();
+        if self.add_bos {
+            ids.extend(self.bos);
+        }
+        // SentencePiece starts with a space the text at the start and after special tokens.
+        let mut after_special = true;
+        for fragment in self.split_special(text) {
+            match fragment {
+                Fragment::Special(id) => {
+                    ids.push(id);
+                    after_special = true;
+                }
+                Fragment::Text(text) => {
+                    match self.kind {
+                        Kind::SentencePiece => self.tokenize_spm(text, after_special, &mut ids),
+                        Kind::BytePair => {
+                            for word in pre_tokenize(&self.split, text) {
+                                self.tokenize_bpe(word, &mut ids);
+                            }
+                        }
+                    }
+                    after_special = false;
+                }
+            }
+        }
+        ids
+    }
+
+    /// Splits `text` at the special tokens, the longest one where several start.
+    fn split_special<'a>(&self, mut text: &'a str) -> Vec<Fragment<'a>> {
+        let mut fragments = Vec::new();
+        while !text.is_empty() {
+            let next = self
+                .special
+                .iter()
+                .filter_map(|(token, id)| Some((text.find(token.as_str())?, token.len(), *id)))
+                .min_by_key(|&(start, len, _)| (start, Reverse(len)));
+            let Some((start, len, id)) = next else {
+                fragments.push(Fragment::Text(text));
+                break;
+            };
+            if start > 0 {
+                fragments.push(Fragment::Text(&text[..start]));
+            }
+            fragments.push(Fragment::Special(id));
+            text = &text[start + len..];
+        }
+        fragments
+    }
+
+    fn tokenize_spm(&self, text: &str, space_prefix: bool, ids: &mut Vec<u32>) {
+        let prefix = if space_prefix { "▁" } else { "" };
+        let text = format!("{prefix}{}", text.replace(' ', "▁"));
+        let score = |joined: &str, _| {
+            let id = *self.ids.get(joined)?;
+            Some(self.scores.get(id as usize).copied().unwrap_or_default())
+        };
+        for symbol in merge_symbols(&text, score) {
+            if let Some(&id) = self.ids.get(symbol) {
+                ids.push(id);
+                continue;
+            }
+            for byte in symbol.bytes() {
+                match self.ids.get(&format!("<0x{byte:02X}>")) {
+                    Some(&id) => ids.push(id),
+                    None => ids.extend(self.unknown),
+                }
+            }
+        }
+    }
+
+    fn tokenize_bpe(&self, word: &str, ids: &mut Vec<u32>) {
+        let word: String = word.bytes().map(|b| self.byte_chars[b as usize]).collect();
+        // Lower ranks are merged first.
+        let rank = |joined: &str, split: usize| {
+            let merge = format!("{} {}", &joined[..split], &joined[split..]);
+            self.merges.get(&merge).map(|&rank| -(rank as f32))
+        };
+        for symbol in merge_symbols(&word, rank) {
+            if let Some(&id) = self.ids.get(symbol) {
+                ids.push(id);
+                continue;
+            }
+            for c in symbol.chars() {
+                match self.ids.get(&c.to_string()) {
+                    Some(&id) => ids.push(id),
+                    None => ids.extend(self.unknown),
+                }
+            }
+        }
+    }
+}
+
+struct Symbol {
+    start: usize,
+    end: usize,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// A pair of neighbor symbols that can be merged, the first ones being the ones with the
+/// highest priority and then the leftmost ones.
+struct Candidate {
+    priority: f32,
+    left: usize,
+    right: usize,
+    // To skip the candidates whose symbols changed since.
+    len: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .total_cmp(&other.priority)
+            .then(other.left.cmp(&self.left))
+    }
+}
+
+/// Splits `text` in its characters and merges the neighbors while `priority` accepts
+/// them, being given the merged text and where the first symbol ends in it.
+fn merge_symbols(text: &str, priority: impl Fn(&str, usize) -> Option<f32>) -> Vec<&str> {
+    let mut symbols: Vec<Symbol> = text
+        .char_indices()
+        .enumerate()
+        .map(|(i, (start, c))| Symbol {
+            start,
+            end: start + c.len_utf8(),
+            prev: i.checked_sub(1),
+            next: Some(i + 1),
+        })
+        .collect();
+    if let Some(last) = symbols.last_mut() {
+        last.next = None;
+    }
+
+    let mut candidates = BinaryHeap::new();
+    let candidate = |symbols: &[Symbol], left: usize, right: usize| {
+        let (l, r) = (&symbols[left], &symbols[right]);
+        let joined = &text[l.start..r.end];
+        priority(joined, l.end - l.start).map(|priority| Candidate {
+            priority,
+            left,
+            right,
+            len: joined.len(),
+        })
+    };
+    for left in 1..symbols.len() {
+        candidates.extend(candidate(&symbols, left - 1, left));
+    }
+
+    while let Some(Candidate {
+        left, right, len, ..
+    }) = candidates.pop()
+    {
+        let (l, r) = (&symbols[left], &symbols[right]);
+        if l.start == l.end || l.next != Some(right) || r.end - l.start != len {
+            continue;
+        }
+
+        symbols[left].end = symbols[right].end;
+        symbols[left].next = symbols[right].next;
+        symbols[right].end = symbols[right].start;
+        if let Some(next) = symbols[left].next {
+            symbols[next].prev = Some(left);
+            candidates.extend(candidate(&symbols, left, next));
+        }
+        if let Some(prev) = symbols[left].prev {
+            candidates.extend(candidate(&symbols, prev, left));
+        }
+    }
+
+    symbols
+        .iter()
+        .filter(|s| s.start != s.end)
+        .map(|s| &text[s.start..s.end])
+        .collect()
+}
+
+/// The pre-tokenizers of llama.cpp, splitting the text in the words merged by BPE.
+const GPT2_SPLIT: &str =
+    r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+const LLAMA3_SPLIT: &str = r"(?:'[sS]|'[tT]|'[rR][eE]|'[vV][eE]|'[mM]|'[lL][lL]|'[dD])|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+const QWEN2_SPLIT: &str = r"(?:'[sS]|'[tT]|'[rR][eE]|'[vV][eE]|'[mM]|'[lL][lL]|'[dD])|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// The pre-tokenizer named by `tokenizer.ggml.pre`.
+fn split_pattern(pre: Option<&str>) -> &'static str {
+    match pre {
+        Some("llama3" | "llama-bpe" | "llama-v3" | "smaug-bpe" | "falcon3") => LLAMA3_SPLIT,
+        Some("qwen2" | "deepseek-r1-qwen") => QWEN2_SPLIT,
+        _ => GPT2_SPLIT,
+    }
+}
+
+/// The words of `text`, the parts the regex skips being words too.
+fn pre_tokenize<'a>(split: &Regex, text: &'a str) -> Vec<&'a str> {
+    let mut words = Vec::new();
+    let mut end = 0;
+    for m in split.find_iter(text) {
+        let Ok(m) = m else { break };
+        if m.start() > end {
+            words.push(&text[end..m.start()]);
+        }
+        words.push(m.as_str());
+        end = m.end();
+    }
+    if end < text.len() {
+        words.push(&text[end..]);
+    }
+    words
+}
+
+/// The printable characters GPT-2 stands for the bytes with: the printable ASCII and
+/// Latin-1 characters stand for themselves, and the other bytes for the characters from
+/// U+0100 on.
+fn byte_chars() -> [char; 256] {
+    let mut chars = ['\0'; 256];
+    let mut n = 0;
+    for byte in 0..=255u8 {
+        chars[byte as usize] = if matches!(byte, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF) {
+            byte as char
+        } else {
+            n += 1;
+            char::from_u32(255 + n).unwrap_or_default()
+        };
+    }
+    chars
+}
+
+#[test]
+fn test_tokenize() {
+    let strings =
+        |items: &[&str]| Value::Array(items.iter().map(|s| Value::String(s.to_string())).collect());
+    let key = |name: &str| format!("tokenizer.ggml.{name}");
+
+    let spm = Vocab::from_metadata(&HashMap::from([
+        (key("model"), Value::String("llama".to_string())),
+        (
+            key("tokens"),
+            strings(&[
+                "<unk>", "<s>", "▁", "h", "i", "▁h", "▁hi", "<0x21>", "<0xC3>", "<0xA9>",
+            ]),
+        ),
+        (
+            key("scores"),
+            Value::Array(
+                [0.0, 0.0, -1.0, -1.0, -1.0, -2.0, -3.0, 0.0, 0.0, 0.0]
+                    .into_iter()
+                    .map(Value::Float)
+                    .collect(),
+            ),
+        ),
+        (key("bos_token_id"), Value::Int(1)),
+        (key("unknown_token_id"), Value::Int(0)),
+    ]))
+    .unwrap();
+    assert_eq!(spm.tokenize(""), vec![1]);
+    assert_eq!(spm.tokenize("hi"), vec![1, 6]);
+    assert_eq!(spm.tokenize("hi hi!"), vec![1, 6, 6, 7]);
+    // Byte fallback.
+    assert_eq!(spm.tokenize("é"), vec![1, 2, 8, 9]);
+
+    let bpe = Vocab::from_metadata(&HashMap::from([
+        (key("model"), Value::String("gpt2".to_string())),
+        (
+            key("tokens"),
+            strings(&["h", "i", "Ġ", "hi", "Ġh", "Ġhi", "!", "'s"]),
+        ),
+        (key("merges"), strings(&["Ġ h", "h i", "Ġh i", "' s"])),
+    ]))
+    .unwrap();
+    assert_eq!(bpe.tokenize("hi hi!"), vec![3, 5, 6]);
+    assert_eq!(bpe.tokenize("hi's"), vec![3, 7]);
+    let gpt2 = Regex::new(GPT2_SPLIT).unwrap();
+    assert_eq!(
+        pre_tokenize(&gpt2, "a  b\n c12's"),
+        vec!["a", " ", " b", "\n", " c", "12", "'s"]
+    );
+    let llama3 = Regex::new(split_pattern(Some("llama-bpe"))).unwrap();
+    assert_eq!(
+        pre_tokenize(&llama3, "Hello,  world!\n\n12345 It's"),
+        vec!["Hello", ",", " ", " world", "!\n\n", "123", "45", " It", "'s"]
+    );
+}
+
+#[test]
+fn test_tokenize_special_tokens() {
+    let strings =
+        |items: &[&str]| Value::Array(items.iter().map(|s| Value::String(s.to_string())).collect());
+    let key = |name: &str| format!("tokenizer.ggml.{name}");
+
+    let bpe = Vocab::from_metadata(&HashMap::from([
+        (key("model"), Value::String("gpt2".to_string())),
+        (key("pre"), Value::String("llama-bpe".to_string())),
+        (
+            key("tokens"),
+            strings(&["h", "i", "Ġ", "hi", "<|eot|>", "<|eot|>x", "<"]),
+        ),
+        (
+            key("token_type"),
+            Value::Array([1, 1, 1, 1, 3, 4, 1].into_iter().map(Value::Int).collect()),
+        ),
+        (key("merges"), strings(&["h i"])),
+    ]))
+    .unwrap();
+    assert_eq!(bpe.tokenize("hi<|eot|>hi"), vec![3, 4, 3]);
+    // The longest special token wins.
+    assert_eq!(bpe.tokenize("<|eot|>x<|eot|>"), vec![5, 4]);
+
+    let spm = Vocab::from_metadata(&HashMap::from([
+        (key("model"), Value::String("llama".to_string())),
+        (
+            key("tokens"),
+            strings(&["<unk>", "<s>", "</s>", "▁hi", "hi"]),
+        ),
+        (
+            key("token_type"),
+            Value::Array([2, 3, 3, 1, 1].into_iter().map(Value::Int).collect()),
+        ),
+        (
+            key("scores"),
+            Value::Array([0.0; 5].into_iter().map(Value::Float).collect()),
+        ),
+        (key("bos_token_id"), Value::Int(1)),
+    ]))
+    .unwrap();
+    assert_eq!(spm.tokenize("hi</s>hi"), vec![1, 3, 2, 3]);
+}
+
+/// Checks the counts against the ones of llama.cpp with the vocabulary of a Llama 3 model,
+/// e.g. `MOLY_TEST_LLAMA3_GGUF=Meta-Llama-3-8B-Instruct-Q4_K_M.gguf`. Skipped without it.
+#[test]
+fn test_tokenize_llama3() {
+    use super::chat_template;
+    use moly_protocol::open_ai::{Message, Role};
+
+    let Ok(path) = std::env::var("MOLY_TEST_LLAMA3_GGUF") else {
+        return;
+    };
+    let vocab = Vocab::read(Path::new(&path)).unwrap();
+    assert_eq!(vocab.tokenize("Hello world!"), vec![128000, 9906, 1917, 0]);
+
+    let messages = [Message {
+        content: "Hello world!".into(),
+        role: Role::User,
+        name: None,
+    }];
+    let prompt = chat_template::render("llama-3-chat", &messages);
+    assert_eq!(
+        vocab.tokenize(&prompt),
+        vec![128000, 128006, 882, 128007, 271, 9906, 1917, 0, 128009, 128006, 78191, 128007, 271]
+    );
+}
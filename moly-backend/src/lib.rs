@@ -4,6 +4,11 @@ mod store;
 use moly_protocol::protocol::Command;
 use std::{path::Path, sync::mpsc};
 
+pub use backend_impls::{capture_stderr, EngineRegistry, StderrCapture, DEFAULT_ENGINE};
+
+/// Environment variable naming the engine models are loaded with, see [`DEFAULT_ENGINE`].
+pub const ENGINE_ENV_VAR: &str = "MOLY_ENGINE";
+
 pub struct Backend {
     pub command_sender: mpsc::Sender<Command>,
 }
@@ -13,18 +18,57 @@ impl Backend {
     /// * `app_data_dir` - The directory where application data should be stored.
     /// * `models_dir` - The directory where models should be downloaded.
     /// * `max_download_threads` - Maximum limit on simultaneous file downloads.
+    /// * `offline` - Run without any network access. Search is served from the last
+    ///   synced catalog and network-dependent commands fail with [`MolyError::Offline`].
+    ///
+    /// The engine is taken from the `MOLY_ENGINE` environment variable, and can be
+    /// changed later with `Command::SetEngine`.
+    ///
+    /// [`MolyError::Offline`]: moly_protocol::protocol::MolyError::Offline
     pub fn new<A: AsRef<Path>, M: AsRef<Path>>(
         app_data_dir: A,
         models_dir: M,
         max_download_threads: usize,
+        offline: bool,
+    ) -> Backend {
+        let engine = std::env::var(ENGINE_ENV_VAR).unwrap_or(DEFAULT_ENGINE.to_string());
+        Self::with_engines(
+            app_data_dir,
+            models_dir,
+            max_download_threads,
+            offline,
+            EngineRegistry::default(),
+            &engine,
+        )
+    }
+
+    /// Like [`Backend::new`], with the engines to choose from and the one to start with.
+    pub fn with_engines<A: AsRef<Path>, M: AsRef<Path>>(
+        app_data_dir: A,
+        models_dir: M,
+        max_download_threads: usize,
+        offline: bool,
+        engines: EngineRegistry,
+        engine: &str,
     ) -> Backend {
         #[cfg(debug_assertions)]
         env_logger::init();
-        let command_sender = backend_impls::LlamaEdgeApiServerBackend::build_command_sender(
+        let command_sender = backend_impls::BackendImpl::build_command_sender(
             app_data_dir,
             models_dir,
             max_download_threads,
+            offline,
+            engines,
+            engine,
         );
         Backend { command_sender }
     }
+
+    /// Attaches to a backend running in a `moly-daemon` process, listening on the
+    /// Unix domain socket at `socket_path`, instead of starting one in this process.
+    #[cfg(unix)]
+    pub fn connect<P: AsRef<Path>>(socket_path: P) -> anyhow::Result<Backend> {
+        let command_sender = moly_protocol::wire::connect_unix_socket(socket_path)?;
+        Ok(Backend { command_sender })
+    }
 }
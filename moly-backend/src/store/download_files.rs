@@ -20,16 +20,42 @@ pub struct DownloadedFile {
     pub tags: Vec<String>,
     pub featured: bool,
     pub sha256: String,
+    /// Names of the parts of a sharded file, empty when the file is not sharded.
+    pub parts: Vec<String>,
+    /// Name of the multimodal projector of a vision model, empty when there is none.
+    pub mmproj: String,
+    /// A LoRA adapter of the model, which is loaded with one of its other files.
+    pub is_adapter: bool,
 }
 
 impl DownloadedFile {
+    /// The names of the files on disk that make up this file, in order.
+    pub fn part_names(&self) -> Vec<String> {
+        if self.parts.is_empty() {
+            vec![self.name.clone()]
+        } else {
+            self.parts.clone()
+        }
+    }
+
+    /// The names of every file downloaded for this file, including its projector.
+    pub fn files_on_disk(&self) -> Vec<String> {
+        let mut names = self.part_names();
+        if !self.mmproj.is_empty() {
+            names.push(self.mmproj.clone());
+        }
+        names
+    }
+
     pub fn insert_into_db(&self, conn: &rusqlite::Connection) -> rusqlite::Result<()> {
         conn.execute(
             "INSERT OR REPLACE INTO download_files (
                 id, model_id, name, size, quantization,
                 prompt_template, reverse_prompt, context_size,
-                downloaded, file_size, download_dir, downloaded_at, tags, featured, sha256)
-                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+                downloaded, file_size, download_dir, downloaded_at, tags, featured, sha256, parts,
+                mmproj, is_adapter)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+                    ?18)",
             rusqlite::params![
                 self.id,
                 self.model_id,
@@ -46,6 +72,9 @@ impl DownloadedFile {
                 serde_json::to_string(&self.tags).unwrap(),
                 self.featured,
                 self.sha256,
+                serde_json::to_string(&self.parts).unwrap(),
+                self.mmproj,
+                self.is_adapter,
             ],
         )?;
 
@@ -70,6 +99,22 @@ impl DownloadedFile {
         Ok(())
     }
 
+    pub fn update_prompt_template(
+        conn: &rusqlite::Connection,
+        id: &str,
+        prompt_template: &str,
+        reverse_prompt: &str,
+    ) -> rusqlite::Result<()> {
+        conn.execute(
+            "UPDATE download_files
+                SET prompt_template = ?2,
+                    reverse_prompt = ?3
+                WHERE id = ?1",
+            rusqlite::params![id, prompt_template, reverse_prompt],
+        )?;
+        Ok(())
+    }
+
     fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
         let downloaded_at =
             chrono::DateTime::parse_from_rfc3339(&row.get::<_, String>("downloaded_at")?)
@@ -77,6 +122,8 @@ impl DownloadedFile {
                 .unwrap_or_default();
 
         let tags = serde_json::from_str(row.get::<_, String>("tags")?.as_str()).unwrap_or_default();
+        let parts =
+            serde_json::from_str(row.get::<_, String>("parts")?.as_str()).unwrap_or_default();
 
         Ok(DownloadedFile {
             id: Arc::new(row.get("id")?),
@@ -94,6 +141,9 @@ impl DownloadedFile {
             tags,
             featured: row.get("featured")?,
             sha256: row.get("sha256")?,
+            parts,
+            mmproj: row.get("mmproj")?,
+            is_adapter: row.get("is_adapter")?,
         })
     }
 
@@ -186,6 +236,28 @@ fn check_context_size(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
     Ok(())
 }
 
+fn check_column(
+    conn: &rusqlite::Connection,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("PRAGMA table_info(download_files)")?;
+    let mut rows = stmt.query_map([], |row| {
+        let name: String = row.get(1)?;
+        Ok(name)
+    })?;
+
+    let check = rows.find(|row| matches!(row.as_deref(), Ok(name) if name == column));
+
+    if check.is_none() {
+        conn.execute(
+            &format!("ALTER TABLE download_files ADD COLUMN {column} {definition}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
 pub fn create_table_download_files(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
     conn.execute_batch(
         "BEGIN;
@@ -204,7 +276,10 @@ pub fn create_table_download_files(conn: &rusqlite::Connection) -> rusqlite::Res
             downloaded_at TEXT NOT NULL,
             tags TEXT NOT NULL,
             featured INTEGER DEFAULT 0,
-            sha256 TEXT NOT NULL DEFAULT ''
+            sha256 TEXT NOT NULL DEFAULT '',
+            parts TEXT NOT NULL DEFAULT '[]',
+            mmproj TEXT NOT NULL DEFAULT '',
+            is_adapter INTEGER NOT NULL DEFAULT 0
         );
         CREATE INDEX IF NOT EXISTS index_model_id ON download_files (model_id);
         CREATE INDEX IF NOT EXISTS index_downloaded ON download_files (downloaded);
@@ -212,6 +287,9 @@ pub fn create_table_download_files(conn: &rusqlite::Connection) -> rusqlite::Res
     )?;
 
     check_context_size(conn)?;
+    check_column(conn, "parts", "TEXT NOT NULL DEFAULT '[]'")?;
+    check_column(conn, "mmproj", "TEXT NOT NULL DEFAULT ''")?;
+    check_column(conn, "is_adapter", "INTEGER NOT NULL DEFAULT 0")?;
 
     Ok(())
 }
@@ -237,6 +315,12 @@ fn test_sql() {
         tags: vec!["test".to_string()],
         featured: false,
         sha256: Default::default(),
+        parts: vec![
+            "test-00001-of-00002".to_string(),
+            "test-00002-of-00002".to_string(),
+        ],
+        mmproj: "mmproj-test".to_string(),
+        is_adapter: false,
     };
 
     downloaded_file.insert_into_db(&conn).unwrap();
@@ -0,0 +1,113 @@
+use moly_protocol::protocol::LoadPreset;
+use rusqlite::params;
+
+pub fn create_table_load_presets(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS load_presets (
+            file_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            is_default INTEGER NOT NULL DEFAULT 0,
+            options TEXT NOT NULL,
+            PRIMARY KEY (file_id, name)
+        )",
+        (),
+    )?;
+    Ok(())
+}
+
+/// The presets of a file, sorted by name.
+pub fn get_load_presets(
+    conn: &rusqlite::Connection,
+    file_id: &str,
+) -> rusqlite::Result<Vec<LoadPreset>> {
+    let mut stmt = conn.prepare(
+        "SELECT name, is_default, options FROM load_presets WHERE file_id = ?1 ORDER BY name",
+    )?;
+    let rows = stmt.query_map(params![file_id], |row| {
+        let options: String = row.get(2)?;
+        Ok(LoadPreset {
+            name: row.get(0)?,
+            is_default: row.get(1)?,
+            options: serde_json::from_str(&options).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, e.into())
+            })?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Saves a preset, replacing the one with the same name. A default preset stops
+/// being the default when another one becomes it.
+pub fn save_load_preset(
+    conn: &mut rusqlite::Connection,
+    file_id: &str,
+    preset: &LoadPreset,
+) -> rusqlite::Result<()> {
+    let mut options = preset.options.clone();
+    options.override_server_address = None;
+    let options = serde_json::to_string(&options)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+
+    let tx = conn.transaction()?;
+    if preset.is_default {
+        tx.execute(
+            "UPDATE load_presets SET is_default = 0 WHERE file_id = ?1",
+            params![file_id],
+        )?;
+    }
+    tx.execute(
+        "INSERT OR REPLACE INTO load_presets (file_id, name, is_default, options)
+            VALUES (?1, ?2, ?3, ?4)",
+        params![file_id, preset.name, preset.is_default, options],
+    )?;
+    tx.commit()
+}
+
+pub fn delete_load_preset(
+    conn: &rusqlite::Connection,
+    file_id: &str,
+    name: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM load_presets WHERE file_id = ?1 AND name = ?2",
+        params![file_id, name],
+    )?;
+    Ok(())
+}
+
+#[test]
+fn test_load_presets() {
+    use moly_protocol::protocol::{GPULayers, LoadModelOptions};
+
+    let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+    create_table_load_presets(&conn).unwrap();
+
+    let preset = |name: &str, is_default: bool, n_ctx: u32| LoadPreset {
+        name: name.to_string(),
+        is_default,
+        options: LoadModelOptions {
+            override_server_address: Some("localhost:8080".to_string()),
+            n_ctx: Some(n_ctx),
+            gpu_layers: GPULayers::Specific(20),
+            ..Default::default()
+        },
+    };
+
+    save_load_preset(&mut conn, "file1", &preset("short", true, 2048)).unwrap();
+    save_load_preset(&mut conn, "file1", &preset("long", false, 8192)).unwrap();
+    save_load_preset(&mut conn, "file2", &preset("other", true, 4096)).unwrap();
+    // Replaced by name, and made the default.
+    save_load_preset(&mut conn, "file1", &preset("long", true, 16384)).unwrap();
+
+    let presets = get_load_presets(&conn, "file1").unwrap();
+    assert_eq!(presets.len(), 2);
+    assert_eq!(presets[0].name, "long");
+    assert!(presets[0].is_default);
+    assert_eq!(presets[0].options.n_ctx, Some(16384));
+    assert_eq!(presets[0].options.override_server_address, None);
+    assert!(!presets[1].is_default);
+
+    delete_load_preset(&conn, "file1", "long").unwrap();
+    assert_eq!(get_load_presets(&conn, "file1").unwrap().len(), 1);
+    assert!(get_load_presets(&conn, "file2").unwrap()[0].is_default);
+}
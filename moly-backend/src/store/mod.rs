@@ -1,15 +1,29 @@
 pub mod download_files;
+pub mod load_presets;
 pub mod models;
 pub mod remote;
 
 pub mod model_cards;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use moly_protocol::data::FileID;
+use moly_protocol::protocol::{FileVerification, LibraryUpdate, MolyError};
 
 pub use remote::*;
 
+pub fn db_error(e: rusqlite::Error) -> MolyError {
+    MolyError::Database(e.to_string())
+}
+
+/// Like [`db_error`], for a lookup of a downloaded file that may not exist.
+pub fn file_error(file_id: &str, e: rusqlite::Error) -> MolyError {
+    match e {
+        rusqlite::Error::QueryReturnedNoRows => MolyError::FileNotFound(file_id.to_string()),
+        e => db_error(e),
+    }
+}
+
 pub fn get_all_download_file(
     conn: &rusqlite::Connection,
 ) -> rusqlite::Result<Vec<moly_protocol::data::DownloadedFile>> {
@@ -58,6 +72,8 @@ pub fn get_all_download_file(
                 downloaded_path,
                 tags: file.tags,
                 featured: false,
+                is_adapter: file.is_adapter,
+                mmproj: Some(file.mmproj).filter(|mmproj| !mmproj.is_empty()),
             },
             model,
             downloaded_at: file.downloaded_at,
@@ -90,6 +106,8 @@ pub fn get_all_pending_downloads(
             downloaded_path: None,
             tags: file.tags.clone(),
             featured: file.featured,
+            is_adapter: file.is_adapter,
+            mmproj: Some(file.mmproj.clone()).filter(|mmproj| !mmproj.is_empty()),
         };
 
         let model = if let Some(model) = models.get(&file.model_id) {
@@ -115,15 +133,20 @@ pub fn get_all_pending_downloads(
             moly_protocol::data::Model::default()
         };
 
-        let file_path = Path::new(&file.download_dir)
-            .join(&file.model_id)
-            .join(&file.name);
-
-        let downloaded = if let Ok(file_meta) = std::fs::metadata(file_path) {
-            file_meta.len()
-        } else {
-            0
-        };
+        let downloaded = file
+            .files_on_disk()
+            .iter()
+            .map(|name| {
+                let file_path = Path::new(&file.download_dir)
+                    .join(&file.model_id)
+                    .join(name);
+                if let Ok(file_meta) = std::fs::metadata(file_path) {
+                    file_meta.len()
+                } else {
+                    0
+                }
+            })
+            .sum::<u64>();
         let progress = (downloaded as f64 / file.file_size as f64) * 100.0;
 
         let pending_download = moly_protocol::data::PendingDownload {
@@ -140,13 +163,366 @@ pub fn get_all_pending_downloads(
     Ok(result)
 }
 
-pub fn remove_downloaded_file(models_dir: String, file_id: FileID) -> anyhow::Result<()> {
+/// The model card of a downloaded model, if the catalog has it.
+fn downloaded_model_card(
+    model_indexs: &mut model_cards::ModelCardManager,
+    model_id: &str,
+) -> Option<model_cards::ModelCard> {
+    let index = model_indexs.get_index_by_id(model_id).cloned()?;
+    model_indexs
+        .load_model_card(&index)
+        .map_err(|e| log::warn!("failed to load model card {}: {}", model_id, e))
+        .ok()
+}
+
+/// The names of the files and adapters of a model card.
+fn card_file_names(card: &model_cards::ModelCard) -> Vec<String> {
+    card.files
+        .iter()
+        .chain(&card.adapters)
+        .map(|f| f.name.clone())
+        .collect()
+}
+
+/// Gives the models saved before their list of known files was recorded the current
+/// files of their card, so only files published afterwards are reported.
+pub fn record_known_files(
+    conn: &rusqlite::Connection,
+    model_indexs: &mut model_cards::ModelCardManager,
+) -> anyhow::Result<()> {
+    for (model_id, model) in models::Model::get_all(conn)? {
+        if !model.known_files.is_empty() {
+            continue;
+        }
+        if let Some(card) = downloaded_model_card(model_indexs, &model_id) {
+            models::Model::update_known_files(conn, &model_id, &card_file_names(&card))?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds the files of the model card to the known files of the model, they are no
+/// longer reported as [`LibraryUpdate::NewFile`].
+pub fn acknowledge_new_files(
+    conn: &rusqlite::Connection,
+    model_indexs: &mut model_cards::ModelCardManager,
+    model_id: &str,
+) -> anyhow::Result<()> {
+    let mut model = models::Model::get_all(conn)?
+        .remove(model_id)
+        .ok_or_else(|| anyhow::anyhow!("model {model_id} is not downloaded"))?;
+    let card = downloaded_model_card(model_indexs, model_id)
+        .ok_or_else(|| anyhow::anyhow!("model card of {model_id} not found"))?;
+
+    for name in card_file_names(&card) {
+        if !model.known_files.contains(&name) {
+            model.known_files.push(name);
+        }
+    }
+    models::Model::update_known_files(conn, model_id, &model.known_files)?;
+    Ok(())
+}
+
+/// Compares the downloaded models against their model cards.
+pub fn get_library_updates(
+    conn: &rusqlite::Connection,
+    model_indexs: &mut model_cards::ModelCardManager,
+) -> anyhow::Result<Vec<LibraryUpdate>> {
+    let files = download_files::DownloadedFile::get_finished(conn)?;
+    let models = models::Model::get_all(conn)?;
+
+    let mut updates = vec![];
+
+    for (model_id, model) in models {
+        let local_files = files
+            .values()
+            .filter(|file| file.model_id == model_id)
+            .collect::<Vec<_>>();
+        if local_files.is_empty() {
+            continue;
+        }
+
+        if let Some(card) = downloaded_model_card(model_indexs, &model_id) {
+            updates.extend(model_updates(&card, &model.known_files, &local_files));
+        }
+    }
+
+    Ok(updates)
+}
+
+/// The updates of a downloaded model, with the files of `local_files` and the ones of
+/// its card listed in `known_files`. Without known files, none is reported as new.
+fn model_updates(
+    card: &model_cards::ModelCard,
+    known_files: &[String],
+    local_files: &[&download_files::DownloadedFile],
+) -> Vec<LibraryUpdate> {
+    let mut updates = vec![];
+
+    let files = card.files.iter().map(|f| (f, false));
+    let adapters = card.adapters.iter().map(|f| (f, true));
+    for (remote_file, is_adapter) in files.chain(adapters) {
+        let file_id = format!("{}#{}", card.id, remote_file.name);
+
+        let Some(local_file) = local_files.iter().find(|f| f.name == remote_file.name) else {
+            if !known_files.is_empty() && !known_files.contains(&remote_file.name) {
+                updates.push(LibraryUpdate::NewFile(
+                    card.id.clone(),
+                    moly_protocol::data::File {
+                        id: file_id,
+                        name: remote_file.name.clone(),
+                        size: remote_file.size.clone(),
+                        quantization: remote_file.quantization.clone(),
+                        downloaded: false,
+                        downloaded_path: None,
+                        tags: remote_file.tags.clone(),
+                        featured: false,
+                        is_adapter,
+                        mmproj: remote_file.mmproj.as_ref().map(|part| part.name.clone()),
+                    },
+                ));
+            }
+            continue;
+        };
+
+        let remote_sha256 = remote_file.sha256.as_deref().unwrap_or_default();
+        if !local_file.sha256.is_empty()
+            && !remote_sha256.is_empty()
+            && !local_file.sha256.eq_ignore_ascii_case(remote_sha256)
+        {
+            updates.push(LibraryUpdate::ChecksumChanged {
+                file_id: file_id.clone(),
+                local_sha256: local_file.sha256.clone(),
+                remote_sha256: remote_sha256.to_string(),
+            });
+        }
+
+        // Adapters are loaded with the template of the file they apply to.
+        if !is_adapter
+            && (local_file.prompt_template != card.prompt_template
+                || local_file.reverse_prompt != card.reverse_prompt)
+        {
+            updates.push(LibraryUpdate::TemplateChanged {
+                file_id,
+                prompt_template: card.prompt_template.clone(),
+                reverse_prompt: card.reverse_prompt.clone(),
+            });
+        }
+    }
+
+    updates
+}
+
+/// Replaces the prompt template of a downloaded file with the one from its model card.
+pub fn apply_template_update(
+    conn: &rusqlite::Connection,
+    model_indexs: &mut model_cards::ModelCardManager,
+    file_id: &str,
+) -> anyhow::Result<()> {
+    let file = download_files::DownloadedFile::get_by_id(conn, file_id)?;
+    let index = model_indexs
+        .get_index_by_id(&file.model_id)
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("model card of {} not found", file.model_id))?;
+    let card = model_indexs.load_model_card(&index)?;
+
+    download_files::DownloadedFile::update_prompt_template(
+        conn,
+        file_id,
+        &card.prompt_template,
+        &card.reverse_prompt,
+    )?;
+    models::Model::update_prompt_template(
+        conn,
+        &file.model_id,
+        &card.prompt_template,
+        &card.reverse_prompt,
+    )?;
+
+    Ok(())
+}
+
+/// The paths of the files on disk of a downloaded file, with the sha256 expected for
+/// each one if the catalog provides it.
+pub fn file_parts_to_verify(
+    conn: &rusqlite::Connection,
+    model_indexs: &mut model_cards::ModelCardManager,
+    file_id: &str,
+) -> Result<Vec<(PathBuf, Option<String>)>, MolyError> {
+    let file = download_files::DownloadedFile::get_by_id(conn, file_id)
+        .map_err(|e| file_error(file_id, e))?;
+    let model_dir = Path::new(&file.download_dir).join(&file.model_id);
+
+    let remote_parts = model_indexs
+        .get_index_by_id(&file.model_id)
+        .cloned()
+        .and_then(|index| model_indexs.load_model_card(&index).ok())
+        .and_then(|card| card.files.into_iter().find(|f| f.name == file.name))
+        .map(|remote_file| remote_file.download_parts())
+        .unwrap_or_default();
+
+    let parts = file
+        .files_on_disk()
+        .into_iter()
+        .map(|name| {
+            let sha256 = remote_parts
+                .iter()
+                .find(|part| part.name == name)
+                .and_then(|part| part.sha256.clone())
+                .or_else(|| (name == file.name).then(|| file.sha256.clone()))
+                .filter(|sha256| !sha256.is_empty());
+            (model_dir.join(name), sha256)
+        })
+        .collect();
+
+    Ok(parts)
+}
+
+pub fn verify_parts(parts: &[(PathBuf, Option<String>)]) -> Result<FileVerification, MolyError> {
+    let mut checked = false;
+
+    for (path, sha256) in parts {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        if !path.exists() {
+            return Ok(FileVerification::Missing(name));
+        }
+
+        if let Some(expected) = sha256 {
+            let actual = file_sha256(path)?;
+            if !actual.eq_ignore_ascii_case(expected) {
+                return Ok(FileVerification::ChecksumMismatch {
+                    part: name,
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+            checked = true;
+        }
+    }
+
+    if checked {
+        Ok(FileVerification::Valid)
+    } else {
+        Ok(FileVerification::Unchecked)
+    }
+}
+
+/// Names of the files on disk that belong only to this file: its parts, and its
+/// projector unless another downloaded file of the same model uses it too.
+pub fn owned_file_names(
+    conn: &rusqlite::Connection,
+    file_id: &str,
+) -> rusqlite::Result<Vec<String>> {
+    let file = download_files::DownloadedFile::get_by_id(conn, file_id)?;
+    let mut names = file.part_names();
+
+    if !file.mmproj.is_empty() {
+        let others = download_files::DownloadedFile::get_by_models(conn, &[&file.model_id])?;
+        let shared = others
+            .values()
+            .any(|other| other.id != file.id && other.mmproj == file.mmproj);
+        if !shared {
+            names.push(file.mmproj);
+        }
+    }
+
+    Ok(names)
+}
+
+/// Removes the given files of the model from disk, see [`owned_file_names`].
+/// Without names only the file named in `file_id` is removed.
+pub fn remove_downloaded_file(
+    models_dir: String,
+    file_id: FileID,
+    names: &[String],
+) -> anyhow::Result<()> {
     let (model_id, file) = file_id
         .split_once("#")
         .ok_or_else(|| anyhow::anyhow!("Illegal file_id"))?;
 
-    let filename = format!("{}/{}/{}", models_dir, model_id, file);
+    let file = file.to_string();
+    let names = if names.is_empty() {
+        std::slice::from_ref(&file)
+    } else {
+        names
+    };
+
+    for part in names {
+        let filename = format!("{}/{}/{}", models_dir, model_id, part);
+
+        log::info!("Removing file {}", filename);
+        match std::fs::remove_file(filename) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_model_updates() {
+    let card: model_cards::ModelCard = serde_json::from_value(serde_json::json!({
+        "id": "org/model",
+        "released_at": "2024-01-01T00:00:00Z",
+        "files": [
+            {"name": "model.Q4.gguf", "sha256": "aaaa"},
+            {"name": "model.Q8.gguf"},
+            {"name": "model.Q2.gguf"},
+        ],
+        "adapters": [{"name": "style.gguf"}],
+        "prompt_template": "new template",
+        "reverse_prompt": "",
+        "context_size": 4096,
+        "author": {"name": "", "url": "", "description": ""},
+    }))
+    .unwrap();
+
+    let local_file = download_files::DownloadedFile {
+        model_id: "org/model".to_string(),
+        name: "model.Q4.gguf".to_string(),
+        sha256: "AAAA".to_string(),
+        prompt_template: "new template".to_string(),
+        ..Default::default()
+    };
+    let known_files = vec!["model.Q4.gguf".to_string(), "model.Q8.gguf".to_string()];
+
+    // The files published after the known ones, adapters included.
+    let updates = model_updates(&card, &known_files, &[&local_file]);
+    let new_files = updates
+        .iter()
+        .map(|update| match update {
+            LibraryUpdate::NewFile(model_id, file) => (model_id.as_str(), file.id.as_str()),
+            update => panic!("unexpected update {update:?}"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        new_files,
+        [
+            ("org/model", "org/model#model.Q2.gguf"),
+            ("org/model", "org/model#style.gguf")
+        ]
+    );
+
+    // Without known files, none is new.
+    assert!(model_updates(&card, &[], &[&local_file]).is_empty());
 
-    log::info!("Removing file {}", filename);
-    Ok(std::fs::remove_file(filename)?)
+    let local_file = download_files::DownloadedFile {
+        sha256: "bbbb".to_string(),
+        prompt_template: "old template".to_string(),
+        ..local_file
+    };
+    let updates = model_updates(&card, &card_file_names(&card), &[&local_file]);
+    assert!(matches!(
+        &updates[..],
+        [
+            LibraryUpdate::ChecksumChanged { file_id, remote_sha256, .. },
+            LibraryUpdate::TemplateChanged { prompt_template, .. },
+        ] if file_id == "org/model#model.Q4.gguf"
+            && remote_sha256 == "aaaa"
+            && prompt_template == "new template"
+    ));
 }
@@ -7,6 +7,23 @@ use std::path::{Path, PathBuf};
 use std::str;
 use std::sync::Arc;
 
+pub use moly_protocol::data::ModelCardError;
+
+/// Reference pointing to the last fetched commit whose model cards were all valid.
+const KNOWN_GOOD_REF: &str = "refs/moly/known-good";
+
+/// The model cards checkout is a read-only mirror, so the history is never needed.
+fn fetch_options<'a>() -> FetchOptions<'a> {
+    let mut fo = FetchOptions::new();
+    if let Ok(proxy) = std::env::var("https_proxy").or_else(|_| std::env::var("all_proxy")) {
+        let mut proxy_opt = ProxyOptions::new();
+        proxy_opt.url(&proxy);
+        fo.proxy_options(proxy_opt);
+    }
+    fo.depth(1);
+    fo
+}
+
 fn do_fetch<'a>(
     repo: &'a git2::Repository,
     refs: &[&str],
@@ -35,13 +52,8 @@ fn do_fetch<'a>(
         true
     });
 
-    let mut fo = git2::FetchOptions::new();
+    let mut fo = fetch_options();
     fo.remote_callbacks(cb);
-    if let Ok(proxy) = std::env::var("https_proxy").or_else(|_| std::env::var("all_proxy")) {
-        let mut proxy_opt = ProxyOptions::new();
-        proxy_opt.url(&proxy);
-        fo.proxy_options(proxy_opt);
-    }
     // Always fetch all tags.
     // Perform a download and also update tips
     // fo.download_tags(git2::AutotagOption::All);
@@ -73,116 +85,108 @@ fn do_fetch<'a>(
     Ok(repo.reference_to_annotated_commit(&fetch_head)?)
 }
 
-fn fast_forward(
-    repo: &Repository,
-    lb: &mut git2::Reference,
-    rc: &git2::AnnotatedCommit,
-) -> Result<(), git2::Error> {
-    let name = match lb.name() {
-        Some(s) => s.to_string(),
-        None => String::from_utf8_lossy(lb.name_bytes()).to_string(),
-    };
-    let msg = format!("Fast-Forward: Setting {} to id: {}", name, rc.id());
+/// Points the local branch to `commit` and makes the working directory match it exactly,
+/// discarding any local change or leftover from a previous sync.
+fn hard_reset(repo: &Repository, branch: &str, commit: git2::Oid) -> Result<(), git2::Error> {
+    let refname = format!("refs/heads/{}", branch);
+    let msg = format!("Reset: Setting {} to id: {}", refname, commit);
     log::debug!("{}", msg);
-    lb.set_target(rc.id(), &msg)?;
-    repo.set_head(&name)?;
-    repo.checkout_head(Some(
-        git2::build::CheckoutBuilder::default()
-            // For some reason the force is required to make the working directory actually get updated
-            // I suspect we should be adding some logic to handle dirty working directory states
-            // but this is just an example so maybe not.
-            .force(),
-    ))?;
-    Ok(())
+
+    repo.reference(&refname, commit, true, &msg)?;
+    repo.set_head(&refname)?;
+
+    let object = repo.find_object(commit, None)?;
+    repo.reset(
+        &object,
+        git2::ResetType::Hard,
+        Some(
+            git2::build::CheckoutBuilder::default()
+                .force()
+                .remove_untracked(true),
+        ),
+    )
 }
 
-fn normal_merge(
-    repo: &Repository,
-    local: &git2::AnnotatedCommit,
-    remote: &git2::AnnotatedCommit,
-) -> Result<(), git2::Error> {
-    let local_tree = repo.find_commit(local.id())?.tree()?;
-    let remote_tree = repo.find_commit(remote.id())?.tree()?;
-    let ancestor = repo
-        .find_commit(repo.merge_base(local.id(), remote.id())?)?
-        .tree()?;
-    let mut idx = repo.merge_trees(&ancestor, &local_tree, &remote_tree, None)?;
-
-    if idx.has_conflicts() {
-        log::debug!("Merge conflicts detected...");
-        repo.checkout_index(Some(&mut idx), None)?;
-        return Ok(());
-    }
-    let result_tree = repo.find_tree(idx.write_tree_to(repo)?)?;
-    // now create the merge commit
-    let msg = format!("Merge: {} into {}", remote.id(), local.id());
-    let sig = repo.signature()?;
-    let local_commit = repo.find_commit(local.id())?;
-    let remote_commit = repo.find_commit(remote.id())?;
-    // Do our merge commit and set current branch head to that commit.
-    let _merge_commit = repo.commit(
-        Some("HEAD"),
-        &sig,
-        &sig,
-        &msg,
-        &result_tree,
-        &[&local_commit, &remote_commit],
-    )?;
-    // Set working tree to match head.
-    repo.checkout_head(None)?;
-    Ok(())
+/// The commit checked out by [`update_mirror`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorCommit {
+    /// The fetched commit. Its cards are all valid, unless there was no known-good
+    /// commit to fall back to.
+    Fetched(git2::Oid),
+    /// The last known-good commit, the fetched one having an invalid index or cards.
+    KnownGood(git2::Oid),
 }
 
-fn do_merge<'a>(
-    repo: &'a Repository,
+/// Updates the model cards mirror to the tip of `remote_branch`.
+///
+/// The fetched commit is only kept if its index and all of its model cards are valid,
+/// otherwise the checkout goes back to the last known-good commit.
+pub fn update_mirror(
+    repo: &Repository,
+    remote_name: &str,
     remote_branch: &str,
-    fetch_commit: git2::AnnotatedCommit<'a>,
-) -> Result<(), git2::Error> {
-    // 1. do a merge analysis
-    let analysis = repo.merge_analysis(&[&fetch_commit])?;
-
-    // 2. Do the appropriate merge
-    if analysis.0.is_fast_forward() {
-        log::debug!("Doing a fast forward");
-        // do a fast forward
-        let refname = format!("refs/heads/{}", remote_branch);
-        match repo.find_reference(&refname) {
-            Ok(mut r) => {
-                fast_forward(repo, &mut r, &fetch_commit)?;
-            }
-            Err(_) => {
-                // The branch doesn't exist so just set the reference to the
-                // commit directly. Usually this is because you are pulling
-                // into an empty repository.
-                repo.reference(
-                    &refname,
-                    fetch_commit.id(),
-                    true,
-                    &format!("Setting {} to {}", remote_branch, fetch_commit.id()),
-                )?;
-                repo.set_head(&refname)?;
-                repo.checkout_head(Some(
-                    git2::build::CheckoutBuilder::default()
-                        .allow_conflicts(true)
-                        .conflict_style_merge(true)
-                        .force(),
-                ))?;
+) -> anyhow::Result<MirrorCommit> {
+    let mut remote = repo.find_remote(remote_name)?;
+    let fetch_commit = do_fetch(&repo, &[remote_branch], &mut remote)?.id();
+    check_out_valid(repo, remote_branch, fetch_commit)
+}
+
+/// Checks out `fetch_commit` on `branch` and keeps it if its cards are valid, going back
+/// to the last known-good commit otherwise.
+fn check_out_valid(
+    repo: &Repository,
+    branch: &str,
+    fetch_commit: git2::Oid,
+) -> anyhow::Result<MirrorCommit> {
+    hard_reset(repo, branch, fetch_commit)?;
+
+    let repo_dir = repo
+        .workdir()
+        .ok_or_else(|| anyhow::anyhow!("The model cards repo has no working directory"))?;
+    match validate_cards(repo_dir) {
+        Ok(card_errors) if card_errors.is_empty() => {
+            repo.reference(KNOWN_GOOD_REF, fetch_commit, true, "Model cards validated")?;
+            return Ok(MirrorCommit::Fetched(fetch_commit));
+        }
+        Ok(card_errors) => {
+            for error in &card_errors {
+                log::error!("Invalid model card in commit {fetch_commit}: {error}");
             }
-        };
-    } else if analysis.0.is_normal() {
-        // do a normal merge
-        let head_commit = repo.reference_to_annotated_commit(&repo.head()?)?;
-        normal_merge(&repo, &head_commit, &fetch_commit)?;
-    } else {
-        log::debug!("Nothing to do...");
+        }
+        Err(e) => log::error!("Invalid model cards index in commit {fetch_commit}: {e}"),
+    }
+
+    match repo.refname_to_id(KNOWN_GOOD_REF) {
+        Ok(known_good) => {
+            log::warn!("Keeping the last known-good model cards commit {known_good}");
+            hard_reset(repo, branch, known_good)?;
+            Ok(MirrorCommit::KnownGood(known_good))
+        }
+        // Nothing better to fall back to, the invalid cards are skipped when loading.
+        Err(_) => Ok(MirrorCommit::Fetched(fetch_commit)),
     }
-    Ok(())
 }
 
-pub fn pull(repo: &Repository, remote_name: &str, remote_branch: &str) -> Result<(), git2::Error> {
-    let mut remote = repo.find_remote(remote_name)?;
-    let fetch_commit = do_fetch(&repo, &[remote_branch], &mut remote)?;
-    do_merge(&repo, &remote_branch, fetch_commit)
+/// Parses the `index.json` of the model cards checkout at `repo_dir` and every card it
+/// references, returning the errors of the cards that could not be loaded.
+fn validate_cards(repo_dir: &Path) -> anyhow::Result<Vec<ModelCardError>> {
+    let index_list = std::fs::read_to_string(repo_dir.join("index.json"))?;
+    let index_list: Vec<ModelIndex> = serde_json::from_str(&index_list)?;
+
+    let errors = index_list
+        .into_iter()
+        .filter_map(|index| {
+            index
+                .load_model_card_from_repo(repo_dir)
+                .err()
+                .map(|e| ModelCardError {
+                    model_id: index.id,
+                    error: e.to_string(),
+                })
+        })
+        .collect();
+
+    Ok(errors)
 }
 
 pub fn open_or_clone<P: AsRef<Path>>(url: &str, repo_path: P) -> Result<Repository, git2::Error> {
@@ -191,33 +195,32 @@ pub fn open_or_clone<P: AsRef<Path>>(url: &str, repo_path: P) -> Result<Reposito
         url,
         repo_path.as_ref()
     );
-    if let Ok(repo) = Repository::open(&repo_path) {
-        log::debug!("open_or_clone: repo opened");
-        Ok(repo)
-    } else {
-        log::debug!("open_or_clone: cloning repo");
-        let mut builder = if let Ok(proxy) =
-            std::env::var("https_proxy").or_else(|_| std::env::var("all_proxy"))
-        {
-            let mut proxy_opt = ProxyOptions::new();
-            proxy_opt.url(&proxy);
-            let mut fetch_opt = FetchOptions::new();
-            fetch_opt.proxy_options(proxy_opt);
-            let mut builder = git2::build::RepoBuilder::new();
-            builder.fetch_options(fetch_opt);
-            builder
-        } else {
-            git2::build::RepoBuilder::new()
-        };
+    match Repository::open(&repo_path) {
+        Ok(repo) => {
+            log::debug!("open_or_clone: repo opened");
+            return Ok(repo);
+        }
+        Err(e) if repo_path.as_ref().exists() => {
+            // The checkout is only a mirror, so a broken one can be cloned again.
+            log::warn!("open_or_clone: removing unreadable repo: {e}");
+            let _ = std::fs::remove_dir_all(&repo_path);
+        }
+        Err(_) => {}
+    }
 
-        for _ in 0..2 {
-            let r = builder.clone(url, repo_path.as_ref());
-            if r.is_ok() {
-                return r;
-            }
+    log::debug!("open_or_clone: cloning repo");
+    let mut builder = git2::build::RepoBuilder::new();
+    builder.fetch_options(fetch_options());
+
+    for _ in 0..2 {
+        let r = builder.clone(url, repo_path.as_ref());
+        if r.is_ok() {
+            return r;
         }
-        builder.clone(url, repo_path.as_ref())
+        // Don't leave a half cloned repo behind.
+        let _ = std::fs::remove_dir_all(&repo_path);
     }
+    builder.clone(url, repo_path.as_ref())
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -253,71 +256,85 @@ fn get_model_cards_repo() -> (String, String) {
 
 pub static REPO_NAME: &'static str = "model-cards";
 
+/// Copy of the last index fetched from the release URL, kept so the catalog
+/// can still be served when there is no network.
+pub static INDEX_SNAPSHOT_NAME: &'static str = "model-cards-index.json";
+
 pub fn sync_model_cards_repo<P: AsRef<Path>>(app_data_dir: P) -> anyhow::Result<ModelCardManager> {
     let (repo_url, country_code) = get_model_cards_repo();
     log::info!("Using model_cards repo: {}", repo_url);
     let repo_dirs = app_data_dir.as_ref().join(REPO_NAME);
 
     let repo = open_or_clone(&repo_url, &repo_dirs)?;
-    let mut r = Ok(());
+    let mut r = Err(anyhow::anyhow!("Model cards mirror not updated"));
     for _ in 0..2 {
-        r = pull(&repo, "origin", "main");
+        r = update_mirror(&repo, "origin", "main");
         if r.is_ok() {
             break;
         }
     }
 
-    if let Err(e) = r {
-        log::error!("Failed to pull: {:?}", e);
-        log::error!("please remove the repo({:?}) and try again", &repo_dirs);
-    }
+    let index_list = match r {
+        Ok(MirrorCommit::KnownGood(commit)) => {
+            // The release index follows the fetched commit, it can list cards that the
+            // known-good checkout doesn't have, so the index of the checkout is served.
+            log::info!("Model cards mirror back at known-good commit {}", commit);
+            let index_list = std::fs::read_to_string(repo_dirs.join("index.json"))?;
+            let index_list: Vec<ModelIndex> = serde_json::from_str(&index_list)?;
+            let _ = std::fs::write(
+                app_data_dir.as_ref().join(INDEX_SNAPSHOT_NAME),
+                serde_json::to_string(&index_list)?,
+            );
+            index_list
+        }
+        Ok(MirrorCommit::Fetched(commit)) => {
+            log::info!("Model cards mirror at commit {}", commit);
+            fetch_release_index(&repo_url, app_data_dir.as_ref())?
+        }
+        Err(e) => {
+            log::error!("Failed to update the model cards mirror, using the current checkout: {e}");
+            fetch_release_index(&repo_url, app_data_dir.as_ref())?
+        }
+    };
 
+    ModelCardManager::new(app_data_dir, country_code, index_list, false)
+}
+
+/// The index published with the releases of the model cards repo, or the last snapshot
+/// of it when it can't be fetched.
+fn fetch_release_index(repo_url: &str, app_data_dir: &Path) -> anyhow::Result<Vec<ModelIndex>> {
     let index_url = format!("{}/releases/download/index_release/index.json", repo_url);
 
-    let index_list = if let Ok(remote_index) =
+    if let Ok(remote_index) =
         reqwest::blocking::get(index_url).and_then(|r| r.json::<Vec<ModelIndex>>())
     {
-        remote_index
+        if let Ok(snapshot) = serde_json::to_string(&remote_index) {
+            let _ = std::fs::write(app_data_dir.join(INDEX_SNAPSHOT_NAME), snapshot);
+        }
+        Ok(remote_index)
     } else {
-        let index_list = std::fs::read_to_string(repo_dirs.join("index.json"))?;
-        let index_list: Vec<ModelIndex> = serde_json::from_str(&index_list)?;
-        index_list
-    };
-
-    let mut indexs = HashMap::with_capacity(index_list.len());
-    for index in index_list {
-        indexs.insert(index.id.clone(), index);
+        read_index_snapshot(app_data_dir)
     }
+}
 
-    let embedding_index =
-        if let Ok(embedding_index) = std::fs::read_to_string(repo_dirs.join("embedding.json")) {
-            let embedding_index: EmbeddingIndex = serde_json::from_str(&embedding_index)?;
-            if !embedding_index.check_file_exist(app_data_dir.as_ref()) {
-                let app_data_dir_path = app_data_dir.as_ref().to_path_buf();
-                let r = std::thread::spawn(move || {
-                    if let Ok(_) = embedding_index.download(&app_data_dir_path) {
-                        log::debug!("Downloaded embedding model ok");
-                        Some(embedding_index)
-                    } else {
-                        log::warn!("Failed to download embedding model");
-                        None
-                    }
-                });
-                EmbeddingState::Pending(r)
-            } else {
-                EmbeddingState::Finish(Some(embedding_index))
-            }
-        } else {
-            EmbeddingState::Finish(None)
-        };
+/// Builds the catalog from the last synced snapshot, without any network access.
+pub fn load_model_cards_snapshot<P: AsRef<Path>>(
+    app_data_dir: P,
+) -> anyhow::Result<ModelCardManager> {
+    let index_list = read_index_snapshot(app_data_dir.as_ref())?;
+    ModelCardManager::new(
+        app_data_dir,
+        ModelCardManager::DEFAULT_COUNTRY_CODE.to_string(),
+        index_list,
+        true,
+    )
+}
 
-    Ok(ModelCardManager {
-        app_data_dir: app_data_dir.as_ref().to_path_buf(),
-        embedding_index,
-        country_code,
-        indexs,
-        caches: HashMap::new(),
-    })
+fn read_index_snapshot(app_data_dir: &Path) -> anyhow::Result<Vec<ModelIndex>> {
+    let index_list = std::fs::read_to_string(app_data_dir.join(INDEX_SNAPSHOT_NAME))
+        .or_else(|_| std::fs::read_to_string(app_data_dir.join(REPO_NAME).join("index.json")))?;
+    let index_list: Vec<ModelIndex> = serde_json::from_str(&index_list)?;
+    Ok(index_list)
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -340,6 +357,10 @@ pub struct ModelIndex {
 
 impl ModelIndex {
     pub fn load_model_card(&self, app_data_dir: &Path) -> anyhow::Result<ModelCard> {
+        self.load_model_card_from_repo(&app_data_dir.join(REPO_NAME))
+    }
+
+    fn load_model_card_from_repo(&self, repo_dir: &Path) -> anyhow::Result<ModelCard> {
         let (org_name, model_name) = self
             .id
             .split_once("/")
@@ -351,12 +372,19 @@ impl ModelIndex {
             model_name
         };
 
-        let model_card_path = app_data_dir
-            .join(REPO_NAME)
-            .join(org_name)
-            .join(format!("{}.json", sub_name));
+        let model_card_path = repo_dir.join(org_name).join(format!("{}.json", sub_name));
         let model_card = std::fs::read_to_string(model_card_path)?;
         let mut model_card: ModelCard = serde_json::from_str(&model_card)?;
+        for file in &model_card.files {
+            if let Some(first_part) = file.parts.first() {
+                if first_part.name != file.name {
+                    return Err(anyhow::anyhow!(
+                        "The first part of {} must have the same name as the file",
+                        file.name
+                    ));
+                }
+            }
+        }
         model_card.like_count = self.like_count;
         model_card.download_count = self.download_count;
 
@@ -397,6 +425,10 @@ pub struct ModelCard {
     pub released_at: DateTime<Utc>,
     #[serde(default)]
     pub files: Vec<RemoteFile>,
+    /// LoRA adapters made for this model. They download like its files, under the same
+    /// model id.
+    #[serde(default)]
+    pub adapters: Vec<RemoteFile>,
     pub prompt_template: String,
     pub reverse_prompt: String,
     pub context_size: u64,
@@ -423,6 +455,54 @@ pub struct RemoteFile {
     pub sha256: Option<String>,
     #[serde(default)]
     pub download: HashMap<String, String>,
+    /// Ordered parts of a sharded file (`*-00001-of-00004.gguf`).
+    /// The first part has the same name as the file and is the one passed to WasmEdge.
+    #[serde(default)]
+    pub parts: Vec<RemoteFilePart>,
+    /// Multimodal projector needed by vision models, downloaded along with the file.
+    #[serde(default)]
+    pub mmproj: Option<RemoteFilePart>,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct RemoteFilePart {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub download: HashMap<String, String>,
+}
+
+impl ModelCard {
+    /// The file of the card with this name, and whether it is an adapter.
+    pub fn find_file(&self, name: &str) -> Option<(&RemoteFile, bool)> {
+        let files = self.files.iter().map(|f| (f, false));
+        let adapters = self.adapters.iter().map(|f| (f, true));
+        files.chain(adapters).find(|(f, _)| f.name == name)
+    }
+}
+
+impl RemoteFile {
+    /// The parts of the file, a file that is not sharded is its own single part.
+    pub fn parts(&self) -> Vec<RemoteFilePart> {
+        if self.parts.is_empty() {
+            vec![RemoteFilePart {
+                name: self.name.clone(),
+                sha256: self.sha256.clone(),
+                download: self.download.clone(),
+            }]
+        } else {
+            self.parts.clone()
+        }
+    }
+
+    /// Everything to download for this file: its parts followed by its projector, if any.
+    pub fn download_parts(&self) -> Vec<RemoteFilePart> {
+        let mut parts = self.parts();
+        parts.extend(self.mmproj.clone());
+        parts
+    }
 }
 
 impl ModelCard {
@@ -439,6 +519,7 @@ impl ModelCard {
         fn to_file(
             model_id: &str,
             remote_files: &[RemoteFile],
+            is_adapter: bool,
             save_files: &HashMap<Arc<String>, super::download_files::DownloadedFile>,
         ) -> rusqlite::Result<Vec<moly_protocol::data::File>> {
             let mut files = vec![];
@@ -463,6 +544,8 @@ impl ModelCard {
                     downloaded_path,
                     tags: remote_f.tags.clone(),
                     featured: false,
+                    is_adapter,
+                    mmproj: remote_f.mmproj.as_ref().map(|part| part.name.clone()),
                 };
 
                 files.push(file);
@@ -474,6 +557,10 @@ impl ModelCard {
         let mut models = Vec::with_capacity(remote_models.len());
 
         for remote_m in remote_models {
+            // The adapters are listed after the files they apply to.
+            let mut model_files = to_file(&remote_m.id, &remote_m.files, false, &files)?;
+            model_files.extend(to_file(&remote_m.id, &remote_m.adapters, true, &files)?);
+
             let model = moly_protocol::data::Model {
                 id: remote_m.id.clone(),
                 name: remote_m.name.clone(),
@@ -482,7 +569,7 @@ impl ModelCard {
                 requires: remote_m.requires.clone(),
                 architecture: remote_m.architecture.clone(),
                 released_at: remote_m.released_at.clone(),
-                files: to_file(&remote_m.id, &remote_m.files, &files)?,
+                files: model_files,
                 author: moly_protocol::data::Author {
                     name: remote_m.author.name.clone(),
                     url: remote_m.author.url.clone(),
@@ -506,6 +593,7 @@ pub struct ModelCardManager {
     embedding_index: EmbeddingState,
     indexs: HashMap<String, ModelIndex>,
     caches: HashMap<String, ModelCard>,
+    card_errors: Vec<ModelCardError>,
 }
 
 pub enum EmbeddingState {
@@ -516,6 +604,71 @@ pub enum EmbeddingState {
 impl ModelCardManager {
     const DEFAULT_COUNTRY_CODE: &'static str = "default";
 
+    fn new<P: AsRef<Path>>(
+        app_data_dir: P,
+        country_code: String,
+        index_list: Vec<ModelIndex>,
+        offline: bool,
+    ) -> anyhow::Result<Self> {
+        // Every card is parsed upfront, so a broken one is reported instead of
+        // silently missing from the search results.
+        let mut indexs = HashMap::with_capacity(index_list.len());
+        let mut caches = HashMap::with_capacity(index_list.len());
+        let mut card_errors = vec![];
+        for index in index_list {
+            match index.load_model_card(app_data_dir.as_ref()) {
+                Ok(card) => {
+                    caches.insert(index.id.clone(), card);
+                    indexs.insert(index.id.clone(), index);
+                }
+                Err(e) => {
+                    let error = ModelCardError {
+                        model_id: index.id,
+                        error: e.to_string(),
+                    };
+                    log::warn!("Skipping invalid model card {error}");
+                    card_errors.push(error);
+                }
+            }
+        }
+
+        let repo_dirs = app_data_dir.as_ref().join(REPO_NAME);
+        let embedding_index = if let Ok(embedding_index) =
+            std::fs::read_to_string(repo_dirs.join("embedding.json"))
+        {
+            let embedding_index: EmbeddingIndex = serde_json::from_str(&embedding_index)?;
+            if embedding_index.check_file_exist(app_data_dir.as_ref()) {
+                EmbeddingState::Finish(Some(embedding_index))
+            } else if offline {
+                log::info!("Embedding model is missing, skipping its download in offline mode");
+                EmbeddingState::Finish(None)
+            } else {
+                let app_data_dir_path = app_data_dir.as_ref().to_path_buf();
+                let r = std::thread::spawn(move || {
+                    if let Ok(_) = embedding_index.download(&app_data_dir_path) {
+                        log::debug!("Downloaded embedding model ok");
+                        Some(embedding_index)
+                    } else {
+                        log::warn!("Failed to download embedding model");
+                        None
+                    }
+                });
+                EmbeddingState::Pending(r)
+            }
+        } else {
+            EmbeddingState::Finish(None)
+        };
+
+        Ok(Self {
+            app_data_dir: app_data_dir.as_ref().to_path_buf(),
+            embedding_index,
+            country_code,
+            indexs,
+            caches,
+            card_errors,
+        })
+    }
+
     pub fn empty(app_data_dir: PathBuf) -> Self {
         Self {
             app_data_dir,
@@ -523,6 +676,7 @@ impl ModelCardManager {
             caches: HashMap::new(),
             country_code: Self::DEFAULT_COUNTRY_CODE.to_string(),
             embedding_index: EmbeddingState::Finish(None),
+            card_errors: vec![],
         }
     }
 
@@ -534,6 +688,11 @@ impl ModelCardManager {
         Ok(r.clone())
     }
 
+    /// The cards of the current catalog that failed to load.
+    pub fn card_errors(&self) -> &[ModelCardError] {
+        &self.card_errors
+    }
+
     pub fn get_index_by_id(&self, id: &str) -> Option<&ModelIndex> {
         self.indexs.get(id)
     }
@@ -643,3 +802,116 @@ impl EmbeddingIndex {
         Ok(())
     }
 }
+
+#[test]
+fn test_invalid_model_cards_are_reported() {
+    let app_data_dir = std::env::temp_dir().join(format!(
+        "moly_test_invalid_model_cards_{}",
+        uuid::Uuid::new_v4()
+    ));
+    let repo_dir = app_data_dir.join(REPO_NAME);
+    std::fs::create_dir_all(repo_dir.join("org")).unwrap();
+
+    std::fs::write(
+        repo_dir.join("index.json"),
+        r#"[
+            {"id": "org/good", "name": "good", "model_type": "chat"},
+            {"id": "org/broken", "name": "broken", "model_type": "chat"}
+        ]"#,
+    )
+    .unwrap();
+    std::fs::write(
+        repo_dir.join("org").join("good.json"),
+        r#"{
+            "id": "org/good",
+            "released_at": "2024-01-01T00:00:00Z",
+            "prompt_template": "llama-2-chat",
+            "reverse_prompt": "",
+            "context_size": 4096,
+            "author": {"name": "org", "url": "", "description": ""}
+        }"#,
+    )
+    .unwrap();
+    std::fs::write(repo_dir.join("org").join("broken.json"), "{").unwrap();
+
+    let validation_errors = validate_cards(&repo_dir).unwrap();
+    assert_eq!(validation_errors.len(), 1);
+    assert_eq!(validation_errors[0].model_id, "org/broken");
+
+    let manager = load_model_cards_snapshot(&app_data_dir).unwrap();
+    assert_eq!(manager.card_errors().len(), 1);
+    assert_eq!(manager.card_errors()[0].model_id, "org/broken");
+
+    let found = manager.search("", 100, 0).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, "org/good");
+
+    let _ = std::fs::remove_dir_all(&app_data_dir);
+}
+
+#[test]
+fn test_invalid_commit_keeps_known_good_cards() {
+    let repo_dir = std::env::temp_dir().join(format!(
+        "moly_test_known_good_cards_{}",
+        uuid::Uuid::new_v4()
+    ));
+    let repo = Repository::init(&repo_dir).unwrap();
+    let commit = |files: &[(&str, &str)]| {
+        for (name, content) in files {
+            let path = repo_dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("moly", "moly@example.com").unwrap();
+        let parent = repo
+            .refname_to_id("refs/heads/main")
+            .ok()
+            .map(|id| repo.find_commit(id).unwrap());
+        repo.commit(
+            Some("refs/heads/main"),
+            &signature,
+            &signature,
+            "cards",
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )
+        .unwrap()
+    };
+
+    let good = commit(&[
+        (
+            "index.json",
+            r#"[{"id": "org/good", "name": "good", "model_type": "chat"}]"#,
+        ),
+        (
+            "org/good.json",
+            r#"{
+                "id": "org/good",
+                "released_at": "2024-01-01T00:00:00Z",
+                "prompt_template": "llama-2-chat",
+                "reverse_prompt": "",
+                "context_size": 4096,
+                "author": {"name": "org", "url": "", "description": ""}
+            }"#,
+        ),
+    ]);
+    assert_eq!(
+        check_out_valid(&repo, "main", good).unwrap(),
+        MirrorCommit::Fetched(good)
+    );
+
+    // An index that can't be parsed is not kept either.
+    let broken = commit(&[("index.json", "[{")]);
+    assert_eq!(
+        check_out_valid(&repo, "main", broken).unwrap(),
+        MirrorCommit::KnownGood(good)
+    );
+    assert!(validate_cards(&repo_dir).unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(&repo_dir);
+}
@@ -21,10 +21,69 @@ pub fn create_table_models(conn: &rusqlite::Connection) -> rusqlite::Result<()>
             author_url TEXT NOT NULL,
             author_description TEXT NOT NULL,
             like_count INTEGER NOT NULL,
-            download_count INTEGER NOT NULL
+            download_count INTEGER NOT NULL,
+            known_files TEXT NOT NULL DEFAULT '[]'
         )",
         (),
     )?;
+
+    check_known_files(conn)?;
+
+    // Kept apart from `models`, which is replaced whenever a file of the model is downloaded.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS model_engines (
+            model_id TEXT PRIMARY KEY,
+            engine TEXT NOT NULL
+        )",
+        (),
+    )?;
+
+    Ok(())
+}
+
+/// The engines chosen for specific models, by model id.
+pub fn get_engine_overrides(
+    conn: &rusqlite::Connection,
+) -> rusqlite::Result<HashMap<String, String>> {
+    let mut stmt = conn.prepare("SELECT model_id, engine FROM model_engines")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Sets the engine used for a model, or goes back to the default one with `None`.
+pub fn set_engine_override(
+    conn: &rusqlite::Connection,
+    model_id: &str,
+    engine: Option<&str>,
+) -> rusqlite::Result<()> {
+    match engine {
+        Some(engine) => conn.execute(
+            "INSERT OR REPLACE INTO model_engines (model_id, engine) VALUES (?1, ?2)",
+            params![model_id, engine],
+        )?,
+        None => conn.execute(
+            "DELETE FROM model_engines WHERE model_id = ?1",
+            params![model_id],
+        )?,
+    };
+    Ok(())
+}
+
+fn check_known_files(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("PRAGMA table_info(models)")?;
+    let mut rows = stmt.query_map([], |row| {
+        let name: String = row.get(1)?;
+        Ok(name)
+    })?;
+
+    let check = rows.find(|row| matches!(row.as_deref(), Ok("known_files")));
+
+    if check.is_none() {
+        conn.execute(
+            "ALTER TABLE models ADD COLUMN known_files TEXT NOT NULL DEFAULT '[]'",
+            [],
+        )?;
+    }
     Ok(())
 }
 
@@ -42,6 +101,9 @@ pub struct Model {
     pub author: Arc<Author>,
     pub like_count: u32,
     pub download_count: u32,
+    /// Names of the files listed in the model card when the model was last saved,
+    /// used to tell which files were published afterwards.
+    pub known_files: Vec<String>,
 }
 
 impl Model {
@@ -50,8 +112,8 @@ impl Model {
             "INSERT OR REPLACE INTO models (
                 id, name, summary, size, requires, architecture, released_at, 
                 prompt_template, reverse_prompt, author_name, author_url, 
-                author_description, like_count, download_count)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                author_description, like_count, download_count, known_files)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
             params![
                 self.id,
                 self.name,
@@ -66,12 +128,38 @@ impl Model {
                 self.author.url,
                 self.author.description,
                 self.like_count,
-                self.download_count
+                self.download_count,
+                serde_json::to_string(&self.known_files).unwrap(),
             ],
         )?;
         Ok(())
     }
 
+    pub fn update_known_files(
+        conn: &rusqlite::Connection,
+        id: &str,
+        known_files: &[String],
+    ) -> rusqlite::Result<()> {
+        conn.execute(
+            "UPDATE models SET known_files = ?2 WHERE id = ?1",
+            params![id, serde_json::to_string(known_files).unwrap()],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_prompt_template(
+        conn: &rusqlite::Connection,
+        id: &str,
+        prompt_template: &str,
+        reverse_prompt: &str,
+    ) -> rusqlite::Result<()> {
+        conn.execute(
+            "UPDATE models SET prompt_template = ?2, reverse_prompt = ?3 WHERE id = ?1",
+            params![id, prompt_template, reverse_prompt],
+        )?;
+        Ok(())
+    }
+
     pub fn get_all(conn: &rusqlite::Connection) -> rusqlite::Result<HashMap<String, Model>> {
         let mut stmt = conn.prepare("SELECT * FROM models")?;
         let mut rows = stmt.query([])?;
@@ -89,6 +177,8 @@ impl Model {
             });
 
             let id = row.get::<_, String>(0)?;
+            let known_files =
+                serde_json::from_str(row.get::<_, String>(14)?.as_str()).unwrap_or_default();
 
             models.insert(
                 id.clone(),
@@ -105,6 +195,7 @@ impl Model {
                     author,
                     like_count: row.get(12)?,
                     download_count: row.get(13)?,
+                    known_files,
                 },
             );
         }
@@ -139,6 +230,7 @@ fn test_sql() {
         author,
         like_count: 0,
         download_count: 0,
+        known_files: vec!["file1".to_string()],
     };
 
     model.save_to_db(&conn).unwrap();
@@ -146,3 +238,18 @@ fn test_sql() {
     assert_eq!(models.len(), 1);
     assert_eq!(models[model.id.as_ref()], model);
 }
+
+#[test]
+fn test_engine_overrides() {
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    create_table_models(&conn).unwrap();
+
+    set_engine_override(&conn, "model1", Some("ChatBotModel")).unwrap();
+    set_engine_override(&conn, "model2", Some("ChatBotModel")).unwrap();
+    set_engine_override(&conn, "model2", Some("LLamaEdgeApiServer")).unwrap();
+    set_engine_override(&conn, "model1", None).unwrap();
+
+    let overrides = get_engine_overrides(&conn).unwrap();
+    assert_eq!(overrides.len(), 1);
+    assert_eq!(overrides["model2"], "LLamaEdgeApiServer");
+}
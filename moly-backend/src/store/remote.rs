@@ -5,14 +5,30 @@ use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 
 use moly_protocol::data::Model;
-use moly_protocol::protocol::FileDownloadResponse;
+use moly_protocol::protocol::{BackendEvent, DownloadState, FileDownloadResponse, MolyError};
 use std::time::Duration;
 use tokio::time::timeout;
 
-use crate::backend_impls::DownloadControlCommand;
+use crate::backend_impls::{DownloadControlCommand, EventBus};
 
-async fn get_file_content_length(client: &reqwest::Client, url: &str) -> reqwest::Result<u64> {
-    let response = client.head(url).send().await?;
+/// Classifies an error of a request to `url`, an error status is reported as [`MolyError::Http`].
+pub fn network_error(url: &str, e: reqwest::Error) -> MolyError {
+    match e.status() {
+        Some(status) => MolyError::Http {
+            url: url.to_string(),
+            status: status.as_u16(),
+        },
+        None => MolyError::Network(e.to_string()),
+    }
+}
+
+async fn get_file_content_length(client: &reqwest::Client, url: &str) -> Result<u64, MolyError> {
+    let response = client
+        .head(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| network_error(url, e))?;
 
     let content_length = response
         .headers()
@@ -24,6 +40,51 @@ async fn get_file_content_length(client: &reqwest::Client, url: &str) -> reqwest
     Ok(content_length)
 }
 
+/// Computes the sha256 of a file as a lowercase hex string.
+pub fn file_sha256<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Checks the size of a downloaded part and its sha256, only hashed when the model card
+/// provides one.
+async fn verify_part(
+    path: &Path,
+    content_length: u64,
+    sha256: Option<&str>,
+) -> Result<(), MolyError> {
+    let file_length = std::fs::metadata(path)?.len();
+    if content_length > 0 && file_length != content_length {
+        return Err(MolyError::IncompleteDownload {
+            path: path.display().to_string(),
+            expected: content_length,
+            actual: file_length,
+        });
+    }
+
+    let Some(expected) = sha256.filter(|s| !s.is_empty()) else {
+        return Ok(());
+    };
+
+    let path_ = path.to_path_buf();
+    let actual = tokio::task::spawn_blocking(move || file_sha256(path_))
+        .await
+        .map_err(|e| MolyError::Other(e.to_string()))??;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(MolyError::ChecksumMismatch {
+            path: path.display().to_string(),
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+
+    Ok(())
+}
+
 pub enum DownloadResult {
     Completed(f64),
     Stopped(f64),
@@ -35,8 +96,8 @@ async fn download_file<P: AsRef<Path>>(
     url: &str,
     local_path: P,
     step: f64,
-    report_fn: &mut (dyn FnMut(f64) -> anyhow::Result<()> + Send),
-) -> anyhow::Result<DownloadResult> {
+    report_fn: &mut (dyn FnMut(f64) -> Result<(), MolyError> + Send),
+) -> Result<DownloadResult, MolyError> {
     use futures_util::stream::StreamExt;
 
     let path: &Path = local_path.as_ref();
@@ -55,7 +116,8 @@ async fn download_file<P: AsRef<Path>>(
             .header("Range", range)
             .send()
             .await
-            .map_err(|e| anyhow::anyhow!(e))?;
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| network_error(url, e))?;
 
         let mut downloaded: u64 = file_length;
         let mut last_progress = 0.0;
@@ -63,9 +125,12 @@ async fn download_file<P: AsRef<Path>>(
         let mut stream = resp.bytes_stream();
 
         loop {
-            match timeout(Duration::from_secs(10), stream.next()).await? {
+            let next = timeout(Duration::from_secs(10), stream.next())
+                .await
+                .map_err(|_| MolyError::Network(format!("download of {url} timed out")))?;
+            match next {
                 Some(chunk) => {
-                    let chunk = chunk.map_err(|e| anyhow::anyhow!(e))?;
+                    let chunk = chunk.map_err(|e| network_error(url, e))?;
                     let len = chunk.len();
                     file.write_all(&chunk)?;
                     downloaded += len as u64;
@@ -101,6 +166,7 @@ pub struct ModelFileDownloader {
     client: reqwest::Client,
     sql_conn: Arc<Mutex<rusqlite::Connection>>,
     control_tx: tokio::sync::broadcast::Sender<DownloadControlCommand>,
+    events: EventBus,
     country_code: String,
     step: f64,
 }
@@ -111,6 +177,7 @@ impl ModelFileDownloader {
         client: reqwest::Client,
         sql_conn: Arc<Mutex<rusqlite::Connection>>,
         control_tx: tokio::sync::broadcast::Sender<DownloadControlCommand>,
+        events: EventBus,
         country_code: String,
         step: f64,
     ) -> Self {
@@ -118,6 +185,7 @@ impl ModelFileDownloader {
             client,
             sql_conn,
             control_tx,
+            events,
             country_code,
             step,
         }
@@ -125,21 +193,19 @@ impl ModelFileDownloader {
 
     fn get_download_url(
         &self,
-        file: &super::download_files::DownloadedFile,
-        remote_file: &super::model_cards::RemoteFile,
+        model_id: &str,
+        part: &super::model_cards::RemoteFilePart,
     ) -> String {
-        remote_file
-            .download
+        part.download
             .get(&self.country_code)
             .cloned()
             .unwrap_or_else(|| {
-                remote_file
-                    .download
+                part.download
                     .get(Self::DEFAULT_COUNTRY_CODE)
                     .cloned()
                     .unwrap_or(format!(
                         "https://huggingface.co/{}/resolve/main/{}",
-                        file.model_id, file.name
+                        model_id, part.name
                     ))
             })
     }
@@ -147,10 +213,15 @@ impl ModelFileDownloader {
     async fn download(
         self,
         file: super::download_files::DownloadedFile,
-        remote_file: super::model_cards::RemoteFile,
+        parts: Vec<(super::model_cards::RemoteFilePart, u64)>,
         tx: Sender<anyhow::Result<FileDownloadResponse>>,
     ) {
         let file_id = file.id.to_string();
+        let events = self.events.clone();
+        events.publish(BackendEvent::DownloadStateChanged(
+            file_id.clone(),
+            DownloadState::Downloading,
+        ));
 
         let mut send_progress = |progress| {
             let r = tx.send(Ok(FileDownloadResponse::Progress(
@@ -158,22 +229,45 @@ impl ModelFileDownloader {
                 progress as f32,
             )));
             log::debug!("send progress {file_id} {progress} {r:?}");
+            events.publish(BackendEvent::DownloadProgress(
+                file_id.clone(),
+                progress as f32,
+            ));
             Ok(())
         };
 
         let r = self
-            .download_file_from_remote(file, remote_file, &mut send_progress)
+            .download_file_from_remote(file, parts, &mut send_progress)
             .await;
 
         match r {
             Ok(Some(response)) => {
+                events.publish(BackendEvent::DownloadStateChanged(
+                    file_id,
+                    DownloadState::Completed,
+                ));
                 let _ = tx.send(Ok(response));
             }
             Ok(None) => {
                 // TODO Implement file removal when download is stopped, nothing to do when it is paused
             }
             Err(e) => {
-                let _ = tx.send(Err(e));
+                events.publish(BackendEvent::DownloadStateChanged(
+                    file_id,
+                    DownloadState::Failed(e.to_string()),
+                ));
+                // A corrupted part is removed once the UI knows why, so the next attempt
+                // downloads it again instead of resuming it.
+                let corrupted = match &e {
+                    MolyError::ChecksumMismatch { path, .. } => Some(path.clone()),
+                    _ => None,
+                };
+                let _ = tx.send(Err(e.into()));
+                if let Some(path) = corrupted {
+                    if let Err(e) = std::fs::remove_file(&path) {
+                        log::warn!("Failed to remove the corrupted download {path}: {e}");
+                    }
+                }
             }
         }
     }
@@ -191,37 +285,55 @@ impl ModelFileDownloader {
         let semaphore = Arc::new(tokio::sync::Semaphore::new(max_downloader));
 
         while let Some((model, mut file, remote_file, tx)) = download_rx.recv().await {
-            let url = downloader.get_download_url(&file, &remote_file);
-            log::info!("Downloading file: {}", url);
-
             let f = async {
-                let content_length = get_file_content_length(&downloader.client, &url)
-                    .await
-                    .map_err(|e| anyhow::anyhow!(e))?;
+                let mut parts = vec![];
+                for part in remote_file.download_parts() {
+                    let url = downloader.get_download_url(&file.model_id, &part);
+                    log::info!("Downloading file: {}", url);
+
+                    let content_length = get_file_content_length(&downloader.client, &url).await?;
+                    parts.push((part, content_length));
+                }
 
                 {
-                    file.file_size = content_length;
+                    file.file_size = parts.iter().map(|(_, len)| len).sum();
                     let conn = downloader.sql_conn.lock().unwrap();
                     // insert a pending download
-                    file.insert_into_db(&conn).map_err(|e| anyhow::anyhow!(e))?;
-                    model.save_to_db(&conn).map_err(|e| anyhow::anyhow!(e))?;
+                    file.insert_into_db(&conn).map_err(super::db_error)?;
+                    model.save_to_db(&conn).map_err(super::db_error)?;
                 }
 
-                Ok(())
+                Ok(parts)
             };
 
-            let r: anyhow::Result<()> = f.await;
+            let r: Result<_, MolyError> = f.await;
+
+            let parts = match r {
+                Ok(parts) => parts,
+                Err(e) => {
+                    downloader
+                        .events
+                        .publish(BackendEvent::DownloadStateChanged(
+                            file.id.to_string(),
+                            DownloadState::Failed(e.to_string()),
+                        ));
+                    let _ = tx.send(Err(e.into()));
+                    continue;
+                }
+            };
 
-            if let Err(e) = r {
-                let _ = tx.send(Err(e));
-                continue;
-            }
+            downloader
+                .events
+                .publish(BackendEvent::DownloadStateChanged(
+                    file.id.to_string(),
+                    DownloadState::Queued,
+                ));
 
             let downloader_ = downloader.clone();
             let semaphore_ = semaphore.clone();
             tokio::spawn(async move {
                 let permit = semaphore_.acquire_owned().await.unwrap();
-                downloader_.download(file, remote_file, tx).await;
+                downloader_.download(file, parts, tx).await;
                 drop(permit);
             });
         }
@@ -230,14 +342,12 @@ impl ModelFileDownloader {
     async fn download_file_from_remote(
         &self,
         mut file: super::download_files::DownloadedFile,
-        remote_file: super::model_cards::RemoteFile,
-        report_fn: &mut (dyn FnMut(f64) -> anyhow::Result<()> + Send),
-    ) -> anyhow::Result<Option<FileDownloadResponse>> {
-        let url = self.get_download_url(&file, &remote_file);
-
-        let local_path = Path::new(&file.download_dir)
-            .join(&file.model_id)
-            .join(&file.name);
+        parts: Vec<(super::model_cards::RemoteFilePart, u64)>,
+        report_fn: &mut (dyn FnMut(f64) -> Result<(), MolyError> + Send),
+    ) -> Result<Option<FileDownloadResponse>, MolyError> {
+        let model_dir = Path::new(&file.download_dir).join(&file.model_id);
+        // For a sharded file this is the first part, which is what gets loaded.
+        let local_path = model_dir.join(&file.name);
 
         let file_id_ = file.id.as_ref().clone();
         let mut control_rx = self.control_tx.subscribe();
@@ -253,15 +363,40 @@ impl ModelFileDownloader {
             }
         };
 
+        // The parts and the projector are downloaded one after the other and reported
+        // as a single progress.
+        let total_length = file.file_size.max(1) as f64;
+        let download_parts = async {
+            let mut downloaded: u64 = 0;
+            for (part, content_length) in &parts {
+                let url = self.get_download_url(&file.model_id, part);
+                let part_path = model_dir.join(&part.name);
+
+                let done = downloaded as f64;
+                let part_length = *content_length as f64;
+                let mut report_part = |progress: f64| {
+                    report_fn((done + part_length * progress / 100.0) / total_length * 100.0)
+                };
+
+                download_file(
+                    &self.client,
+                    *content_length,
+                    &url,
+                    &part_path,
+                    self.step,
+                    &mut report_part,
+                )
+                .await?;
+                verify_part(&part_path, *content_length, part.sha256.as_deref()).await?;
+
+                downloaded += content_length;
+            }
+
+            Ok::<_, MolyError>(DownloadResult::Completed(100.0))
+        };
+
         let r = tokio::select! {
-            r = download_file(
-                &self.client,
-                file.file_size,
-                &url,
-                &local_path,
-                self.step,
-                report_fn,
-            ) => r?,
+            r = download_parts => r?,
             r = listen_control_cmd => {
                 r
             }
@@ -275,7 +410,7 @@ impl ModelFileDownloader {
                     let _ = file.update_downloaded(&conn);
                 }
 
-                Ok(Some(FileDownloadResponse::Completed(
+                Ok(Some(FileDownloadResponse::Completed(Box::new(
                     moly_protocol::data::DownloadedFile {
                         file: moly_protocol::data::File {
                             id: file.id.as_ref().clone(),
@@ -291,6 +426,8 @@ impl ModelFileDownloader {
                             ),
                             tags: file.tags,
                             featured: false,
+                            is_adapter: file.is_adapter,
+                            mmproj: Some(file.mmproj).filter(|mmproj| !mmproj.is_empty()),
                         },
                         model: Model::default(),
                         downloaded_at: file.downloaded_at,
@@ -298,9 +435,47 @@ impl ModelFileDownloader {
                             moly_protocol::data::CompatibilityGuess::PossiblySupported,
                         information: String::new(),
                     },
-                )))
+                ))))
             }
             DownloadResult::Stopped(_) => Ok(None),
         }
     }
 }
+
+#[test]
+fn test_verify_part() {
+    let dir = std::env::temp_dir().join(format!("moly_test_verify_part_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("model.gguf");
+    std::fs::write(&path, b"hello").unwrap();
+    // sha256 of "hello".
+    let sha256 = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        assert!(verify_part(&path, 5, None).await.is_ok());
+        assert!(verify_part(&path, 5, Some("")).await.is_ok());
+        assert!(verify_part(&path, 5, Some(sha256)).await.is_ok());
+        assert!(matches!(
+            verify_part(&path, 6, None).await,
+            Err(MolyError::IncompleteDownload {
+                expected: 6,
+                actual: 5,
+                ..
+            })
+        ));
+        match verify_part(&path, 5, Some("00")).await {
+            Err(MolyError::ChecksumMismatch {
+                expected, actual, ..
+            }) => {
+                assert_eq!(expected, "00");
+                assert_eq!(actual, sha256);
+            }
+            r => panic!("unexpected result: {r:?}"),
+        }
+    });
+    // Removing the part is left to the caller, once the error is reported.
+    assert!(path.exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,49 @@
+//! Locations and settings shared by the Moly app, `moly-runner` and `moly-daemon`,
+//! so all of them use the same app data directory, preferences and models.
+
+use std::path::PathBuf;
+
+use directories::ProjectDirs;
+
+pub const APP_QUALIFIER: &str = "com";
+pub const APP_ORGANIZATION: &str = "moxin-org";
+pub const APP_NAME: &str = "moly";
+
+/// The file of the app preferences, within the preferences directory.
+pub const PREFERENCES_FILENAME: &str = "preferences.json";
+
+/// The directory models are downloaded to by default, within the app data directory.
+pub const MODEL_DOWNLOADS_DIR_NAME: &str = "model_downloads";
+
+/// Set by `moly-runner --offline`, forces the offline mode regardless of the preferences.
+pub const OFFLINE_ENV_VAR: &str = "MOLY_OFFLINE";
+
+/// The directories of Moly, `None` if the home directory of the user can't be found.
+pub fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from(APP_QUALIFIER, APP_ORGANIZATION, APP_NAME)
+}
+
+pub fn preferences_path(dirs: &ProjectDirs) -> PathBuf {
+    dirs.preference_dir().join(PREFERENCES_FILENAME)
+}
+
+/// Whether the value of a boolean environment variable is set: `true`, `t` or `1`, in
+/// any case.
+pub fn is_truthy(value: &str) -> bool {
+    ["true", "t", "1"].contains(&value.to_lowercase().as_str())
+}
+
+/// Whether Moly was launched with `--offline`, see [`OFFLINE_ENV_VAR`].
+pub fn offline_from_env() -> bool {
+    std::env::var(OFFLINE_ENV_VAR).is_ok_and(|value| is_truthy(&value))
+}
+
+#[test]
+fn test_is_truthy() {
+    for value in ["true", "TRUE", "t", "T", "1"] {
+        assert!(is_truthy(value), "{value}");
+    }
+    for value in ["", "false", "0", "yes", "11"] {
+        assert!(!is_truthy(value), "{value}");
+    }
+}
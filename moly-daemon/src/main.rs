@@ -0,0 +1,307 @@
+//! `moly-daemon` runs the Moly backend as a headless service.
+//!
+//! The daemon owns the app data directory, the model catalog, the downloader and
+//! the loaded model, exactly like the backend embedded in the desktop app does.
+//! Clients talk to it with the message-based protocol from [`moly_protocol::wire`],
+//! either over a Unix domain socket (the default) or over stdio.
+//!
+//! The daemon links against WasmEdge, so it must be started with the same environment
+//! as the main Moly app. The simplest way is to run it through `moly-runner`,
+//! which finds (or installs) the app-local WasmEdge before starting it:
+//! ```sh
+//! moly daemon [--socket <path>] [--models-dir <path>] [--stdio] [--offline]
+//! ```
+//!
+//! The desktop app attaches to a running daemon instead of starting its own backend
+//! when the `MOLY_DAEMON_SOCKET` environment variable points to the daemon socket.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use moly_backend::Backend;
+use moly_config::MODEL_DOWNLOADS_DIR_NAME;
+use moly_protocol::wire;
+
+/// The file name of the socket, within the app data directory, used when no `--socket` is given.
+const DEFAULT_SOCKET_FILE_NAME: &str = "moly-daemon.sock";
+/// Same default as the desktop app.
+const DEFAULT_MAX_DOWNLOAD_THREADS: usize = 3;
+
+const USAGE: &str = "Usage: moly-daemon [--socket <path>] [--models-dir <path>] \
+    [--max-download-threads <n>] [--stdio] [--offline]";
+
+#[derive(Debug, Default)]
+struct Args {
+    socket: Option<PathBuf>,
+    models_dir: Option<PathBuf>,
+    max_download_threads: Option<usize>,
+    stdio: bool,
+    offline: bool,
+}
+
+/// Parses the command line arguments, without the name of the binary.
+fn parse_args(cli_args: impl IntoIterator<Item = String>) -> anyhow::Result<Args> {
+    let mut args = Args::default();
+    let mut iter = cli_args.into_iter();
+
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .ok_or_else(|| anyhow!("Missing value for {arg}\n{USAGE}"))
+        };
+
+        match arg.as_str() {
+            "--socket" => args.socket = Some(value()?.into()),
+            "--models-dir" => args.models_dir = Some(value()?.into()),
+            "--max-download-threads" => args.max_download_threads = Some(value()?.parse()?),
+            "--stdio" => args.stdio = true,
+            "--offline" => args.offline = true,
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ => return Err(anyhow!("Unknown argument {arg}\n{USAGE}")),
+        }
+    }
+
+    Ok(args)
+}
+
+/// Uses the models directory chosen in the desktop app preferences, if any, so both
+/// share the downloaded models.
+fn default_models_dir(preferences_path: &Path, app_data_dir: &Path) -> PathBuf {
+    std::fs::read_to_string(preferences_path)
+        .ok()
+        .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok())
+        .and_then(|preferences| {
+            preferences
+                .get("downloaded_files_dir")?
+                .as_str()
+                .map(PathBuf::from)
+        })
+        .filter(|dir| dir.exists())
+        .unwrap_or_else(|| app_data_dir.join(MODEL_DOWNLOADS_DIR_NAME))
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = parse_args(std::env::args().skip(1))?;
+    args.offline |= moly_config::offline_from_env();
+
+    // Only the backend and its runtimes write to the stderr of the daemon, so its last
+    // lines can be reported when a model crashes.
+    let _stderr_capture = moly_backend::capture_stderr()
+        .map_err(|e| eprintln!("Not capturing stderr: {e}"))
+        .ok();
+
+    // The runtimes log to the stdout of the process too, which must only carry the
+    // protocol when serving over stdio.
+    let stdout = args.stdio.then(protocol_stdout).transpose()?;
+
+    let dirs = moly_config::project_dirs()
+        .ok_or_else(|| anyhow!("Failed to obtain Moly project directories"))?;
+    let app_data_dir = dirs.data_dir();
+    let models_dir = args
+        .models_dir
+        .unwrap_or_else(|| default_models_dir(&moly_config::preferences_path(&dirs), app_data_dir));
+    std::fs::create_dir_all(&models_dir)?;
+
+    eprintln!(
+        "Starting the Moly backend:
+        app data dir: {}
+        models dir:   {}
+        offline:      {}",
+        app_data_dir.display(),
+        models_dir.display(),
+        args.offline,
+    );
+
+    let backend = Backend::new(
+        app_data_dir,
+        &models_dir,
+        args.max_download_threads
+            .unwrap_or(DEFAULT_MAX_DOWNLOAD_THREADS),
+        args.offline,
+    );
+
+    if let Some(stdout) = stdout {
+        return wire::serve(backend.command_sender.clone(), std::io::stdin(), stdout);
+    }
+
+    let socket = args
+        .socket
+        .unwrap_or_else(|| app_data_dir.join(DEFAULT_SOCKET_FILE_NAME));
+    serve_socket(&socket, &backend)
+}
+
+/// Takes the stdout of the process for the protocol, and sends whatever is written to
+/// stdout from now on to stderr instead.
+#[cfg(unix)]
+fn protocol_stdout() -> std::io::Result<Box<dyn Write + Send>> {
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: plain calls on file descriptors, the duplicate is owned by the file.
+    unsafe {
+        let stdout = libc::dup(libc::STDOUT_FILENO);
+        if stdout < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            let e = std::io::Error::last_os_error();
+            libc::close(stdout);
+            return Err(e);
+        }
+        Ok(Box::new(std::fs::File::from_raw_fd(stdout)))
+    }
+}
+
+#[cfg(not(unix))]
+fn protocol_stdout() -> std::io::Result<Box<dyn Write + Send>> {
+    Ok(Box::new(std::io::stdout()))
+}
+
+#[cfg(unix)]
+fn serve_socket(socket: &Path, backend: &Backend) -> anyhow::Result<()> {
+    eprintln!("Listening on {}", socket.display());
+    wire::serve_unix_socket(socket, backend.command_sender.clone())
+}
+
+#[cfg(not(unix))]
+fn serve_socket(_socket: &Path, _backend: &Backend) -> anyhow::Result<()> {
+    Err(anyhow!(
+        "Unix domain sockets are not available on this platform, use --stdio instead"
+    ))
+}
+
+#[test]
+fn test_parse_args() {
+    let parse = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string()));
+
+    let args = parse(&[]).unwrap();
+    assert!(args.socket.is_none() && !args.stdio && !args.offline);
+
+    let args = parse(&[
+        "--socket",
+        "/tmp/moly.sock",
+        "--models-dir",
+        "/models",
+        "--max-download-threads",
+        "5",
+        "--stdio",
+        "--offline",
+    ])
+    .unwrap();
+    assert_eq!(args.socket, Some(PathBuf::from("/tmp/moly.sock")));
+    assert_eq!(args.models_dir, Some(PathBuf::from("/models")));
+    assert_eq!(args.max_download_threads, Some(5));
+    assert!(args.stdio && args.offline);
+
+    assert!(parse(&["--socket"]).is_err());
+    assert!(parse(&["--max-download-threads", "many"]).is_err());
+    assert!(parse(&["--unknown"]).is_err());
+}
+
+#[test]
+fn test_default_models_dir() {
+    let dir = std::env::temp_dir().join(format!("moly-daemon-test-{}", std::process::id()));
+    let preferences_path = dir.join(moly_config::PREFERENCES_FILENAME);
+    let app_data_dir = dir.join("data");
+    let downloads_dir = app_data_dir.join(MODEL_DOWNLOADS_DIR_NAME);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Without preferences.
+    assert_eq!(
+        default_models_dir(&preferences_path, &app_data_dir),
+        downloads_dir
+    );
+
+    // With the directory chosen in the app, as long as it still exists.
+    let chosen_dir = dir.join("chosen");
+    let preferences = serde_json::json!({ "downloaded_files_dir": chosen_dir });
+    std::fs::write(&preferences_path, preferences.to_string()).unwrap();
+    assert_eq!(
+        default_models_dir(&preferences_path, &app_data_dir),
+        downloads_dir
+    );
+    std::fs::create_dir_all(&chosen_dir).unwrap();
+    assert_eq!(
+        default_models_dir(&preferences_path, &app_data_dir),
+        chosen_dir
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_protocol_stdout() {
+    use moly_protocol::{data::Model, protocol::Command};
+    use std::os::fd::FromRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    // Stdout is a pipe read by the client, like when the CLI starts the daemon.
+    let mut fds = [0; 2];
+    // SAFETY: plain calls on file descriptors owned by the test.
+    let (original, from_server) = unsafe {
+        assert_eq!(libc::pipe(fds.as_mut_ptr()), 0);
+        let original = libc::dup(libc::STDOUT_FILENO);
+        assert!(libc::dup2(fds[1], libc::STDOUT_FILENO) >= 0);
+        libc::close(fds[1]);
+        (original, std::fs::File::from_raw_fd(fds[0]))
+    };
+    let stdout = protocol_stdout().unwrap();
+
+    // A runtime logging to stdout while the replies are written.
+    let logging = Arc::new(AtomicBool::new(true));
+    let logging_ = logging.clone();
+    let logger = thread::spawn(move || {
+        while logging_.load(Ordering::Acquire) {
+            let line = b"runtime log\n";
+            // SAFETY: writes a valid buffer to fd 1.
+            unsafe { libc::write(libc::STDOUT_FILENO, line.as_ptr().cast(), line.len()) };
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+
+    let (to_server, server_in) = UnixStream::pair().unwrap();
+    let (backend_tx, backend_rx) = channel::<Command>();
+    thread::spawn(move || wire::serve(backend_tx, server_in, stdout));
+    thread::spawn(move || {
+        for command in backend_rx {
+            if let Command::GetFeaturedModels(tx) = command {
+                let model = Model {
+                    summary: "x".repeat(256 * 1024),
+                    ..Default::default()
+                };
+                let _ = tx.send(Ok(vec![model]));
+            }
+        }
+    });
+    let command_sender = wire::connect(from_server, to_server);
+
+    for _ in 0..5 {
+        let (tx, rx) = channel();
+        command_sender.send(Command::GetFeaturedModels(tx)).unwrap();
+        // A corrupted reply or end of the request would be skipped, and never come.
+        let timeout = Duration::from_secs(10);
+        let models = rx.recv_timeout(timeout).unwrap().unwrap();
+        assert_eq!(models[0].summary.len(), 256 * 1024);
+        assert!(matches!(
+            rx.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+
+    logging.store(false, Ordering::Release);
+    logger.join().unwrap();
+    // SAFETY: puts back the stdout of the test.
+    unsafe {
+        libc::dup2(original, libc::STDOUT_FILENO);
+        libc::close(original);
+    }
+}
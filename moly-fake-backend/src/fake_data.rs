@@ -12,6 +12,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: None,
             tags: vec![],
             featured: false,
+            is_adapter: false,
+            mmproj: None,
         },
         File {
             id: "2".to_string(),
@@ -22,6 +24,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: None,
             tags: vec![],
             featured: false,
+            is_adapter: false,
+            mmproj: None,
         },
         File {
             id: "3".to_string(),
@@ -32,6 +36,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: None,
             tags: vec![],
             featured: false,
+            is_adapter: false,
+            mmproj: None,
         },
         File {
             id: "4".to_string(),
@@ -42,6 +48,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: None,
             tags: vec![],
             featured: false,
+            is_adapter: false,
+            mmproj: None,
         },
         File {
             id: "5".to_string(),
@@ -52,6 +60,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: None,
             tags: vec![],
             featured: false,
+            is_adapter: false,
+            mmproj: None,
         },
         File {
             id: "6".to_string(),
@@ -62,6 +72,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: Some("/home/user/.moly/stablelm-zephyr-3b.Q4_K_S.gguf".to_string()),
             tags: vec!["Small & Fast".to_string()],
             featured: true,
+            is_adapter: false,
+            mmproj: None,
         },
         File {
             id: "7".to_string(),
@@ -72,6 +84,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: None,
             tags: vec!["Less Compressed".to_string(), "Might be slower".to_string()],
             featured: true,
+            is_adapter: false,
+            mmproj: None,
         },
     ];
 
@@ -85,6 +99,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: None,
             tags: vec!["Small & Fast".to_string()],
             featured: true,
+            is_adapter: false,
+            mmproj: None,
         },
         File {
             id: "9".to_string(),
@@ -95,6 +111,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: Some("/home/user/.moly/nexusraven-v2-13b.Q6_K.gguf".to_string()),
             tags: vec!["Less Compressed".to_string(), "Might be slower".to_string()],
             featured: true,
+            is_adapter: false,
+            mmproj: None,
         },
     ];
 
@@ -108,6 +126,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: Some("/home/user/.moly/nexusraven-v2-13b.Q4_K_S.gguf".to_string()),
             tags: vec!["Small & Fast".to_string()],
             featured: true,
+            is_adapter: false,
+            mmproj: None,
         },
         File {
             id: "11".to_string(),
@@ -118,6 +138,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: None,
             tags: vec!["Less Compressed".to_string(), "Might be slower".to_string()],
             featured: true,
+            is_adapter: false,
+            mmproj: None,
         },
     ];
 
@@ -131,6 +153,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: None,
             tags: vec!["Less Compressed".to_string(), "Might be slower".to_string()],
             featured: true,
+            is_adapter: false,
+            mmproj: None,
         },
         File {
             id: "TheBloke/Llama-2-7B-Chat-GGUF#llama-2-7b-chat.Q2_K.gguf".to_string(),
@@ -141,6 +165,8 @@ pub fn get_models() -> Vec<Model> {
             downloaded_path: None,
             tags: vec!["Less Compressed".to_string(), "Might be slower".to_string()],
             featured: true,
+            is_adapter: false,
+            mmproj: None,
         },
     ];
 
@@ -1,6 +1,6 @@
 pub mod fake_data;
 
-use moly_protocol::protocol::Command;
+use moly_protocol::protocol::{BackendFeatures, BackendInfo, Command, PROTOCOL_VERSION};
 use std::sync::mpsc;
 
 pub struct Backend {
@@ -35,6 +35,22 @@ impl Backend {
                                 .collect();
                             tx.send(Ok(filtered)).unwrap();
                         }
+                        Command::GetBackendInfo(tx) => {
+                            tx.send(Ok(BackendInfo {
+                                protocol_version: PROTOCOL_VERSION,
+                                implementation: "fake".to_string(),
+                                engines: vec!["fake".to_string()],
+                                supported_commands: vec![
+                                    "GetFeaturedModels".to_string(),
+                                    "SearchModels".to_string(),
+                                    "GetBackendInfo".to_string(),
+                                ],
+                                features: BackendFeatures::default(),
+                                wasmedge_version: None,
+                                plugins: vec![],
+                            }))
+                            .unwrap();
+                        }
                         _ => {}
                     }
                 }
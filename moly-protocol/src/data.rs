@@ -19,6 +19,14 @@ pub struct File {
     pub tags: Vec<String>,
     #[serde(default)]
     pub featured: bool,
+    /// A LoRA adapter of the model, loaded on top of one of its other files with
+    /// `LoadModelOptions::lora_adapters`.
+    #[serde(default)]
+    pub is_adapter: bool,
+    /// The multimodal projector loaded along with the file, which the model needs to
+    /// take images.
+    #[serde(default)]
+    pub mmproj: Option<String>,
 }
 
 #[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
@@ -28,7 +36,7 @@ pub struct Author {
     pub description: String,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
 pub enum CompatibilityGuess {
     #[default]
     PossiblySupported,
@@ -44,7 +52,7 @@ impl CompatibilityGuess {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct DownloadedFile {
     pub file: File,
     pub model: Model,
@@ -53,7 +61,7 @@ pub struct DownloadedFile {
     pub information: String,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
 pub enum PendingDownloadsStatus {
     #[default]
     Initializing,
@@ -62,7 +70,7 @@ pub enum PendingDownloadsStatus {
     Error,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct PendingDownload {
     pub file: File,
     pub model: Model,
@@ -88,3 +96,16 @@ pub struct Model {
     pub download_count: u32,
     pub metrics: HashMap<String, f32>,
 }
+
+/// A model card listed in the catalog index that could not be loaded.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct ModelCardError {
+    pub model_id: ModelID,
+    pub error: String,
+}
+
+impl std::fmt::Display for ModelCardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.model_id, self.error)
+    }
+}
@@ -1,3 +1,4 @@
 pub mod data;
 pub mod open_ai;
 pub mod protocol;
+pub mod wire;
@@ -1,16 +1,87 @@
 use crate::data::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Message {
-    pub content: String,
+    pub content: MessageContent,
     pub role: Role,
     pub name: Option<String>,
 }
 
-// Based on https://platform.openai.com/docs/api-reference/chat/object
+/// Content of a message, plain text or a list of parts when it includes images.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// The text of the message, without its images.
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    ContentPart::ImageUrl { .. } => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ContentPart {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(rename = "image_url")]
+    ImageUrl { image_url: ImageUrl },
+}
+
+impl ContentPart {
+    /// An image read from a local file. The backend inlines it before sending the request.
+    pub fn image_from_path(path: &std::path::Path) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl {
+                url: format!("file://{}", path.display()),
+            },
+        }
+    }
+
+    /// An image already encoded in base64, e.g. `image/png`.
+    pub fn image_from_base64(mime_type: &str, data: &str) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl {
+                url: format!("data:{};base64,{}", mime_type, data),
+            },
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+// Based on https://platform.openai.com/docs/api-reference/chat/object
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ChatRequestData {
     pub messages: Vec<Message>,
 
@@ -19,23 +90,84 @@ pub struct ChatRequestData {
     pub model: ModelID,
 
     pub frequency_penalty: Option<f32>,
-    #[serde(skip)]
+    /// Whether the chunks carry the log probability of their tokens, see [`LogProbsData`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub logprobs: Option<bool>,
-    #[serde(skip)]
+    /// How many of the most likely tokens to return at each position, with `logprobs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub top_logprobs: Option<u32>,
     pub max_tokens: Option<u32>,
     pub presence_penalty: Option<f32>,
-    #[serde(skip)]
+    /// The same seed and parameters give the same answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub seed: Option<u32>,
     pub stop: Option<Vec<String>>,
     pub stream: Option<bool>,
     pub temperature: Option<f32>,
     pub top_p: Option<f32>,
 
+    // Sampling parameters of llama.cpp that are not part of the OpenAI API. The server
+    // uses its defaults for the ones that are not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typical_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+    /// How many of the last tokens `repeat_penalty` looks at, -1 for the whole context.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_last_n: Option<i32>,
+    /// 0 to disable it, 1 for Mirostat and 2 for Mirostat 2.0. When enabled it replaces
+    /// the other samplers but the temperature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirostat: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirostat_tau: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirostat_eta: Option<f32>,
+
     // Adding the following fields since there are part of the OpenAI API,
     // but are not likely to be used in the first version of the client
     pub n: Option<u32>,
     pub logit_bias: Option<HashMap<String, f32>>,
+
+    // Requests of different users take turns when they have to wait for each other
+    pub user: Option<String>,
+
+    /// Constrains the output to JSON, see [`ResponseFormat`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// A GBNF grammar the output must follow, as in llama.cpp. It can't be combined
+    /// with a `response_format` other than text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<String>,
+}
+
+// Based on https://platform.openai.com/docs/api-reference/chat/create#chat-create-response_format
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum ResponseFormat {
+    #[serde(rename = "text")]
+    Text,
+    /// Any valid JSON object.
+    #[serde(rename = "json_object")]
+    JsonObject,
+    /// JSON matching a schema. An answer that doesn't match it is followed by a
+    /// [`MolyError::SchemaMismatch`](crate::protocol::MolyError::SchemaMismatch).
+    #[serde(rename = "json_schema")]
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub schema: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
 }
 
 // Shared structs for ChatResponse and ChatResponseChunk
@@ -68,14 +200,26 @@ pub struct LogProbsItemData {
     pub token: String,
     pub logprob: f32,
     pub bytes: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub top_logprobs: Vec<TopLogProbsItemData>,
 }
 
+/// The log probabilities of the tokens of a message, or of a chunk when streaming.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LogProbsData {
+    #[serde(default, deserialize_with = "null_as_default")]
     pub content: Vec<LogProbsItemData>,
 }
 
+// Some servers send `null` instead of an empty list.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum StopReason {
     #[serde(rename = "stop")]
@@ -138,6 +282,9 @@ pub struct ChatResponseChunkData {
     pub created: u32,
     pub model: ModelID,
     pub system_fingerprint: String,
+    /// Sent by some servers with the last chunk of the answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageData>,
 
     #[serde(default = "response_chunk_object")]
     pub object: String,
@@ -147,8 +294,16 @@ fn response_chunk_object() -> String {
     "chat.completion.chunk".to_string()
 }
 
-#[derive(Clone, Debug)]
+/// Identifies a chat request, to stop it with `Command::StopChatCompletion`.
+pub type ChatRequestID = u64;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ChatResponse {
+    // The first response of every request
+    Accepted(ChatRequestID),
+    // Sent while the request waits for a free slot, with the number of requests
+    // that will start before it. Sent again whenever that number changes
+    Queued(usize),
     // https://platform.openai.com/docs/api-reference/chat/object
     ChatFinalResponseData(ChatResponseData),
     // https://platform.openai.com/docs/api-reference/chat/streaming
@@ -1,44 +1,189 @@
 use crate::data::*;
 use crate::open_ai::*;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 
-#[derive(Clone, Debug)]
+/// Version of the commands and data types of this crate, bumped on breaking changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum FileDownloadResponse {
     Progress(FileID, f32),
-    Completed(DownloadedFile),
+    Completed(Box<DownloadedFile>),
 }
 
-#[derive(Clone, Debug)]
+/// Difference between the model catalog and a model in the local library,
+/// found after the model cards are synced.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LibraryUpdate {
+    /// A file of a downloaded model that was published after the download.
+    NewFile(ModelID, File),
+    /// The file was replaced upstream, the local copy no longer matches its checksum.
+    ChecksumChanged {
+        file_id: FileID,
+        local_sha256: String,
+        remote_sha256: String,
+    },
+    /// The prompt template or the reverse prompt of the model changed upstream.
+    /// It can be applied with `Command::ApplyTemplateUpdate` without downloading the file again.
+    TemplateChanged {
+        file_id: FileID,
+        prompt_template: String,
+        reverse_prompt: String,
+    },
+}
+
+/// Result of checking a downloaded file against the checksums of the model catalog.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum FileVerification {
+    /// Every part of the file is on disk and matches its checksum.
+    Valid,
+    /// The files are on disk but the catalog has no checksum to compare with.
+    Unchecked,
+    /// A part of the file, or its projector, is not on disk.
+    Missing(String),
+    ChecksumMismatch {
+        part: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ContextOverflowPolicy {
     StopAtLimit,
     TruncateMiddle,
     TruncatePastMessages,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum GPULayers {
     Specific(u32),
     Max,
 }
 
-#[derive(Clone, Debug)]
+/// The data type of the KV cache. The smaller ones take less memory at some cost
+/// in quality.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum KvCacheType {
+    F16,
+    Q8_0,
+    Q4_0,
+}
+
+impl KvCacheType {
+    /// The name llama.cpp knows it by.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KvCacheType::F16 => "f16",
+            KvCacheType::Q8_0 => "q8_0",
+            KvCacheType::Q4_0 => "q4_0",
+        }
+    }
+}
+
+/// How a model is split across several GPUs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum SplitMode {
+    /// Everything on the main GPU.
+    None,
+    /// Whole layers on each GPU.
+    #[default]
+    Layer,
+    /// Rows of the tensors on each GPU.
+    Row,
+}
+
+impl SplitMode {
+    /// The name llama.cpp knows it by.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SplitMode::None => "none",
+            SplitMode::Layer => "layer",
+            SplitMode::Row => "row",
+        }
+    }
+}
+
+/// Changing any of them reloads the model.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LoadModelOptions {
     pub override_server_address: Option<String>,
     pub prompt_template: Option<String>,
     pub gpu_layers: GPULayers,
     pub use_mlock: bool,
     pub n_batch: Option<u32>,
+    // Chosen by the backend from the model and the free memory when None.
     pub n_ctx: Option<u32>,
+    // 0 means the value the model was trained with.
     pub rope_freq_scale: f32,
     pub rope_freq_base: f32,
     // TBD Not really sure if this is something backend manages or if it is matter of
     // the client (if it is done by tweaking the JSON payload for the chat completition)
     pub context_overflow_policy: ContextOverflowPolicy,
+    // The number of CPU threads, all of them when None.
+    #[serde(default)]
+    pub n_threads: Option<u32>,
+    // The physical batch size, at most `n_batch`.
+    #[serde(default)]
+    pub n_ubatch: Option<u32>,
+    #[serde(default)]
+    pub flash_attention: bool,
+    // For both keys and values, f16 when None.
+    #[serde(default)]
+    pub kv_cache_type: Option<KvCacheType>,
+    #[serde(default)]
+    pub split_mode: SplitMode,
+    // Applied on top of the model, in order.
+    #[serde(default)]
+    pub lora_adapters: Vec<LoraAdapter>,
 }
 
-#[derive(Clone, Debug)]
+/// A downloaded LoRA adapter of the model being loaded, see [`File::is_adapter`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoraAdapter {
+    pub file_id: FileID,
+    /// How much the adapter changes the model, 1.0 as it was trained.
+    pub scale: f32,
+}
+
+/// The options the app loads models with when nothing else is chosen.
+impl Default for LoadModelOptions {
+    fn default() -> Self {
+        Self {
+            override_server_address: None,
+            prompt_template: None,
+            gpu_layers: GPULayers::Max,
+            use_mlock: false,
+            n_batch: None,
+            n_ctx: None,
+            rope_freq_scale: 0.0,
+            rope_freq_base: 0.0,
+            context_overflow_policy: ContextOverflowPolicy::StopAtLimit,
+            n_threads: None,
+            n_ubatch: None,
+            flash_attention: false,
+            kv_cache_type: None,
+            split_mode: SplitMode::default(),
+            lora_adapters: vec![],
+        }
+    }
+}
+
+/// Named options to load a downloaded file with. Presets are shared as JSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoadPreset {
+    pub name: String,
+    /// The preset the app loads the file with. At most one per file.
+    #[serde(default)]
+    pub is_default: bool,
+    /// `override_server_address` is not kept, it is chosen when loading.
+    pub options: LoadModelOptions,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LoadedModelInfo {
     pub file_id: FileID,
     pub model_id: ModelID,
@@ -49,22 +194,44 @@ pub struct LoadedModelInfo {
 
     // JSON formatted string with the model information. See "Model Inspector" in LMStudio.
     pub information: String,
+
+    // The context size the model was loaded with, in tokens.
+    #[serde(default)]
+    pub n_ctx: u32,
 }
 
-#[derive(Clone, Debug)]
+/// A sample of what the whole backend process is using. The model runs within it, but
+/// so does the rest of the backend, and the app too when it embeds the backend instead
+/// of connecting to the daemon.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ModelResourcesInfo {
+    // Resident memory, in GB.
     pub ram_usage: f32,
+    // Since the previous sample, in percent of a single core.
     pub cpu_usage: f32,
 }
 
-#[derive(Clone, Debug)]
+/// The steps of a model load, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LoadStage {
+    /// Opening the model file.
+    Mapping,
+    /// Handing the model to the runtime.
+    Preloading,
+    /// Waiting for the runtime to be ready for chats.
+    WarmingUp,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum LoadModelResponse {
-    Progress(FileID, f32),
+    /// The current stage of the load and how far the whole load is, from 0 to 1.
+    Progress(FileID, LoadStage, f32),
     Completed(LoadedModelInfo),
+    /// Sent every few seconds after `Completed`, until the model is unloaded.
     ModelResourcesUsage(ModelResourcesInfo),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LocalServerConfig {
     pub port: u16,
     pub cors: bool,
@@ -73,12 +240,320 @@ pub struct LocalServerConfig {
     pub apply_prompt_formatting: bool,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum LocalServerResponse {
     Started,
     Log(String),
 }
 
+/// What the engine running the models can do.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BackendFeatures {
+    pub streaming: bool,
+    pub embeddings: bool,
+    pub tool_calls: bool,
+    pub logprobs: bool,
+    /// Images in chat messages, for models with a projector file.
+    pub vision: bool,
+    /// Loading models with [`LoadModelOptions::lora_adapters`].
+    #[serde(default)]
+    pub lora_adapters: bool,
+    /// Chats with a [`ResponseFormat`](crate::open_ai::ResponseFormat) or a grammar.
+    #[serde(default)]
+    pub constrained_output: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackendInfo {
+    pub protocol_version: u32,
+    /// The name of the engine models are loaded with unless they have an override,
+    /// e.g. `LLamaEdgeApiServer` or `ChatBotModel`.
+    pub implementation: String,
+    /// Names of the engines that can be selected with `Command::SetEngine`.
+    pub engines: Vec<String>,
+    /// Names of the commands that are handled, see [`Command::name`].
+    pub supported_commands: Vec<String>,
+    pub features: BackendFeatures,
+    pub wasmedge_version: Option<String>,
+    /// File names of the WasmEdge plugins found in the plugin directory.
+    pub plugins: Vec<String>,
+}
+
+impl BackendInfo {
+    pub fn supports(&self, command_name: &str) -> bool {
+        self.supported_commands
+            .iter()
+            .any(|name| name == command_name)
+    }
+}
+
+/// State of a download, as broadcast by [`BackendEvent::DownloadStateChanged`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DownloadState {
+    /// The download was accepted and waits for a free download slot.
+    Queued,
+    Downloading,
+    Paused,
+    Cancelled,
+    Completed,
+    Failed(String),
+}
+
+/// Something that happened in the backend, broadcast to every subscriber
+/// registered with `Command::Subscribe` regardless of who issued the command causing it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum BackendEvent {
+    DownloadProgress(FileID, f32),
+    DownloadStateChanged(FileID, DownloadState),
+
+    ModelLoaded(LoadedModelInfo),
+    ModelEjected(FileID),
+    ModelLoadFailed(FileID, String),
+    /// The runtime of the loaded model stopped working. It is restarted with the same
+    /// options, and the chats it was answering fail with [`MolyError::ModelCrashed`].
+    ModelCrashed {
+        file_id: FileID,
+        reason: String,
+        /// The last lines written to stderr before the crash, if the backend process
+        /// captures it (see `moly_backend::capture_stderr`), empty otherwise.
+        stderr: String,
+    },
+    /// The loaded model was ejected after the idle timeout. [`BackendEvent::ModelEjected`]
+    /// follows.
+    ModelIdle(FileID),
+    /// A file of the preload queue was read ahead, the next load of it is faster.
+    FileWarmed(FileID),
+
+    /// The model catalog was synced, with the model cards that could not be read and are
+    /// missing from it. New subscribers receive the last sync result right away.
+    CatalogSynced(Vec<ModelCardError>),
+    CatalogSyncFailed(String),
+
+    /// The server of the loaded model listens on this port for other clients, which
+    /// keeps working across restarts after a crash. New subscribers receive it right away
+    /// while the server is running.
+    LocalServerListening(u16),
+    /// The server that listened on this port stopped, with the model it served.
+    LocalServerStopped(u16),
+}
+
+/// The kinds of [`BackendEvent`] a subscriber is interested in.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EventFilter {
+    pub downloads: bool,
+    pub models: bool,
+    pub catalog: bool,
+    #[serde(default)]
+    pub local_server: bool,
+}
+
+impl EventFilter {
+    pub fn all() -> Self {
+        Self {
+            downloads: true,
+            models: true,
+            catalog: true,
+            local_server: true,
+        }
+    }
+
+    pub fn matches(&self, event: &BackendEvent) -> bool {
+        match event {
+            BackendEvent::DownloadProgress(..) | BackendEvent::DownloadStateChanged(..) => {
+                self.downloads
+            }
+            BackendEvent::ModelLoaded(_)
+            | BackendEvent::ModelEjected(_)
+            | BackendEvent::ModelLoadFailed(..)
+            | BackendEvent::ModelCrashed { .. }
+            | BackendEvent::ModelIdle(_)
+            | BackendEvent::FileWarmed(_) => self.models,
+            BackendEvent::CatalogSynced(_) | BackendEvent::CatalogSyncFailed(_) => self.catalog,
+            BackendEvent::LocalServerListening(_) | BackendEvent::LocalServerStopped(_) => {
+                self.local_server
+            }
+        }
+    }
+}
+
+/// Error of the backend commands.
+///
+/// The response channels carry `anyhow::Result`, the backend sends a `MolyError`
+/// as the error so the client can get it back with `err.downcast_ref::<MolyError>()`.
+/// Its [`code`](MolyError::code) is stable and can be matched by clients that only see
+/// the serialized form.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MolyError {
+    /// The operation needs network access and the backend is running in offline mode.
+    Offline,
+    /// A chat was requested before any model was loaded.
+    ModelNotLoaded,
+    /// A file id that doesn't have the `<model_id>#<file_name>` form.
+    InvalidFileId(String),
+    /// The model is not in the catalog.
+    ModelNotFound(ModelID),
+    /// The file is neither in the catalog nor in the local library.
+    FileNotFound(FileID),
+    /// The port where the model server should listen is taken by another process.
+    PortInUse(u16),
+    /// The server answered a request with an error status.
+    Http {
+        url: String,
+        status: u16,
+    },
+    /// The request could not be completed, e.g. no connection or a timeout.
+    Network(String),
+    /// A downloaded file ended before its expected size.
+    IncompleteDownload {
+        path: String,
+        expected: u64,
+        actual: u64,
+    },
+    /// A downloaded file does not match the checksum of the model catalog.
+    ChecksumMismatch {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+    OutOfMemory(String),
+    ModelLoadFailed {
+        file_id: FileID,
+        reason: String,
+    },
+    /// The runtime of the model stopped while answering, see [`BackendEvent::ModelCrashed`].
+    ModelCrashed(String),
+    /// The model server sent something that could not be parsed.
+    InvalidResponse(String),
+    Io(String),
+    Database(String),
+    Catalog(String),
+    /// No engine is registered with this name, see [`BackendInfo::engines`].
+    UnknownEngine(String),
+    /// The file is not a downloaded LoRA adapter of the model being loaded.
+    IncompatibleAdapter {
+        adapter: FileID,
+        model_id: ModelID,
+    },
+    /// The response format or the grammar of a chat request can't be used.
+    InvalidResponseFormat(String),
+    /// The output of the model doesn't match the JSON schema of the request.
+    SchemaMismatch(String),
+    /// The command is not handled by the backend, see [`BackendInfo::supported_commands`].
+    Unsupported(String),
+    Other(String),
+}
+
+impl MolyError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            MolyError::Offline => "offline",
+            MolyError::ModelNotLoaded => "model_not_loaded",
+            MolyError::InvalidFileId(_) => "invalid_file_id",
+            MolyError::ModelNotFound(_) => "model_not_found",
+            MolyError::FileNotFound(_) => "file_not_found",
+            MolyError::PortInUse(_) => "port_in_use",
+            MolyError::Http { .. } => "http",
+            MolyError::Network(_) => "network",
+            MolyError::IncompleteDownload { .. } => "incomplete_download",
+            MolyError::ChecksumMismatch { .. } => "checksum_mismatch",
+            MolyError::OutOfMemory(_) => "out_of_memory",
+            MolyError::ModelLoadFailed { .. } => "model_load_failed",
+            MolyError::ModelCrashed(_) => "model_crashed",
+            MolyError::InvalidResponse(_) => "invalid_response",
+            MolyError::Io(_) => "io",
+            MolyError::Database(_) => "database",
+            MolyError::Catalog(_) => "catalog",
+            MolyError::UnknownEngine(_) => "unknown_engine",
+            MolyError::IncompatibleAdapter { .. } => "incompatible_adapter",
+            MolyError::InvalidResponseFormat(_) => "invalid_response_format",
+            MolyError::SchemaMismatch(_) => "schema_mismatch",
+            MolyError::Unsupported(_) => "unsupported",
+            MolyError::Other(_) => "other",
+        }
+    }
+
+    /// Whether trying the same command again later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            MolyError::Network(_)
+            | MolyError::IncompleteDownload { .. }
+            | MolyError::ModelCrashed(_) => true,
+            MolyError::Http { status, .. } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Gets the `MolyError` sent as an `anyhow::Error`. Errors from anywhere else are
+    /// kept as [`MolyError::Other`] with their message.
+    pub fn from_anyhow(error: &anyhow::Error) -> MolyError {
+        error
+            .downcast_ref::<MolyError>()
+            .cloned()
+            .unwrap_or_else(|| MolyError::Other(format!("{error:#}")))
+    }
+}
+
+impl std::fmt::Display for MolyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MolyError::Offline => write!(f, "This operation is not available in offline mode"),
+            MolyError::ModelNotLoaded => write!(f, "No model is loaded"),
+            MolyError::InvalidFileId(file_id) => write!(f, "Invalid file id {file_id}"),
+            MolyError::ModelNotFound(model_id) => write!(f, "Model {model_id} not found"),
+            MolyError::FileNotFound(file_id) => write!(f, "File {file_id} not found"),
+            MolyError::PortInUse(port) => write!(f, "Port {port} is already in use"),
+            MolyError::Http { url, status } => write!(f, "HTTP {status} from {url}"),
+            MolyError::Network(e) => write!(f, "Network error: {e}"),
+            MolyError::IncompleteDownload {
+                path,
+                expected,
+                actual,
+            } => write!(f, "{path} is incomplete: {actual} of {expected} bytes"),
+            MolyError::ChecksumMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Checksum mismatch for {path}: expected {expected}, got {actual}"
+            ),
+            MolyError::OutOfMemory(e) => write!(f, "Out of memory: {e}"),
+            MolyError::ModelLoadFailed { file_id, reason } => {
+                write!(f, "Failed to load {file_id}: {reason}")
+            }
+            MolyError::ModelCrashed(reason) => write!(f, "The model stopped working: {reason}"),
+            MolyError::InvalidResponse(e) => write!(f, "Invalid response from the model: {e}"),
+            MolyError::Io(e) => write!(f, "I/O error: {e}"),
+            MolyError::Database(e) => write!(f, "Database error: {e}"),
+            MolyError::Catalog(e) => write!(f, "Model catalog error: {e}"),
+            MolyError::UnknownEngine(name) => write!(f, "Unknown engine {name}"),
+            MolyError::IncompatibleAdapter { adapter, model_id } => {
+                write!(f, "{adapter} is not a LoRA adapter of {model_id}")
+            }
+            MolyError::InvalidResponseFormat(e) => write!(f, "Invalid response format: {e}"),
+            MolyError::SchemaMismatch(e) => {
+                write!(f, "The answer doesn't match the response format: {e}")
+            }
+            MolyError::Unsupported(command) => {
+                write!(f, "The {command} command is not supported by this backend")
+            }
+            MolyError::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for MolyError {}
+
+impl From<std::io::Error> for MolyError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::OutOfMemory => MolyError::OutOfMemory(e.to_string()),
+            std::io::ErrorKind::TimedOut => MolyError::Network(e.to_string()),
+            _ => MolyError::Io(e.to_string()),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Command {
     GetFeaturedModels(Sender<Result<Vec<Model>>>),
@@ -93,20 +568,113 @@ pub enum Command {
     PauseDownload(FileID, Sender<Result<()>>),
     CancelDownload(FileID, Sender<Result<()>>),
     DeleteFile(FileID, Sender<Result<()>>),
+    // Hash the downloaded file and compare it with the checksums of the catalog
+    VerifyFile(FileID, Sender<Result<FileVerification>>),
 
     GetCurrentDownloads(Sender<Result<Vec<PendingDownload>>>),
     GetDownloadedFiles(Sender<Result<Vec<DownloadedFile>>>),
 
+    // Compare the downloaded models against the model catalog
+    GetLibraryUpdates(Sender<Result<Vec<LibraryUpdate>>>),
+    // Stop reporting the files of the model published so far as `LibraryUpdate::NewFile`
+    AcknowledgeNewFiles(ModelID, Sender<Result<()>>),
+    // Use the upstream prompt template of the model for an already downloaded file
+    ApplyTemplateUpdate(FileID, Sender<Result<()>>),
+
     LoadModel(FileID, LoadModelOptions, Sender<Result<LoadModelResponse>>),
 
     // Eject currently loaded model, if any is provided
     EjectModel(Sender<Result<()>>),
 
-    Chat(ChatRequestData, Sender<Result<ChatResponse>>),
-    StopChatCompletion(Sender<Result<()>>),
+    // Requests run concurrently up to a limit, the other ones wait in a queue
+    Chat(Box<ChatRequestData>, Sender<Result<ChatResponse>>),
+    // Stop a single chat request, running or queued, by its id
+    StopChatCompletion(ChatRequestID, Sender<Result<()>>),
+    // The ids of the tokens of the text with the tokenizer of the loaded model
+    Tokenize(String, Sender<Result<Vec<u32>>>),
+    // How many tokens the text is for the loaded model, the ones it starts with included
+    CountTokens(String, Sender<Result<u32>>),
+    // How many tokens the messages are for the loaded model once rendered with its chat
+    // template, the start of the answer included
+    CountChatTokens(Vec<Message>, Sender<Result<u32>>),
 
     // Command to start a local server to interact with chat models
     StartLocalServer(LocalServerConfig, Sender<Result<LocalServerResponse>>),
     // Command to stop the local server
     StopLocalServer(Sender<Result<()>>),
+
+    // Receive the events of the backend matching the filter, until the receiver is dropped
+    Subscribe(EventFilter, Sender<BackendEvent>),
+
+    // Describe the backend: versions, engine and what it supports
+    GetBackendInfo(Sender<Result<BackendInfo>>),
+
+    // Change the engine models are loaded with. A model loaded with another engine is ejected
+    SetEngine(String, Sender<Result<()>>),
+    // Always load this model with the given engine, or with the default one when None
+    SetModelEngine(ModelID, Option<String>, Sender<Result<()>>),
+
+    // The load presets saved for a downloaded file, by name
+    GetLoadPresets(FileID, Sender<Result<Vec<LoadPreset>>>),
+    // Save a load preset of the file, replacing the one with the same name
+    SaveLoadPreset(FileID, LoadPreset, Sender<Result<()>>),
+    DeleteLoadPreset(FileID, String, Sender<Result<()>>),
+
+    // Eject the loaded model after this many seconds without chats, never when None
+    SetIdleTimeout(Option<u64>, Sender<Result<()>>),
+    // Once the backend settles after startup, load the first file with its default load
+    // preset and read ahead the other ones, such that loading them later is faster
+    PreloadModels(Vec<FileID>, Sender<Result<()>>),
 }
+
+/// Implements [`Command::name`] and [`Command::NAMES`] from the same list, which has to
+/// include every command for the match to compile.
+macro_rules! command_names {
+    ($($variant:ident),* $(,)?) => {
+        impl Command {
+            /// The names of every command of the protocol.
+            pub const NAMES: &'static [&'static str] = &[$(stringify!($variant)),*];
+
+            /// The name of the command, as listed in [`BackendInfo::supported_commands`].
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Command::$variant(..) => stringify!($variant),)*
+                }
+            }
+        }
+    };
+}
+
+command_names!(
+    GetFeaturedModels,
+    ChangeModelsDir,
+    SearchModels,
+    DownloadFile,
+    PauseDownload,
+    CancelDownload,
+    DeleteFile,
+    VerifyFile,
+    GetCurrentDownloads,
+    GetDownloadedFiles,
+    GetLibraryUpdates,
+    AcknowledgeNewFiles,
+    ApplyTemplateUpdate,
+    LoadModel,
+    EjectModel,
+    Chat,
+    StopChatCompletion,
+    Tokenize,
+    CountTokens,
+    CountChatTokens,
+    StartLocalServer,
+    StopLocalServer,
+    Subscribe,
+    GetBackendInfo,
+    SetEngine,
+    SetModelEngine,
+    GetLoadPresets,
+    SaveLoadPreset,
+    DeleteLoadPreset,
+    SetIdleTimeout,
+    PreloadModels,
+);
@@ -0,0 +1,680 @@
+//! Message-based form of the protocol, used to run the backend out of process.
+//!
+//! A client sends [`Request`]s, each one with its own id, and receives a stream of
+//! [`Response`] frames carrying that id. The stream of a request is closed with
+//! [`ResponseBody::End`] once the backend drops the sender of the command.
+//!
+//! Messages are encoded as JSON, one per line, over any byte stream such as a Unix
+//! domain socket or the stdio of a child process.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::data::*;
+use crate::open_ai::*;
+use crate::protocol::*;
+
+pub type RequestID = u64;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub id: RequestID,
+    pub command: CommandPayload,
+}
+
+/// The arguments of a [`Command`], without its response sender.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum CommandPayload {
+    GetFeaturedModels,
+    ChangeModelsDir(PathBuf),
+    SearchModels(String),
+    DownloadFile(FileID),
+    PauseDownload(FileID),
+    CancelDownload(FileID),
+    DeleteFile(FileID),
+    VerifyFile(FileID),
+    GetCurrentDownloads,
+    GetDownloadedFiles,
+    GetLibraryUpdates,
+    AcknowledgeNewFiles(ModelID),
+    ApplyTemplateUpdate(FileID),
+    LoadModel(FileID, LoadModelOptions),
+    EjectModel,
+    Chat(Box<ChatRequestData>),
+    StopChatCompletion(ChatRequestID),
+    Tokenize(String),
+    CountTokens(String),
+    CountChatTokens(Vec<Message>),
+    StartLocalServer(LocalServerConfig),
+    StopLocalServer,
+    Subscribe(EventFilter),
+    GetBackendInfo,
+    SetEngine(String),
+    SetModelEngine(ModelID, Option<String>),
+    GetLoadPresets(FileID),
+    SaveLoadPreset(FileID, LoadPreset),
+    DeleteLoadPreset(FileID, String),
+    SetIdleTimeout(Option<u64>),
+    PreloadModels(Vec<FileID>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub id: RequestID,
+    pub body: ResponseBody,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ResponseBody {
+    Unit,
+    Models(Vec<Model>),
+    FileDownload(FileDownloadResponse),
+    PendingDownloads(Vec<PendingDownload>),
+    DownloadedFiles(Vec<DownloadedFile>),
+    LibraryUpdates(Vec<LibraryUpdate>),
+    FileVerification(FileVerification),
+    LoadModel(LoadModelResponse),
+    Chat(ChatResponse),
+    Tokens(Vec<u32>),
+    TokenCount(u32),
+    LocalServer(LocalServerResponse),
+    Event(BackendEvent),
+    BackendInfo(BackendInfo),
+    LoadPresets(Vec<LoadPreset>),
+    Error(MolyError),
+    /// No more frames will be sent for the request.
+    End,
+}
+
+/// The sender of a [`Command`] issued through [`connect`], waiting for the frames
+/// of its request.
+enum ReplySender {
+    None,
+    Unit(Sender<Result<()>>),
+    Models(Sender<Result<Vec<Model>>>),
+    FileDownload(Sender<Result<FileDownloadResponse>>),
+    PendingDownloads(Sender<Result<Vec<PendingDownload>>>),
+    DownloadedFiles(Sender<Result<Vec<DownloadedFile>>>),
+    LibraryUpdates(Sender<Result<Vec<LibraryUpdate>>>),
+    FileVerification(Sender<Result<FileVerification>>),
+    LoadModel(Sender<Result<LoadModelResponse>>),
+    Chat(Sender<Result<ChatResponse>>),
+    Tokens(Sender<Result<Vec<u32>>>),
+    TokenCount(Sender<Result<u32>>),
+    LocalServer(Sender<Result<LocalServerResponse>>),
+    Events(Sender<BackendEvent>),
+    BackendInfo(Sender<Result<BackendInfo>>),
+    LoadPresets(Sender<Result<Vec<LoadPreset>>>),
+}
+
+impl ReplySender {
+    fn send(&self, body: ResponseBody) {
+        macro_rules! reply {
+            ($tx:expr, $variant:path) => {{
+                let _ = match body {
+                    $variant(value) => $tx.send(Ok(value)),
+                    ResponseBody::Error(e) => $tx.send(Err(e.into())),
+                    other => $tx.send(Err(anyhow!("Unexpected response: {:?}", other))),
+                };
+            }};
+        }
+
+        match self {
+            ReplySender::None => {}
+            ReplySender::Unit(tx) => {
+                let _ = match body {
+                    ResponseBody::Unit => tx.send(Ok(())),
+                    ResponseBody::Error(e) => tx.send(Err(e.into())),
+                    other => tx.send(Err(anyhow!("Unexpected response: {:?}", other))),
+                };
+            }
+            ReplySender::Models(tx) => reply!(tx, ResponseBody::Models),
+            ReplySender::FileDownload(tx) => reply!(tx, ResponseBody::FileDownload),
+            ReplySender::PendingDownloads(tx) => reply!(tx, ResponseBody::PendingDownloads),
+            ReplySender::DownloadedFiles(tx) => reply!(tx, ResponseBody::DownloadedFiles),
+            ReplySender::LibraryUpdates(tx) => reply!(tx, ResponseBody::LibraryUpdates),
+            ReplySender::FileVerification(tx) => reply!(tx, ResponseBody::FileVerification),
+            ReplySender::LoadModel(tx) => reply!(tx, ResponseBody::LoadModel),
+            ReplySender::Chat(tx) => reply!(tx, ResponseBody::Chat),
+            ReplySender::Tokens(tx) => reply!(tx, ResponseBody::Tokens),
+            ReplySender::TokenCount(tx) => reply!(tx, ResponseBody::TokenCount),
+            ReplySender::LocalServer(tx) => reply!(tx, ResponseBody::LocalServer),
+            ReplySender::BackendInfo(tx) => reply!(tx, ResponseBody::BackendInfo),
+            ReplySender::LoadPresets(tx) => reply!(tx, ResponseBody::LoadPresets),
+            ReplySender::Events(tx) => {
+                if let ResponseBody::Event(event) = body {
+                    let _ = tx.send(event);
+                }
+            }
+        }
+    }
+}
+
+fn split_command(command: Command) -> (CommandPayload, ReplySender) {
+    use CommandPayload as P;
+    use ReplySender as R;
+
+    match command {
+        Command::GetFeaturedModels(tx) => (P::GetFeaturedModels, R::Models(tx)),
+        Command::ChangeModelsDir(path) => (P::ChangeModelsDir(path), R::None),
+        Command::SearchModels(query, tx) => (P::SearchModels(query), R::Models(tx)),
+        Command::DownloadFile(file_id, tx) => (P::DownloadFile(file_id), R::FileDownload(tx)),
+        Command::PauseDownload(file_id, tx) => (P::PauseDownload(file_id), R::Unit(tx)),
+        Command::CancelDownload(file_id, tx) => (P::CancelDownload(file_id), R::Unit(tx)),
+        Command::DeleteFile(file_id, tx) => (P::DeleteFile(file_id), R::Unit(tx)),
+        Command::VerifyFile(file_id, tx) => (P::VerifyFile(file_id), R::FileVerification(tx)),
+        Command::GetCurrentDownloads(tx) => (P::GetCurrentDownloads, R::PendingDownloads(tx)),
+        Command::GetDownloadedFiles(tx) => (P::GetDownloadedFiles, R::DownloadedFiles(tx)),
+        Command::GetLibraryUpdates(tx) => (P::GetLibraryUpdates, R::LibraryUpdates(tx)),
+        Command::AcknowledgeNewFiles(model_id, tx) => {
+            (P::AcknowledgeNewFiles(model_id), R::Unit(tx))
+        }
+        Command::ApplyTemplateUpdate(file_id, tx) => (P::ApplyTemplateUpdate(file_id), R::Unit(tx)),
+        Command::LoadModel(file_id, options, tx) => {
+            (P::LoadModel(file_id, options), R::LoadModel(tx))
+        }
+        Command::EjectModel(tx) => (P::EjectModel, R::Unit(tx)),
+        Command::Chat(data, tx) => (P::Chat(data), R::Chat(tx)),
+        Command::StopChatCompletion(request_id, tx) => {
+            (P::StopChatCompletion(request_id), R::Unit(tx))
+        }
+        Command::Tokenize(text, tx) => (P::Tokenize(text), R::Tokens(tx)),
+        Command::CountTokens(text, tx) => (P::CountTokens(text), R::TokenCount(tx)),
+        Command::CountChatTokens(messages, tx) => (P::CountChatTokens(messages), R::TokenCount(tx)),
+        Command::StartLocalServer(config, tx) => (P::StartLocalServer(config), R::LocalServer(tx)),
+        Command::StopLocalServer(tx) => (P::StopLocalServer, R::Unit(tx)),
+        Command::Subscribe(filter, tx) => (P::Subscribe(filter), R::Events(tx)),
+        Command::GetBackendInfo(tx) => (P::GetBackendInfo, R::BackendInfo(tx)),
+        Command::SetEngine(name, tx) => (P::SetEngine(name), R::Unit(tx)),
+        Command::SetModelEngine(model_id, name, tx) => {
+            (P::SetModelEngine(model_id, name), R::Unit(tx))
+        }
+        Command::GetLoadPresets(file_id, tx) => (P::GetLoadPresets(file_id), R::LoadPresets(tx)),
+        Command::SaveLoadPreset(file_id, preset, tx) => {
+            (P::SaveLoadPreset(file_id, preset), R::Unit(tx))
+        }
+        Command::DeleteLoadPreset(file_id, name, tx) => {
+            (P::DeleteLoadPreset(file_id, name), R::Unit(tx))
+        }
+        Command::SetIdleTimeout(secs, tx) => (P::SetIdleTimeout(secs), R::Unit(tx)),
+        Command::PreloadModels(file_ids, tx) => (P::PreloadModels(file_ids), R::Unit(tx)),
+    }
+}
+
+/// Creates the sender of a command on the backend side. Everything sent to it is
+/// written as a frame of the request, followed by `End` when it is dropped.
+fn forward<T: Send + 'static>(
+    id: RequestID,
+    out: Sender<Response>,
+    wrap: fn(T) -> ResponseBody,
+) -> Sender<Result<T>> {
+    let (tx, rx) = channel::<Result<T>>();
+    thread::spawn(move || {
+        for reply in rx {
+            let body = match reply {
+                Ok(value) => wrap(value),
+                Err(e) => ResponseBody::Error(MolyError::from_anyhow(&e)),
+            };
+            if out.send(Response { id, body }).is_err() {
+                return;
+            }
+        }
+        let _ = out.send(Response {
+            id,
+            body: ResponseBody::End,
+        });
+    });
+    tx
+}
+
+fn join_command(id: RequestID, payload: CommandPayload, out: Sender<Response>) -> Command {
+    use CommandPayload as P;
+    use ResponseBody as B;
+
+    match payload {
+        P::GetFeaturedModels => Command::GetFeaturedModels(forward(id, out, B::Models)),
+        P::ChangeModelsDir(path) => {
+            let _ = out.send(Response { id, body: B::End });
+            Command::ChangeModelsDir(path)
+        }
+        P::SearchModels(query) => Command::SearchModels(query, forward(id, out, B::Models)),
+        P::DownloadFile(file_id) => {
+            Command::DownloadFile(file_id, forward(id, out, B::FileDownload))
+        }
+        P::PauseDownload(file_id) => Command::PauseDownload(file_id, forward(id, out, |_| B::Unit)),
+        P::CancelDownload(file_id) => {
+            Command::CancelDownload(file_id, forward(id, out, |_| B::Unit))
+        }
+        P::DeleteFile(file_id) => Command::DeleteFile(file_id, forward(id, out, |_| B::Unit)),
+        P::VerifyFile(file_id) => {
+            Command::VerifyFile(file_id, forward(id, out, B::FileVerification))
+        }
+        P::GetCurrentDownloads => {
+            Command::GetCurrentDownloads(forward(id, out, B::PendingDownloads))
+        }
+        P::GetDownloadedFiles => Command::GetDownloadedFiles(forward(id, out, B::DownloadedFiles)),
+        P::GetLibraryUpdates => Command::GetLibraryUpdates(forward(id, out, B::LibraryUpdates)),
+        P::AcknowledgeNewFiles(model_id) => {
+            Command::AcknowledgeNewFiles(model_id, forward(id, out, |_| B::Unit))
+        }
+        P::ApplyTemplateUpdate(file_id) => {
+            Command::ApplyTemplateUpdate(file_id, forward(id, out, |_| B::Unit))
+        }
+        P::LoadModel(file_id, options) => {
+            Command::LoadModel(file_id, options, forward(id, out, B::LoadModel))
+        }
+        P::EjectModel => Command::EjectModel(forward(id, out, |_| B::Unit)),
+        P::Chat(data) => Command::Chat(data, forward(id, out, B::Chat)),
+        P::StopChatCompletion(request_id) => {
+            Command::StopChatCompletion(request_id, forward(id, out, |_| B::Unit))
+        }
+        P::Tokenize(text) => Command::Tokenize(text, forward(id, out, B::Tokens)),
+        P::CountTokens(text) => Command::CountTokens(text, forward(id, out, B::TokenCount)),
+        P::CountChatTokens(messages) => {
+            Command::CountChatTokens(messages, forward(id, out, B::TokenCount))
+        }
+        P::StartLocalServer(config) => {
+            Command::StartLocalServer(config, forward(id, out, B::LocalServer))
+        }
+        P::StopLocalServer => Command::StopLocalServer(forward(id, out, |_| B::Unit)),
+        P::Subscribe(filter) => Command::Subscribe(filter, forward_events(id, out)),
+        P::GetBackendInfo => Command::GetBackendInfo(forward(id, out, B::BackendInfo)),
+        P::SetEngine(name) => Command::SetEngine(name, forward(id, out, |_| B::Unit)),
+        P::SetModelEngine(model_id, name) => {
+            Command::SetModelEngine(model_id, name, forward(id, out, |_| B::Unit))
+        }
+        P::GetLoadPresets(file_id) => {
+            Command::GetLoadPresets(file_id, forward(id, out, B::LoadPresets))
+        }
+        P::SaveLoadPreset(file_id, preset) => {
+            Command::SaveLoadPreset(file_id, preset, forward(id, out, |_| B::Unit))
+        }
+        P::DeleteLoadPreset(file_id, name) => {
+            Command::DeleteLoadPreset(file_id, name, forward(id, out, |_| B::Unit))
+        }
+        P::SetIdleTimeout(secs) => Command::SetIdleTimeout(secs, forward(id, out, |_| B::Unit)),
+        P::PreloadModels(file_ids) => {
+            Command::PreloadModels(file_ids, forward(id, out, |_| B::Unit))
+        }
+    }
+}
+
+/// Like [`forward`], for the events of a subscription. Once the client is gone the
+/// receiver is dropped, which ends the subscription in the backend.
+fn forward_events(id: RequestID, out: Sender<Response>) -> Sender<BackendEvent> {
+    let (tx, rx) = channel::<BackendEvent>();
+    thread::spawn(move || {
+        for event in rx {
+            let body = ResponseBody::Event(event);
+            if out.send(Response { id, body }).is_err() {
+                return;
+            }
+        }
+        let _ = out.send(Response {
+            id,
+            body: ResponseBody::End,
+        });
+    });
+    tx
+}
+
+fn write_lines<T: Serialize, W: Write>(mut writer: W, rx: Receiver<T>) -> std::io::Result<()> {
+    for message in rx {
+        // In a single write, so a frame is never split by a buffered writer.
+        let mut frame = serde_json::to_vec(&message)?;
+        frame.push(b'\n');
+        writer.write_all(&frame)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Serves the backend behind `command_sender` on a connection, until the client
+/// closes it.
+pub fn serve<R: Read, W: Write + Send + 'static>(
+    command_sender: Sender<Command>,
+    reader: R,
+    writer: W,
+) -> Result<()> {
+    let (out_tx, out_rx) = channel::<Response>();
+    thread::spawn(move || write_lines(writer, out_rx));
+
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                // Still answer the request if at least its id can be read.
+                let id = serde_json::from_str::<serde_json::Value>(&line)
+                    .ok()
+                    .and_then(|value| value.get("id")?.as_u64());
+                if let Some(id) = id {
+                    let _ = out_tx.send(Response {
+                        id,
+                        body: ResponseBody::Error(MolyError::Other(format!(
+                            "Invalid request: {e}"
+                        ))),
+                    });
+                    let _ = out_tx.send(Response {
+                        id,
+                        body: ResponseBody::End,
+                    });
+                }
+                continue;
+            }
+        };
+
+        let command = join_command(request.id, request.command, out_tx.clone());
+        command_sender
+            .send(command)
+            .map_err(|_| anyhow!("The backend has stopped"))?;
+    }
+
+    Ok(())
+}
+
+/// Serves the backend on the stdin and stdout of the current process. Nothing else may
+/// write to stdout meanwhile, or the client may lose replies.
+pub fn serve_stdio(command_sender: Sender<Command>) -> Result<()> {
+    serve(command_sender, std::io::stdin(), std::io::stdout())
+}
+
+/// Connects to a backend served with [`serve`]. The returned sender works like the
+/// one of a backend running in the same process.
+pub fn connect<R: Read + Send + 'static, W: Write + Send + 'static>(
+    reader: R,
+    writer: W,
+) -> Sender<Command> {
+    let (command_tx, command_rx) = channel::<Command>();
+    let (request_tx, request_rx) = channel::<Request>();
+    let pending = Arc::new(Mutex::new(HashMap::<RequestID, ReplySender>::new()));
+
+    thread::spawn(move || write_lines(writer, request_rx));
+
+    let pending_ = pending.clone();
+    thread::spawn(move || {
+        let mut next_id: RequestID = 0;
+        for command in command_rx {
+            next_id += 1;
+            let (payload, reply) = split_command(command);
+            pending_.lock().unwrap().insert(next_id, reply);
+
+            let request = Request {
+                id: next_id,
+                command: payload,
+            };
+            if request_tx.send(request).is_err() {
+                break;
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let Ok(response) = serde_json::from_str::<Response>(&line) else {
+                continue;
+            };
+
+            let mut pending = pending.lock().unwrap();
+            if let ResponseBody::End = response.body {
+                pending.remove(&response.id);
+            } else if let Some(reply) = pending.get(&response.id) {
+                reply.send(response.body);
+            }
+        }
+
+        for (_, reply) in pending.lock().unwrap().drain() {
+            reply.send(ResponseBody::Error(MolyError::Network(
+                "The connection to the backend was closed".to_string(),
+            )));
+        }
+    });
+
+    command_tx
+}
+
+#[cfg(unix)]
+pub use unix_socket::*;
+
+#[cfg(unix)]
+mod unix_socket {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+
+    /// Serves the backend on a Unix domain socket, one thread per client. The socket is
+    /// only accessible to the user running the backend.
+    pub fn serve_unix_socket<P: AsRef<Path>>(
+        path: P,
+        command_sender: Sender<Command>,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(anyhow!("{} is already in use", path.display()));
+            }
+            // Left behind by a backend that did not shut down cleanly.
+            std::fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        // Whoever can connect drives the backend, so only the user running it can.
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let reader = stream.try_clone()?;
+            let command_sender = command_sender.clone();
+            thread::spawn(move || serve(command_sender, reader, stream));
+        }
+
+        Ok(())
+    }
+
+    pub fn connect_unix_socket<P: AsRef<Path>>(path: P) -> Result<Sender<Command>> {
+        let stream = UnixStream::connect(path)?;
+        let reader = stream.try_clone()?;
+        Ok(connect(reader, stream))
+    }
+}
+
+#[test]
+fn test_messages_round_trip() {
+    let chat: ChatRequestData = serde_json::from_str(
+        r#"{
+            "messages": [{"content": "hello", "role": "user", "name": null}],
+            "model": "model",
+            "frequency_penalty": null,
+            "max_tokens": 10,
+            "presence_penalty": null,
+            "stop": null,
+            "stream": true,
+            "temperature": 0.5,
+            "top_p": null,
+            "top_k": 40,
+            "n": null,
+            "logit_bias": null,
+            "user": null,
+            "response_format": {"type": "json_object"}
+        }"#,
+    )
+    .unwrap();
+
+    let requests = [
+        CommandPayload::GetFeaturedModels,
+        CommandPayload::Chat(Box::new(chat)),
+        CommandPayload::LoadModel("org/model#file.gguf".to_string(), Default::default()),
+        CommandPayload::Subscribe(EventFilter::all()),
+        CommandPayload::SetIdleTimeout(None),
+    ];
+    for (id, command) in requests.into_iter().enumerate() {
+        let request = Request {
+            id: id as RequestID,
+            command,
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        let decoded: Request = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{decoded:?}"), format!("{request:?}"));
+    }
+
+    let bodies = [
+        ResponseBody::Unit,
+        ResponseBody::FileDownload(FileDownloadResponse::Completed(Default::default())),
+        ResponseBody::TokenCount(42),
+        ResponseBody::Event(BackendEvent::CatalogSynced(vec![ModelCardError {
+            model_id: "org/model".to_string(),
+            error: "invalid".to_string(),
+        }])),
+        ResponseBody::Error(MolyError::Offline),
+        ResponseBody::End,
+    ];
+    for body in bodies {
+        let response = Response { id: 7, body };
+        let json = serde_json::to_string(&response).unwrap();
+        let decoded: Response = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{decoded:?}"), format!("{response:?}"));
+    }
+}
+
+#[test]
+fn test_serve_and_connect() {
+    /// One direction of an in-memory connection.
+    struct PipeWriter(Sender<Vec<u8>>);
+    struct PipeReader(Receiver<Vec<u8>>, std::io::Cursor<Vec<u8>>);
+
+    impl Write for PipeWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0
+                .send(buf.to_vec())
+                .map_err(|_| std::io::ErrorKind::BrokenPipe)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for PipeReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            loop {
+                let n = self.1.read(buf)?;
+                if n > 0 || buf.is_empty() {
+                    return Ok(n);
+                }
+                match self.0.recv() {
+                    Ok(bytes) => self.1 = std::io::Cursor::new(bytes),
+                    // The writer was dropped.
+                    Err(_) => return Ok(0),
+                }
+            }
+        }
+    }
+
+    fn pipe() -> (PipeWriter, PipeReader) {
+        let (tx, rx) = channel();
+        (PipeWriter(tx), PipeReader(rx, Default::default()))
+    }
+
+    let (to_server, server_in) = pipe();
+    let (server_out, from_server) = pipe();
+    let (backend_tx, backend_rx) = channel::<Command>();
+    thread::spawn(move || serve(backend_tx, server_in, server_out));
+    thread::spawn(move || {
+        for command in backend_rx {
+            match command {
+                Command::GetFeaturedModels(tx) => {
+                    let model = Model {
+                        id: "org/model".to_string(),
+                        ..Default::default()
+                    };
+                    let _ = tx.send(Ok(vec![model]));
+                }
+                Command::SearchModels(_, tx) => {
+                    let _ = tx.send(Err(MolyError::Offline.into()));
+                }
+                // Dropping the sender ends the request without any frame.
+                _ => {}
+            }
+        }
+    });
+    let command_sender = connect(from_server, to_server);
+
+    let (tx, rx) = channel();
+    command_sender.send(Command::GetFeaturedModels(tx)).unwrap();
+    let models = rx.recv().unwrap().unwrap();
+    assert_eq!(models[0].id, "org/model");
+    // `End` closes the stream of the request.
+    assert!(rx.recv().is_err());
+
+    let (tx, rx) = channel();
+    command_sender
+        .send(Command::SearchModels("query".to_string(), tx))
+        .unwrap();
+    let error = rx.recv().unwrap().unwrap_err();
+    assert_eq!(error.downcast_ref::<MolyError>(), Some(&MolyError::Offline));
+    assert!(rx.recv().is_err());
+
+    let (tx, rx) = channel();
+    command_sender.send(Command::EjectModel(tx)).unwrap();
+    assert!(rx.recv().is_err());
+
+    // The pending requests fail when the backend goes away.
+    let (to_server, server_in) = pipe();
+    let (server_out, from_server) = pipe();
+    let command_sender = connect(from_server, to_server);
+    let (tx, rx) = channel();
+    command_sender
+        .send(Command::GetDownloadedFiles(tx))
+        .unwrap();
+    let mut request = String::new();
+    BufReader::new(server_in).read_line(&mut request).unwrap();
+    assert!(request.contains("GetDownloadedFiles"));
+    drop(server_out);
+    let error = rx.recv().unwrap().unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<MolyError>(),
+        Some(MolyError::Network(_))
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_socket() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("moly-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let (backend_tx, _backend_rx) = channel::<Command>();
+    let backend_tx_ = backend_tx.clone();
+    let path_ = path.clone();
+    thread::spawn(move || serve_unix_socket(path_, backend_tx_));
+
+    let start = std::time::Instant::now();
+    while connect_unix_socket(&path).is_err() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let error = serve_unix_socket(&path, backend_tx).unwrap_err();
+    assert!(error.to_string().contains("already in use"));
+
+    let _ = std::fs::remove_file(&path);
+}
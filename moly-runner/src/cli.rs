@@ -0,0 +1,613 @@
+//! Command-line interface to the Moly backend, for scripting and for machines without a display.
+//!
+//! ```sh
+//! moly search <keywords>        # search the model catalog
+//! moly download <file_id>       # download a model file, showing its progress
+//! moly list                     # list the downloaded model files
+//! moly rm <file_id>             # delete a downloaded model file
+//! moly verify <file_id>         # check a downloaded file against the catalog checksums
+//! moly load <file_id>           # load a model in the running daemon
+//! moly chat <file_id>           # chat with a model, or answer a single prompt read from stdin
+//! ```
+//!
+//! Every subcommand accepts `--json`, which prints the responses of the backend as JSON,
+//! one per line, instead of the human readable output.
+//!
+//! The CLI talks to the same backend as the desktop app through [`moly_protocol::wire`].
+//! It attaches to a running `moly daemon` if there is one (see `MOLY_DAEMON_SOCKET`),
+//! otherwise it starts a private daemon for the duration of the command.
+
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::process::{Child, Stdio};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use anyhow::{anyhow, Context};
+use moly_protocol::data::{DownloadedFile, FileID, Model};
+use moly_protocol::open_ai::{ChatRequestData, ChatResponse, Message, Role};
+use moly_protocol::protocol::{
+    Command, FileDownloadResponse, FileVerification, LoadModelOptions, LoadModelResponse,
+};
+use moly_protocol::wire;
+use serde::Serialize;
+
+use crate::{MOLY_DAEMON_BINARY, OFFLINE_FLAG};
+
+/// The subcommands handled by the CLI instead of starting the desktop app.
+pub const SUBCOMMANDS: &[&str] = &["search", "download", "list", "rm", "verify", "load", "chat"];
+
+/// Same as in the desktop app, the socket of a daemon started elsewhere.
+const DAEMON_SOCKET_ENV_VAR: &str = "MOLY_DAEMON_SOCKET";
+/// Same as in `moly-daemon`, the socket used when it is started without `--socket`.
+const DEFAULT_SOCKET_FILE_NAME: &str = "moly-daemon.sock";
+
+const JSON_FLAG: &str = "--json";
+const VERBOSE_FLAG: &str = "--verbose";
+
+const USAGE: &str = "Usage: moly <command> [--json] [--offline] [--verbose] [args]
+
+Commands:
+    search <keywords>     Search the model catalog
+    download <file_id>    Download a model file
+    list                  List the downloaded model files
+    rm <file_id>          Delete a downloaded model file
+    verify <file_id>      Check a downloaded file against the catalog checksums
+    load <file_id>        Load a model in the running daemon
+    chat <file_id>        Chat with a model. When stdin is not a terminal,
+                          the whole input is sent as a single prompt";
+
+/// Returns the CLI subcommand given to `moly`, if any.
+///
+/// Only the first argument that is not a flag is considered, such that
+/// `moly cargo run` and `moly daemon` keep their meaning.
+pub fn subcommand_from_args() -> Option<String> {
+    std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .filter(|arg| SUBCOMMANDS.contains(&arg.as_str()))
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    subcommand: String,
+    params: Vec<String>,
+    json: bool,
+    offline: bool,
+    verbose: bool,
+    help: bool,
+}
+
+/// Parses the arguments given to `moly`, without the name of the binary.
+fn parse_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Args> {
+    let mut subcommand = None;
+    let mut params = Vec::new();
+    let mut json = false;
+    let mut offline = false;
+    let mut verbose = false;
+
+    for arg in args {
+        match arg.as_str() {
+            JSON_FLAG => json = true,
+            OFFLINE_FLAG => offline = true,
+            VERBOSE_FLAG => verbose = true,
+            "--help" | "-h" => {
+                return Ok(Args {
+                    help: true,
+                    ..Default::default()
+                })
+            }
+            _ if arg.starts_with("--") => return Err(anyhow!("Unknown flag {arg}\n\n{USAGE}")),
+            _ if subcommand.is_none() => subcommand = Some(arg),
+            _ => params.push(arg),
+        }
+    }
+
+    Ok(Args {
+        subcommand: subcommand.ok_or_else(|| anyhow!(USAGE))?,
+        params,
+        json,
+        offline,
+        verbose,
+        help: false,
+    })
+}
+
+/// Runs the CLI subcommand given in the arguments.
+///
+/// Must be called after the WasmEdge environment variables are set,
+/// as it may start the Moly daemon.
+pub fn run() -> anyhow::Result<()> {
+    let args = parse_args(std::env::args().skip(1))?;
+    if args.help {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    let backend = Backend::connect(args.offline, args.verbose)?;
+
+    let param = |name: &str| {
+        args.params
+            .first()
+            .cloned()
+            .ok_or_else(|| anyhow!("Missing <{name}> for `moly {}`\n\n{USAGE}", args.subcommand))
+    };
+
+    match args.subcommand.as_str() {
+        "search" => search(&backend, args.params.join(" "), args.json),
+        "download" => download(&backend, param("file_id")?, args.json),
+        "list" => list(&backend, args.json),
+        "rm" => remove(&backend, param("file_id")?, args.json),
+        "verify" => verify(&backend, param("file_id")?, args.json),
+        "load" => {
+            if backend.daemon.is_some() {
+                eprintln!(
+                    "Warning: there is no running daemon, the model is unloaded when this command exits"
+                );
+            }
+            load(&backend, param("file_id")?, args.json)
+        }
+        "chat" => chat(&backend, param("file_id")?, args.json),
+        other => Err(anyhow!("Unknown command {other}\n\n{USAGE}")),
+    }
+}
+
+/// The connection to the backend, with the daemon started for this command if none was running.
+struct Backend {
+    command_sender: Sender<Command>,
+    daemon: Option<Child>,
+}
+
+impl Backend {
+    fn connect(offline: bool, verbose: bool) -> anyhow::Result<Self> {
+        if let Some(command_sender) = connect_running_daemon()? {
+            if offline {
+                return Err(anyhow!(
+                    "{OFFLINE_FLAG} only applies to the daemon started by the command, \
+                    the running daemon keeps its own mode. Restart it with `moly daemon {OFFLINE_FLAG}`, \
+                    or leave out {OFFLINE_FLAG}"
+                ));
+            }
+            return Ok(Self {
+                command_sender,
+                daemon: None,
+            });
+        }
+
+        let current_exe = std::env::current_exe()?;
+        let daemon_binary_path = current_exe.parent().unwrap().join(MOLY_DAEMON_BINARY);
+
+        let mut daemon_cmd = std::process::Command::new(&daemon_binary_path);
+        daemon_cmd
+            .arg("--stdio")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(if verbose {
+                Stdio::inherit()
+            } else {
+                Stdio::null()
+            });
+        if offline {
+            daemon_cmd.arg(OFFLINE_FLAG);
+        }
+
+        let mut daemon = daemon_cmd.spawn().with_context(|| {
+            format!(
+                "Couldn't start the Moly daemon at {}, run `cargo build [--release] -p moly-daemon` first",
+                daemon_binary_path.display()
+            )
+        })?;
+
+        let reader = daemon.stdout.take().unwrap();
+        let writer = daemon.stdin.take().unwrap();
+
+        Ok(Self {
+            command_sender: wire::connect(reader, writer),
+            daemon: Some(daemon),
+        })
+    }
+
+    /// Sends a command built around a new response channel, and returns its receiver.
+    fn send<T>(
+        &self,
+        command: impl FnOnce(Sender<anyhow::Result<T>>) -> Command,
+    ) -> anyhow::Result<Receiver<anyhow::Result<T>>> {
+        let (tx, rx) = channel();
+        self.command_sender
+            .send(command(tx))
+            .map_err(|_| anyhow!("The connection to the backend was closed"))?;
+        Ok(rx)
+    }
+
+    /// Sends a command that has a single response.
+    fn request<T>(
+        &self,
+        command: impl FnOnce(Sender<anyhow::Result<T>>) -> Command,
+    ) -> anyhow::Result<T> {
+        self.send(command)?
+            .recv()
+            .map_err(|_| anyhow!("The backend did not answer"))?
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        if let Some(daemon) = &mut self.daemon {
+            let _ = daemon.kill();
+            let _ = daemon.wait();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn connect_running_daemon() -> anyhow::Result<Option<Sender<Command>>> {
+    if let Ok(socket) = std::env::var(DAEMON_SOCKET_ENV_VAR) {
+        let sender = wire::connect_unix_socket(&socket)
+            .with_context(|| format!("Couldn't connect to the Moly daemon at {socket}"))?;
+        return Ok(Some(sender));
+    }
+
+    let default_socket =
+        moly_config::project_dirs().map(|dirs| dirs.data_dir().join(DEFAULT_SOCKET_FILE_NAME));
+    Ok(default_socket.and_then(|socket| wire::connect_unix_socket(socket).ok()))
+}
+
+#[cfg(not(unix))]
+fn connect_running_daemon() -> anyhow::Result<Option<Sender<Command>>> {
+    Ok(None)
+}
+
+fn print_json<T: Serialize>(value: &T) -> anyhow::Result<()> {
+    write_json(&mut std::io::stdout().lock(), value)
+}
+
+/// Writes `value` as JSON on a single line, the output of `--json`.
+fn write_json<T: Serialize>(out: &mut impl Write, value: &T) -> anyhow::Result<()> {
+    writeln!(out, "{}", serde_json::to_string(value)?)?;
+    Ok(())
+}
+
+fn search(backend: &Backend, query: String, json: bool) -> anyhow::Result<()> {
+    let models = if query.is_empty() {
+        backend.request(Command::GetFeaturedModels)?
+    } else {
+        backend.request(|tx| Command::SearchModels(query, tx))?
+    };
+
+    if json {
+        return print_json(&models);
+    }
+
+    for model in &models {
+        print_model(model);
+    }
+    if models.is_empty() {
+        eprintln!("No models found");
+    }
+    Ok(())
+}
+
+fn print_model(model: &Model) {
+    println!(
+        "{} ({}, {} params)",
+        model.name, model.architecture, model.size
+    );
+    for file in &model.files {
+        let downloaded = if file.downloaded {
+            "  [downloaded]"
+        } else {
+            ""
+        };
+        println!(
+            "    {:<60} {:>10} {}{downloaded}",
+            file.id, file.size, file.quantization
+        );
+    }
+}
+
+fn download(backend: &Backend, file_id: FileID, json: bool) -> anyhow::Result<()> {
+    let rx = backend.send(|tx| Command::DownloadFile(file_id.clone(), tx))?;
+    let interactive = !json && std::io::stderr().is_terminal();
+
+    for response in rx {
+        let response = response?;
+        if json {
+            print_json(&response)?;
+            continue;
+        }
+
+        match response {
+            FileDownloadResponse::Progress(_, progress) => {
+                if interactive {
+                    eprint!("\r{}", progress_bar(progress));
+                }
+            }
+            FileDownloadResponse::Completed(file) => {
+                if interactive {
+                    eprintln!("\r{}", progress_bar(100.0));
+                }
+                println!(
+                    "Downloaded {} to {}",
+                    file.file.id,
+                    file.file.downloaded_path.unwrap_or_default()
+                );
+                return Ok(());
+            }
+        }
+    }
+
+    if json {
+        Ok(())
+    } else {
+        Err(anyhow!("The download of {file_id} was interrupted"))
+    }
+}
+
+fn progress_bar(progress: f32) -> String {
+    const WIDTH: usize = 40;
+    let progress = progress.clamp(0.0, 100.0);
+    let filled = (progress / 100.0 * WIDTH as f32).round() as usize;
+    format!(
+        "[{}{}] {progress:5.1}%",
+        "#".repeat(filled),
+        " ".repeat(WIDTH - filled)
+    )
+}
+
+fn list(backend: &Backend, json: bool) -> anyhow::Result<()> {
+    let files = backend.request(Command::GetDownloadedFiles)?;
+    if json {
+        return print_json(&files);
+    }
+
+    for DownloadedFile {
+        file,
+        model,
+        downloaded_at,
+        ..
+    } in &files
+    {
+        println!(
+            "{:<60} {:>10} {:<10} {}",
+            file.id,
+            file.size,
+            model.architecture,
+            downloaded_at.format("%Y-%m-%d"),
+        );
+    }
+    if files.is_empty() {
+        eprintln!("No downloaded models");
+    }
+    Ok(())
+}
+
+fn remove(backend: &Backend, file_id: FileID, json: bool) -> anyhow::Result<()> {
+    backend.request(|tx| Command::DeleteFile(file_id.clone(), tx))?;
+    if json {
+        return print_json(&file_id);
+    }
+    println!("Deleted {file_id}");
+    Ok(())
+}
+
+fn verify(backend: &Backend, file_id: FileID, json: bool) -> anyhow::Result<()> {
+    let verification = backend.request(|tx| Command::VerifyFile(file_id.clone(), tx))?;
+    if json {
+        print_json(&verification)?;
+        return match verification {
+            FileVerification::Valid | FileVerification::Unchecked => Ok(()),
+            _ => Err(anyhow!("{file_id} failed verification")),
+        };
+    }
+
+    match verification {
+        FileVerification::Valid => println!("{file_id}: OK"),
+        FileVerification::Unchecked => {
+            println!("{file_id}: present, but the catalog has no checksum for it")
+        }
+        FileVerification::Missing(part) => return Err(anyhow!("{file_id}: {part} is missing")),
+        FileVerification::ChecksumMismatch { part, expected, actual } => {
+            return Err(anyhow!(
+                "{file_id}: checksum mismatch in {part}\n    expected: {expected}\n    actual:   {actual}"
+            ))
+        }
+    }
+    Ok(())
+}
+
+fn load(backend: &Backend, file_id: FileID, json: bool) -> anyhow::Result<()> {
+    let rx =
+        backend.send(|tx| Command::LoadModel(file_id.clone(), LoadModelOptions::default(), tx))?;
+
+    for response in rx {
+        let response = response?;
+        if json {
+            print_json(&response)?;
+        }
+        match response {
+            LoadModelResponse::Progress(_, stage, progress) if !json => {
+                eprintln!("{stage:?} ({:.0}%)", progress * 100.0);
+            }
+            LoadModelResponse::Completed(info) => {
+                if !json {
+                    eprintln!(
+                        "Loaded {} (port {}, context of {} tokens)",
+                        info.file_id, info.listen_port, info.n_ctx
+                    );
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+
+    Err(anyhow!("Failed to load {file_id}"))
+}
+
+fn chat(backend: &Backend, file_id: FileID, json: bool) -> anyhow::Result<()> {
+    let model_id = file_id
+        .split_once('#')
+        .map(|(model_id, _)| model_id.to_string())
+        .ok_or_else(|| anyhow!("Invalid file id {file_id}, expected <model_id>#<file_name>"))?;
+
+    let rx =
+        backend.send(|tx| Command::LoadModel(file_id.clone(), LoadModelOptions::default(), tx))?;
+    let loaded = rx
+        .iter()
+        .find_map(|response| match response {
+            Ok(LoadModelResponse::Completed(_)) => Some(Ok(())),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+        .unwrap_or_else(|| Err(anyhow!("Failed to load {file_id}")));
+    loaded?;
+
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        let mut prompt = String::new();
+        stdin.lock().read_to_string(&mut prompt)?;
+        let mut messages = vec![user_message(prompt)];
+        complete(backend, &model_id, &mut messages, json)?;
+        return Ok(());
+    }
+
+    eprintln!("Chatting with {file_id}. Send an empty line or press Ctrl-D to exit.");
+    let mut messages = vec![];
+    loop {
+        eprint!("> ");
+        std::io::stderr().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 || line.trim().is_empty() {
+            return Ok(());
+        }
+
+        messages.push(user_message(line.trim().to_string()));
+        if let Err(e) = complete(backend, &model_id, &mut messages, json) {
+            // Keep the conversation going, the prompt can be sent again.
+            messages.pop();
+            eprintln!("Error: {e:#}");
+        }
+    }
+}
+
+fn user_message(content: String) -> Message {
+    Message {
+        content: content.into(),
+        role: Role::User,
+        name: None,
+    }
+}
+
+/// Streams the answer of the model to `messages`, and adds it to them.
+fn complete(
+    backend: &Backend,
+    model_id: &str,
+    messages: &mut Vec<Message>,
+    json: bool,
+) -> anyhow::Result<()> {
+    let request = ChatRequestData {
+        messages: messages.clone(),
+        model: model_id.to_string(),
+        stream: Some(true),
+        ..Default::default()
+    };
+
+    let rx = backend.send(|tx| Command::Chat(Box::new(request), tx))?;
+    let mut answer = String::new();
+    let mut stdout = std::io::stdout();
+
+    for response in rx {
+        let response = response?;
+        if json {
+            print_json(&response)?;
+        }
+
+        let content = match response {
+            ChatResponse::Accepted(_) => continue,
+            ChatResponse::Queued(position) => {
+                if !json {
+                    eprintln!("Queued, {position} requests ahead");
+                }
+                continue;
+            }
+            ChatResponse::ChatResponseChunk(chunk) => chunk
+                .choices
+                .into_iter()
+                .map(|choice| choice.delta.content)
+                .collect::<String>(),
+            ChatResponse::ChatFinalResponseData(data) => data
+                .choices
+                .into_iter()
+                .map(|choice| choice.message.content)
+                .collect::<String>(),
+        };
+
+        if !json {
+            write!(stdout, "{content}")?;
+            stdout.flush()?;
+        }
+        answer.push_str(&content);
+    }
+
+    if !json {
+        println!();
+    }
+
+    messages.push(Message {
+        content: answer.into(),
+        role: Role::Assistant,
+        name: None,
+    });
+    Ok(())
+}
+
+#[test]
+fn test_parse_args() {
+    let args = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string()));
+
+    assert_eq!(
+        args(&["download", "--json", "model#file.gguf"]).unwrap(),
+        Args {
+            subcommand: "download".to_string(),
+            params: vec!["model#file.gguf".to_string()],
+            json: true,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        args(&["--offline", "--verbose", "search", "llama", "3"]).unwrap(),
+        Args {
+            subcommand: "search".to_string(),
+            params: vec!["llama".to_string(), "3".to_string()],
+            offline: true,
+            verbose: true,
+            ..Default::default()
+        }
+    );
+    assert!(args(&["list", "-h"]).unwrap().help);
+    assert!(args(&["list", "--jsn"]).is_err());
+    assert!(args(&["--json"]).is_err());
+}
+
+#[test]
+fn test_json_output() {
+    use moly_protocol::protocol::LoadStage;
+
+    let responses = vec![
+        LoadModelResponse::Progress("model#file.gguf".to_string(), LoadStage::WarmingUp, 0.5),
+        LoadModelResponse::Progress("model#file.gguf".to_string(), LoadStage::WarmingUp, 1.0),
+    ];
+    let mut out = Vec::new();
+    for response in &responses {
+        write_json(&mut out, response).unwrap();
+    }
+
+    // One response per line, that can be read back.
+    let out = String::from_utf8(out).unwrap();
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    let first = serde_json::from_str::<LoadModelResponse>(lines[0]).unwrap();
+    assert!(matches!(first, LoadModelResponse::Progress(_, _, progress) if progress == 0.5));
+
+    let mut out = Vec::new();
+    write_json(&mut out, &FileVerification::Valid).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\"Valid\"\n");
+}
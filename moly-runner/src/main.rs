@@ -68,6 +68,8 @@
 
 #![allow(unused)]
 
+mod cli;
+
 use std::{
     ffi::OsStr,
     path::{Path, PathBuf},
@@ -75,6 +77,8 @@ use std::{
 };
 
 pub const MOLY_APP_BINARY: &str = "_moly_app";
+/// The headless backend binary, started with `moly daemon`.
+pub const MOLY_DAEMON_BINARY: &str = "moly-daemon";
 
 /// The name of the wasmedge root directory.
 const WASMEDGE_ROOT_DIR_NAME: &str = {
@@ -145,6 +149,9 @@ const ENV_LD_LIBRARY_PATH: &str = "LD_LIBRARY_PATH";
 #[cfg(target_os = "macos")]
 const ENV_DYLD_FALLBACK_LIBRARY_PATH: &str = "DYLD_FALLBACK_LIBRARY_PATH";
 
+/// The CLI flag that runs Moly in offline mode.
+const OFFLINE_FLAG: &str = "--offline";
+
 
 /// Returns the URL of the WASI-NN plugin that should be downloaded, and its inner directory name.
 ///
@@ -211,6 +218,11 @@ fn main() -> std::io::Result<()> {
     // because the run_moly() function will set the current working directory to `Contents/MacOS/`
     // within the app bundle, which is the subdirectory that contains the actual moly executables.
     std::env::set_var(ENV_WASMEDGE_PLUGIN_PATH, "../Frameworks");
+    set_offline_env_var();
+
+    if cli::subcommand_from_args().is_some() {
+        return run_cli();
+    }
 
     println!("Running within a macOS app bundle.
         {ENV_WASMEDGE_PLUGIN_PATH}: {:?}",
@@ -227,39 +239,64 @@ fn main() -> std::io::Result<()> {
 
     assert_cpu_features();
 
+    let offline = set_offline_env_var();
+    // `moly <subcommand>` runs the CLI, which keeps stdout for its own output.
+    let cli = cli::subcommand_from_args().is_some();
+
     let (wasmedge_root_dir_in_use, main_dylib_path, wasi_nn_plugin_path) = 
         // First, try to find the wasmedge installation directory in the app data dir.
         existing_wasmedge_default_dir()
         // If we have a wasmedge installation directory, try to find the dylibs within it.
         .and_then(|wasmedge_root_dir| find_wasmedge_dylibs_in_dir(&wasmedge_root_dir))
         // If we couldn't find the wasmedge directory or the dylibs within an existing directory,
-        // then we must install wasmedge.
+        // then we must install wasmedge, which isn't possible in offline mode.
         .or_else(|| wasmedge_default_dir_path()
+            .filter(|_| !offline)
             .and_then(|default_path| install_wasmedge(default_path).ok())
             // If we successfully installed wasmedge, try to find the dylibs again.
             .and_then(find_wasmedge_dylibs_in_dir)
         )
-        .expect("failed to find or install wasmedge dylibs");
-
-    println!("Found required wasmedge files:
-        wasmedge root dir: {}
-        wasmedge dylib:    {}
-        wasi_nn plugin:    {}",
-        wasmedge_root_dir_in_use.display(),
-        main_dylib_path.display(),
-        wasi_nn_plugin_path.display(),
-    );
+        .unwrap_or_else(|| if offline {
+            panic!("failed to find wasmedge dylibs, they cannot be installed in offline mode")
+        } else {
+            panic!("failed to find or install wasmedge dylibs")
+        });
+
+    if !cli {
+        println!("Found required wasmedge files:
+            wasmedge root dir: {}
+            wasmedge dylib:    {}
+            wasi_nn plugin:    {}",
+            wasmedge_root_dir_in_use.display(),
+            main_dylib_path.display(),
+            wasi_nn_plugin_path.display(),
+        );
+    }
 
     // These CLI args allow `moly-runner` to be used to bootstrap a cargo command,
     // while automatically setting the env vars for you (saving the dev time & effort).
+    // `moly daemon [args]` starts the headless backend with the same env vars.
     let mut install = false;
     let mut cargo = false;
     let mut cargo_args = Vec::new();
+    let mut daemon = false;
+    let mut daemon_args = Vec::new();
     for arg in std::env::args().skip(1) {
+        if arg == OFFLINE_FLAG && !cargo && !daemon {
+            continue;
+        }
+        if daemon {
+            daemon_args.push(arg);
+            continue;
+        }
         if arg == "install" || arg == "--install" {
             install = true;
             break;
         }
+        if arg == "daemon" && !cargo {
+            daemon = true;
+            continue;
+        }
         if arg == "cargo" {
             cargo = true;
             continue;
@@ -283,7 +320,9 @@ fn main() -> std::io::Result<()> {
 
     set_env_vars(&wasmedge_root_dir_in_use);
 
-    if cargo {
+    if cli {
+        run_cli()
+    } else if cargo {
         let mut cargo_cmd = Command::new("cargo");
         cargo_cmd.args(cargo_args);
         println!("Running command: {cargo_cmd:?}");
@@ -296,6 +335,8 @@ fn main() -> std::io::Result<()> {
         } else {
             Err(std::io::Error::last_os_error())
         }
+    } else if daemon {
+        run_moly_daemon(daemon_args)
     } else {
         run_moly()
     }
@@ -308,16 +349,12 @@ fn existing_wasmedge_default_dir() -> Option<PathBuf> {
 }
 
 
-pub const APP_QUALIFIER: &str = "com";
-pub const APP_ORGANIZATION: &str = "moxin-org";
-pub const APP_NAME: &str = "moly";
-
 /// Returns the path to the default WasmEdge root directory,
 /// which is currently in the app data directory.
 ///
 /// This does not check if the directory actually exists.
 fn wasmedge_default_dir_path() -> Option<PathBuf> {
-    directories::ProjectDirs::from(APP_QUALIFIER, APP_ORGANIZATION, APP_NAME)
+    moly_config::project_dirs()
         .map(|dirs| dirs.data_dir().join(WASMEDGE_ROOT_DIR_NAME))
 }
 
@@ -565,11 +602,32 @@ fn get_cuda_version() -> Option<CudaVersion> {
 }
 
 
+/// Checks whether the `--offline` flag was passed, and if so, sets the environment variable
+/// that tells the main Moly app to run without any network access.
+///
+/// Only the arguments before `cargo` are checked, such that `cargo [...] --offline`
+/// is still passed through to cargo itself.
+fn set_offline_env_var() -> bool {
+    let offline = std::env::args()
+        .skip(1)
+        .take_while(|arg| arg != "cargo")
+        .any(|arg| arg == OFFLINE_FLAG);
+    if offline {
+        eprintln!("Running Moly in offline mode.");
+        // Tells the main Moly app to skip every network call.
+        std::env::set_var(moly_config::OFFLINE_ENV_VAR, "1");
+    }
+    offline
+}
+
+
 /// Runs the `_moly_app` binary, which must be located in the same directory as this moly-runner binary.
 fn run_moly() -> std::io::Result<()> {
     let current_exe = std::env::current_exe()?;
     let current_exe_dir = current_exe.parent().unwrap();
-    let args = std::env::args().collect::<Vec<_>>();
+    let args = std::env::args()
+        .filter(|arg| arg != OFFLINE_FLAG)
+        .collect::<Vec<_>>();
 
     println!("Running the main Moly binary:
         working directory: {}
@@ -597,6 +655,50 @@ fn run_moly() -> std::io::Result<()> {
 }
 
 
+/// Runs a `moly <subcommand>` of the CLI, exiting with an error code if it fails.
+fn run_cli() -> std::io::Result<()> {
+    if let Err(e) = cli::run() {
+        eprintln!("Error: {e:#}");
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+
+/// Runs the `moly-daemon` binary, which must be located in the same directory as this moly-runner binary.
+///
+/// The daemon is not a GUI app, so it keeps the caller's working directory
+/// and runs until it is stopped.
+fn run_moly_daemon(args: Vec<String>) -> std::io::Result<()> {
+    let current_exe = std::env::current_exe()?;
+    let current_exe_dir = current_exe.parent().unwrap();
+
+    println!("Running the Moly daemon:
+        args: {:?}",
+        args,
+    );
+
+    let daemon_binary_path = current_exe_dir.join(MOLY_DAEMON_BINARY);
+    let status = Command::new(&daemon_binary_path)
+        .args(args)
+        .spawn()
+        .inspect_err(|e| if e.kind() == std::io::ErrorKind::NotFound {
+            eprintln!("\nError: couldn't find the Moly daemon binary at {}\n\
+                \t--> Have you compiled it yet?\n\
+                \t--> If not, run `cargo build [--release] -p moly-daemon` first.\n",
+                daemon_binary_path.display(),
+            );
+        })?
+        .wait()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::Other, format!("The Moly daemon exited with {status}")))
+    }
+}
+
+
 /// Checks that the current CPU supports AVX512, or either SSE4.2 or SSE4a,
 /// at least one of which is required by the current builds of WasmEdge 0.14.0 on Windows.
 ///
@@ -265,7 +265,8 @@ fn before_each_package(host_os: &str) -> std::io::Result<()> {
 /// This function effectively runs the following shell commands:
 /// ```sh
 ///    MAKEPAD_PACKAGE_DIR=../Resources  cargo build --workspace --release --features macos_bundle \
-///    && install_name_tool -add_rpath "@executable_path/../Frameworks" ./target/release/_moly_app;
+///    && install_name_tool -add_rpath "@executable_path/../Frameworks" ./target/release/_moly_app \
+///    && install_name_tool -add_rpath "@executable_path/../Frameworks" ./target/release/moly-daemon;
 /// ```
 fn before_each_package_macos(package_format: &str, host_os: &str) -> std::io::Result<()> {
     assert!(host_os == "macos", "'app' and 'dmg' packages can only be created on macOS.");
@@ -276,22 +277,25 @@ fn before_each_package_macos(package_format: &str, host_os: &str) -> std::io::Re
         &["--features", "macos_bundle"],
     )?;
 
-    // Use `install_name_tool` to add the `@executable_path` rpath to the binary.
-    let install_name_tool_cmd = Command::new("install_name_tool")
-        .arg("-add_rpath")
-        .arg("@executable_path/../Frameworks")
-        .arg("./target/release/_moly_app")
-        .spawn()?;
+    // Use `install_name_tool` to add the `@executable_path` rpath to the binaries
+    // that link against WasmEdge.
+    for binary in ["./target/release/_moly_app", "./target/release/moly-daemon"] {
+        let install_name_tool_cmd = Command::new("install_name_tool")
+            .arg("-add_rpath")
+            .arg("@executable_path/../Frameworks")
+            .arg(binary)
+            .spawn()?;
 
-    let output = install_name_tool_cmd.wait_with_output()?;
-    if !output.status.success() {
-        eprintln!("Failed to run install_name_tool command: {}
-            ------------------------- stderr: -------------------------
-            {:?}",
-            output.status,
-            String::from_utf8_lossy(&output.stderr),
-        );
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, "Failed to run install_name_tool command for macOS"));
+        let output = install_name_tool_cmd.wait_with_output()?;
+        if !output.status.success() {
+            eprintln!("Failed to run install_name_tool command: {}
+                ------------------------- stderr: -------------------------
+                {:?}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr),
+            );
+            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Failed to run install_name_tool command for macOS"));
+        }
     }
 
     Ok(())
@@ -462,6 +466,7 @@ fn strip_unneeded_linux_binaries(host_os: &str) -> std::io::Result<()> {
         .arg("--remove-section=.note")
         .arg("target/release/_moly_app")
         .arg("target/release/moly")
+        .arg("target/release/moly-daemon")
         .spawn()?;
 
     let output = strip_cmd.wait_with_output()?;
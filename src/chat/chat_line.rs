@@ -1,7 +1,9 @@
 use crate::chat::chat_line_loading::ChatLineLoadingWidgetExt;
 use crate::chat::chat_line_loading::AnswerLoadingWidgetExt;
+use crate::chat::token_probabilities::TokenProbabilitiesWidgetExt;
 use makepad_widgets::markdown::MarkdownWidgetExt;
 use makepad_widgets::*;
+use moly_protocol::open_ai::LogProbsItemData;
 
 use makepad_markdown::parse_markdown;
 
@@ -16,9 +18,11 @@ live_design! {
     import crate::shared::resource_imports::*;
     import crate::chat::chat_line_loading::ChatLineLoading;
     import crate::chat::chat_line_loading::AnswerLoading;
+    import crate::chat::token_probabilities::TokenProbabilities;
 
     ICON_EDIT = dep("crate://self/resources/icons/edit.svg")
     ICON_DELETE = dep("crate://self/resources/icons/delete.svg")
+    ICON_INSPECT = dep("crate://self/resources/icons/visibility.svg")
 
     ChatLineEditButton = <MolyButton> {
         width: 56,
@@ -176,6 +180,13 @@ live_design! {
                 }
             }
 
+            token_probabilities_container = <View> {
+                visible: false,
+                width: Fill,
+                height: Fit,
+                token_probabilities = <TokenProbabilities> {}
+            }
+
             edit_buttons = <View> {
                 visible: false,
                 width: Fit,
@@ -244,6 +255,10 @@ live_design! {
                     delete_button = <ChatLineActionButton> {
                         draw_icon: { svg_file: (ICON_DELETE) }
                     }
+                    inspect_button = <ChatLineActionButton> {
+                        visible: false,
+                        draw_icon: { svg_file: (ICON_INSPECT) }
+                    }
                 }
             }
         }
@@ -279,6 +294,10 @@ pub struct ChatLine {
 
     #[rust]
     hovered: bool,
+
+    // Shows the tokens of the answer with their probabilities instead of its text.
+    #[rust]
+    inspecting: bool,
 }
 
 impl Widget for ChatLine {
@@ -328,11 +347,19 @@ impl ChatLine {
         self.view(id!(actions_section.actions)).set_visible(false);
         self.view(id!(edit_buttons)).set_visible(enabled);
         self.view(id!(input_container)).set_visible(enabled);
+        self.set_inspecting(false);
         self.show_or_hide_message_label(!enabled);
 
         self.redraw(cx);
     }
 
+    fn set_inspecting(&mut self, inspecting: bool) {
+        self.inspecting = inspecting;
+        self.view(id!(token_probabilities_container))
+            .set_visible(inspecting);
+        self.show_or_hide_message_label(!inspecting);
+    }
+
     pub fn show_or_hide_message_label(&mut self, show: bool) {
         let text = self.text_input(id!(input)).text();
         let to_markdown = parse_markdown(&text);
@@ -357,6 +384,11 @@ impl ChatLine {
             let text_to_copy = self.text_input(id!(input)).text();
             cx.copy_to_clipboard(&text_to_copy);
         }
+
+        if self.button(id!(inspect_button)).clicked(&actions) {
+            self.set_inspecting(!self.inspecting);
+            self.redraw(cx);
+        }
     }
 
     pub fn handle_on_edit_actions(&mut self, cx: &mut Cx, actions: &Actions) {
@@ -459,12 +491,31 @@ impl ChatLineRef {
                     loading_widget.stop_animation();
                 }
 
-                inner.show_or_hide_message_label(true);
+                let show_message = !inner.inspecting;
+                inner.show_or_hide_message_label(show_message);
             }
             ChatLineState::OnEdit => {}
         }
     }
 
+    /// The tokens of the answer to inspect. The inspect button is only shown when there
+    /// are some.
+    pub fn set_logprobs(&mut self, cx: &mut Cx, logprobs: &[LogProbsItemData]) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        inner
+            .button(id!(inspect_button))
+            .set_visible(!logprobs.is_empty());
+        if logprobs.is_empty() && inner.inspecting {
+            inner.set_inspecting(false);
+        }
+        inner
+            .token_probabilities(id!(token_probabilities))
+            .set_tokens(cx, logprobs);
+    }
+
     pub fn set_message_id(&mut self, message_id: usize) {
         let Some(mut inner) = self.borrow_mut() else {
             return;
@@ -1,6 +1,7 @@
 use makepad_widgets::*;
 use moly_protocol::data::FileID;
 use std::cell::{Ref, RefCell, RefMut};
+use std::path::PathBuf;
 
 use crate::{
     chat::{
@@ -9,10 +10,13 @@ use crate::{
         model_selector_list::ModelSelectorAction,
     },
     data::{
-        chats::chat::{Chat, ChatEntityAction, ChatID, ChatMessage},
+        chats::{
+            chat::{Chat, ChatEntityAction, ChatID, ChatMessage},
+            token_counter::TokenCountChanged,
+        },
         store::Store,
     },
-    shared::actions::ChatAction,
+    shared::{actions::ChatAction, utils::hex_rgb_color},
 };
 
 use super::chat_history_card::ChatHistoryCardAction;
@@ -31,6 +35,7 @@ live_design! {
 
     ICON_PROMPT = dep("crate://self/resources/icons/prompt.svg")
     ICON_STOP = dep("crate://self/resources/icons/stop.svg")
+    ICON_ATTACH = dep("crate://self/resources/icons/add.svg")
     ICON_JUMP_TO_BOTTOM = dep("crate://self/resources/icons/jump_to_bottom.svg")
 
     CircleButton = <MolyButton> {
@@ -216,6 +221,21 @@ live_design! {
             border_width: 1.0,
         }
 
+        attach_image_button = <PromptButton> {
+            draw_icon: {
+                svg_file: (ICON_ATTACH),
+            }
+        }
+
+        attachment_label = <Label> {
+            width: Fit,
+            margin: {bottom: 7},
+            draw_text: {
+                text_style: <REGULAR_FONT>{font_size: 9},
+                color: #667085
+            }
+        }
+
         prompt = <MolyTextInput> {
             width: Fill,
             height: Fit,
@@ -239,6 +259,15 @@ live_design! {
             }
         }
 
+        token_count_label = <Label> {
+            width: Fit,
+            margin: {bottom: 7},
+            draw_text: {
+                text_style: <REGULAR_FONT>{font_size: 9},
+                color: #667085
+            }
+        }
+
         prompt_send_button = <PromptButton> {
             draw_icon: {
                 svg_file: (ICON_PROMPT),
@@ -459,6 +488,10 @@ pub struct ChatPanel {
 
     #[rust(false)]
     focus_on_prompt_input_pending: bool,
+
+    /// Images attached to the next message, for vision models.
+    #[rust]
+    attached_images: Vec<PathBuf>,
 }
 
 impl Widget for ChatPanel {
@@ -516,6 +549,10 @@ impl WidgetMatchEvent for ChatPanel {
                     }
                 }
             }
+
+            if action.downcast_ref::<TokenCountChanged>().is_some() {
+                self.redraw(cx);
+            }
         }
 
         for action in actions
@@ -764,6 +801,64 @@ impl ChatPanel {
         if let Some(prompt) = prompt_input.returned(actions) {
             self.send_message(cx, scope, prompt);
         }
+
+        if self
+            .button(id!(main_prompt_input.attach_image_button))
+            .clicked(&actions)
+        {
+            let res = rfd::FileDialog::new()
+                .add_filter("Images", &["png", "jpg", "jpeg", "gif", "webp"])
+                .pick_file();
+
+            if let Some(path) = res {
+                self.attached_images.push(path);
+                self.update_attachment_label(cx);
+            }
+        }
+    }
+
+    fn update_attachment_label(&mut self, cx: &mut Cx) {
+        let names = self
+            .attached_images
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        self.label(id!(main_prompt_input.attachment_label))
+            .set_text(&names);
+        self.redraw(cx);
+    }
+
+    /// Shows the tokens of the prompt and of the history it would be sent with, against
+    /// the context size of the model.
+    fn update_token_count_label(&mut self, cx: &mut Cx, scope: &mut Scope) {
+        let store = scope.data.get::<Store>().unwrap();
+        let label = self.label(id!(main_prompt_input.token_count_label));
+        if !store.chats.model_loader.is_loaded() {
+            label.set_text("");
+            return;
+        }
+
+        // The previous counts are kept until the new ones are known.
+        let prompt = self.text_input(id!(main_prompt_input.prompt)).text();
+        let Some((prompt_tokens, history_tokens, n_ctx)) = store.chats.context_usage(&prompt)
+        else {
+            return;
+        };
+
+        let mut text = format!("{} + {}", prompt_tokens, history_tokens);
+        if n_ctx > 0 {
+            text.push_str(&format!(" / {}", n_ctx));
+        }
+        let color = if n_ctx > 0 && prompt_tokens + history_tokens > n_ctx {
+            hex_rgb_color(0xD92D20)
+        } else {
+            hex_rgb_color(0x667085)
+        };
+        label.set_text(&text);
+        label.apply_over(cx, live! { draw_text: { color: (color) } });
     }
 
     fn send_message(&mut self, cx: &mut Cx, scope: &mut Scope, prompt: String) {
@@ -783,7 +878,8 @@ impl ChatPanel {
             } | State::ModelSelectedWithEmptyChat { is_loading: false }
         ) {
             let store = scope.data.get_mut::<Store>().unwrap();
-            store.send_chat_message(prompt.clone());
+            store.send_chat_message(prompt.clone(), std::mem::take(&mut self.attached_images));
+            self.update_attachment_label(cx);
 
             let prompt_input = self.text_input(id!(main_prompt_input.prompt));
             prompt_input.set_text_and_redraw(cx, "");
@@ -799,6 +895,11 @@ impl ChatPanel {
         self.update_visibilities();
         self.update_prompt_input(cx);
 
+        let supports_vision = scope.data.get::<Store>().unwrap().supports_vision();
+        self.button(id!(main_prompt_input.attach_image_button))
+            .set_visible(supports_vision);
+        self.update_token_count_label(cx, scope);
+
         match self.state {
             State::ModelSelectedWithEmptyChat { .. } => {
                 let store = scope.data.get::<Store>().unwrap();
@@ -861,6 +962,9 @@ impl ChatPanel {
         let store = scope.data.get::<Store>().unwrap();
         let messages = get_chat_messages(store).unwrap();
         let messages_count = messages.len();
+        let queue_position = get_chat(store).and_then(|chat| chat.borrow().queue_position);
+        let error_text = get_chat(store).and_then(|chat| chat.borrow().error_text());
+        let supports_logprobs = store.supports_logprobs();
 
         self.portal_list_end_reached = false;
         list.set_item_range(cx, 0, messages_count + 1);
@@ -886,6 +990,13 @@ impl ChatPanel {
                 };
 
                 chat_line_item.set_message_id(chat_line_data.id);
+                // Only inspected with an engine that returns them.
+                let logprobs = if supports_logprobs {
+                    chat_line_data.logprobs.as_slice()
+                } else {
+                    &[]
+                };
+                chat_line_item.set_logprobs(cx, logprobs);
 
                 // Disable actions for the last chat line when model is streaming
                 if matches!(
@@ -896,10 +1007,25 @@ impl ChatPanel {
                     }
                 ) && item_id == messages_count - 1
                 {
-                    chat_line_item.set_message_text(cx, &chat_line_data.content, true);
+                    match queue_position {
+                        Some(position) if chat_line_data.content.is_empty() => {
+                            let text = format!("Waiting for other chats ({} ahead)", position);
+                            chat_line_item.set_message_text(cx, &text, true);
+                        }
+                        _ => chat_line_item.set_message_text(cx, &chat_line_data.content, true),
+                    }
                     chat_line_item.set_actions_enabled(cx, false);
                 } else {
-                    chat_line_item.set_message_text(cx, &chat_line_data.content, false);
+                    match &error_text {
+                        // The answer that failed.
+                        Some(text)
+                            if item_id == messages_count - 1
+                                && chat_line_data.content.is_empty() =>
+                        {
+                            chat_line_item.set_message_text(cx, text, false)
+                        }
+                        _ => chat_line_item.set_message_text(cx, &chat_line_data.content, false),
+                    }
                     chat_line_item.set_actions_enabled(cx, true);
                 }
 
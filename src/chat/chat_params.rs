@@ -1,7 +1,15 @@
 use makepad_widgets::*;
 
+use moly_protocol::{
+    data::FileID,
+    protocol::{GPULayers, LoadModelOptions, LoadPreset, LoraAdapter},
+};
+
 use crate::{
-    data::{chats::chat::ChatID, store::Store},
+    data::{
+        chats::{chat::ChatID, Chats},
+        store::Store,
+    },
     shared::tooltip::TooltipWidgetExt,
 };
 
@@ -12,6 +20,7 @@ live_design! {
     import crate::shared::styles::*;
     import crate::shared::widgets::*;
     import crate::shared::tooltip::*;
+    import crate::landing::sorting::ModelsDropDown;
     import makepad_draw::shader::std::*;
 
     ICON_CLOSE_PANEL = dep("crate://self/resources/icons/close_right_panel.svg")
@@ -33,12 +42,70 @@ live_design! {
         }
     }
 
+    ChatParamsField = <View> {
+        flow: Right
+        height: Fit
+        width: Fill
+        align: {y: 0.5}
+        spacing: 8
+        padding: {left: 4}
+
+        label = <Label> {
+            width: 110
+            draw_text: {
+                text_style: <REGULAR_FONT>{font_size: 10},
+                color: #000
+            }
+        }
+        <RoundedView> {
+            width: Fill
+            height: Fit
+            show_bg: true
+            draw_bg: {
+                radius: 5.0
+                color: #fff
+                border_width: 1.0,
+                border_color: #D9D9D9,
+            }
+            input = <MolyTextInput> {
+                width: Fill,
+                height: Fit,
+                empty_message: " "
+                draw_bg: {
+                    radius: 0
+                    color: #0000
+                    border_width: 0
+                }
+                draw_text: {
+                    text_style: <REGULAR_FONT>{font_size: 10},
+                }
+            }
+        }
+    }
+
+    ChatParamsButton = <MolyButton> {
+        width: Fit
+        height: Fit
+        padding: {top: 6, bottom: 6, left: 10, right: 10}
+        draw_bg: {
+            color: #fff
+            border_width: 1.0
+            border_color: #D0D5DD
+        }
+        draw_text: {
+            text_style: <REGULAR_FONT>{font_size: 9},
+            fn get_color(self) -> vec4 {
+                return #000
+            }
+        }
+    }
+
     ChatParams = {{ChatParams}} <MolyTogglePanel> {
         open_content = {
-            <View> {
+            <ScrollYView> {
                 width: Fill
                 height: Fill
-                padding: {top: 70, left: 25.0, right: 25.0}
+                padding: {top: 70, left: 25.0, right: 25.0, bottom: 25.0}
                 spacing: 35
                 flow: Down
                 show_bg: true
@@ -191,6 +258,183 @@ live_design! {
                         min: 0.0
                         max: 1.0
                     }
+
+                    top_k = <MolySlider> {
+                        text: "Top K"
+                        min: 0.0
+                        max: 100.0
+                        step: 1.0
+                    }
+
+                    min_p = <MolySlider> {
+                        text: "Min P"
+                        min: 0.0
+                        max: 1.0
+                    }
+
+                    typical_p = <MolySlider> {
+                        text: "Typical P"
+                        min: 0.0
+                        max: 1.0
+                    }
+
+                    repeat_penalty = <MolySlider> {
+                        text: "Repeat Penalty"
+                        min: 1.0
+                        max: 2.0
+                    }
+
+                    repeat_last_n = <MolySlider> {
+                        text: "Repeat Last N"
+                        min: 0.0
+                        max: 512.0
+                        step: 1.0
+                    }
+
+                    mirostat = <MolySlider> {
+                        text: "Mirostat"
+                        min: 0.0
+                        max: 2.0
+                        step: 1.0
+                    }
+
+                    seed = <ChatParamsField> {
+                        label = {
+                            text: "Seed"
+                            hover_actions_enabled: true
+                        }
+                        input = { empty_message: "Random" }
+                    }
+
+                    logprobs_section = <View> {
+                        flow: Down
+                        height: Fit
+                        width: Fill
+                        spacing: 12
+
+                        <View> {
+                            flow: Right
+                            height: Fit
+                            width: Fill
+                            align: {y: 0.5}
+                            padding: {left: 4}
+                            logprobs_label = <Label> {
+                                width: Fill
+                                draw_text: {
+                                    text_style: <BOLD_FONT>{font_size: 10},
+                                    color: #000
+                                }
+                                text: "Token Probabilities"
+                                hover_actions_enabled: true
+                            }
+                            logprobs = <MolySwitch> {}
+                        }
+
+                        top_logprobs_section = <View> {
+                            height: Fit
+                            width: Fill
+                            top_logprobs = <MolySlider> {
+                                text: "Alternatives"
+                                min: 1.0
+                                max: 20.0
+                                step: 1.0
+                            }
+                        }
+                    }
+                }
+
+                load_preset_section = <View> {
+                    flow: Down
+                    height: Fit
+                    width: Fill
+                    spacing: 12
+
+                    load_preset_label = <Label> {
+                        draw_text: {
+                            text_style: <BOLD_FONT>{font_size: 10}
+                            color: #667085
+                        }
+                        text: "LOAD PRESET"
+                        hover_actions_enabled: true
+                    }
+
+                    load_presets = <ModelsDropDown> {
+                        width: Fill
+                        padding: {top: 10.0, right: 10.0, bottom: 10.0, left: 4.0}
+                        labels: ["New preset"]
+                    }
+
+                    preset_name = <ChatParamsField> {
+                        label = { text: "Name" }
+                        input = { empty_message: "Preset name" }
+                    }
+                    preset_n_ctx = <ChatParamsField> {
+                        label = { text: "Context Size" }
+                        input = { empty_message: "Auto" }
+                    }
+                    preset_gpu_layers = <ChatParamsField> {
+                        label = { text: "GPU Layers" }
+                        input = { empty_message: "Max" }
+                    }
+                    preset_n_batch = <ChatParamsField> {
+                        label = { text: "Batch Size" }
+                        input = { empty_message: "Auto" }
+                    }
+                    preset_prompt_template = <ChatParamsField> {
+                        label = { text: "Template" }
+                        input = { empty_message: "From the model" }
+                    }
+                    preset_rope_freq_base = <ChatParamsField> {
+                        label = { text: "RoPE Base" }
+                        input = { empty_message: "From the model" }
+                    }
+                    preset_rope_freq_scale = <ChatParamsField> {
+                        label = { text: "RoPE Scale" }
+                        input = { empty_message: "From the model" }
+                    }
+                    preset_lora = <ChatParamsField> {
+                        label = { text: "LoRA Adapters" }
+                        input = { empty_message: "file.gguf:1.0, ..." }
+                    }
+
+                    <View> {
+                        flow: Right
+                        height: Fit
+                        width: Fill
+                        align: {y: 0.5}
+                        padding: {left: 4}
+                        preset_default_label = <Label> {
+                            width: Fill
+                            draw_text: {
+                                text_style: <BOLD_FONT>{font_size: 10},
+                                color: #000
+                            }
+                            text: "Load the model with it"
+                            hover_actions_enabled: true
+                        }
+                        preset_default = <MolySwitch> {}
+                    }
+
+                    <View> {
+                        flow: Right
+                        height: Fit
+                        width: Fill
+                        spacing: 8
+                        save_preset = <ChatParamsButton> { text: "Save" }
+                        delete_preset = <ChatParamsButton> { text: "Delete" }
+                        export_preset = <ChatParamsButton> { text: "Copy JSON" }
+                    }
+
+                    preset_json = <ChatParamsField> {
+                        label = { text: "Shared JSON" }
+                        input = { empty_message: "Paste a preset" }
+                    }
+                    <View> {
+                        height: Fit
+                        width: Fill
+                        padding: {left: 4}
+                        import_preset = <ChatParamsButton> { text: "Import" }
+                    }
                 }
             }
         }
@@ -233,6 +477,15 @@ pub struct ChatParams {
 
     #[rust]
     current_chat_id: Option<ChatID>,
+
+    // The load presets of the file of the current chat.
+    #[rust]
+    presets_file_id: Option<FileID>,
+    #[rust]
+    presets: Vec<LoadPreset>,
+    // An index past the presets is a new one.
+    #[rust]
+    selected_preset: usize,
 }
 
 impl Widget for ChatParams {
@@ -247,6 +500,10 @@ impl Widget for ChatParams {
         if let Some(chat) = store.chats.get_current_chat() {
             self.visible = true;
 
+            // Without an engine that returns them, the probabilities would never show.
+            self.view(id!(logprobs_section))
+                .set_visible(store.supports_logprobs());
+
             let chat = chat.borrow();
             let ip = &chat.inferences_params;
 
@@ -257,6 +514,7 @@ impl Widget for ChatParams {
             let presence_penalty = self.slider(id!(presence_penalty));
             let stop = self.text_input(id!(stop));
             let stream = self.check_box(id!(stream));
+            let logprobs = self.check_box(id!(logprobs));
 
             let system_prompt = self.text_input(id!(system_prompt));
 
@@ -266,6 +524,21 @@ impl Widget for ChatParams {
             frequency_penalty.set_value(ip.frequency_penalty.into());
             presence_penalty.set_value(ip.presence_penalty.into());
             stop.set_text(&ip.stop);
+            self.slider(id!(top_k)).set_value(ip.top_k.into());
+            self.slider(id!(min_p)).set_value(ip.min_p.into());
+            self.slider(id!(typical_p)).set_value(ip.typical_p.into());
+            self.slider(id!(repeat_penalty))
+                .set_value(ip.repeat_penalty.into());
+            self.slider(id!(repeat_last_n))
+                .set_value(ip.repeat_last_n.into());
+            self.slider(id!(mirostat)).set_value(ip.mirostat.into());
+            self.slider(id!(top_logprobs))
+                .set_value(ip.top_logprobs.into());
+            let seed = self.text_input(id!(seed.input));
+            // Keeps what is being typed, even when it is not a valid seed yet.
+            if seed.text().trim().parse::<u32>().ok() != ip.seed {
+                seed.set_text(&ip.seed.map(|s| s.to_string()).unwrap_or_default());
+            }
 
             let system_prompt_value = chat.system_prompt.clone().unwrap_or_default();
             system_prompt.set_text(&system_prompt_value);
@@ -276,6 +549,14 @@ impl Widget for ChatParams {
             if stream.selected(cx) != ip.stream {
                 stream.set_selected(cx, ip.stream);
             }
+            if logprobs.selected(cx) != ip.logprobs {
+                logprobs.set_selected(cx, ip.logprobs);
+            }
+            self.view(id!(top_logprobs_section))
+                .set_visible(ip.logprobs);
+
+            self.view(id!(load_preset_section))
+                .set_visible(self.presets_file_id.is_some());
         } else {
             self.visible = false;
         }
@@ -304,6 +585,12 @@ impl WidgetMatchEvent for ChatParams {
             self.set_open(cx, true);
         }
 
+        let file_id = store
+            .chats
+            .get_current_chat()
+            .and_then(|chat| chat.borrow().last_used_file_id.clone());
+        self.handle_preset_actions(cx, actions, &store.chats, file_id);
+
         if let Some(chat) = store.chats.get_current_chat() {
             let mut chat = chat.borrow_mut();
 
@@ -342,6 +629,43 @@ impl WidgetMatchEvent for ChatParams {
                 ip.stream = value;
             }
 
+            if let Some(value) = self.slider(id!(top_k)).slided(&actions) {
+                ip.top_k = value as u32;
+            }
+
+            if let Some(value) = self.slider(id!(min_p)).slided(&actions) {
+                ip.min_p = value as f32;
+            }
+
+            if let Some(value) = self.slider(id!(typical_p)).slided(&actions) {
+                ip.typical_p = value as f32;
+            }
+
+            if let Some(value) = self.slider(id!(repeat_penalty)).slided(&actions) {
+                ip.repeat_penalty = value as f32;
+            }
+
+            if let Some(value) = self.slider(id!(repeat_last_n)).slided(&actions) {
+                ip.repeat_last_n = value as u32;
+            }
+
+            if let Some(value) = self.slider(id!(mirostat)).slided(&actions) {
+                ip.mirostat = value as u32;
+            }
+
+            if let Some(value) = self.text_input(id!(seed.input)).changed(&actions) {
+                ip.seed = value.trim().parse().ok();
+            }
+
+            if let Some(value) = self.check_box(id!(logprobs)).changed(actions) {
+                ip.logprobs = value;
+                self.redraw(cx);
+            }
+
+            if let Some(value) = self.slider(id!(top_logprobs)).slided(&actions) {
+                ip.top_logprobs = value as u32;
+            }
+
             if let Some(value) = self.text_input(id!(system_prompt)).changed(&actions) {
                 if value.is_empty() {
                     chat.system_prompt = None;
@@ -354,6 +678,239 @@ impl WidgetMatchEvent for ChatParams {
 }
 
 impl ChatParams {
+    fn handle_preset_actions(
+        &mut self,
+        cx: &mut Cx,
+        actions: &Actions,
+        chats: &Chats,
+        file_id: Option<FileID>,
+    ) {
+        if file_id != self.presets_file_id {
+            self.presets_file_id = file_id;
+            self.reload_presets(cx, chats, None);
+        }
+        let Some(file_id) = self.presets_file_id.clone() else {
+            return;
+        };
+
+        if let Some(index) = self.drop_down(id!(load_presets)).selected(actions) {
+            self.selected_preset = index;
+            self.show_selected_preset(cx);
+        }
+
+        if self.button(id!(save_preset)).clicked(actions) {
+            match self.preset_from_fields(cx) {
+                Ok(preset) => {
+                    let name = preset.name.clone();
+                    if let Err(e) = chats.save_load_preset(&file_id, preset) {
+                        eprintln!("Error saving the load preset: {e:#}");
+                    }
+                    self.reload_presets(cx, chats, Some(&name));
+                }
+                Err(e) => eprintln!("Invalid load preset: {e}"),
+            }
+        }
+
+        if self.button(id!(delete_preset)).clicked(actions) {
+            if let Some(preset) = self.presets.get(self.selected_preset) {
+                if let Err(e) = chats.delete_load_preset(&file_id, preset.name.clone()) {
+                    eprintln!("Error deleting the load preset: {e:#}");
+                }
+                self.reload_presets(cx, chats, None);
+            }
+        }
+
+        if self.button(id!(export_preset)).clicked(actions) {
+            match self.preset_from_fields(cx) {
+                Ok(preset) => cx.copy_to_clipboard(&serde_json::to_string_pretty(&preset).unwrap()),
+                Err(e) => eprintln!("Invalid load preset: {e}"),
+            }
+        }
+
+        if self.button(id!(import_preset)).clicked(actions) {
+            let json = self.text_input(id!(preset_json.input)).text();
+            match serde_json::from_str::<LoadPreset>(&json) {
+                Ok(preset) => {
+                    let name = preset.name.clone();
+                    if let Err(e) = chats.save_load_preset(&file_id, preset) {
+                        eprintln!("Error importing the load preset: {e:#}");
+                    }
+                    self.text_input(id!(preset_json.input)).set_text("");
+                    self.reload_presets(cx, chats, Some(&name));
+                }
+                Err(e) => eprintln!("Invalid load preset JSON: {e}"),
+            }
+        }
+    }
+
+    /// Fetches the presets of the file again, selecting the one named `select`, or
+    /// the default one.
+    fn reload_presets(&mut self, cx: &mut Cx, chats: &Chats, select: Option<&str>) {
+        self.presets = match &self.presets_file_id {
+            Some(file_id) => chats.load_presets(file_id),
+            None => vec![],
+        };
+
+        self.selected_preset = self
+            .presets
+            .iter()
+            .position(|preset| match select {
+                Some(name) => preset.name == name,
+                None => preset.is_default,
+            })
+            .unwrap_or(self.presets.len());
+
+        let mut labels = self
+            .presets
+            .iter()
+            .map(|preset| preset.name.clone())
+            .collect::<Vec<_>>();
+        labels.push("New preset".to_string());
+
+        let drop_down = self.drop_down(id!(load_presets));
+        drop_down.set_labels(labels);
+        drop_down.set_selected_item(self.selected_preset);
+
+        self.show_selected_preset(cx);
+    }
+
+    fn show_selected_preset(&mut self, cx: &mut Cx) {
+        let (name, is_default, options) = match self.presets.get(self.selected_preset) {
+            Some(preset) => (
+                preset.name.clone(),
+                preset.is_default,
+                preset.options.clone(),
+            ),
+            None => (String::new(), false, LoadModelOptions::default()),
+        };
+
+        let optional = |value: Option<u32>| value.map(|v| v.to_string()).unwrap_or_default();
+        let rope = |value: f32| {
+            if value == 0.0 {
+                String::new()
+            } else {
+                value.to_string()
+            }
+        };
+        let gpu_layers = match options.gpu_layers {
+            GPULayers::Specific(n) => n.to_string(),
+            GPULayers::Max => String::new(),
+        };
+
+        self.text_input(id!(preset_name.input)).set_text(&name);
+        self.text_input(id!(preset_n_ctx.input))
+            .set_text(&optional(options.n_ctx));
+        self.text_input(id!(preset_gpu_layers.input))
+            .set_text(&gpu_layers);
+        self.text_input(id!(preset_n_batch.input))
+            .set_text(&optional(options.n_batch));
+        self.text_input(id!(preset_prompt_template.input))
+            .set_text(&options.prompt_template.unwrap_or_default());
+        self.text_input(id!(preset_rope_freq_base.input))
+            .set_text(&rope(options.rope_freq_base));
+        self.text_input(id!(preset_rope_freq_scale.input))
+            .set_text(&rope(options.rope_freq_scale));
+        // Adapters are files of the same model, shown by their name.
+        let adapters = options
+            .lora_adapters
+            .iter()
+            .map(|adapter| {
+                let name = adapter
+                    .file_id
+                    .split_once('#')
+                    .map_or(adapter.file_id.as_str(), |(_, name)| name);
+                format!("{name}:{}", adapter.scale)
+            })
+            .collect::<Vec<_>>();
+        self.text_input(id!(preset_lora.input))
+            .set_text(&adapters.join(", "));
+
+        let default = self.check_box(id!(preset_default));
+        if default.selected(cx) != is_default {
+            default.set_selected(cx, is_default);
+        }
+
+        self.redraw(cx);
+    }
+
+    /// The preset being edited. Options without a field keep the values of the
+    /// selected preset.
+    fn preset_from_fields(&self, cx: &mut Cx) -> Result<LoadPreset, String> {
+        let text = |id: &[LiveId]| self.text_input(id).text().trim().to_string();
+        let number = |id: &[LiveId], label: &str| -> Result<Option<u32>, String> {
+            let value = text(id);
+            if value.is_empty() {
+                return Ok(None);
+            }
+            value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("{label} must be a whole number"))
+        };
+        let rope = |id: &[LiveId], label: &str| -> Result<f32, String> {
+            let value = text(id);
+            if value.is_empty() {
+                return Ok(0.0);
+            }
+            value
+                .parse()
+                .map_err(|_| format!("{label} must be a number"))
+        };
+
+        let name = text(id!(preset_name.input));
+        if name.is_empty() {
+            return Err("the preset needs a name".to_string());
+        }
+
+        let mut options = self
+            .presets
+            .get(self.selected_preset)
+            .map(|preset| preset.options.clone())
+            .unwrap_or_default();
+        options.n_ctx = number(id!(preset_n_ctx.input), "Context Size")?;
+        options.gpu_layers = match number(id!(preset_gpu_layers.input), "GPU Layers")? {
+            Some(n) => GPULayers::Specific(n),
+            None => GPULayers::Max,
+        };
+        options.n_batch = number(id!(preset_n_batch.input), "Batch Size")?;
+        let prompt_template = text(id!(preset_prompt_template.input));
+        options.prompt_template = (!prompt_template.is_empty()).then_some(prompt_template);
+        options.rope_freq_base = rope(id!(preset_rope_freq_base.input), "RoPE Base")?;
+        options.rope_freq_scale = rope(id!(preset_rope_freq_scale.input), "RoPE Scale")?;
+        let model_id = self
+            .presets_file_id
+            .as_ref()
+            .and_then(|file_id| file_id.split_once('#'))
+            .map_or("", |(model_id, _)| model_id);
+        options.lora_adapters = text(id!(preset_lora.input))
+            .split(',')
+            .map(str::trim)
+            .filter(|adapter| !adapter.is_empty())
+            .map(|adapter| {
+                let (name, scale) = match adapter.rsplit_once(':') {
+                    Some((name, scale)) => (
+                        name.trim(),
+                        scale
+                            .trim()
+                            .parse()
+                            .map_err(|_| format!("the scale of {name} must be a number"))?,
+                    ),
+                    None => (adapter, 1.0),
+                };
+                Ok(LoraAdapter {
+                    file_id: format!("{model_id}#{name}"),
+                    scale,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(LoadPreset {
+            name,
+            is_default: self.check_box(id!(preset_default)).selected(cx),
+            options,
+        })
+    }
+
     fn handle_tooltip_actions(&mut self, cx: &mut Cx, actions: &Actions) {
         if !self.is_open(cx) {
             return;
@@ -408,6 +965,55 @@ impl ChatParams {
             cx, actions
         );
 
+        self.handle_tooltip_actions_for_label(
+            id!(load_preset_label),
+            "Load presets are named options to load the model with, like its context size or the number of layers offloaded to the GPU. The one chosen to load the model with is used every time the model is loaded for a chat. Presets can be shared by copying their JSON.".to_string(),
+            TOOLTIP_OFFSET_BOTTOM,
+            cx, actions
+        );
+
+        self.handle_tooltip_actions_for_slider(
+            id!(top_k),
+            "Top K limits the choice of the next token to the K most likely ones. Lower values give more focused answers, 0 disables it.".to_string(),
+            TOOLTIP_OFFSET_BOTTOM,
+            cx, actions
+        );
+
+        self.handle_tooltip_actions_for_slider(
+            id!(min_p),
+            "Min P discards the tokens whose probability is lower than this fraction of the probability of the most likely token.".to_string(),
+            TOOLTIP_OFFSET_BOTTOM,
+            cx, actions
+        );
+
+        self.handle_tooltip_actions_for_slider(
+            id!(repeat_penalty),
+            "Lowers the probability of the tokens found in the last tokens of the conversation, as many as Repeat Last N. 1 disables it.".to_string(),
+            TOOLTIP_OFFSET_BOTTOM,
+            cx, actions
+        );
+
+        self.handle_tooltip_actions_for_slider(
+            id!(mirostat),
+            "Mirostat adapts the sampling to keep the surprise of the answer constant, instead of Top K, Top P and Min P. 0 disables it, 1 and 2 select its version.".to_string(),
+            TOOLTIP_OFFSET_BOTTOM,
+            cx, actions
+        );
+
+        self.handle_tooltip_actions_for_label(
+            id!(logprobs_label),
+            "Keeps the probability of every token of the answers, and of the most likely alternatives to it, to inspect how sure the model was.".to_string(),
+            TOOLTIP_OFFSET_BOTTOM,
+            cx, actions
+        );
+
+        self.handle_tooltip_actions_for_label(
+            id!(seed.label),
+            "The seed of the random choices of the model. A fixed seed gives the same answer to the same conversation, while an empty one picks a new seed every time.".to_string(),
+            TOOLTIP_OFFSET_BOTTOM,
+            cx, actions
+        );
+
         self.handle_tooltip_actions_for_slider(
             id!(presence_penalty),
             "This parameter is used to encourage the model to include a diverse range of tokens in the generated text. It is a value that is subtracted from the log-probability of a token each time it is generated. A higher presence_penalty value will result in the model being more likely to generate tokens that have not yet been included in the generated text.".to_string(),
@@ -12,6 +12,7 @@ pub mod model_selector;
 pub mod model_selector_list;
 pub mod model_selector_loading;
 pub mod shared;
+pub mod token_probabilities;
 
 use makepad_widgets::Cx;
 
@@ -19,6 +20,7 @@ pub fn live_design(cx: &mut Cx) {
     chat_history_card::live_design(cx);
     chat_history::live_design(cx);
     chat_line_loading::live_design(cx);
+    token_probabilities::live_design(cx);
     chat_line::live_design(cx);
     chat_panel::live_design(cx);
     chat_params::live_design(cx);
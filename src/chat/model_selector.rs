@@ -1,11 +1,12 @@
 use crate::{
-    data::store::Store,
+    data::{chats::model_loader::ModelLoaderStatusChanged, store::Store},
     shared::{
         actions::ChatAction,
         utils::{format_model_size, hex_rgb_color},
     },
 };
 use makepad_widgets::*;
+use moly_protocol::protocol::LoadStage;
 
 use super::{
     model_selector_list::{ModelSelectorAction, ModelSelectorListWidgetExt},
@@ -19,6 +20,7 @@ live_design! {
     import crate::shared::styles::*;
 
     import crate::chat::model_info::ModelInfo;
+    import crate::chat::model_info::ModelAttributeTag;
     import crate::chat::model_selector_list::ModelSelectorList;
     import crate::chat::model_selector_loading::ModelSelectorLoading;
 
@@ -86,6 +88,13 @@ live_design! {
                             text_style: <BOLD_FONT>{font_size: 11},
                         }
                     }
+
+                    resources_tag = <ModelAttributeTag> {
+                        visible: false,
+                        draw_bg: {
+                            color: #E8F5E9,
+                        }
+                    }
                 }
             }
 
@@ -310,6 +319,14 @@ impl Widget for ModelSelector {
 
 const MAX_OPTIONS_HEIGHT: f64 = 400.0;
 
+fn stage_caption(stage: LoadStage) -> &'static str {
+    match stage {
+        LoadStage::Mapping => "Opening file",
+        LoadStage::Preloading => "Preloading",
+        LoadStage::WarmingUp => "Warming up",
+    }
+}
+
 impl WidgetMatchEvent for ModelSelector {
     fn handle_actions(&mut self, cx: &mut Cx, actions: &Actions, scope: &mut Scope) {
         let store = scope.data.get::<Store>().unwrap();
@@ -343,6 +360,10 @@ impl WidgetMatchEvent for ModelSelector {
         }
 
         for action in actions {
+            if let Some(_) = action.downcast_ref::<ModelLoaderStatusChanged>() {
+                self.redraw(cx);
+            }
+
             match action.cast() {
                 ModelSelectorAction::Selected(_) => {
                     self.hide_options(cx);
@@ -403,7 +424,15 @@ impl ModelSelector {
             };
 
             let caption = if is_loading {
-                format!("Loading {}", file.name.trim())
+                match store.chats.model_loader.progress() {
+                    Some((stage, progress)) => format!(
+                        "Loading {} · {} {:.0}%",
+                        file.name.trim(),
+                        stage_caption(stage),
+                        progress * 100.0
+                    ),
+                    None => format!("Loading {}", file.name.trim()),
+                }
             } else {
                 file.name.trim().to_string()
             };
@@ -419,6 +448,23 @@ impl ModelSelector {
                 },
             );
 
+            let resources = store
+                .chats
+                .model_loader
+                .resources()
+                .filter(|_| Some(&file.id) == loaded_file.map(|f| &f.id));
+            let is_resources_visible = resources.is_some();
+            let resources = resources
+                .map(|r| format!("RAM {:.1} GB · CPU {:.0}%", r.ram_usage, r.cpu_usage))
+                .unwrap_or_default();
+
+            selected_view.apply_over(
+                cx,
+                live! {
+                    resources_tag = { visible: (is_resources_visible), caption = { text: (resources) }}
+                },
+            );
+
             if let Some(model) = store.downloads.get_model_by_file_id(&file.id) {
                 let architecture = model.architecture.trim();
                 let params_size = model.size.trim();
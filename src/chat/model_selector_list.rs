@@ -140,6 +140,7 @@ impl ModelSelectorList {
     fn draw_items(&mut self, cx: &mut Cx2d, store: &Store) {
 
         let mut items = store.downloads.downloaded_files.clone();
+        items.retain(|f| !f.file.is_adapter);
         items.sort_by(|a, b| b.downloaded_at.cmp(&a.downloaded_at));
 
         if items.is_empty() {
@@ -0,0 +1,284 @@
+use makepad_widgets::*;
+use moly_protocol::open_ai::LogProbsItemData;
+
+use crate::shared::utils::hex_rgb_color;
+
+live_design! {
+    import makepad_widgets::base::*;
+    import makepad_widgets::theme_desktop_dark::*;
+
+    import crate::shared::styles::*;
+
+    TokenChip = <RoundedView> {
+        width: Fit,
+        height: Fit,
+        padding: {top: 2, bottom: 2, left: 1, right: 1}
+        margin: {bottom: 2}
+
+        show_bg: true
+        draw_bg: {
+            radius: 2.0,
+            color: #fff,
+        }
+
+        label = <Label> {
+            draw_text: {
+                text_style: <REGULAR_FONT>{font_size: 10},
+                color: #000
+            }
+        }
+    }
+
+    TokenProbabilities = {{TokenProbabilities}} {
+        width: Fill,
+        height: Fit,
+        flow: Down,
+        spacing: 12,
+
+        token_template: <TokenChip> {}
+
+        alternatives: <RoundedView> {
+            width: Fill,
+            height: Fit,
+            padding: 10,
+
+            show_bg: true
+            draw_bg: {
+                radius: 5.0,
+                color: #F9FAFB,
+                border_width: 1.0,
+                border_color: #EAECF0,
+            }
+
+            alternatives_label = <Label> {
+                width: Fill,
+                draw_text: {
+                    text_style: <REGULAR_FONT>{font_size: 9},
+                    wrap: Word,
+                    color: #667085
+                }
+                text: "Hover or click a token to see its alternatives"
+            }
+        }
+    }
+}
+
+/// The tokens of an answer colored by how sure the model was of them. The alternatives
+/// of a token are shown while hovering it, or until another one is clicked.
+#[derive(Live, LiveHook, Widget)]
+pub struct TokenProbabilities {
+    #[redraw]
+    #[rust]
+    area: Area,
+
+    #[walk]
+    walk: Walk,
+
+    #[layout]
+    layout: Layout,
+
+    #[live]
+    token_template: Option<LivePtr>,
+
+    #[live]
+    #[find]
+    alternatives: View,
+
+    #[rust]
+    tokens: Vec<LogProbsItemData>,
+
+    // In the order of the tokens.
+    #[rust]
+    items: Vec<WidgetRef>,
+
+    #[rust]
+    hovered: Option<usize>,
+
+    #[rust]
+    pinned: Option<usize>,
+}
+
+impl Widget for TokenProbabilities {
+    fn handle_event(&mut self, cx: &mut Cx, event: &Event, _scope: &mut Scope) {
+        let mut changed = false;
+        for (index, item) in self.items.iter().enumerate() {
+            match event.hits(cx, item.area()) {
+                Hit::FingerHoverIn(_) => {
+                    self.hovered = Some(index);
+                    changed = true;
+                }
+                Hit::FingerHoverOut(_) if self.hovered == Some(index) => {
+                    self.hovered = None;
+                    changed = true;
+                }
+                Hit::FingerDown(_) => {
+                    self.pinned = if self.pinned == Some(index) {
+                        None
+                    } else {
+                        Some(index)
+                    };
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+
+        if changed {
+            self.show_alternatives();
+            self.redraw(cx);
+        }
+    }
+
+    fn draw_walk(&mut self, cx: &mut Cx2d, scope: &mut Scope, walk: Walk) -> DrawStep {
+        cx.begin_turtle(walk, self.layout);
+
+        cx.begin_turtle(
+            Walk::fill_fit(),
+            Layout {
+                flow: Flow::RightWrap,
+                ..Layout::default()
+            },
+        );
+        for item in self.items.iter_mut() {
+            let _ = item.draw_all(cx, scope);
+        }
+        cx.end_turtle();
+
+        let _ = self.alternatives.draw_all(cx, scope);
+
+        cx.end_turtle_with_area(&mut self.area);
+        DrawStep::done()
+    }
+}
+
+impl TokenProbabilities {
+    fn show_alternatives(&mut self) {
+        let text = match self
+            .hovered
+            .or(self.pinned)
+            .and_then(|i| self.tokens.get(i))
+        {
+            Some(token) => {
+                let mut text = format!(
+                    "{} · {}",
+                    display_token(&token.token),
+                    format_probability(token.logprob)
+                );
+                for alternative in &token.top_logprobs {
+                    text.push_str(&format!(
+                        "\n{} · {}",
+                        display_token(&alternative.token),
+                        format_probability(alternative.logprob)
+                    ));
+                }
+                text
+            }
+            None => "Hover or click a token to see its alternatives".to_string(),
+        };
+        self.label(id!(alternatives_label)).set_text(&text);
+    }
+}
+
+impl TokenProbabilitiesRef {
+    /// Only the tokens that were not shown yet are added while the answer streams, and
+    /// nothing is done when they are all shown already, as it is called on every draw.
+    pub fn set_tokens(&self, cx: &mut Cx, tokens: &[LogProbsItemData]) {
+        let Some(mut inner) = self.borrow_mut() else {
+            return;
+        };
+
+        let kept = kept_tokens(&inner.tokens, tokens);
+        if kept == inner.tokens.len() && kept == tokens.len() {
+            return;
+        }
+        if kept < inner.tokens.len() {
+            inner.tokens.clear();
+            inner.items.clear();
+            inner.hovered = None;
+            inner.pinned = None;
+        }
+
+        for token in &tokens[inner.tokens.len()..] {
+            let item = WidgetRef::new_from_ptr(cx, inner.token_template);
+            let text = token.token.replace('\n', "↵");
+            let color = confidence_color(token.logprob);
+            item.apply_over(
+                cx,
+                live! {
+                    draw_bg: { color: (color) }
+                    label = { text: (text) }
+                },
+            );
+            inner.items.push(item);
+            inner.tokens.push(token.clone());
+        }
+
+        inner.show_alternatives();
+    }
+}
+
+/// How many of the `shown` tokens start `tokens` too. The tokens of an answer are only
+/// added to, so comparing the last one shown is enough to tell it is the same answer.
+fn kept_tokens(shown: &[LogProbsItemData], tokens: &[LogProbsItemData]) -> usize {
+    let Some(last) = shown.last() else {
+        return 0;
+    };
+    match tokens.get(shown.len() - 1) {
+        Some(token) if token.token == last.token && token.logprob == last.logprob => shown.len(),
+        _ => 0,
+    }
+}
+
+/// From red for unlikely tokens to white for the ones the model was sure of.
+fn confidence_color(logprob: f32) -> Vec4 {
+    let probability = logprob.exp();
+    let hex = if probability >= 0.9 {
+        0xFFFFFF
+    } else if probability >= 0.7 {
+        0xECFDF3
+    } else if probability >= 0.5 {
+        0xFEF0C7
+    } else if probability >= 0.3 {
+        0xFEDF89
+    } else if probability >= 0.1 {
+        0xFDB022
+    } else {
+        0xFDA29B
+    };
+    hex_rgb_color(hex)
+}
+
+fn format_probability(logprob: f32) -> String {
+    format!("{:.1}%", logprob.exp() * 100.0)
+}
+
+/// Makes the whitespace of a token visible.
+fn display_token(token: &str) -> String {
+    format!("\"{}\"", token.replace('\n', "\\n"))
+}
+
+#[test]
+fn test_kept_tokens() {
+    let token = |token: &str, logprob: f32| LogProbsItemData {
+        token: token.to_string(),
+        logprob,
+        bytes: None,
+        top_logprobs: vec![],
+    };
+    let answer = [
+        token("Hello", -0.1),
+        token(",", -0.5),
+        token(" world", -1.2),
+    ];
+
+    assert_eq!(kept_tokens(&[], &answer), 0);
+    // Streaming adds tokens to the ones shown.
+    assert_eq!(kept_tokens(&answer[..2], &answer), 2);
+    // Nothing changed since the last draw.
+    assert_eq!(kept_tokens(&answer, &answer), 3);
+    // Another answer, or a regenerated one, is shown from the start.
+    let other = [token("Hi", -0.1), token("!", -0.2)];
+    assert_eq!(kept_tokens(&answer[..2], &other), 0);
+    assert_eq!(kept_tokens(&answer, &other), 0);
+    assert_eq!(kept_tokens(&answer, &[]), 0);
+}
@@ -3,7 +3,7 @@ use makepad_widgets::Cx;
 use moly_backend::Backend;
 use moly_protocol::data::{File, FileID};
 use moly_protocol::open_ai::*;
-use moly_protocol::protocol::Command;
+use moly_protocol::protocol::{Command, LoadModelOptions, MolyError};
 use std::path::PathBuf;
 use std::sync::mpsc::channel;
 use std::thread;
@@ -16,6 +16,9 @@ use super::model_loader::ModelLoader;
 
 pub type ChatID = u128;
 
+/// Sent with the chats that don't have a system prompt of their own.
+const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful, respectful, and honest assistant.";
+
 #[derive(Debug)]
 pub struct ChatEntityAction {
     pub chat_id: ChatID,
@@ -24,8 +27,12 @@ pub struct ChatEntityAction {
 
 #[derive(Debug)]
 enum ChatEntityActionKind {
-    AppendDelta(String),
+    Accepted(ChatRequestID),
+    Queued(usize),
+    AppendDelta(String, Vec<LogProbsItemData>),
+    Usage(UsageData),
     StreamingDone,
+    Failed(MolyError),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -34,9 +41,35 @@ pub struct ChatMessage {
     pub role: Role,
     pub username: Option<String>,
     pub content: String,
+    /// Local paths of the images attached to the message.
+    #[serde(default)]
+    pub images: Vec<PathBuf>,
+    /// The tokens of an answer with their probability and alternatives, when they were
+    /// asked for with `ChatInferenceParams::logprobs`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub logprobs: Vec<LogProbsItemData>,
+    /// The tokens of the turn ended by an answer, as counted by the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageData>,
 }
 
 impl ChatMessage {
+    fn to_message_content(&self) -> MessageContent {
+        if self.images.is_empty() {
+            return self.content.clone().into();
+        }
+
+        let mut parts = vec![ContentPart::Text {
+            text: self.content.clone(),
+        }];
+        parts.extend(
+            self.images
+                .iter()
+                .map(|path| ContentPart::image_from_path(path)),
+        );
+        MessageContent::Parts(parts)
+    }
+
     pub fn is_assistant(&self) -> bool {
         matches!(self.role, Role::Assistant)
     }
@@ -69,6 +102,18 @@ pub struct ChatInferenceParams {
     pub presence_penalty: f32,
     pub temperature: f32,
     pub top_p: f32,
+    pub top_k: u32,
+    pub min_p: f32,
+    pub typical_p: f32,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: u32,
+    /// 0 when disabled, else the version of Mirostat.
+    pub mirostat: u32,
+    /// A random seed is used when empty.
+    pub seed: Option<u32>,
+    /// Asks for the probabilities of the tokens of the answer and of their alternatives.
+    pub logprobs: bool,
+    pub top_logprobs: u32,
     pub stream: bool,
     pub stop: String,
 }
@@ -81,6 +126,16 @@ impl Default for ChatInferenceParams {
             presence_penalty: 0.0,
             temperature: 1.0,
             top_p: 1.0,
+            // The defaults of llama.cpp.
+            top_k: 40,
+            min_p: 0.05,
+            typical_p: 1.0,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+            mirostat: 0,
+            seed: None,
+            logprobs: false,
+            top_logprobs: 5,
             stream: true,
             stop: "".into(),
         }
@@ -94,6 +149,12 @@ pub struct Chat {
     pub last_used_file_id: Option<FileID>,
     pub messages: Vec<ChatMessage>,
     pub is_streaming: bool,
+    /// The request being answered, to stop it.
+    pub chat_request_id: Option<ChatRequestID>,
+    /// Requests of other chats that will be answered before this one, while it waits.
+    pub queue_position: Option<usize>,
+    /// Why the last answer failed, until the next message is sent.
+    pub error: Option<MolyError>,
     pub inferences_params: ChatInferenceParams,
     pub system_prompt: Option<String>,
     pub accessed_at: chrono::DateTime<chrono::Utc>,
@@ -115,6 +176,9 @@ impl Chat {
             messages: vec![],
             last_used_file_id: None,
             is_streaming: false,
+            chat_request_id: None,
+            queue_position: None,
+            error: None,
             title_state: TitleState::default(),
             chats_dir,
             inferences_params: ChatInferenceParams::default(),
@@ -134,6 +198,9 @@ impl Chat {
                     title: data.title,
                     title_state: data.title_state,
                     is_streaming: false,
+                    chat_request_id: None,
+                    queue_position: None,
+                    error: None,
                     chats_dir,
                     inferences_params: ChatInferenceParams::default(),
                     system_prompt: data.system_prompt,
@@ -206,59 +273,44 @@ impl Chat {
     pub fn send_message_to_model(
         &mut self,
         prompt: String,
+        images: Vec<PathBuf>,
         wanted_file: &File,
+        load_options: LoadModelOptions,
         mut model_loader: ModelLoader,
         backend: &Backend,
     ) {
-        let mut messages: Vec<_> = self
-            .messages
-            .iter()
-            .map(|message| Message {
-                content: message.content.clone(),
-                role: message.role.clone(),
-                name: None,
-            })
-            .collect();
+        let mut messages = self.history_messages();
 
-        messages.push(Message {
+        let next_id = self.messages.last().map(|m| m.id).unwrap_or(0) + 1;
+        let user_message = ChatMessage {
+            id: next_id,
+            role: Role::User,
+            username: None,
             content: prompt.clone(),
+            images,
+            logprobs: vec![],
+            usage: None,
+        };
+
+        messages.push(Message {
+            content: user_message.to_message_content(),
             role: Role::User,
             name: None,
         });
 
-        if let Some(system_prompt) = &self.system_prompt {
-            messages.insert(
-                0 as usize,
-                Message {
-                    content: system_prompt.clone(),
-                    role: Role::System,
-                    name: None,
-                },
-            );
-        } else {
-            messages.insert(
-                0 as usize,
-                Message {
-                    content: "You are a helpful, respectful, and honest assistant.".to_string(),
-                    role: Role::System,
-                    name: None,
-                },
-            );
-        }
-
         let (tx, rx) = channel();
 
         let ip = &self.inferences_params;
         let cmd = Command::Chat(
-            ChatRequestData {
+            Box::new(ChatRequestData {
                 messages,
                 model: wanted_file.name.clone(),
                 frequency_penalty: Some(ip.frequency_penalty),
-                logprobs: None,
-                top_logprobs: None,
+                logprobs: Some(ip.logprobs),
+                top_logprobs: ip.logprobs.then_some(ip.top_logprobs),
                 max_tokens: Some(ip.max_tokens),
                 presence_penalty: Some(ip.presence_penalty),
-                seed: None,
+                seed: ip.seed,
                 stop: Some(
                     ip.stop
                         .split(",")
@@ -270,35 +322,44 @@ impl Chat {
                 stream: Some(ip.stream),
                 temperature: Some(ip.temperature),
                 top_p: Some(ip.top_p),
-                n: None,
-                logit_bias: None,
-            },
+                top_k: Some(ip.top_k),
+                min_p: Some(ip.min_p),
+                typical_p: Some(ip.typical_p),
+                repeat_penalty: Some(ip.repeat_penalty),
+                repeat_last_n: Some(ip.repeat_last_n as i32),
+                mirostat: Some(ip.mirostat),
+                ..Default::default()
+            }),
             tx,
         );
 
-        let next_id = self.messages.last().map(|m| m.id).unwrap_or(0) + 1;
-        self.messages.push(ChatMessage {
-            id: next_id,
-            role: Role::User,
-            username: None,
-            content: prompt.clone(),
-        });
+        self.messages.push(user_message);
 
         self.messages.push(ChatMessage {
             id: next_id + 1,
             role: Role::Assistant,
             username: Some(wanted_file.name.clone()),
             content: "".to_string(),
+            images: vec![],
+            logprobs: vec![],
+            usage: None,
         });
 
         self.is_streaming = true;
+        self.error = None;
 
         let wanted_file = wanted_file.clone();
         let command_sender = backend.command_sender.clone();
         let chat_id = self.id;
         thread::spawn(move || {
-            if let Err(err) = model_loader.load(wanted_file.id, command_sender.clone(), None) {
+            if let Err(err) =
+                model_loader.load(wanted_file.id, load_options, command_sender.clone(), None)
+            {
                 eprintln!("Error loading model: {}", err);
+                Cx::post_action(ChatEntityAction {
+                    chat_id,
+                    kind: ChatEntityActionKind::Failed(MolyError::from_anyhow(&err)),
+                });
                 return;
             }
 
@@ -307,17 +368,41 @@ impl Chat {
             loop {
                 if let Ok(response) = rx.recv() {
                     match response {
+                        Ok(ChatResponse::Accepted(request_id)) => {
+                            Cx::post_action(ChatEntityAction {
+                                chat_id,
+                                kind: ChatEntityActionKind::Accepted(request_id),
+                            });
+                        }
+                        Ok(ChatResponse::Queued(position)) => {
+                            Cx::post_action(ChatEntityAction {
+                                chat_id,
+                                kind: ChatEntityActionKind::Queued(position),
+                            });
+                        }
                         Ok(ChatResponse::ChatResponseChunk(data)) => {
                             let mut is_done = false;
 
+                            // The chunk with the usage may come without choices.
+                            if let Some(usage) = data.usage {
+                                Cx::post_action(ChatEntityAction {
+                                    chat_id,
+                                    kind: ChatEntityActionKind::Usage(usage),
+                                });
+                            }
+                            let Some(choice) = data.choices.first() else {
+                                continue;
+                            };
+
                             Cx::post_action(ChatEntityAction {
                                 chat_id,
                                 kind: ChatEntityActionKind::AppendDelta(
-                                    data.choices[0].delta.content.clone(),
+                                    choice.delta.content.clone(),
+                                    logprobs_content(&choice.logprobs),
                                 ),
                             });
 
-                            if let Some(_reason) = &data.choices[0].finish_reason {
+                            if let Some(_reason) = &choice.finish_reason {
                                 is_done = true;
 
                                 Cx::post_action(ChatEntityAction {
@@ -335,9 +420,18 @@ impl Chat {
                                 chat_id,
                                 kind: ChatEntityActionKind::AppendDelta(
                                     data.choices[0].message.content.clone(),
+                                    logprobs_content(&data.choices[0].logprobs),
                                 ),
                             });
 
+                            // Engines that don't count the tokens leave them at 0.
+                            if data.usage.total_tokens > 0 {
+                                Cx::post_action(ChatEntityAction {
+                                    chat_id,
+                                    kind: ChatEntityActionKind::Usage(data.usage),
+                                });
+                            }
+
                             Cx::post_action(ChatEntityAction {
                                 chat_id,
                                 kind: ChatEntityActionKind::StreamingDone,
@@ -345,7 +439,14 @@ impl Chat {
 
                             break;
                         }
-                        Err(err) => eprintln!("Error receiving response chunk: {:?}", err),
+                        Err(err) => {
+                            eprintln!("Error receiving response chunk: {:?}", err);
+                            Cx::post_action(ChatEntityAction {
+                                chat_id,
+                                kind: ChatEntityActionKind::Failed(MolyError::from_anyhow(&err)),
+                            });
+                            break;
+                        }
                     }
                 } else {
                     break;
@@ -357,13 +458,55 @@ impl Chat {
     }
 
     pub fn cancel_streaming(&mut self, backend: &Backend) {
+        let Some(request_id) = self.chat_request_id else {
+            return;
+        };
+
         let (tx, _rx) = channel();
-        let cmd = Command::StopChatCompletion(tx);
+        let cmd = Command::StopChatCompletion(request_id, tx);
         backend.command_sender.send(cmd).unwrap();
 
         makepad_widgets::log!("Cancel streaming");
     }
 
+    /// The system prompt and the messages sent with the next one.
+    pub fn history_messages(&self) -> Vec<Message> {
+        let system_prompt = Message {
+            content: self
+                .system_prompt
+                .as_deref()
+                .unwrap_or(DEFAULT_SYSTEM_PROMPT)
+                .into(),
+            role: Role::System,
+            name: None,
+        };
+        std::iter::once(system_prompt)
+            .chain(self.messages.iter().map(|message| Message {
+                content: message.to_message_content(),
+                role: message.role.clone(),
+                name: None,
+            }))
+            .collect()
+    }
+
+    /// What the user is told about the failure of the last answer.
+    pub fn error_text(&self) -> Option<String> {
+        let text = match self.error.as_ref()? {
+            MolyError::OutOfMemory(_) => "There is not enough memory to run this model. \
+                Try a smaller file of the model, or a smaller context size."
+                .to_string(),
+            MolyError::PortInUse(port) => format!(
+                "The port {port} is used by another application. \
+                Choose another one in the settings."
+            ),
+            MolyError::ModelCrashed(_) => {
+                "The model stopped working, it is being restarted. Please try again.".to_string()
+            }
+            error => error.to_string(),
+        };
+        Some(text)
+    }
+
     pub fn delete_message(&mut self, message_id: usize) {
         self.messages.retain(|message| message.id != message_id);
     }
@@ -371,6 +514,9 @@ impl Chat {
     pub fn edit_message(&mut self, message_id: usize, updated_message: String) {
         if let Some(message) = self.messages.iter_mut().find(|m| m.id == message_id) {
             message.content = updated_message;
+            // They were the ones of the previous text.
+            message.logprobs.clear();
+            message.usage = None;
         }
     }
 
@@ -389,14 +535,40 @@ impl Chat {
 
     pub fn handle_action(&mut self, action: &ChatEntityAction) {
         match &action.kind {
-            ChatEntityActionKind::AppendDelta(response) => {
+            ChatEntityActionKind::Accepted(request_id) => {
+                self.chat_request_id = Some(*request_id);
+            }
+            ChatEntityActionKind::Queued(position) => {
+                self.queue_position = Some(*position);
+            }
+            ChatEntityActionKind::AppendDelta(response, logprobs) => {
+                self.queue_position = None;
                 let last = self.messages.last_mut().unwrap();
                 last.content.push_str(&response);
+                last.logprobs.extend(logprobs.iter().cloned());
+            }
+            ChatEntityActionKind::Usage(usage) => {
+                self.messages.last_mut().unwrap().usage = Some(usage.clone());
             }
             ChatEntityActionKind::StreamingDone => {
                 self.is_streaming = false;
+                self.chat_request_id = None;
+                self.queue_position = None;
+            }
+            ChatEntityActionKind::Failed(error) => {
+                self.is_streaming = false;
+                self.chat_request_id = None;
+                self.queue_position = None;
+                self.error = Some(error.clone());
             }
         }
         self.save();
     }
 }
+
+fn logprobs_content(logprobs: &Option<LogProbsData>) -> Vec<LogProbsItemData> {
+    logprobs
+        .as_ref()
+        .map(|logprobs| logprobs.content.clone())
+        .unwrap_or_default()
+}
@@ -1,16 +1,19 @@
 pub mod chat;
 pub mod model_loader;
+pub mod token_counter;
 
 use anyhow::{Context, Result};
 use chat::{Chat, ChatEntityAction, ChatID};
 use makepad_widgets::ActionTrait;
-use model_loader::ModelLoader;
+use model_loader::{ModelLoader, ModelLoaderStatus};
 use moly_backend::Backend;
 use moly_protocol::data::*;
-use moly_protocol::protocol::Command;
+use moly_protocol::open_ai::{Message, Role};
+use moly_protocol::protocol::{Command, LoadModelOptions, LoadPreset};
 use std::fs;
 use std::sync::mpsc::channel;
 use std::{cell::RefCell, path::PathBuf, rc::Rc};
+use token_counter::TokenCounter;
 
 use super::filesystem::setup_chats_folder;
 
@@ -22,6 +25,7 @@ pub struct Chats {
     pub model_loader: ModelLoader,
 
     current_chat_id: Option<ChatID>,
+    token_counter: TokenCounter,
     chats_dir: PathBuf,
 
     override_port: Option<u16>,
@@ -29,12 +33,16 @@ pub struct Chats {
 
 impl Chats {
     pub fn new(backend: Rc<Backend>) -> Self {
+        let model_loader = ModelLoader::new();
+        model_loader.watch_backend_events(&backend.command_sender);
+
         Self {
             backend,
             saved_chats: Vec::new(),
             current_chat_id: None,
             loaded_model: None,
-            model_loader: ModelLoader::new(),
+            model_loader,
+            token_counter: TokenCounter::new(),
             chats_dir: setup_chats_folder(),
             override_port: None,
         }
@@ -80,11 +88,146 @@ impl Chats {
         self.override_port = override_port;
         self.model_loader.load_async(
             file.id.clone(),
+            self.load_options(&file.id),
             self.backend.command_sender.clone(),
             override_port,
         );
     }
 
+    /// The options a file is loaded with, the ones of its default load preset if any.
+    pub fn load_options(&self, file_id: &FileID) -> LoadModelOptions {
+        self.load_presets(file_id)
+            .into_iter()
+            .find(|preset| preset.is_default)
+            .map(|preset| preset.options)
+            .unwrap_or_default()
+    }
+
+    pub fn load_presets(&self, file_id: &FileID) -> Vec<LoadPreset> {
+        let (tx, rx) = channel();
+        let presets = self
+            .backend
+            .command_sender
+            .send(Command::GetLoadPresets(file_id.clone(), tx))
+            .context("Failed to send get load presets command")
+            .and_then(|_| rx.recv().context("Failed to receive load presets"))
+            .and_then(|presets| presets);
+
+        presets.unwrap_or_else(|e| {
+            eprintln!("Error getting the load presets of {file_id}: {e:#}");
+            vec![]
+        })
+    }
+
+    pub fn save_load_preset(&self, file_id: &FileID, preset: LoadPreset) -> Result<()> {
+        let (tx, rx) = channel();
+        self.backend
+            .command_sender
+            .send(Command::SaveLoadPreset(file_id.clone(), preset, tx))
+            .context("Failed to send save load preset command")?;
+
+        rx.recv()
+            .context("Failed to receive save load preset response")?
+            .context("Save load preset operation failed")
+    }
+
+    pub fn delete_load_preset(&self, file_id: &FileID, name: String) -> Result<()> {
+        let (tx, rx) = channel();
+        self.backend
+            .command_sender
+            .send(Command::DeleteLoadPreset(file_id.clone(), name, tx))
+            .context("Failed to send delete load preset command")?;
+
+        rx.recv()
+            .context("Failed to receive delete load preset response")?
+            .context("Delete load preset operation failed")
+    }
+
+    /// Ejects the loaded model after `minutes` without chats, never when None.
+    pub fn set_idle_timeout(&self, minutes: Option<u64>) -> Result<()> {
+        let (tx, rx) = channel();
+        self.backend
+            .command_sender
+            .send(Command::SetIdleTimeout(minutes.map(|m| m * 60), tx))
+            .context("Failed to send set idle timeout command")?;
+
+        rx.recv()
+            .context("Failed to receive set idle timeout response")?
+            .context("Set idle timeout operation failed")
+    }
+
+    /// Loads the first file once the backend settles, and reads the other ones ahead.
+    pub fn preload_models(&self, file_ids: Vec<FileID>) -> Result<()> {
+        let (tx, rx) = channel();
+        self.backend
+            .command_sender
+            .send(Command::PreloadModels(file_ids, tx))
+            .context("Failed to send preload models command")?;
+
+        rx.recv()
+            .context("Failed to receive preload models response")?
+            .context("Preload models operation failed")
+    }
+
+    /// The files used by the most recent chats, the most recent first.
+    pub fn recently_used_files(&self) -> Vec<FileID> {
+        let mut chats = self
+            .saved_chats
+            .iter()
+            .map(|c| c.borrow())
+            .collect::<Vec<_>>();
+        chats.sort_by(|a, b| b.accessed_at.cmp(&a.accessed_at));
+
+        let mut file_ids = Vec::new();
+        for file_id in chats.iter().filter_map(|c| c.last_used_file_id.clone()) {
+            if !file_ids.contains(&file_id) {
+                file_ids.push(file_id);
+            }
+        }
+        file_ids
+    }
+
+    /// The tokens of the prompt and of the history it would be sent with in the current
+    /// chat, both with the markers of the chat template around them, and the context size
+    /// of the loaded model. None until the counts are known.
+    pub fn context_usage(&self, prompt: &str) -> Option<(u32, u32, u32)> {
+        let ModelLoaderStatus::Loaded(info) = self.model_loader.status() else {
+            return None;
+        };
+        let chat = self.get_current_chat()?.borrow();
+        // The answer changes with every chunk, it is counted once done.
+        if chat.is_streaming {
+            return None;
+        }
+
+        let command_sender = &self.backend.command_sender;
+        let history_messages = chat.history_messages();
+        let history_count = || {
+            self.token_counter
+                .count(&info.file_id, &history_messages, command_sender)
+        };
+        // The runtime counted the history of the last answer itself.
+        let history = match chat.messages.last().and_then(|m| m.usage.as_ref()) {
+            Some(usage) => usage.total_tokens,
+            None => history_count()?,
+        };
+        let prompt = if prompt.is_empty() {
+            0
+        } else {
+            let mut messages = history_messages.clone();
+            messages.push(Message {
+                content: prompt.into(),
+                role: Role::User,
+                name: None,
+            });
+            let with_prompt = self
+                .token_counter
+                .count(&info.file_id, &messages, command_sender)?;
+            with_prompt.saturating_sub(history_count()?)
+        };
+        Some((prompt, history, info.n_ctx))
+    }
+
     pub fn get_current_chat_id(&self) -> Option<ChatID> {
         self.current_chat_id
     }
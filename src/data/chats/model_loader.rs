@@ -2,7 +2,10 @@ use anyhow::anyhow;
 use makepad_widgets::Cx;
 use moly_protocol::{
     data::FileID,
-    protocol::{Command, LoadModelOptions, LoadModelResponse, LoadedModelInfo},
+    protocol::{
+        BackendEvent, Command, EventFilter, LoadModelOptions, LoadModelResponse, LoadStage,
+        LoadedModelInfo, ModelResourcesInfo, MolyError,
+    },
 };
 use std::{
     sync::{
@@ -30,6 +33,11 @@ pub enum ModelLoaderStatus {
 struct ModelLoaderInner {
     status: ModelLoaderStatus,
     file_id: Option<FileID>,
+    options: Option<LoadModelOptions>,
+    progress: Option<(LoadStage, f32)>,
+    resources: Option<ModelResourcesInfo>,
+    // Why the last load failed.
+    error: Option<MolyError>,
 }
 
 /// Unit for handling the non-blocking loading of models across threads.
@@ -44,6 +52,7 @@ impl ModelLoader {
     pub fn load(
         &mut self,
         file_id: FileID,
+        options: LoadModelOptions,
         command_sender: Sender<Command>,
         override_port: Option<u16>,
     ) -> Result<(), anyhow::Error> {
@@ -54,7 +63,7 @@ impl ModelLoader {
             ModelLoaderStatus::Loaded(_) => {
                 if override_port.is_none() {
                     if let Some(prev_file_id) = self.file_id() {
-                        if prev_file_id == file_id {
+                        if prev_file_id == file_id && self.options().as_ref() == Some(&options) {
                             return Ok(());
                         }
                     }
@@ -63,48 +72,137 @@ impl ModelLoader {
             _ => {}
         };
 
+        {
+            let mut inner = self.0.lock().unwrap();
+            inner.progress = None;
+            inner.resources = None;
+            inner.error = None;
+        }
         self.set_status(ModelLoaderStatus::Loading);
         self.set_file_id(Some(file_id.clone()));
+        self.set_options(Some(options.clone()));
 
-        let response = dispatch_load_command(command_sender, file_id.clone(), override_port).recv();
+        let rx = dispatch_load_command(command_sender, file_id.clone(), options, override_port);
 
-        let result = if let Ok(response) = response {
-            match response {
-                Ok(LoadModelResponse::Completed(info)) => {
+        loop {
+            match rx.recv() {
+                Ok(Ok(LoadModelResponse::Progress(_, stage, progress))) => {
+                    self.0.lock().unwrap().progress = Some((stage, progress));
+                    Cx::post_action(ModelLoaderStatusChanged);
+                }
+                Ok(Ok(LoadModelResponse::Completed(info))) => {
                     self.set_status(ModelLoaderStatus::Loaded(info));
-                    Ok(())
+                    self.watch_resources(file_id, rx);
+                    return Ok(());
                 }
-                Ok(response) => {
-                    self.set_status(ModelLoaderStatus::Failed);
-                    Err(anyhow!("Unexpected response: {:?}", response))
+                Ok(Ok(LoadModelResponse::ModelResourcesUsage(_))) => {}
+                Ok(Err(err)) => {
+                    self.set_error(MolyError::from_anyhow(&err));
+                    return Err(err);
                 }
-                Err(err) => {
-                    self.set_status(ModelLoaderStatus::Failed);
-                    Err(anyhow!(err))
+                Err(_) => {
+                    let error = MolyError::Other("Internal communication error".to_string());
+                    self.set_error(error.clone());
+                    return Err(error.into());
                 }
             }
-        } else {
-            self.set_status(ModelLoaderStatus::Failed);
-            Err(anyhow!("Internal communication error"))
+        }
+    }
+
+    /// Keeps the last resources usage of the loaded model, sent until it is unloaded.
+    fn watch_resources(
+        &self,
+        file_id: FileID,
+        rx: Receiver<Result<LoadModelResponse, anyhow::Error>>,
+    ) {
+        let inner = self.0.clone();
+        thread::spawn(move || {
+            for response in rx {
+                let Ok(LoadModelResponse::ModelResourcesUsage(resources)) = response else {
+                    continue;
+                };
+                let mut inner = inner.lock().unwrap();
+                if inner.file_id.as_ref() != Some(&file_id) {
+                    break;
+                }
+                inner.resources = Some(resources);
+                drop(inner);
+                Cx::post_action(ModelLoaderStatusChanged);
+            }
+        });
+    }
+
+    /// Follows the models the backend loads and ejects by itself, as the preloaded ones
+    /// and the ones ejected for being idle.
+    pub fn watch_backend_events(&self, command_sender: &Sender<Command>) {
+        let (tx, rx) = channel();
+        let filter = EventFilter {
+            models: true,
+            ..Default::default()
         };
+        if command_sender.send(Command::Subscribe(filter, tx)).is_err() {
+            eprintln!("Error subscribing to the model events");
+            return;
+        }
 
-        result
+        let inner = self.0.clone();
+        thread::spawn(move || {
+            for event in rx {
+                let mut inner = inner.lock().unwrap();
+                match event {
+                    // The loads of the app are followed by `load`.
+                    BackendEvent::ModelLoaded(info)
+                        if !matches!(inner.status, ModelLoaderStatus::Loading) =>
+                    {
+                        inner.file_id = Some(info.file_id.clone());
+                        inner.options = None;
+                        inner.resources = None;
+                        inner.status = ModelLoaderStatus::Loaded(info);
+                    }
+                    BackendEvent::ModelEjected(file_id)
+                        if matches!(inner.status, ModelLoaderStatus::Loaded(_))
+                            && inner.file_id.as_ref() == Some(&file_id) =>
+                    {
+                        inner.resources = None;
+                        inner.status = ModelLoaderStatus::Unloaded;
+                    }
+                    _ => continue,
+                }
+                drop(inner);
+                Cx::post_action(ModelLoaderStatusChanged);
+            }
+        });
     }
 
     pub fn load_async(
         &mut self,
         file_id: FileID,
+        options: LoadModelOptions,
         command_sender: Sender<Command>,
         override_port: Option<u16>,
     ) {
         let mut self_clone = self.clone();
         thread::spawn(move || {
-            if let Err(err) = self_clone.load(file_id, command_sender, override_port) {
+            if let Err(err) = self_clone.load(file_id, options, command_sender, override_port) {
                 eprintln!("Error loading model: {}", err);
             }
         });
     }
 
+    fn set_error(&mut self, error: MolyError) {
+        self.0.lock().unwrap().error = Some(error);
+        self.set_status(ModelLoaderStatus::Failed);
+    }
+
+    /// Why the last load failed, while the loader is [`ModelLoaderStatus::Failed`].
+    pub fn error(&self) -> Option<MolyError> {
+        let inner = self.0.lock().unwrap();
+        match inner.status {
+            ModelLoaderStatus::Failed => inner.error.clone(),
+            _ => None,
+        }
+    }
+
     fn set_status(&mut self, status: ModelLoaderStatus) {
         self.0.lock().unwrap().status = status;
         Cx::post_action(ModelLoaderStatusChanged);
@@ -118,6 +216,33 @@ impl ModelLoader {
         self.0.lock().unwrap().file_id.clone()
     }
 
+    fn set_options(&mut self, options: Option<LoadModelOptions>) {
+        self.0.lock().unwrap().options = options;
+    }
+
+    /// The stage of the current load, and how far it is from 0 to 1.
+    pub fn progress(&self) -> Option<(LoadStage, f32)> {
+        if self.is_loading() {
+            self.0.lock().unwrap().progress
+        } else {
+            None
+        }
+    }
+
+    /// The last sample of what the loaded model is using.
+    pub fn resources(&self) -> Option<ModelResourcesInfo> {
+        if self.is_loaded() {
+            self.0.lock().unwrap().resources.clone()
+        } else {
+            None
+        }
+    }
+
+    /// The options of the model that is loaded or being loaded.
+    pub fn options(&self) -> Option<LoadModelOptions> {
+        self.0.lock().unwrap().options.clone()
+    }
+
     pub fn status(&self) -> ModelLoaderStatus {
         self.0.lock().unwrap().status.clone()
     }
@@ -156,26 +281,16 @@ impl ModelLoader {
 fn dispatch_load_command(
     command_sender: Sender<Command>,
     file_id: String,
+    options: LoadModelOptions,
     override_port: Option<u16>,
 ) -> Receiver<Result<LoadModelResponse, anyhow::Error>> {
     let (tx, rx) = channel();
 
-    let override_server_address = override_port.map(|port| format!("localhost:{}", port));
-    let cmd = Command::LoadModel(
-        file_id,
-        LoadModelOptions {
-            override_server_address,
-            prompt_template: None,
-            gpu_layers: moly_protocol::protocol::GPULayers::Max,
-            use_mlock: false,
-            rope_freq_scale: 0.0,
-            rope_freq_base: 0.0,
-            context_overflow_policy: moly_protocol::protocol::ContextOverflowPolicy::StopAtLimit,
-            n_batch: None,
-            n_ctx: None,
-        },
-        tx,
-    );
+    let options = LoadModelOptions {
+        override_server_address: override_port.map(|port| format!("localhost:{}", port)),
+        ..options
+    };
+    let cmd = Command::LoadModel(file_id, options, tx);
     command_sender.send(cmd).unwrap();
     rx
 }
@@ -0,0 +1,160 @@
+use makepad_widgets::Cx;
+use moly_protocol::{data::FileID, open_ai::Message, protocol::Command};
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Message emitted when the backend answered a count of tokens.
+#[derive(Debug)]
+pub struct TokenCountChanged;
+
+/// The counts kept at most, the prompt changing with every key typed.
+const MAX_COUNTS: usize = 32;
+
+/// How long the typing pauses before the counts are asked for.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// How long the counts aren't asked for again after the backend failed to count.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+struct Request {
+    key: String,
+    messages: Vec<Message>,
+}
+
+#[derive(Default)]
+struct TokenCounterInner {
+    file_id: Option<FileID>,
+    counts: HashMap<String, u32>,
+    // The last counts asked for, the older ones are no longer shown.
+    wanted: Vec<Request>,
+    retry_at: Option<Instant>,
+    // Wakes the worker up, started with the first count.
+    worker: Option<Sender<()>>,
+}
+
+/// Counts the tokens of conversations with the tokenizer and the chat template of the
+/// loaded model, without blocking: the counts are asked for to the backend by a worker
+/// once the typing pauses, and kept once answered.
+#[derive(Clone, Default)]
+pub struct TokenCounter(Arc<Mutex<TokenCounterInner>>);
+
+impl TokenCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The count of `messages` for the model of `file_id`, if it is known. Otherwise it
+    /// is asked for, and `TokenCountChanged` is posted once answered.
+    pub fn count(
+        &self,
+        file_id: &FileID,
+        messages: &[Message],
+        command_sender: &Sender<Command>,
+    ) -> Option<u32> {
+        let key = serde_json::to_string(messages).ok()?;
+        let mut inner = self.0.lock().unwrap();
+        if inner.file_id.as_ref() != Some(file_id) {
+            let worker = inner.worker.take();
+            *inner = TokenCounterInner {
+                file_id: Some(file_id.clone()),
+                worker,
+                ..Default::default()
+            };
+        }
+        if let Some(count) = inner.counts.get(&key) {
+            return Some(*count);
+        }
+        if inner.retry_at.is_some_and(|at| Instant::now() < at)
+            || inner.wanted.iter().any(|request| request.key == key)
+        {
+            return None;
+        }
+
+        // The history and the history with the prompt.
+        const MAX_WANTED: usize = 2;
+        if inner.wanted.len() >= MAX_WANTED {
+            inner.wanted.remove(0);
+        }
+        inner.wanted.push(Request {
+            key,
+            messages: messages.to_vec(),
+        });
+        if inner.worker.is_none() {
+            inner.worker = Some(self.spawn_worker(command_sender.clone()));
+        }
+        if let Some(worker) = &inner.worker {
+            let _ = worker.send(());
+        }
+        None
+    }
+
+    fn spawn_worker(&self, command_sender: Sender<Command>) -> Sender<()> {
+        let (wake_tx, wake_rx) = channel();
+        let inner = self.0.clone();
+        thread::spawn(move || {
+            while wake_rx.recv().is_ok() {
+                // Waits for the typing to pause.
+                loop {
+                    match wake_rx.recv_timeout(DEBOUNCE) {
+                        Ok(()) => continue,
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+
+                loop {
+                    let (file_id, request) = {
+                        let mut inner = inner.lock().unwrap();
+                        let Some(request) = inner.wanted.pop() else {
+                            break;
+                        };
+                        (inner.file_id.clone(), request)
+                    };
+
+                    let (tx, rx) = channel();
+                    if command_sender
+                        .send(Command::CountChatTokens(request.messages, tx))
+                        .is_err()
+                    {
+                        eprintln!("Error sending the count chat tokens command");
+                        return;
+                    }
+                    let response = rx.recv();
+
+                    let mut inner = inner.lock().unwrap();
+                    if inner.file_id != file_id {
+                        continue;
+                    }
+                    match response {
+                        Ok(Ok(count)) => {
+                            if inner.counts.len() >= MAX_COUNTS {
+                                inner.counts.clear();
+                            }
+                            inner.counts.insert(request.key, count);
+                            inner.retry_at = None;
+                        }
+                        Ok(Err(err)) => {
+                            eprintln!("Error counting tokens: {}", err);
+                            inner.wanted.clear();
+                            inner.retry_at = Some(Instant::now() + RETRY_DELAY);
+                        }
+                        Err(_) => {
+                            inner.wanted.clear();
+                            inner.retry_at = Some(Instant::now() + RETRY_DELAY);
+                        }
+                    }
+                    drop(inner);
+                    Cx::post_action(TokenCountChanged);
+                }
+            }
+        });
+        wake_tx
+    }
+}
@@ -1,9 +1,12 @@
 use makepad_widgets::Cx;
 use moly_backend::Backend;
 use moly_protocol::data::*;
-use moly_protocol::protocol::{Command, FileDownloadResponse};
+use moly_protocol::protocol::{Command, FileDownloadResponse, MolyError};
 use std::sync::mpsc::channel;
 use std::thread;
+use std::time::Duration;
+
+const MAX_DOWNLOAD_RETRIES: u32 = 3;
 
 #[derive(Debug)]
 pub struct DownloadFileAction {
@@ -14,7 +17,7 @@ pub struct DownloadFileAction {
 #[derive(Debug)]
 enum DownloadFileActionKind {
     Progress(f64),
-    Error,
+    Error(MolyError),
     StreamingDone,
 }
 
@@ -31,6 +34,8 @@ pub struct Download {
     pub file: File,
     pub state: DownloadState,
     pub notification_pending: bool,
+    /// Why the download failed, if it did.
+    pub error: Option<MolyError>,
 }
 
 impl Download {
@@ -39,6 +44,7 @@ impl Download {
             file: file,
             state: DownloadState::Initializing(progress),
             notification_pending: false,
+            error: None,
         };
 
         download.start(backend);
@@ -46,62 +52,73 @@ impl Download {
     }
 
     pub fn start(&mut self, backend: &Backend) {
-        let (tx, rx) = channel();
-
-        let cmd = Command::DownloadFile(self.file.id.clone(), tx);
-        backend.command_sender.send(cmd).unwrap();
+        let command_sender = backend.command_sender.clone();
         let file_id = self.file.id.clone();
 
-        thread::spawn(move || loop {
-            let mut is_done = false;
-            if let Ok(response) = rx.recv() {
-                match response {
-                    Ok(response) => match response {
-                        FileDownloadResponse::Completed(_completed) => {
-                            is_done = true;
+        thread::spawn(move || {
+            let mut retries = 0;
+            'download: loop {
+                let (tx, rx) = channel();
+                let cmd = Command::DownloadFile(file_id.clone(), tx);
+                command_sender.send(cmd).unwrap();
+
+                for response in rx {
+                    match response {
+                        Ok(FileDownloadResponse::Completed(_completed)) => {
                             Cx::post_action(DownloadFileAction {
                                 file_id: file_id.clone(),
                                 kind: DownloadFileActionKind::StreamingDone,
                             });
+                            return;
                         }
-                        FileDownloadResponse::Progress(_file, value) => {
+                        Ok(FileDownloadResponse::Progress(_file, value)) => {
                             Cx::post_action(DownloadFileAction {
                                 file_id: file_id.clone(),
                                 kind: DownloadFileActionKind::Progress(value as f64),
                             })
                         }
-                    },
-                    Err(err) => {
-                        Cx::post_action(DownloadFileAction {
-                            file_id: file_id.clone(),
-                            kind: DownloadFileActionKind::Error,
-                        });
-
-                        eprintln!("Error downloading file: {:?}", err)
+                        Err(err) => {
+                            // Network hiccups are retried, the download resumes where it stopped.
+                            let retryable = err
+                                .downcast_ref::<MolyError>()
+                                .is_some_and(MolyError::is_retryable);
+                            if retryable && retries < MAX_DOWNLOAD_RETRIES {
+                                retries += 1;
+                                eprintln!("Retrying download of {file_id} after error: {err}");
+                                thread::sleep(Duration::from_secs(2u64.pow(retries)));
+                                continue 'download;
+                            }
+
+                            Cx::post_action(DownloadFileAction {
+                                file_id: file_id.clone(),
+                                kind: DownloadFileActionKind::Error(MolyError::from_anyhow(&err)),
+                            });
+
+                            eprintln!("Error downloading file: {:?}", err);
+                            return;
+                        }
                     }
                 }
-            } else {
-                break;
-            }
 
-            if is_done {
-                break;
+                // The download was paused or cancelled.
+                return;
             }
         });
     }
 
     pub fn handle_action(&mut self, action: &DownloadFileAction) {
-        match action.kind {
+        match &action.kind {
             DownloadFileActionKind::StreamingDone => {
                 self.state = DownloadState::Completed;
                 self.notification_pending = true;
             }
             DownloadFileActionKind::Progress(value) => {
-                self.state = DownloadState::Downloading(value)
+                self.state = DownloadState::Downloading(*value)
             }
-            DownloadFileActionKind::Error => {
+            DownloadFileActionKind::Error(error) => {
                 let current_progress = self.get_progress();
                 self.state = DownloadState::Errored(current_progress);
+                self.error = Some(error.clone());
                 self.notification_pending = true;
             }
         }
@@ -2,18 +2,23 @@ pub mod download;
 
 use anyhow::{Context, Result};
 use download::{Download, DownloadFileAction, DownloadState};
-use makepad_widgets::Action;
+use makepad_widgets::{Action, Cx};
 use moly_backend::Backend;
 use moly_protocol::{
-    data::{DownloadedFile, File, FileID, Model, PendingDownload, PendingDownloadsStatus},
-    protocol::Command,
+    data::{DownloadedFile, File, FileID, Model, ModelID, PendingDownload, PendingDownloadsStatus},
+    protocol::{self, BackendEvent, Command, EventFilter, LibraryUpdate, MolyError},
 };
-use std::{collections::HashMap, rc::Rc, sync::mpsc::channel};
+use std::{collections::HashMap, rc::Rc, sync::mpsc::channel, thread};
+
+/// Message posted for each download event of the backend, see
+/// [`Downloads::watch_backend_events`].
+#[derive(Debug)]
+pub struct DownloadEventAction(pub BackendEvent);
 
 #[derive(Debug)]
 pub enum DownloadPendingNotification {
     DownloadedFile(File),
-    DownloadErrored(File),
+    DownloadErrored(File, Option<MolyError>),
 }
 pub struct Downloads {
     pub backend: Rc<Backend>,
@@ -21,6 +26,7 @@ pub struct Downloads {
     pub pending_downloads: Vec<PendingDownload>,
     pub current_downloads: HashMap<FileID, Download>,
     pub pending_notifications: Vec<DownloadPendingNotification>,
+    pub library_updates: Vec<LibraryUpdate>,
 }
 
 impl Downloads {
@@ -31,6 +37,7 @@ impl Downloads {
             pending_downloads: Vec::new(),
             current_downloads: HashMap::new(),
             pending_notifications: Vec::new(),
+            library_updates: Vec::new(),
         }
     }
 
@@ -52,6 +59,82 @@ impl Downloads {
         };
     }
 
+    pub fn load_library_updates(&mut self) {
+        let (tx, rx) = channel();
+        self.backend
+            .as_ref()
+            .command_sender
+            .send(Command::GetLibraryUpdates(tx))
+            .unwrap();
+
+        if let Ok(response) = rx.recv() {
+            match response {
+                Ok(updates) => {
+                    self.library_updates = updates;
+                }
+                Err(err) => eprintln!("Error fetching library updates: {:?}", err),
+            }
+        };
+    }
+
+    /// Updates known for a downloaded file. The new files published for its model are
+    /// only shown with the first file downloaded of the model.
+    pub fn library_updates_for(&self, file: &DownloadedFile) -> Vec<&LibraryUpdate> {
+        let shows_new_files = self
+            .downloaded_files
+            .iter()
+            .filter(|f| f.model.id == file.model.id)
+            .min_by(|a, b| (a.downloaded_at, &a.file.id).cmp(&(b.downloaded_at, &b.file.id)))
+            .is_some_and(|first| first.file.id == file.file.id);
+
+        self.library_updates
+            .iter()
+            .filter(|update| match update {
+                LibraryUpdate::NewFile(model_id, _) => {
+                    shows_new_files && *model_id == file.model.id
+                }
+                LibraryUpdate::ChecksumChanged { file_id, .. }
+                | LibraryUpdate::TemplateChanged { file_id, .. } => *file_id == file.file.id,
+            })
+            .collect()
+    }
+
+    /// Stops reporting the new files of the model published so far.
+    pub fn acknowledge_new_files(&mut self, model_id: &ModelID) -> Result<()> {
+        let (tx, rx) = channel();
+        self.backend
+            .as_ref()
+            .command_sender
+            .send(Command::AcknowledgeNewFiles(model_id.clone(), tx))
+            .context("Failed to send acknowledge new files command")?;
+
+        rx.recv()
+            .context("Failed to receive acknowledge new files response")?
+            .context("Acknowledge new files operation failed")?;
+
+        self.library_updates
+            .retain(|update| !matches!(update, LibraryUpdate::NewFile(id, _) if id == model_id));
+        Ok(())
+    }
+
+    pub fn apply_template_update(&mut self, file_id: &FileID) -> Result<()> {
+        let (tx, rx) = channel();
+        self.backend
+            .as_ref()
+            .command_sender
+            .send(Command::ApplyTemplateUpdate(file_id.clone(), tx))
+            .context("Failed to send apply template update command")?;
+
+        rx.recv()
+            .context("Failed to receive apply template update response")?
+            .context("Apply template update operation failed")?;
+
+        self.library_updates.retain(|update| {
+            !matches!(update, LibraryUpdate::TemplateChanged { file_id: id, .. } if id == file_id)
+        });
+        Ok(())
+    }
+
     pub fn load_pending_downloads(&mut self) {
         let (tx, rx) = channel();
         self.backend
@@ -85,6 +168,92 @@ impl Downloads {
         };
     }
 
+    /// Follows the downloads through the events of the backend, including the ones
+    /// started by other clients of a shared backend.
+    pub fn watch_backend_events(&self) {
+        let (tx, rx) = channel();
+        let filter = EventFilter {
+            downloads: true,
+            ..Default::default()
+        };
+        if self
+            .backend
+            .command_sender
+            .send(Command::Subscribe(filter, tx))
+            .is_err()
+        {
+            eprintln!("Error subscribing to the download events");
+            return;
+        }
+
+        thread::spawn(move || {
+            for event in rx {
+                Cx::post_action(DownloadEventAction(event));
+            }
+        });
+    }
+
+    /// Updates the pending downloads with an event of the backend. Returns the id of the
+    /// file if it just finished downloading.
+    ///
+    /// The downloads started here are followed by their own [`Download`], the events only
+    /// matter for the other ones.
+    pub fn handle_backend_event(&mut self, event: &BackendEvent) -> Option<FileID> {
+        let (file_id, state) = match event {
+            BackendEvent::DownloadProgress(file_id, progress) => {
+                if self.current_downloads.contains_key(file_id) {
+                    return None;
+                }
+                match self
+                    .pending_downloads
+                    .iter_mut()
+                    .find(|d| d.file.id == *file_id)
+                {
+                    Some(pending) => {
+                        pending.progress = *progress as f64;
+                        pending.status = PendingDownloadsStatus::Downloading;
+                    }
+                    None => self.load_pending_downloads(),
+                }
+                return None;
+            }
+            BackendEvent::DownloadStateChanged(file_id, state) => (file_id, state),
+            _ => return None,
+        };
+        if self.current_downloads.contains_key(file_id) {
+            return None;
+        }
+
+        let status = match state {
+            protocol::DownloadState::Queued => PendingDownloadsStatus::Initializing,
+            protocol::DownloadState::Downloading => PendingDownloadsStatus::Downloading,
+            protocol::DownloadState::Paused => PendingDownloadsStatus::Paused,
+            protocol::DownloadState::Failed(_) => PendingDownloadsStatus::Error,
+            protocol::DownloadState::Cancelled => {
+                self.pending_downloads.retain(|d| d.file.id != *file_id);
+                return None;
+            }
+            protocol::DownloadState::Completed => {
+                self.pending_downloads.retain(|d| d.file.id != *file_id);
+                if self.downloaded_files.iter().any(|f| f.file.id == *file_id) {
+                    return None;
+                }
+                self.load_downloaded_files();
+                self.load_library_updates();
+                return Some(file_id.clone());
+            }
+        };
+        match self
+            .pending_downloads
+            .iter_mut()
+            .find(|d| d.file.id == *file_id)
+        {
+            Some(pending) => pending.status = status,
+            None => self.load_pending_downloads(),
+        }
+        None
+    }
+
     pub fn download_file(&mut self, model: Model, file: File) {
         let mut current_progress = 0.0;
 
@@ -213,6 +382,7 @@ impl Downloads {
 
         self.load_downloaded_files();
         self.load_pending_downloads();
+        self.load_library_updates();
         Ok(())
     }
 
@@ -261,7 +431,10 @@ impl Downloads {
                         pending.status = PendingDownloadsStatus::Error;
                         if download.must_show_notification() {
                             self.pending_notifications.push(
-                                DownloadPendingNotification::DownloadErrored(download.file.clone()),
+                                DownloadPendingNotification::DownloadErrored(
+                                    download.file.clone(),
+                                    download.error.clone(),
+                                ),
                             );
                         }
                     }
@@ -285,10 +458,12 @@ impl Downloads {
             self.current_downloads.remove(id);
         }
 
-        // Reload downloaded files and pending downloads from the backend
+        // The other pending downloads are kept up to date by the events of the backend.
         if !completed_download_ids.is_empty() {
+            self.pending_downloads
+                .retain(|d| !completed_download_ids.contains(&d.file.id));
             self.load_downloaded_files();
-            self.load_pending_downloads();
+            self.load_library_updates();
         }
 
         completed_download_ids
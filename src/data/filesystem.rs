@@ -1,4 +1,5 @@
 use directories::ProjectDirs;
+use moly_config::MODEL_DOWNLOADS_DIR_NAME;
 use std::{
     fs::{self,File},
     io::{Read, Write},
@@ -6,21 +7,16 @@ use std::{
     sync::OnceLock,
 };
 
-pub const APP_QUALIFIER: &str = "com";
-pub const APP_ORGANIZATION: &str = "moxin-org";
-pub const APP_NAME: &str = "moly";
-
 pub fn project_dirs() -> &'static ProjectDirs {
     // This can be redesigned once std::sync::LazyLock is stabilized.
     static MOLY_PROJECT_DIRS: OnceLock<ProjectDirs> = OnceLock::new();
 
     MOLY_PROJECT_DIRS.get_or_init(|| {
-        ProjectDirs::from(APP_QUALIFIER, APP_ORGANIZATION, APP_NAME)
+        moly_config::project_dirs()
             .expect("Failed to obtain Moly project directories")
     })
 }
 
-pub const MODEL_DOWNLOADS_DIR_NAME: &str = "model_downloads";
 pub fn setup_model_downloads_folder() -> PathBuf {
     let downloads_dir = project_dirs().data_dir().join(MODEL_DOWNLOADS_DIR_NAME);
 
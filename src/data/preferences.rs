@@ -6,13 +6,24 @@ use serde::{Deserialize, Serialize};
 use super::filesystem::{
     setup_preferences_folder, setup_model_downloads_folder, read_from_file, write_to_file,
 };
-const PREFERENCES_FILENAME: &str = "preferences.json";
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Preferences {
     pub current_chat_model: Option<FileID>,
     #[serde(default)]
     pub downloaded_files_dir: PathBuf,
+    #[serde(default)]
+    pub offline_mode: bool,
+    /// Minutes without chats after which the loaded model is ejected, never when None.
+    #[serde(default)]
+    pub idle_unload_minutes: Option<u64>,
+    /// Whether the models used last are loaded again once the app settles after startup.
+    #[serde(default = "default_preload_on_startup")]
+    pub preload_on_startup: bool,
+}
+
+fn default_preload_on_startup() -> bool {
+    true
 }
 
 impl Preferences {
@@ -33,6 +44,9 @@ impl Preferences {
             Self {
                 current_chat_model: None,
                 downloaded_files_dir: setup_model_downloads_folder(),
+                offline_mode: false,
+                idle_unload_minutes: None,
+                preload_on_startup: true,
             }
         }
 
@@ -53,9 +67,30 @@ impl Preferences {
         self.downloaded_files_dir = path;
         self.save();
     }
+
+    pub fn set_offline_mode(&mut self, offline_mode: bool) {
+        self.offline_mode = offline_mode;
+        self.save();
+    }
+
+    pub fn set_idle_unload_minutes(&mut self, minutes: Option<u64>) {
+        self.idle_unload_minutes = minutes;
+        self.save();
+    }
+
+    pub fn set_preload_on_startup(&mut self, preload_on_startup: bool) {
+        self.preload_on_startup = preload_on_startup;
+        self.save();
+    }
+
+    /// Whether Moly should run without any network access, either because it was
+    /// enabled in the preferences or because the app was launched with `--offline`.
+    pub fn is_offline(&self) -> bool {
+        self.offline_mode || moly_config::offline_from_env()
+    }
 }
 
 fn preferences_path() -> PathBuf {
     let preference_dir = setup_preferences_folder();
-    preference_dir.join(PREFERENCES_FILENAME)
+    preference_dir.join(moly_config::PREFERENCES_FILENAME)
 }
@@ -1,6 +1,6 @@
 use super::chats::chat::ChatID;
-use super::chats::model_loader::ModelLoaderStatusChanged;
-use super::downloads::download::DownloadFileAction;
+use super::chats::model_loader::{ModelLoaderStatus, ModelLoaderStatusChanged};
+use super::downloads::{download::DownloadFileAction, DownloadEventAction};
 use super::filesystem::project_dirs;
 use super::preferences::Preferences;
 use super::search::SortCriteria;
@@ -10,9 +10,14 @@ use chrono::{DateTime, Utc};
 use makepad_widgets::{Action, ActionDefaultRef, DefaultNone};
 use moly_backend::Backend;
 use moly_protocol::data::{Author, DownloadedFile, File, FileID, Model, ModelID, PendingDownload};
+use moly_protocol::protocol::{BackendInfo, Command};
+use std::path::PathBuf;
 use std::rc::Rc;
 
 pub const DEFAULT_MAX_DOWNLOAD_THREADS: usize = 3;
+const DAEMON_SOCKET_ENV_VAR: &str = "MOLY_DAEMON_SOCKET";
+/// The models used last that are preloaded, only the first one is loaded.
+const MAX_PRELOADED_FILES: usize = 3;
 
 #[derive(Clone, DefaultNone, Debug)]
 pub enum StoreAction {
@@ -46,6 +51,8 @@ pub struct Store {
     /// This is the backend representation, including the sender and receiver ends of the channels to
     /// communicate with the backend thread.
     pub backend: Rc<Backend>,
+    /// What the backend supports, `None` if it could not tell.
+    pub backend_info: Option<BackendInfo>,
 
     pub search: Search,
     pub downloads: Downloads,
@@ -59,18 +66,58 @@ impl Default for Store {
     }
 }
 
+/// Connects to the `moly-daemon` listening on the socket given by `MOLY_DAEMON_SOCKET`, if set.
+fn attach_to_daemon() -> Option<Backend> {
+    let socket_path = std::env::var_os(DAEMON_SOCKET_ENV_VAR)?;
+
+    #[cfg(unix)]
+    match Backend::connect(&socket_path) {
+        Ok(backend) => return Some(backend),
+        Err(err) => eprintln!(
+            "Error attaching to the Moly daemon at {:?}, starting a local backend: {:?}",
+            socket_path, err
+        ),
+    }
+
+    #[cfg(not(unix))]
+    eprintln!("Attaching to the Moly daemon is not supported on this platform");
+
+    None
+}
+
+fn load_backend_info(backend: &Backend) -> Option<BackendInfo> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    backend
+        .command_sender
+        .send(Command::GetBackendInfo(tx))
+        .ok()?;
+
+    match rx.recv() {
+        Ok(Ok(info)) => Some(info),
+        Ok(Err(err)) => {
+            eprintln!("Error fetching backend info: {:?}", err);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
 impl Store {
     pub fn new() -> Self {
         let preferences = Preferences::load();
         let app_data_dir = project_dirs().data_dir();
 
-        let backend = Rc::new(Backend::new(
-            app_data_dir,
-            preferences.downloaded_files_dir.clone(),
-            DEFAULT_MAX_DOWNLOAD_THREADS,
-        ));
+        let backend = Rc::new(attach_to_daemon().unwrap_or_else(|| {
+            Backend::new(
+                app_data_dir,
+                preferences.downloaded_files_dir.clone(),
+                DEFAULT_MAX_DOWNLOAD_THREADS,
+                preferences.is_offline(),
+            )
+        }));
 
         let mut store = Self {
+            backend_info: load_backend_info(&backend),
             backend: backend.clone(),
             search: Search::new(backend.clone()),
             downloads: Downloads::new(backend.clone()),
@@ -80,14 +127,49 @@ impl Store {
 
         store.downloads.load_downloaded_files();
         store.downloads.load_pending_downloads();
+        store.downloads.load_library_updates();
+        store.downloads.watch_backend_events();
 
         store.chats.load_chats();
         store.init_current_chat();
+        store.init_model_residency();
 
         store.search.load_featured_models();
         store
     }
 
+    /// Whether images can be sent to the model of the current chat: its file must come
+    /// with a multimodal projector, and the backend must handle images, which is assumed
+    /// when it could not tell.
+    pub fn supports_vision(&self) -> bool {
+        let backend_supports_vision = self
+            .backend_info
+            .as_ref()
+            .map_or(true, |info| info.features.vision);
+        backend_supports_vision
+            && self
+                .current_chat_file()
+                .is_some_and(|file| file.mmproj.is_some())
+    }
+
+    /// Whether the backend returns the probabilities of the tokens of the answers.
+    /// Assumed when it could not tell.
+    pub fn supports_logprobs(&self) -> bool {
+        self.backend_info
+            .as_ref()
+            .map_or(true, |info| info.features.logprobs)
+    }
+
+    /// The file the current chat sends its messages to, the loaded one by default.
+    fn current_chat_file(&self) -> Option<&File> {
+        let file_id = self
+            .chats
+            .get_current_chat()
+            .and_then(|chat| chat.borrow().last_used_file_id.clone())
+            .or_else(|| self.chats.loaded_model.as_ref().map(|file| file.id.clone()))?;
+        self.downloads.get_file(&file_id)
+    }
+
     pub fn load_model(&mut self, file: &File) {
         self.chats.load_model(file, None);
     }
@@ -101,7 +183,17 @@ impl Store {
     }
 
     fn update_load_model(&mut self) {
+        if matches!(
+            self.chats.model_loader.status(),
+            ModelLoaderStatus::Unloaded
+        ) {
+            self.chats.loaded_model = None;
+            self.backend_info = load_backend_info(&self.backend);
+        }
+
         if self.chats.model_loader.is_loaded() {
+            // The features are the ones of the engine the model was loaded with.
+            self.backend_info = load_backend_info(&self.backend);
             self.chats.loaded_model = self
                 .chats
                 .model_loader
@@ -121,7 +213,7 @@ impl Store {
         }
     }
 
-    pub fn send_chat_message(&mut self, prompt: String) {
+    pub fn send_chat_message(&mut self, prompt: String, images: Vec<PathBuf>) {
         if let Some(mut chat) = self.chats.get_current_chat().map(|c| c.borrow_mut()) {
             let wanted_file = self
                 .chats
@@ -132,7 +224,9 @@ impl Store {
             if let Some(file) = wanted_file {
                 chat.send_message_to_model(
                     prompt,
+                    images,
                     file,
+                    self.chats.load_options(&file.id),
                     self.chats.model_loader.clone(),
                     &self.backend,
                 );
@@ -159,10 +253,18 @@ impl Store {
                 .flatten();
 
             if let Some(file) = wanted_file {
+                let images = chat
+                    .messages
+                    .iter()
+                    .find(|m| m.id == message_id)
+                    .map(|m| m.images.clone())
+                    .unwrap_or_default();
                 chat.remove_messages_from(message_id);
                 chat.send_message_to_model(
                     updated_message,
+                    images,
                     file,
+                    self.chats.load_options(&file.id),
                     self.chats.model_loader.clone(),
                     &self.backend,
                 );
@@ -275,6 +377,13 @@ impl Store {
         if let Some(_) = action.downcast_ref::<DownloadFileAction>() {
             self.update_downloads();
         }
+
+        if let Some(DownloadEventAction(event)) = action.downcast_ref::<DownloadEventAction>() {
+            if let Some(file_id) = self.downloads.handle_backend_event(event) {
+                self.search
+                    .update_downloaded_file_in_search_results(&file_id, true);
+            }
+        }
     }
 
     fn update_downloads(&mut self) {
@@ -294,20 +403,46 @@ impl Store {
         } else {
             self.chats.create_empty_chat();
         }
+    }
 
-        // If there is no load model, let's try to load the one from preferences
-        if self.chats.loaded_model.is_none() {
-            if let Some(ref file_id) = self.preferences.current_chat_model {
-                if let Some(file) = self
-                    .downloads
-                    .downloaded_files
-                    .iter()
-                    .find(|d| d.file.id == *file_id)
-                    .map(|d| d.file.clone())
-                {
-                    self.load_model(&file);
-                }
+    /// Passes the idle timeout to the backend, and queues the models used last to be
+    /// loaded once the app settles, instead of loading them while it starts.
+    fn init_model_residency(&mut self) {
+        if let Err(e) = self
+            .chats
+            .set_idle_timeout(self.preferences.idle_unload_minutes)
+        {
+            eprintln!("Error setting the idle timeout: {e:#}");
+        }
+
+        if !self.preferences.preload_on_startup {
+            return;
+        }
+        let mut file_ids = Vec::new();
+        let candidates = self
+            .preferences
+            .current_chat_model
+            .iter()
+            .cloned()
+            .chain(self.chats.recently_used_files());
+        for file_id in candidates {
+            if file_ids.len() == MAX_PRELOADED_FILES {
+                break;
             }
+            if !file_ids.contains(&file_id) && self.downloads.get_file(&file_id).is_some() {
+                file_ids.push(file_id);
+            }
+        }
+
+        if let Err(e) = self.chats.preload_models(file_ids) {
+            eprintln!("Error queueing the models to preload: {e:#}");
+        }
+    }
+
+    pub fn set_idle_unload_minutes(&mut self, minutes: Option<u64>) {
+        self.preferences.set_idle_unload_minutes(minutes);
+        if let Err(e) = self.chats.set_idle_timeout(minutes) {
+            eprintln!("Error setting the idle timeout: {e:#}");
         }
     }
 
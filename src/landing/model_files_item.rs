@@ -295,11 +295,13 @@ impl Widget for ModelFilesItem {
                 }},
             );
         } else if files_info.file.downloaded {
+            // Adapters are loaded with the files of their model, not chatted with.
+            let can_chat = !files_info.file.is_adapter;
             self.apply_over(
                 cx,
                 live! { cell4 = {
                     download_pending_controls = { visible: false }
-                    start_chat_button = { visible: true }
+                    start_chat_button = { visible: (can_chat) }
                     download_button = { visible: false }
                 }},
             );
@@ -365,8 +367,10 @@ impl ModelFilesItemRef {
 
         item_widget.file_id = Some(file.id.clone());
 
-        item_widget
-            .model_files_tags(id!(tags))
-            .set_tags(cx, &file.tags);
+        let mut tags = file.tags.clone();
+        if file.is_adapter {
+            tags.insert(0, "LoRA adapter".to_string());
+        }
+        item_widget.model_files_tags(id!(tags)).set_tags(cx, &tags);
     }
 }
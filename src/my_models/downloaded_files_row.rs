@@ -1,9 +1,11 @@
 use super::{delete_model_modal::DeleteModelModalAction, model_info_modal::ModelInfoModalAction};
+use crate::data::store::Store;
 use crate::shared::actions::ChatAction;
 use crate::shared::modal::ModalWidgetExt;
 use crate::shared::utils::format_model_size;
 use makepad_widgets::*;
 use moly_protocol::data::{DownloadedFile, FileID};
+use moly_protocol::protocol::LibraryUpdate;
 
 live_design! {
     import makepad_widgets::base::*;
@@ -66,6 +68,45 @@ live_design! {
                     draw_bg: { color: #D4E6F7 },
                 }
             }
+            update_tag = <View> {
+                visible: false
+                width: Fit
+                spacing: 5
+                align: {x: 0.0, y: 0.5}
+                update = <AttributeTag> {
+                    draw_bg: { color: #FEF0C7 },
+                }
+                apply_template_button = <MolyButton> {
+                    visible: false
+                    width: Fit
+                    height: Fit
+                    padding: {top: 6, bottom: 6, left: 10, right: 10}
+                    text: "Apply template"
+                    draw_bg: {
+                        border_color: #ccc,
+                        radius: 2.0,
+                    }
+                    draw_text: {
+                        color: (MODEL_CTA_COLOR)
+                        text_style: <REGULAR_FONT>{font_size: 8}
+                    }
+                }
+                dismiss_new_files_button = <MolyButton> {
+                    visible: false
+                    width: Fit
+                    height: Fit
+                    padding: {top: 6, bottom: 6, left: 10, right: 10}
+                    text: "Dismiss new files"
+                    draw_bg: {
+                        border_color: #ccc,
+                        radius: 2.0,
+                    }
+                    draw_text: {
+                        color: (MODEL_CTA_COLOR)
+                        text_style: <REGULAR_FONT>{font_size: 8}
+                    }
+                }
+            }
         }
         model_version_tag = <View> {
             width: Fit
@@ -183,6 +224,7 @@ live_design! {
 
 pub struct DownloadedFilesRowProps {
     pub downloaded_file: DownloadedFile,
+    pub library_updates: Vec<LibraryUpdate>,
 }
 
 #[derive(Live, LiveHook, Widget)]
@@ -235,6 +277,42 @@ impl Widget for DownloadedFilesRow {
         self.label(id!(h_wrapper.model_file.model_version_tag.version))
             .set_text(&filename);
 
+        // Update tag
+        let update_text = update_summary(&props.library_updates);
+        self.view(id!(h_wrapper.model_file.h_wrapper.update_tag))
+            .set_visible(update_text.is_some());
+        self.label(id!(h_wrapper
+            .model_file
+            .h_wrapper
+            .update_tag
+            .update
+            .attr_name))
+            .set_text(update_text.as_deref().unwrap_or_default());
+        let template_changed = props
+            .library_updates
+            .iter()
+            .any(|u| matches!(u, LibraryUpdate::TemplateChanged { .. }));
+        self.button(id!(h_wrapper
+            .model_file
+            .h_wrapper
+            .update_tag
+            .apply_template_button))
+            .set_visible(template_changed);
+        let new_files = props
+            .library_updates
+            .iter()
+            .any(|u| matches!(u, LibraryUpdate::NewFile(..)));
+        self.button(id!(h_wrapper
+            .model_file
+            .h_wrapper
+            .update_tag
+            .dismiss_new_files_button))
+            .set_visible(new_files);
+
+        // Adapters are loaded with the files of their model, not chatted with.
+        self.button(id!(start_chat_button))
+            .set_visible(!downloaded_file.file.is_adapter);
+
         // File size tag
         let file_size = format_model_size(&downloaded_file.file.size).unwrap_or("-".to_string());
         self.label(id!(h_wrapper.file_size_tag.label))
@@ -250,13 +328,41 @@ impl Widget for DownloadedFilesRow {
 }
 
 impl WidgetMatchEvent for DownloadedFilesRow {
-    fn handle_actions(&mut self, cx: &mut Cx, actions: &Actions, _scope: &mut Scope) {
+    fn handle_actions(&mut self, cx: &mut Cx, actions: &Actions, scope: &mut Scope) {
         if self.button(id!(start_chat_button)).clicked(actions) {
             if let Some(file_id) = &self.file_id {
                 cx.action(ChatAction::Start(file_id.clone()));
             }
         }
 
+        if self.button(id!(apply_template_button)).clicked(actions) {
+            if let Some(file_id) = &self.file_id {
+                let store = scope.data.get_mut::<Store>().unwrap();
+                if let Err(err) = store.downloads.apply_template_update(file_id) {
+                    eprintln!("Error applying template update: {:?}", err);
+                }
+                self.redraw(cx);
+            }
+        }
+
+        if self.button(id!(dismiss_new_files_button)).clicked(actions) {
+            if let Some(file_id) = &self.file_id {
+                let store = scope.data.get_mut::<Store>().unwrap();
+                let model_id = store
+                    .downloads
+                    .downloaded_files
+                    .iter()
+                    .find(|f| f.file.id == *file_id)
+                    .map(|f| f.model.id.clone());
+                if let Some(model_id) = model_id {
+                    if let Err(err) = store.downloads.acknowledge_new_files(&model_id) {
+                        eprintln!("Error dismissing the new files of {}: {:?}", model_id, err);
+                    }
+                }
+                self.redraw(cx);
+            }
+        }
+
         if self.button(id!(row_actions.info_button)).clicked(actions) {
             self.modal(id!(info_modal)).open(cx);
         }
@@ -309,6 +415,36 @@ fn human_readable_name(name: &str) -> String {
     name
 }
 
+/// Short description of the updates available for a file, if any.
+fn update_summary(updates: &[LibraryUpdate]) -> Option<String> {
+    let mut parts = vec![];
+    let new_files = updates
+        .iter()
+        .filter(|u| matches!(u, LibraryUpdate::NewFile(..)))
+        .count();
+    if new_files > 0 {
+        parts.push(format!("{} new file(s)", new_files));
+    }
+    if updates
+        .iter()
+        .any(|u| matches!(u, LibraryUpdate::ChecksumChanged { .. }))
+    {
+        parts.push("file replaced upstream".to_string());
+    }
+    if updates
+        .iter()
+        .any(|u| matches!(u, LibraryUpdate::TemplateChanged { .. }))
+    {
+        parts.push("new prompt template".to_string());
+    }
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(format!("Update available: {}", parts.join(", ")))
+    }
+}
+
 fn dash_if_empty(input: &str) -> &str {
     if input.is_empty() {
         "-"
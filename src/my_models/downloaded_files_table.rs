@@ -136,8 +136,19 @@ impl Widget for DownloadedFilesTable {
                         item.as_downloaded_files_row()
                             .set_file_id(file_data.file.id.clone());
 
+                        let library_updates = scope
+                            .data
+                            .get::<Store>()
+                            .unwrap()
+                            .downloads
+                            .library_updates_for(file_data)
+                            .into_iter()
+                            .cloned()
+                            .collect();
+
                         let props = DownloadedFilesRowProps {
                             downloaded_file: file_data.clone(),
+                            library_updates,
                         };
                         let mut scope = Scope::with_props(&props);
                         item.draw_all(cx, &mut scope);
@@ -1,5 +1,6 @@
 use makepad_code_editor::code_view::CodeViewWidgetExt;
 use makepad_widgets::*;
+use moly_protocol::protocol::MolyError;
 
 use crate::data::{
     chats::model_loader::{ModelLoaderStatus, ModelLoaderStatusChanged},
@@ -45,6 +46,62 @@ live_design! {
                 text: "Settings"
             }
 
+            <View> {
+                width: Fill, height: Fit
+                flow: Down
+                spacing: 10
+
+                <Label> {
+                    draw_text:{
+                        text_style: <BOLD_FONT>{font_size: 16}
+                        color: #000
+                    }
+                    text: "Models in memory"
+                }
+
+                <View> {
+                    width: Fit, height: Fit
+                    flow: Right
+                    spacing: 10
+                    align: {x: 0.0, y: 0.5}
+
+                    <Label> {
+                        draw_text:{
+                            text_style: <REGULAR_FONT>{font_size: 12}
+                            color: #000
+                        }
+                        text: "Eject the model after this many idle minutes:"
+                    }
+
+                    idle_unload_input = <MolyTextInput> {
+                        width: 100,
+                        height: Fit,
+                        empty_message: "Never"
+                        draw_text: {
+                            text_style: <REGULAR_FONT>{font_size: 12}
+                            color: #000
+                        }
+                    }
+                }
+
+                <View> {
+                    width: Fit, height: Fit
+                    flow: Right
+                    spacing: 10
+                    align: {x: 0.0, y: 0.5}
+
+                    <Label> {
+                        draw_text:{
+                            text_style: <REGULAR_FONT>{font_size: 12}
+                            color: #000
+                        }
+                        text: "Load the last used model once Moly starts"
+                    }
+
+                    preload_on_startup = <MolySwitch> {}
+                }
+            }
+
             no_model = <View> {
                 visible: false,
                 width: Fill, height: Fill
@@ -137,15 +194,29 @@ live_design! {
                     }
                 }
 
+                context_size_label = <Label> {
+                    draw_text:{
+                        text_style: <REGULAR_FONT>{font_size: 12}
+                        color: #000
+                    }
+                }
+
+                resources_label = <Label> {
+                    visible: false,
+                    draw_text:{
+                        text_style: <REGULAR_FONT>{font_size: 12}
+                        color: #000
+                    }
+                }
+
                 load_error_label = <View> {
                     visible: false,
                     width: Fit, height: Fit
-                    <Label> {
+                    load_error_text = <Label> {
                         draw_text:{
                             text_style: <REGULAR_FONT>{font_size: 12}
                             color: #000
                         }
-                        text: "Something went wrong while loading the model using this port number. Please try another one."
                     }
                 }
 
@@ -189,6 +260,9 @@ pub struct SettingsScreen {
 
     #[rust]
     override_port: Option<u16>,
+
+    #[rust]
+    preferences_shown: bool,
 }
 
 impl Widget for SettingsScreen {
@@ -200,6 +274,17 @@ impl Widget for SettingsScreen {
     fn draw_walk(&mut self, cx: &mut Cx2d, scope: &mut Scope, walk: Walk) -> DrawStep {
         let store = scope.data.get_mut::<Store>().unwrap();
 
+        if !self.preferences_shown {
+            let minutes = store.preferences.idle_unload_minutes;
+            self.view
+                .text_input(id!(idle_unload_input))
+                .set_text(&minutes.map(|m| m.to_string()).unwrap_or_default());
+            self.view
+                .check_box(id!(preload_on_startup))
+                .set_selected(cx, store.preferences.preload_on_startup);
+            self.preferences_shown = true;
+        }
+
         match self.server_port_state {
             ServerPortState::OnEdit => {
                 self.view.view(id!(editable)).set_visible(false);
@@ -211,13 +296,13 @@ impl Widget for SettingsScreen {
             }
         }
 
-        let port = self.override_port.or_else(|| {
-            if let ModelLoaderStatus::Loaded(info) = store.chats.model_loader.status() {
-                Some(info.listen_port)
-            } else {
-                None
-            }
-        });
+        let loaded_info = match store.chats.model_loader.status() {
+            ModelLoaderStatus::Loaded(info) => Some(info),
+            _ => None,
+        };
+        let port = self
+            .override_port
+            .or_else(|| loaded_info.as_ref().map(|info| info.listen_port));
 
         if let Some(port) = port {
             self.view.view(id!(no_model)).set_visible(false);
@@ -227,6 +312,25 @@ impl Widget for SettingsScreen {
                 .label(id!(port_number_label))
                 .set_text(&format!("{}", port));
 
+            let context_size_label = self.view.label(id!(context_size_label));
+            if let Some(info) = loaded_info.filter(|info| info.n_ctx > 0) {
+                context_size_label.set_text(&format!("Context size: {} tokens", info.n_ctx));
+                context_size_label.set_visible(true);
+            } else {
+                context_size_label.set_visible(false);
+            }
+
+            let resources_label = self.view.label(id!(resources_label));
+            if let Some(resources) = store.chats.model_loader.resources() {
+                resources_label.set_text(&format!(
+                    "Memory used: {:.1} GB · CPU: {:.0}%",
+                    resources.ram_usage, resources.cpu_usage
+                ));
+                resources_label.set_visible(true);
+            } else {
+                resources_label.set_visible(false);
+            }
+
             self.view.code_view(id!(code_snippet)).set_text(&format!(
                 "# Load a model and run this example in your terminal
 # Choose between streaming and non-streaming mode by setting the \"stream\" field
@@ -264,14 +368,41 @@ impl WidgetMatchEvent for SettingsScreen {
                 if store.chats.model_loader.is_loaded() {
                     self.override_port = None;
                 }
-                if store.chats.model_loader.is_failed() {
+                if let Some(error) = store.chats.model_loader.error() {
+                    let text = match error {
+                        MolyError::PortInUse(port) => format!(
+                            "The port {port} is used by another application. Please try another one."
+                        ),
+                        MolyError::OutOfMemory(_) => {
+                            "There is not enough memory to load this model.".to_string()
+                        }
+                        error => format!("Something went wrong while loading the model: {error}"),
+                    };
+                    self.label(id!(load_error_text)).set_text(&text);
                     self.view(id!(load_error_label)).set_visible(true);
                 } else {
                     self.view(id!(load_error_label)).set_visible(false);
                 }
+                self.redraw(cx);
             }
         }
 
+        let idle_unload_input = self.view.text_input(id!(idle_unload_input));
+        if let Some(minutes) = idle_unload_input.returned(actions) {
+            let minutes = minutes.trim();
+            if minutes.is_empty() {
+                store.set_idle_unload_minutes(None);
+            } else if let Ok(minutes) = minutes.parse::<u64>() {
+                store.set_idle_unload_minutes(Some(minutes.max(1)));
+            }
+            let minutes = store.preferences.idle_unload_minutes;
+            idle_unload_input.set_text(&minutes.map(|m| m.to_string()).unwrap_or_default());
+        }
+
+        if let Some(preload) = self.check_box(id!(preload_on_startup)).changed(actions) {
+            store.preferences.set_preload_on_startup(preload);
+        }
+
         let port_number_input = self.view.text_input(id!(port_number_input));
 
         if self.button(id!(edit_port_number)).clicked(actions) {
@@ -1,5 +1,6 @@
 use makepad_widgets::*;
 use moly_protocol::data::{File, FileID};
+use moly_protocol::protocol::MolyError;
 
 use crate::shared::actions::DownloadAction;
 
@@ -212,7 +213,7 @@ pub enum DownloadNotificationPopupAction {
 pub enum DownloadResult {
     #[default]
     Success,
-    Failure,
+    Failure(Option<MolyError>),
 }
 
 #[derive(Live, LiveHook, Widget)]
@@ -280,7 +281,7 @@ impl DownloadNotificationPopup {
     pub fn update_content(&mut self) {
         match self.download_result {
             DownloadResult::Success => self.show_success_content(),
-            DownloadResult::Failure => self.show_failure_content(),
+            DownloadResult::Failure(_) => self.show_failure_content(),
         }
     }
 
@@ -308,12 +309,20 @@ impl DownloadNotificationPopup {
         self.label(id!(title))
             .set_text("Errors while downloading models");
 
-        self.label(id!(summary)).set_text(
-            &(format!(
+        let summary = match &self.download_result {
+            DownloadResult::Failure(Some(MolyError::ChecksumMismatch { .. })) => format!(
+                "{} doesn't match the checksum of the model catalog, the corrupted file was removed.",
+                &self.filename
+            ),
+            DownloadResult::Failure(Some(error)) => {
+                format!("{} couldn't be downloaded: {error}", &self.filename)
+            }
+            _ => format!(
                 "{} encountered some errors when downloading.",
                 &self.filename
-            )),
-        );
+            ),
+        };
+        self.label(id!(summary)).set_text(&summary);
     }
 }
 